@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE xbees_old (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER,
+    name VARCHAR NOT NULL,
+    units VARCHAR NOT NULL
+);
+
+INSERT INTO xbees_old (id, node_id, name, units)
+SELECT id, node_id, name, units FROM xbees;
+
+DROP TABLE xbees;
+ALTER TABLE xbees_old RENAME TO xbees;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN display_order INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX readings_node_timestamp;
+DROP TABLE readings;
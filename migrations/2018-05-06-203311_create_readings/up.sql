@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE readings (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER NOT NULL,
+    reading INTEGER NOT NULL,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX readings_node_timestamp ON readings (node_id, timestamp);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY,
+    username TEXT UNIQUE NOT NULL,
+    password TEXT NOT NULL
+);
+
+INSERT INTO users_old (id, username, password)
+SELECT id, username, password FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- The original casing of usernames can't be recovered, and renamed
+-- users keep their new names.
+DROP INDEX users_username_nocase;
+DROP TABLE username_renames;
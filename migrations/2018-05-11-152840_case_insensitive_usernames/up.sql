@@ -0,0 +1,27 @@
+-- Your SQL goes here
+-- Usernames that only differ in case would collide once lowercased.
+-- The oldest account keeps the name and the others get their id
+-- appended, such as "alice-7". Every rename is kept in
+-- username_renames so the server can log it and the users can be told.
+CREATE TABLE username_renames (
+    user_id INTEGER PRIMARY KEY,
+    old_username TEXT NOT NULL,
+    new_username TEXT NOT NULL
+);
+
+INSERT INTO username_renames (user_id, old_username, new_username)
+SELECT id, username, LOWER(username) || '-' || id FROM users
+WHERE EXISTS (
+    SELECT 1 FROM users AS other
+    WHERE LOWER(other.username) = LOWER(users.username)
+    AND other.id < users.id
+);
+
+UPDATE users SET username = (
+    SELECT new_username FROM username_renames WHERE user_id = users.id
+)
+WHERE id IN (SELECT user_id FROM username_renames);
+
+UPDATE users SET username = LOWER(username);
+
+CREATE UNIQUE INDEX users_username_nocase ON users (username COLLATE NOCASE);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE node_tags (
+    node_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (node_id, tag)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- Rows removed as duplicates can't be recovered.
+DROP INDEX xbees_node_id;
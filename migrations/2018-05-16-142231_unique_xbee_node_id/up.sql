@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- Keep only the most recently added row for each node before
+-- making node ids unique.
+DELETE FROM xbees
+WHERE id NOT IN (SELECT MAX(id) FROM xbees GROUP BY node_id);
+
+CREATE UNIQUE INDEX xbees_node_id ON xbees (node_id);
@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE xbees_old (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER,
+    name VARCHAR NOT NULL,
+    units VARCHAR NOT NULL,
+    display_order INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO xbees_old (id, node_id, name, units, display_order)
+SELECT id, node_id, name, units, display_order FROM xbees;
+
+DROP TABLE xbees;
+ALTER TABLE xbees_old RENAME TO xbees;
+
+CREATE UNIQUE INDEX xbees_node_id ON xbees (node_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN report_interval_secs INTEGER;
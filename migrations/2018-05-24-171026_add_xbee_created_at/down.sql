@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE xbees_old (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER,
+    name VARCHAR NOT NULL,
+    units VARCHAR NOT NULL,
+    display_order INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    report_interval_secs INTEGER
+);
+
+INSERT INTO xbees_old (id, node_id, name, units, display_order, enabled, report_interval_secs)
+SELECT id, node_id, name, units, display_order, enabled, report_interval_secs FROM xbees;
+
+DROP TABLE xbees;
+ALTER TABLE xbees_old RENAME TO xbees;
+
+CREATE UNIQUE INDEX xbees_node_id ON xbees (node_id);
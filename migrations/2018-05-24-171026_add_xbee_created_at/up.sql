@@ -0,0 +1,21 @@
+-- Your SQL goes here
+--  SQLite can't add a column whose default isn't constant, so the
+--  table is recreated. Existing nodes are treated as added now.
+CREATE TABLE xbees_new (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER,
+    name VARCHAR NOT NULL,
+    units VARCHAR NOT NULL,
+    display_order INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    report_interval_secs INTEGER,
+    created_at BIGINT NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER))
+);
+
+INSERT INTO xbees_new (id, node_id, name, units, display_order, enabled, report_interval_secs)
+SELECT id, node_id, name, units, display_order, enabled, report_interval_secs FROM xbees;
+
+DROP TABLE xbees;
+ALTER TABLE xbees_new RENAME TO xbees;
+
+CREATE UNIQUE INDEX xbees_node_id ON xbees (node_id);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN min_alert DOUBLE;
+ALTER TABLE xbees ADD COLUMN max_alert DOUBLE;
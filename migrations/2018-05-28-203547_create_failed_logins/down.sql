@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX failed_logins_timestamp;
+DROP TABLE failed_logins;
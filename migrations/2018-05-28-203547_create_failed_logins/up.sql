@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE failed_logins (
+    id INTEGER PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    ip VARCHAR,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX failed_logins_timestamp ON failed_logins (timestamp);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX sent_messages_node_id_timestamp;
+DROP TABLE sent_messages;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE sent_messages (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER NOT NULL,
+    content VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    sender_id INTEGER NOT NULL,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX sent_messages_node_id_timestamp ON sent_messages (node_id, timestamp);
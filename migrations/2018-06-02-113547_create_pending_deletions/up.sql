@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE pending_deletions (
+    node_id INTEGER PRIMARY KEY,
+    requested_by INTEGER NOT NULL,
+    requested_at BIGINT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE user_prefs (
+    user_id INTEGER PRIMARY KEY,
+    sort_key TEXT NOT NULL,
+    sort_desc BOOLEAN NOT NULL DEFAULT 0
+);
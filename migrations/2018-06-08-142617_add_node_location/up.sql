@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN latitude DOUBLE;
+ALTER TABLE xbees ADD COLUMN longitude DOUBLE;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE alert_acks (
+    node_id INTEGER PRIMARY KEY,
+    acknowledged_by INTEGER NOT NULL,
+    acknowledged_at BIGINT NOT NULL
+);
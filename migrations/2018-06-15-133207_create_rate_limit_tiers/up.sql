@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE rate_limit_tiers (
+    user_id INTEGER PRIMARY KEY,
+    tier TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE session_revocations (
+    user_id INTEGER PRIMARY KEY,
+    revoked_at BIGINT NOT NULL
+);
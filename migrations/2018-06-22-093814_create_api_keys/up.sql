@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    key_hash TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT
+);
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN color TEXT;
+ALTER TABLE xbees ADD COLUMN icon TEXT;
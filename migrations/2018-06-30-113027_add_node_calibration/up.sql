@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN calibration_offset DOUBLE;
+ALTER TABLE xbees ADD COLUMN calibration_scale DOUBLE;
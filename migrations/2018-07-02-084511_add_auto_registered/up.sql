@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE xbees ADD COLUMN auto_registered BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY,
+    username TEXT UNIQUE NOT NULL,
+    password TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'user'
+);
+
+INSERT INTO users_old (id, username, password, role)
+SELECT id, username, password, role FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_username_nocase ON users (username COLLATE NOCASE);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX audit_events_timestamp;
+DROP TABLE audit_events;
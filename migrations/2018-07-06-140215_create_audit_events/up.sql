@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY,
+    actor_id INTEGER,
+    action VARCHAR NOT NULL,
+    node_id INTEGER,
+    target_user_id INTEGER,
+    detail VARCHAR,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX audit_events_timestamp ON audit_events (timestamp);
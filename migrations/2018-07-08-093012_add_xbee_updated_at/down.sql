@@ -0,0 +1,39 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER node_tags_insert_updated_at;
+DROP TRIGGER node_tags_delete_updated_at;
+DROP TRIGGER xbees_insert_updated_at;
+DROP TRIGGER xbees_update_updated_at;
+
+CREATE TABLE xbees_old (
+    id INTEGER PRIMARY KEY,
+    node_id INTEGER,
+    name VARCHAR NOT NULL,
+    units VARCHAR NOT NULL,
+    display_order INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    report_interval_secs INTEGER,
+    created_at BIGINT NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+    min_alert DOUBLE,
+    max_alert DOUBLE,
+    retention_days INTEGER,
+    latitude DOUBLE,
+    longitude DOUBLE,
+    location_name VARCHAR,
+    alert_hysteresis DOUBLE,
+    description TEXT,
+    min_reading_interval_secs INTEGER,
+    color TEXT,
+    icon TEXT,
+    muted_until BIGINT,
+    calibration_offset DOUBLE,
+    calibration_scale DOUBLE,
+    auto_registered BOOLEAN NOT NULL DEFAULT 0
+);
+
+INSERT INTO xbees_old (id, node_id, name, units, display_order, enabled, report_interval_secs, created_at, min_alert, max_alert, retention_days, latitude, longitude, location_name, alert_hysteresis, description, min_reading_interval_secs, color, icon, muted_until, calibration_offset, calibration_scale, auto_registered)
+SELECT id, node_id, name, units, display_order, enabled, report_interval_secs, created_at, min_alert, max_alert, retention_days, latitude, longitude, location_name, alert_hysteresis, description, min_reading_interval_secs, color, icon, muted_until, calibration_offset, calibration_scale, auto_registered FROM xbees;
+
+DROP TABLE xbees;
+ALTER TABLE xbees_old RENAME TO xbees;
+
+CREATE UNIQUE INDEX xbees_node_id ON xbees (node_id);
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- When anything about a node's configuration last changed, including
+-- its tags, so conditional requests notice it. Triggers keep it up to
+-- date however the row is changed.
+ALTER TABLE xbees ADD COLUMN updated_at BIGINT NOT NULL DEFAULT 0;
+
+UPDATE xbees SET updated_at = created_at;
+
+CREATE TRIGGER xbees_insert_updated_at AFTER INSERT ON xbees
+BEGIN
+    UPDATE xbees SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER xbees_update_updated_at AFTER UPDATE ON xbees
+WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE xbees SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER node_tags_insert_updated_at AFTER INSERT ON node_tags
+BEGIN
+    UPDATE xbees SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE node_id = NEW.node_id;
+END;
+
+CREATE TRIGGER node_tags_delete_updated_at AFTER DELETE ON node_tags
+BEGIN
+    UPDATE xbees SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE node_id = OLD.node_id;
+END;
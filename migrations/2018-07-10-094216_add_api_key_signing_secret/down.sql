@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE api_keys_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    key_hash TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT
+);
+
+INSERT INTO api_keys_old (id, user_id, label, key_hash, created_at, last_used_at)
+SELECT id, user_id, label, key_hash, created_at, last_used_at FROM api_keys;
+
+DROP TABLE api_keys;
+ALTER TABLE api_keys_old RENAME TO api_keys;
+
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
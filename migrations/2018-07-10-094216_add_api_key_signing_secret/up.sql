@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- The shared secret signed requests are checked with. Keys without
+-- one don't have to sign their requests.
+ALTER TABLE api_keys ADD COLUMN signing_secret TEXT;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url TEXT NOT NULL,
+    node_id INTEGER,
+    created_by INTEGER NOT NULL,
+    created_at BIGINT NOT NULL
+);
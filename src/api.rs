@@ -1,22 +1,448 @@
-use rocket::Outcome;
-use rocket::http::{Cookie, Cookies};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use rocket::{Data, Outcome, State};
+use rocket::http::{ContentType, Cookie, Cookies, Header, Status};
 use rocket::request::{self, Request, FromRequest};
+use rocket::response::content::{Content, Html};
+use rocket::response::status;
 use rocket_contrib::{Json, JsonValue};
 
 use bcrypt;
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
 
 use db::{self, DbConn};
 use db::models::*;
 use db::schema::users::dsl::*;
 use diesel;
 use diesel::prelude::*;
+use failure::Error;
+use serde_json;
+
+use super::api_key;
+use super::audit::AuditCsv;
+use super::clock::SharedClock;
+use super::conditional::{Conditional, IfModifiedSince};
+use super::config::{Features, Settings};
+use super::convert;
+use super::encoding::Decoded;
+use super::info::{InfoSet, XbeeInfo, BROADCAST};
+use super::links::PageLinks;
+use super::logging::LastEventId;
+use super::metrics::Metrics;
+use super::naming::{Camel, CamelCase, Cased, FieldCase};
+use super::negotiate::{self, Format, Negotiated};
+use super::query::Query;
+use super::rate_limit::{self, Limited, RateLimiter, Tier};
+use super::report;
+use super::session::{self, ActiveSessions};
+use super::sort;
+use super::subscribers::{Subscribers, TAIL_STREAM};
+use super::token;
+use super::webhook;
 
-use super::info::InfoSet;
+/// The version of the API's response contract. This is sent with
+/// every response in the `X-Api-Version` header, so clients can tell
+/// which contract they are getting. It should be bumped whenever a
+/// response changes in a way that could break existing clients.
+pub const API_VERSION: &str = "1";
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Message {
     content: String,
     dest: u32,
+    encoding: Option<String>,
+    condition: Option<SendCondition>,
+}
+
+impl Message {
+    /// Returns an error if the message has nothing to send, or its
+    /// condition isn't one that is supported.
+    fn validate(&self) -> Result<(), String> {
+        if self.content.trim().is_empty() {
+            return Err("Empty message".to_string());
+        }
+
+        match self.condition {
+            Some(ref condition) => condition.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A check a node's current data has to pass for a message to be
+/// sent to it, such as its reading being above some value.
+#[derive(Debug, Deserialize, Serialize)]
+struct SendCondition {
+    field: String,
+    op: String,
+    value: f64,
+}
+
+/// The fields of a node that a send condition can check.
+const CONDITION_FIELDS: &[&str] = &["reading", "value"];
+
+/// The comparisons a send condition can make.
+const CONDITION_OPS: &[&str] = &[">", ">=", "<", "<=", "==", "!="];
+
+impl SendCondition {
+    /// Returns an error if the condition's field or comparison isn't
+    /// one that is supported.
+    fn validate(&self) -> Result<(), String> {
+        if !CONDITION_FIELDS.contains(&self.field.as_str()) {
+            return Err(format!("Condition field must be one of: {}.", CONDITION_FIELDS.join(", ")));
+        }
+
+        if !CONDITION_OPS.contains(&self.op.as_str()) {
+            return Err(format!("Condition op must be one of: {}.", CONDITION_OPS.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the condition against a node's current data, returning
+    /// why it doesn't hold if it doesn't. A node without the field
+    /// never passes. Values are calibrated with the node's config.
+    fn check(&self, node: Option<&XbeeInfo>, config: Option<&Xbees>) -> Result<(), String> {
+        let node = node.ok_or_else(|| "The node hasn't sent its info yet.".to_string())?;
+
+        let current = match self.field.as_str() {
+            "reading" => node.reading.map(f64::from),
+            _ => calibrated(config, node.value()),
+        };
+
+        let current = current.ok_or_else(|| format!("The node has no {} yet.", self.field))?;
+
+        let holds = match self.op.as_str() {
+            ">" => current > self.value,
+            ">=" => current >= self.value,
+            "<" => current < self.value,
+            "<=" => current <= self.value,
+            "==" => (current - self.value).abs() < ::std::f64::EPSILON,
+            _ => (current - self.value).abs() >= ::std::f64::EPSILON,
+        };
+
+        if holds {
+            Ok(())
+        } else {
+            Err(format!("The node's {} is {}, which is not {} {}.", self.field, current, self.op, self.value))
+        }
+    }
+}
+
+/// Converts a message's content to the bytes sent over the xbee
+/// network, using the given encoding or UTF-8 if there is none.
+/// 
+/// The supported encodings are `utf8`, `ascii`, which rejects any
+/// character outside of ASCII, and `hex`, which takes pairs of hex
+/// digits and ignores whitespace between them.
+fn encode_content(content: &str, encoding: Option<&str>) -> Result<Vec<u8>, String> {
+    match encoding.unwrap_or("utf8").to_lowercase().as_str() {
+        "utf8" | "utf-8" => Ok(content.as_bytes().to_vec()),
+        "ascii" => {
+            if content.is_ascii() {
+                Ok(content.as_bytes().to_vec())
+            } else {
+                Err("Content must only contain ASCII characters.".to_string())
+            }
+        }
+        "hex" => {
+            let digits = content.chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<Vec<char>>();
+
+            if digits.len() % 2 != 0 {
+                return Err("Hex content must have an even number of digits.".to_string());
+            }
+
+            digits.chunks(2)
+                .map(|pair| {
+                    let pair = pair.iter().collect::<String>();
+
+                    //  from_str_radix would also take a leading '+'.
+                    if !pair.chars().all(|c| c.is_digit(16)) {
+                        return Err(format!("'{}' is not a hex byte.", pair));
+                    }
+
+                    Ok(u8::from_str_radix(&pair, 16).unwrap_or_default())
+                })
+                .collect()
+        }
+        other => Err(format!("Unknown encoding '{}'. Use utf8, ascii or hex.", other)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Destinations {
+    node_ids: Vec<u32>,
+}
+
+/// Whether a message could be sent to a destination.
+#[derive(Debug, Serialize)]
+struct DestinationStatus {
+    node_id: u32,
+    exists: bool,
+    permitted: bool,
+}
+
+/// The most readings that can be returned by the
+/// /api/history/batch endpoint across all nodes.
+const MAX_BATCH_READINGS: usize = 10000;
+
+/// How many seconds a day of history spans, for estimating the cost
+/// of history requests.
+const SECS_PER_DAY: i64 = 60 * 60 * 24;
+
+/// Estimates how expensive it is to read the history of `nodes` nodes
+/// between two Unix timestamps, in node-days: the number of nodes
+/// times the number of days the range spans, rounded up to at least
+/// one day.
+fn history_cost(nodes: usize, from: i64, to: i64) -> i64 {
+    let days = ((to - from).max(0) + SECS_PER_DAY - 1) / SECS_PER_DAY;
+    nodes as i64 * days.max(1)
+}
+
+/// Checks that reading the history of the given nodes between two
+/// Unix timestamps costs no more than the `history_cost_budget`
+/// setting, if there is one. A missing `from` counts from the oldest
+/// stored reading and a missing `to` counts up to `now`.
+/// 
+/// Requests over the budget get a 400 Bad Request with the `cost` and
+/// `budget`, and database errors get a generic error.
+fn check_history_cost(nodes: &[i32], from: Option<i64>, to: Option<i64>, settings: &Settings, now: i64, conn: &SqliteConnection) -> Result<(), status::Custom<JsonValue>> {
+    let budget = match settings.history_cost_budget {
+        Some(budget) => budget,
+        None => return Ok(()),
+    };
+
+    let from = match from {
+        Some(from) => from,
+        None => match db::reading_range(conn, None) {
+            Ok(range) => range.oldest.unwrap_or(now),
+            Err(why) => {
+                warn!("Could not get reading range: {}", why);
+                return Err(status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                })));
+            }
+        },
+    };
+
+    let mut distinct = nodes.to_vec();
+    distinct.sort();
+    distinct.dedup();
+
+    let cost = history_cost(distinct.len(), from, to.unwrap_or(now));
+
+    if cost > budget {
+        return Err(status::Custom(Status::BadRequest, json!({
+            "error": format!("This request would read {} node-days of history, more than the limit of {}. \
+                              Ask for a shorter range or fewer nodes.", cost, budget),
+            "cost": cost,
+            "budget": budget,
+            "success": false,
+        })));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct HistoryRequest {
+    node_ids: Vec<u32>,
+    from: Option<i64>,
+    to: Option<i64>,
+    points: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StatsRequest {
+    node_ids: Vec<u32>,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TagAssignment {
+    node_ids: Vec<u32>,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct EnabledUpdate {
+    node_ids: Vec<u32>,
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PrefsUpdate {
+    sort: String,
+    #[serde(default)]
+    descending: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TagRename {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ThresholdsByUnits {
+    units: String,
+    min_alert: Option<f64>,
+    max_alert: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct NodeMerge {
+    source: u32,
+    target: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct NodeClone {
+    new_node_id: u32,
+    new_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Reassignment {
+    from_node: u32,
+    to_node: u32,
+    from_time: i64,
+    to_time: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct UserLookup {
+    ids: Vec<i32>,
+}
+
+/// A reading sent to /api/readings/batch. Readings without a
+/// `timestamp` are taken to be from now.
+#[derive(Debug, Deserialize, Serialize)]
+struct IngestReading {
+    node_id: u32,
+    reading: i64,
+    timestamp: Option<i64>,
+}
+
+/// What happened to a single reading sent to /api/readings/batch.
+#[derive(Serialize)]
+struct IngestResult {
+    index: usize,
+    node_id: u32,
+    accepted: bool,
+    error: Option<String>,
+}
+
+/// The units placeholder nodes are given when they are registered by
+/// /api/readings/batch and there are no default units.
+const AUTO_REGISTER_UNITS: &str = "unknown";
+
+/// The most readings that can be sent to /api/readings/batch at once.
+const MAX_INGEST_READINGS: usize = 1000;
+
+/// The body of a request to /api/xbee/<node>/calibrate. A missing
+/// `offset` is 0 and a missing `scale` is 1.
+#[derive(Debug, Deserialize, Serialize)]
+struct CalibrationRequest {
+    offset: Option<f64>,
+    scale: Option<f64>,
+}
+
+/// The body of a request to /api/xbee/<node>/units. Values are only
+/// converted to the new units if `convert` is true.
+#[derive(Debug, Deserialize, Serialize)]
+struct UnitsRequest {
+    units: String,
+    #[serde(default)]
+    convert: bool,
+}
+
+/// The body of a request to /api/xbee/<node>/mute.
+#[derive(Debug, Deserialize, Serialize)]
+struct MuteRequest {
+    until: i64,
+}
+
+/// The body of a request to /api/webhooks.
+#[derive(Debug, Deserialize, Serialize)]
+struct WebhookRequest {
+    url: String,
+    node_id: Option<i32>,
+}
+
+/// The longest a node can be muted for, in seconds.
+const MAX_MUTE_SECS: i64 = 365 * SECS_PER_DAY;
+
+/// The alerts to acknowledge at once. Alerts are named by their
+/// node's id, so `alert_ids` and `node_ids` are the same ids and may
+/// be mixed.
+#[derive(Debug, Deserialize, Serialize)]
+struct AlertAckBatch {
+    #[serde(default)]
+    alert_ids: Vec<u32>,
+    #[serde(default)]
+    node_ids: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct NewKey {
+    label: String,
+    #[serde(default)]
+    signed: bool,
+}
+
+/// The longest label an API key can have.
+const MAX_KEY_LABEL_LENGTH: usize = 100;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PasswordReset {
+    password: String,
+    logout: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TierUpdate {
+    tier: String,
+}
+
+/// The body of a request to /api/register.
+#[derive(Deserialize)]
+struct Registration {
+    user: String,
+    pass: String,
+}
+
+/// Query parameters for the /api/register/available endpoint.
+#[derive(FromForm)]
+struct AvailabilityOptions {
+    user: String,
+}
+
+/// How many username checks a client can make per second,
+/// once it has used up its burst.
+const AVAILABILITY_RATE: f64 = 0.5;
+
+/// How many username checks a client can make at once.
+const AVAILABILITY_BURST: f64 = 10.0;
+
+/// The rate limiter for the /api/register/available endpoint. This
+/// is separate from the global rate limit, and always on, so the
+/// endpoint can't be used to check many usernames quickly.
+pub struct AvailabilityLimiter(RateLimiter);
+
+impl Default for AvailabilityLimiter {
+    fn default() -> AvailabilityLimiter {
+        AvailabilityLimiter(RateLimiter::fixed(AVAILABILITY_RATE, AVAILABILITY_BURST))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -25,181 +451,8291 @@ struct Login {
     pass: String,
 }
 
-/// Represents a user who is authorized via private cookies.
-/// A user will become authorized once they login with
-/// the proper credentials using the /api/login endpoint.
-pub struct AuthedUser;
+/// The most readings that can be included inline per node
+/// by the /api/list endpoint.
+const MAX_INLINE_HISTORY: u32 = 100;
+
+/// The most decimal places values can be rounded to.
+const MAX_PRECISION: u32 = 10;
+
+/// Optional query parameters for the /api/list endpoint.
+#[derive(FromForm)]
+struct ListOptions {
+    history: Option<u32>,
+    precision: Option<u32>,
+    tz: Option<String>,
+    sort: Option<String>,
+    units: Option<String>,
+    delta: Option<bool>,
+    fields: Option<String>,
+}
+
+/// Query parameters for the /api/list/bbox endpoint. They are all
+/// required, but are checked by the endpoint so it can say which is
+/// missing.
+#[derive(FromForm)]
+struct BoxOptions {
+    min_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lat: Option<f64>,
+    max_lon: Option<f64>,
+}
+
+/// Optional query parameters for the /api/history/range endpoint.
+#[derive(FromForm)]
+struct HistoryRangeOptions {
+    node: Option<u32>,
+}
+
+/// Optional query parameters for bounding a time range.
+#[derive(FromForm)]
+struct RangeOptions {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// Optional query parameters for the /api/history/<node>/shape
+/// endpoint.
+#[derive(FromForm)]
+struct ShapeOptions {
+    from: Option<i64>,
+    to: Option<i64>,
+    points: Option<usize>,
+}
+
+/// Optional query parameters for the /api/admin/purge endpoint.
+#[derive(FromForm)]
+struct PurgeOptions {
+    days: Option<i64>,
+}
+
+/// Optional query parameters for the /api/users endpoint.
+#[derive(FromForm)]
+struct UserListOptions {
+    q: Option<String>,
+    include_deleted: Option<bool>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Query parameters for the /api/xbee/<node>/scale endpoint.
+#[derive(FromForm)]
+struct ScaleOptions {
+    reading: u16,
+}
+
+/// Query parameters for the /api/units/convertible endpoint.
+#[derive(FromForm)]
+struct ConvertibleOptions {
+    from: String,
+    to: String,
+}
+
+/// Optional query parameters for the /api/nodes/incomplete endpoint.
+#[derive(FromForm)]
+struct IncompleteOptions {
+    require: Option<String>,
+}
+
+/// The metadata /api/nodes/incomplete can check nodes for.
+const METADATA_FIELDS: &[&str] = &[
+    "name", "units", "location", "thresholds", "report_interval", "description",
+];
+
+/// The metadata /api/nodes/incomplete checks for if `?require` isn't
+/// given.
+const DEFAULT_REQUIRED_FIELDS: &[&str] = &["name", "units", "location", "thresholds"];
+
+/// Returns whether a node is missing the given metadata.
+fn missing_metadata(config: &Xbees, field: &str) -> bool {
+    match field {
+        "name" => config.name.trim().is_empty(),
+        "units" => config.units.trim().is_empty(),
+        "location" => config.latitude.is_none() || config.longitude.is_none(),
+        "thresholds" => config.min_alert.is_none() && config.max_alert.is_none(),
+        "report_interval" => config.report_interval_secs.is_none(),
+        "description" => config.description.as_ref().map_or(true, |description| description.trim().is_empty()),
+        _ => false,
+    }
+}
+
+/// Query parameters for the /api/compare endpoint.
+#[derive(FromForm)]
+struct CompareOptions {
+    a: u32,
+    b: u32,
+}
+
+/// Optional query parameters for the /api/broadcast/preview endpoint,
+/// which are the parts of a send condition.
+#[derive(FromForm)]
+struct BroadcastPreviewOptions {
+    field: Option<String>,
+    op: Option<String>,
+    value: Option<f64>,
+}
+
+/// Optional query parameters for the /api/admin/failed-logins endpoint.
+#[derive(FromForm)]
+struct FailedLoginOptions {
+    username: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Optional query parameters for the /api/audit endpoint. Pages are
+/// only used for JSON.
+#[derive(FromForm)]
+struct AuditOptions {
+    action: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+impl AuditOptions {
+    /// Returns the action to filter by, if one was given.
+    fn action(&self) -> Option<&str> {
+        self.action.as_ref()
+            .map(|action| action.trim())
+            .and_then(|action| if action.is_empty() { None } else { Some(action) })
+    }
+}
+
+/// Optional query parameters for the /api/xbee/<node>/sends endpoint.
+#[derive(FromForm)]
+struct SendHistoryOptions {
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Optional query parameters for the /api/nodes/silent endpoint.
+#[derive(FromForm)]
+struct SilentOptions {
+    since: Option<i64>,
+}
+
+/// A node that hasn't reported for a while, as it is returned
+/// from the /api/nodes/silent endpoint.
+#[derive(Serialize)]
+struct SilentNode {
+    node_id: u32,
+    name: String,
+    units: String,
+    last_update: i64,
+    silent_secs: i64,
+}
+
+/// Optional query parameters for the /api/xbee/recent endpoint.
+#[derive(FromForm)]
+struct PageOptions {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// How many results are listed per page by default.
+const DEFAULT_PER_PAGE: i64 = 25;
+
+/// The most results that can be listed per page.
+const MAX_PER_PAGE: i64 = 100;
+
+/// Returns the page and page size to list, starting from page 1,
+/// using the defaults for any that were not given.
+fn page_bounds(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    let per_page = per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .max(1)
+        .min(MAX_PER_PAGE);
+
+    (page.unwrap_or(1).max(1), per_page)
+}
+
+/// The keys /api/list can be sorted by by default for a user.
+const PREF_SORT_KEYS: &[&str] = &["name", "last_update"];
+
+/// A node as it is returned from the /api/list endpoint.
+#[derive(Serialize)]
+struct Node {
+    #[serde(flatten)]
+    info: XbeeInfo,
+    value: Option<f64>,
+    scaling_valid: bool,
+    enabled: bool,
+    report_interval_secs: Option<i32>,
+    retention_days: Option<i64>,
+    stale: bool,
+    power_status: &'static str,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    location_name: Option<String>,
+    description: Option<String>,
+    color: Option<String>,
+    icon: Option<String>,
+    muted: bool,
+    muted_until: Option<i64>,
+    auto_registered: bool,
+    created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_update_str: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<Reading>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_reading: Option<Option<i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<Option<f64>>,
+}
+
+/// A node as it is returned from /api/list when the `json_field_case`
+/// setting is camelCase, borrowed from a `Node`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CamelNode<'a> {
+    #[serde(flatten)]
+    info: CamelXbeeInfo<'a>,
+    value: Option<f64>,
+    scaling_valid: bool,
+    enabled: bool,
+    report_interval_secs: Option<i32>,
+    retention_days: Option<i64>,
+    stale: bool,
+    power_status: &'static str,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    location_name: &'a Option<String>,
+    description: &'a Option<String>,
+    color: &'a Option<String>,
+    icon: &'a Option<String>,
+    muted: bool,
+    muted_until: Option<i64>,
+    auto_registered: bool,
+    created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_update_str: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<CamelReading>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_reading: Option<Option<i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<Option<f64>>,
+}
+
+/// The info of a node in a `CamelNode`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CamelXbeeInfo<'a> {
+    uuid: u32,
+    name: &'a str,
+    units: &'a str,
+    max_voltage: f32,
+    min_voltage: f32,
+    max_value: f32,
+    min_value: f32,
+    last_update: i64,
+    reading: Option<u16>,
+}
+
+/// A reading in the history of a `CamelNode`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CamelReading {
+    id: i32,
+    node_id: i32,
+    reading: i32,
+    timestamp: i64,
+}
+
+impl CamelCase for Node {
+    fn serialize_camel<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::Serialize;
+
+        let info = &self.info;
+        let camel = CamelNode {
+            info: CamelXbeeInfo {
+                uuid: info.uuid,
+                name: &info.name,
+                units: &info.units,
+                max_voltage: info.max_voltage,
+                min_voltage: info.min_voltage,
+                max_value: info.max_value,
+                min_value: info.min_value,
+                last_update: info.last_update,
+                reading: info.reading,
+            },
+            value: self.value,
+            scaling_valid: self.scaling_valid,
+            enabled: self.enabled,
+            report_interval_secs: self.report_interval_secs,
+            retention_days: self.retention_days,
+            stale: self.stale,
+            power_status: self.power_status,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            location_name: &self.location_name,
+            description: &self.description,
+            color: &self.color,
+            icon: &self.icon,
+            muted: self.muted,
+            muted_until: self.muted_until,
+            auto_registered: self.auto_registered,
+            created_at: self.created_at,
+            last_update_str: self.last_update_str.as_ref(),
+            history: self.history.as_ref().map(|history| {
+                history.iter()
+                    .map(|row| CamelReading {
+                        id: row.id,
+                        node_id: row.node_id,
+                        reading: row.reading,
+                        timestamp: row.timestamp,
+                    })
+                    .collect()
+            }),
+            previous_reading: self.previous_reading,
+            delta: self.delta,
+        };
+
+        camel.serialize(serializer)
+    }
+}
+
+/// The fields of a node in /api/list that can be picked with
+/// `?fields=<fields>`.
+const NODE_FIELDS: &[&str] = &[
+    "uuid", "name", "units", "max_voltage", "min_voltage", "max_value",
+    "min_value", "last_update", "reading", "value", "scaling_valid",
+    "enabled", "report_interval_secs", "retention_days", "stale",
+    "power_status", "latitude", "longitude", "location_name", "description",
+    "color", "icon", "muted", "muted_until", "auto_registered",
+    "created_at", "last_update_str", "history", "previous_reading", "delta",
+];
+
+/// Parses a comma separated list of node fields, always including
+/// `uuid`. Returns an error naming the first field that isn't known.
+fn parse_fields(fields: &str) -> Result<Vec<String>, String> {
+    let mut parsed = vec!["uuid".to_string()];
+
+    for field in fields.split(',').map(|field| field.trim()).filter(|field| !field.is_empty()) {
+        if !NODE_FIELDS.contains(&field) {
+            return Err(format!("Unknown field '{}'.", field));
+        }
+
+        parsed.push(field.to_string());
+    }
+
+    Ok(parsed)
+}
+
+/// Formats a Unix timestamp as a readable date and time in the
+/// given IANA timezone, such as "America/Chicago". Invalid timezones
+/// fall back to UTC, and timestamps too far out to be a date are
+/// returned as they are.
+fn format_timestamp(timestamp: i64, timezone: &str) -> String {
+    let timezone = timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+
+    match Utc.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.with_timezone(&timezone)
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Rounds the given value to the given number of decimal places.
+fn round_to(value: f64, places: u32) -> f64 {
+    let factor = 10f64.powi(places as i32);
+    (value * factor).round() / factor
+}
+
+/// Returns whether a node has gone longer without reporting than it
+/// should have, as of `now`. Nodes without a report interval use
+/// `stale_after_secs` instead.
+fn is_stale(node: &XbeeInfo, config: Option<&Xbees>, stale_after_secs: i64, now: i64) -> bool {
+    now >= stale_since(node, config, stale_after_secs)
+}
+
+/// Returns the Unix timestamp at which a node goes stale if it doesn't
+/// report again.
+fn stale_since(node: &XbeeInfo, config: Option<&Xbees>, stale_after_secs: i64) -> i64 {
+    let stale_after = config
+        .and_then(|config| config.report_interval_secs)
+        .map_or(stale_after_secs, i64::from);
+
+    node.last_update + stale_after + 1
+}
+
+/// Returns when anything /api/xbee/<node> shows about a node last
+/// changed, as of `now`. That is the latest of its last update, its
+/// newest stored reading, the last change to its configuration or
+/// tags, the end of a mute that has run out, and when it went stale if
+/// it has.
+fn node_last_modified(node: &XbeeInfo, config: Option<&Xbees>, newest_reading: Option<i64>, stale_after_secs: i64, now: i64) -> i64 {
+    let mut modified = node.last_update.max(newest_reading.unwrap_or(0));
+
+    if let Some(config) = config {
+        modified = modified.max(config.updated_at);
+
+        if let Some(until) = config.muted_until {
+            if until <= now {
+                modified = modified.max(until);
+            }
+        }
+    }
+
+    let stale = stale_since(node, config, stale_after_secs);
+    if stale <= now {
+        modified = modified.max(stale);
+    }
+
+    modified
+}
+
+/// Returns how healthy a node's power is, from where its latest
+/// reading falls in its voltage range. Readings below the `critical`
+/// fraction of the range are "critical", below the `low` fraction are
+/// "low", and any others are "ok".
+/// 
+/// Nodes without a reading or a valid voltage range are "unknown".
+fn power_status(node: &XbeeInfo, low: f64, critical: f64) -> &'static str {
+    let reading = match node.reading {
+        Some(reading) if node.scaling_valid() => reading,
+        _ => return "unknown",
+    };
+
+    let voltage = f64::from(reading) / 100.0;
+    let fraction = (voltage - f64::from(node.min_voltage)) / f64::from(node.max_voltage - node.min_voltage);
+
+    if fraction < critical {
+        "critical"
+    } else if fraction < low {
+        "low"
+    } else {
+        "ok"
+    }
+}
+
+/// The alert thresholds of a node.
+#[derive(Serialize)]
+struct Thresholds {
+    min_alert: Option<f64>,
+    max_alert: Option<f64>,
+}
+
+/// The alert state of a node as it is returned from the
+/// /api/alerts/summary endpoint.
+#[derive(Serialize)]
+struct AlertSummary {
+    node_id: u32,
+    name: String,
+    alerting: bool,
+    acknowledged: bool,
+    stale: bool,
+    latest_reading: Option<f64>,
+    thresholds: Thresholds,
+}
+
+/// Optional query parameters for the /api/alerts/summary endpoint.
+#[derive(FromForm)]
+struct AlertSummaryOptions {
+    acknowledged: Option<bool>,
+}
+
+/// A change in whether a node is alerting, as it is returned from
+/// the /api/xbee/<node>/alerts endpoint.
+#[derive(Serialize)]
+struct AlertTransition {
+    timestamp: i64,
+    alerting: bool,
+    reading: f64,
+}
+
+/// The most readings looked at when finding a node's alert history.
+const MAX_ALERT_READINGS: i64 = 10000;
+
+/// Optional query parameters for the /api/xbee/<node>/alerts endpoint.
+#[derive(FromForm)]
+struct AlertHistoryOptions {
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Optional query parameters for the /api/activity endpoint.
+#[derive(FromForm)]
+struct ActivityOptions {
+    from: Option<i64>,
+    to: Option<i64>,
+    types: Option<String>,
+    node: Option<u32>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// The kinds of events in the /api/activity feed.
+const ACTIVITY_TYPES: &[&str] = &["alert", "ack", "send", "config", "login", "failed_login"];
+
+/// The audit log actions that show up as `config` events in the
+/// /api/activity feed.
+const CONFIG_ACTIONS: &[&str] = &[
+    "node_add", "node_update", "node_delete", "node_restore", "node_merge",
+    "node_calibrate", "node_units", "node_enable", "node_disable",
+    "mute", "unmute", "auto_register",
+];
+
+/// An event in the /api/activity feed. `type` says which kind it is.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Activity {
+    Alert {
+        timestamp: i64,
+        node_id: u32,
+        alerting: bool,
+        reading: f64,
+    },
+    Ack {
+        timestamp: i64,
+        node_id: u32,
+        acknowledged_by: i32,
+    },
+    Send {
+        timestamp: i64,
+        node_id: u32,
+        content: String,
+        status: String,
+        sender_id: i32,
+    },
+    Config {
+        timestamp: i64,
+        node_id: Option<u32>,
+        action: String,
+        actor_id: Option<i32>,
+        detail: Option<String>,
+    },
+    Login {
+        timestamp: i64,
+        user_id: Option<i32>,
+        ip: Option<String>,
+    },
+    FailedLogin {
+        timestamp: i64,
+        username: String,
+        ip: Option<String>,
+    },
+}
+
+impl Activity {
+    /// Returns when the event happened.
+    fn timestamp(&self) -> i64 {
+        match *self {
+            Activity::Alert { timestamp, .. }
+            | Activity::Ack { timestamp, .. }
+            | Activity::Send { timestamp, .. }
+            | Activity::Config { timestamp, .. }
+            | Activity::Login { timestamp, .. }
+            | Activity::FailedLogin { timestamp, .. } => timestamp,
+        }
+    }
+}
+
+/// How many of a node's latest readings are listed in its report.
+const REPORT_READINGS: usize = 25;
+
+/// Optional query parameters for the /api/xbee/<node>/report endpoint.
+#[derive(FromForm)]
+struct ReportOptions {
+    tz: Option<String>,
+}
+
+/// Returns whether a value is outside the given alert thresholds.
+fn is_alerting(value: f64, min_alert: Option<f64>, max_alert: Option<f64>) -> bool {
+    min_alert.map_or(false, |min| value < min) || max_alert.map_or(false, |max| value > max)
+}
+
+/// How many of each node's latest readings are walked to find whether
+/// it is alerting now, since that depends on whether it was before.
+const ALERT_STATE_READINGS: u32 = 100;
+
+/// Returns whether a node is alerting after a reading with the given
+/// value. A node that wasn't alerting starts as soon as the value is
+/// outside its thresholds, but one that was only stops once the value
+/// is back within them by at least `margin`, so values hovering around
+/// a threshold don't flap in and out of alerting.
+fn alert_state(was_alerting: bool, value: f64, min_alert: Option<f64>, max_alert: Option<f64>, margin: f64) -> bool {
+    if is_alerting(value, min_alert, max_alert) {
+        return true;
+    }
+
+    was_alerting && is_alerting(value, min_alert.map(|min| min + margin), max_alert.map(|max| max - margin))
+}
+
+/// Returns the hysteresis margin of a node, which is its own or the
+/// `alert_hysteresis` setting if it has none.
+fn alert_margin(config: Option<&Xbees>, settings: &Settings) -> f64 {
+    config
+        .and_then(|config| config.alert_hysteresis)
+        .unwrap_or(settings.alert_hysteresis)
+}
+
+/// Returns how many seconds apart a node's ingested readings have to
+/// be, which is its own interval or the `min_reading_interval_secs`
+/// setting if it has none. `None` means any interval is allowed, which
+/// is also what a node's own interval of 0 means.
+fn min_reading_interval(config: Option<&Xbees>, settings: &Settings) -> Option<i64> {
+    match config.and_then(|config| config.min_reading_interval_secs) {
+        Some(secs) if secs > 0 => Some(i64::from(secs)),
+        Some(_) => None,
+        None => settings.min_reading_interval_secs,
+    }
+}
+
+/// Adds `time` to a node's reading times, which are sorted, unless it
+/// is less than `interval` seconds from one of them. Returns whether
+/// it was added.
+fn add_if_spaced(times: &mut Vec<i64>, time: i64, interval: i64) -> bool {
+    let index = match times.binary_search(&time) {
+        Ok(_) => return false,
+        Err(index) => index,
+    };
+
+    let after = times.get(index).map_or(false, |&next| next - time < interval);
+    let before = index > 0 && time - times[index - 1] < interval;
+
+    if after || before {
+        return false;
+    }
+
+    times.insert(index, time);
+    true
+}
+
+/// Returns a node's calibration as the scale and offset applied to its
+/// values, which leave them as they are by default.
+fn calibration(config: Option<&Xbees>) -> (f64, f64) {
+    (
+        config.and_then(|config| config.calibration_scale).unwrap_or(1.0),
+        config.and_then(|config| config.calibration_offset).unwrap_or(0.0),
+    )
+}
+
+/// Returns a value of a node with its calibration applied, which is
+/// the value it shows in /api/list and its thresholds are set against.
+fn calibrated(config: Option<&Xbees>, value: Option<f64>) -> Option<f64> {
+    let (scale, offset) = calibration(config);
+    value.map(|value| value * scale + offset)
+}
+
+/// Returns a node's calibration and alert thresholds converted with
+/// the given conversion. Alerts are checked against calibrated values,
+/// so a node alerts the same way in the new units. Converting the
+/// calibrated value gives the new calibration:
+/// (value * scale + offset) * factor + conversion offset.
+/// Hysteresis is a difference between values, so it is only scaled.
+fn converted_values(config: &Xbees, conversion: convert::Conversion) -> ConvertedValues {
+    let (scale, offset) = calibration(Some(config));
+    let convert_value = |value: f64| value * conversion.factor + conversion.offset;
+
+    ConvertedValues {
+        calibration_scale: scale * conversion.factor,
+        calibration_offset: convert_value(offset),
+        min_alert: config.min_alert.map(&convert_value),
+        max_alert: config.max_alert.map(&convert_value),
+        alert_hysteresis: config.alert_hysteresis.map(|hysteresis| hysteresis * conversion.factor.abs()),
+    }
+}
+
+/// Returns when a node's alerts are muted until, or `None` if they
+/// aren't muted at the given time.
+fn muted_until(config: Option<&Xbees>, now: i64) -> Option<i64> {
+    config
+        .and_then(|config| config.muted_until)
+        .and_then(|until| if until > now { Some(until) } else { None })
+}
+
+/// Returns when a node's current alert started, or `None` if it isn't
+/// alerting now. This is found by walking its latest readings from
+/// `ALERT_STATE_READINGS`, given newest first, through `alert_state`.
+/// A node without readings in the database is checked against its
+/// thresholds by its current value alone, and counts as alerting
+/// since its last update.
+/// 
+/// A node whose alerts are muted at `now` is never alerting.
+fn alerting_since(node: &XbeeInfo, readings: &[Reading], config: Option<&Xbees>, settings: &Settings, now: i64) -> Option<i64> {
+    if muted_until(config, now).is_some() {
+        return None;
+    }
+
+    let min_alert = config.and_then(|config| config.min_alert);
+    let max_alert = config.and_then(|config| config.max_alert);
+    let margin = alert_margin(config, settings);
+
+    let values = readings.iter()
+        .rev()
+        .filter_map(|row| calibrated(config, node.scale(row.reading as u16)).map(|value| (row.timestamp, value)))
+        .collect::<Vec<(i64, f64)>>();
+
+    if values.is_empty() {
+        return if calibrated(config, node.value()).map_or(false, |value| is_alerting(value, min_alert, max_alert)) {
+            Some(node.last_update)
+        } else {
+            None
+        };
+    }
+
+    values.into_iter()
+        .fold(None, |since, (timestamp, value)| {
+            if alert_state(since.is_some(), value, min_alert, max_alert, margin) {
+                since.or(Some(timestamp))
+            } else {
+                None
+            }
+        })
+}
+
+/// Returns whether a node is alerting now. See `alerting_since`.
+fn currently_alerting(node: &XbeeInfo, readings: &[Reading], config: Option<&Xbees>, settings: &Settings, now: i64) -> bool {
+    alerting_since(node, readings, config, settings, now).is_some()
+}
+
+/// Returns whether a node is alerting now, or `None` if its alerts are
+/// muted, since it has no alert state until the mute ends.
+fn unmuted_alerting(node: &XbeeInfo, readings: &[Reading], config: Option<&Xbees>, settings: &Settings, now: i64) -> Option<bool> {
+    if muted_until(config, now).is_some() {
+        None
+    } else {
+        Some(currently_alerting(node, readings, config, settings, now))
+    }
+}
+
+/// Returns every known node with its calibrated value and whether it
+/// is alerting now, decided the same way as in /api/alerts/summary.
+/// See `unmuted_alerting`. This is what webhooks are sent from.
+pub fn alert_states(conn: &SqliteConnection, info: &InfoSet, settings: &Settings, now: i64) -> QueryResult<Vec<(XbeeInfo, Option<f64>, Option<bool>)>> {
+    let configs = db::xbee_config_map(conn)?;
+    let recent = db::recent_readings(conn, ALERT_STATE_READINGS)?;
+
+    Ok(info.nodes()
+        .into_iter()
+        .map(|node| {
+            let config = configs.get(&node.uuid);
+            let value = calibrated(config, node.value());
+            let readings = recent.get(&node.uuid).map_or(&[][..], |readings| &readings[..]);
+            let alerting = unmuted_alerting(&node, readings, config, settings, now);
+
+            (node, value, alerting)
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Serialize)]
+struct Backup {
+    xbees: Vec<XbeeBackup>,
+}
+
+/// Optional query parameters for the /api/login endpoint.
+#[derive(FromForm)]
+struct LoginOptions {
+    token: Option<bool>,
+}
+
+/// Whether a client logging in wants a JWT instead of a cookie.
+/// This is the case if it passes `?token=true` or accepts the
+/// `application/jwt` type.
+struct TokenRequest(bool);
+
+impl<'a, 'r> FromRequest<'a, 'r> for TokenRequest {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<TokenRequest, ()> {
+        let options = request.guard::<Query<LoginOptions>>()?.0;
+        let accepts_jwt = request.headers()
+            .get("Accept")
+            .any(|accept| accept.contains("application/jwt"));
+
+        Outcome::Success(TokenRequest(options.token.unwrap_or(false) || accepts_jwt))
+    }
+}
+
+/// Represents a user who is authorized via a JWT sent in the
+/// `Authorization: Bearer <token>` header. Tokens are issued
+/// by the /api/login endpoint.
+pub struct JwtUser {
+    /// The database id of the user the token was issued to.
+    pub id: i32,
+    /// The role of the user when the token was issued.
+    pub role: String,
+    /// The database id of the admin impersonating the user, if any.
+    pub impersonated_by: Option<i32>,
+    /// When the token was issued, as a Unix timestamp.
+    pub issued_at: i64,
+}
+
+/// Controls how a token user's requests are handled. If the token
+/// has a valid signature and has not expired, it will succeed.
+/// Otherwise the request will be forwarded to another handler.
+impl<'a, 'r> FromRequest<'a, 'r> for JwtUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<JwtUser, ()> {
+        let settings = request.guard::<State<Settings>>()?;
+        let clock = request.guard::<State<SharedClock>>()?;
+        let claims = request.headers()
+            .get_one("Authorization")
+            .and_then(|header| {
+                if header.starts_with("Bearer ") {
+                    Some(&header["Bearer ".len()..])
+                } else {
+                    None
+                }
+            })
+            .and_then(|jwt| token::verify(jwt, &settings, clock.now()));
+
+        match claims {
+            Some(claims) => Outcome::Success(JwtUser {
+                id: claims.sub,
+                role: claims.role,
+                impersonated_by: claims.impersonated_by,
+                issued_at: claims.iat,
+            }),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+/// Represents a user who is authorized via private cookies.
+/// A user will become authorized once they login with
+/// the proper credentials using the /api/login endpoint.
+/// 
+/// Users with a valid JWT or API key are also authorized, so
+/// clients that can't hold cookies can use every endpoint.
+/// 
+/// Sessions started and tokens issued before the user's sessions
+/// were revoked, such as by an admin resetting their password, are
+/// not authorized.
+pub struct AuthedUser {
+    /// The database id of the logged in user.
+    pub id: i32,
+    /// The database id of the admin impersonating the user, if
+    /// this session was started through impersonation. Sensitive
+    /// actions should be refused when this is set.
+    pub impersonated_by: Option<i32>,
+}
+
+/// Controls how an authorized user's requests are handled.
+/// If a user is authenticated, it will succeed. Otherwise
+/// the request will be forwarded to another handler.
+impl<'a, 'r> FromRequest<'a, 'r> for AuthedUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AuthedUser, ()> {
+        match cached_caller(request) {
+            Some(Some(user)) => Outcome::Success(user),
+            Some(None) => Outcome::Forward(()),
+            None => {
+                let outcome = authenticate(request);
+                saw_caller(request, &outcome);
+                outcome
+            }
+        }
+    }
+}
+
+/// The header the user making an API request is kept in once they
+/// have been found, so their session, token or key is only checked
+/// once per request. It holds their id, followed by `:` and the id of
+/// the admin impersonating them if there is one, or `-` if nobody is
+/// logged in. It is replaced on every request, so clients can't set
+/// it.
+const CALLER_HEADER: &str = "X-Authed-User";
+
+/// Finds the user making an API request before it is routed, and
+/// keeps them in `CALLER_HEADER` for `AuthedUser` and the rate limiter
+/// to use. The user making any other request is only found if a route
+/// asks for them.
+pub fn resolve_caller(request: &mut Request, _: &Data) {
+    let caller = if request.uri().path().starts_with("/api/") {
+        let outcome = authenticate(request);
+        saw_caller(request, &outcome);
+
+        match outcome {
+            Outcome::Success(user) => match user.impersonated_by {
+                Some(admin) => format!("{}:{}", user.id, admin),
+                None => user.id.to_string(),
+            },
+            Outcome::Forward(_) => "-".to_string(),
+            //  Leave failures to be found again by the route, so it
+            //  fails the same way.
+            Outcome::Failure(_) => String::new(),
+        }
+    } else {
+        String::new()
+    };
+
+    request.replace_header(Header::new(CALLER_HEADER, caller));
+}
+
+/// Returns the user `resolve_caller` found for a request, `Some(None)`
+/// if nobody is logged in, or `None` if they haven't been found yet.
+fn cached_caller(request: &Request) -> Option<Option<AuthedUser>> {
+    let caller = request.headers().get_one(CALLER_HEADER)?;
+
+    if caller == "-" {
+        return Some(None);
+    }
+
+    let mut ids = caller.splitn(2, ':');
+    let user_id = ids.next()?.parse::<i32>().ok()?;
+    let impersonated_by = match ids.next() {
+        Some(admin) => Some(admin.parse::<i32>().ok()?),
+        None => None,
+    };
+
+    Some(Some(AuthedUser {
+        id: user_id,
+        impersonated_by: impersonated_by,
+    }))
+}
+
+/// Counts the user making a request as active for /metrics, if they
+/// are logged in.
+fn saw_caller(request: &Request, outcome: &request::Outcome<AuthedUser, ()>) {
+    if let Outcome::Success(ref user) = *outcome {
+        if let Outcome::Success(metrics) = request.guard::<State<Metrics>>() {
+            metrics.saw_user(user.id);
+        }
+    }
+}
+
+/// Finds the authorized user from their session cookie, a JWT or an
+/// API key, in that order.
+fn authenticate(request: &Request) -> request::Outcome<AuthedUser, ()> {
+    let settings = request.guard::<State<Settings>>()?;
+    let clock = request.guard::<State<SharedClock>>()?;
+    let sessions = request.guard::<State<ActiveSessions>>()?;
+
+    //  The cookies have to be let go before the JWT guard runs.
+    let current = {
+        let mut cookies = request.cookies();
+        session::current(&mut cookies, &sessions, &settings, clock.now())
+    };
+
+    if let Some((user_id, impersonated_by, started)) = current {
+        if !sessions_revoked(request, user_id, started) {
+            return Outcome::Success(AuthedUser { id: user_id, impersonated_by: impersonated_by });
+        }
+
+        session::end(&mut request.cookies(), &sessions);
+    }
+
+    if let Outcome::Success(user) = request.guard::<JwtUser>() {
+        if sessions_revoked(request, user.id, user.issued_at) {
+            return Outcome::Forward(());
+        }
+
+        return Outcome::Success(AuthedUser {
+            id: user.id,
+            impersonated_by: user.impersonated_by,
+        });
+    }
+
+    request.guard::<ApiKeyUser>().map(|user| AuthedUser {
+        id: user.id,
+        impersonated_by: None,
+    })
+}
+
+/// How often an API key's `last_used_at` is updated, in seconds. Keys
+/// are checked on every request, so it isn't written every time.
+const KEY_USE_PRECISION: i64 = 60;
+
+/// Represents a user who is authorized with an API key given in the
+/// `X-Api-Key` header. Users make keys for machine clients with
+/// /api/me/keys. Requests made with a signed key also have to carry a
+/// valid signature, as `api_key::SignedRequest` checks.
+pub struct ApiKeyUser {
+    /// The database id of the user the key belongs to.
+    pub id: i32,
+}
+
+/// Controls how an API key user's requests are handled. If the key
+/// exists, its secret matches and the request is signed if the key
+/// has to be, it will succeed and the key is marked as used.
+/// Otherwise the request will be forwarded to another handler.
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKeyUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiKeyUser, ()> {
+        let (key_id, secret) = match request.headers().get_one(api_key::HEADER).and_then(api_key::parse) {
+            Some(key) => key,
+            None => return Outcome::Forward(()),
+        };
+
+        let conn = request.guard::<DbConn>()?;
+        let clock = request.guard::<State<SharedClock>>()?;
+
+        let key = match db::find_api_key(&conn, key_id) {
+            Ok(Some(key)) => key,
+            Ok(None) => return Outcome::Forward(()),
+            Err(why) => {
+                warn!("Could not find API key: {}", why);
+                return Outcome::Forward(());
+            }
+        };
+
+        if !api_key::verify(secret, &key.key_hash) {
+            return Outcome::Forward(());
+        }
+
+        match db::user_deleted(&conn, key.user_id) {
+            Ok(false) => {}
+            Ok(true) => return Outcome::Forward(()),
+            Err(why) => {
+                warn!("Could not check whether API key user was deleted: {}", why);
+                return Outcome::Forward(());
+            }
+        }
+
+        let now = clock.now();
+
+        if let Some(ref signing_secret) = key.signing_secret {
+            if !api_key::SignedRequest::from_request(request).verify(signing_secret, now) {
+                info!("Rejected a request with API key {} that wasn't signed properly.", key.id);
+                return Outcome::Forward(());
+            }
+        }
+        if key.last_used_at.map_or(true, |used| now - used >= KEY_USE_PRECISION) {
+            if let Err(why) = db::touch_api_key(&conn, key.id, now) {
+                warn!("Could not record API key use: {}", why);
+            }
+        }
+
+        Outcome::Success(ApiKeyUser { id: key.user_id })
+    }
+}
+
+/// Returns whether a session the user started, or a token they were
+/// issued, at the given time has since been revoked. If that can't be
+/// checked, it counts as revoked.
+fn sessions_revoked(request: &Request, user: i32, started: i64) -> bool {
+    let conn = match request.guard::<DbConn>() {
+        Outcome::Success(conn) => conn,
+        _ => return true,
+    };
+
+    match db::sessions_revoked_at(&conn, user) {
+        Ok(revoked_at) => revoked_at.map_or(false, |revoked_at| started <= revoked_at),
+        Err(why) => {
+            warn!("Could not check whether sessions were revoked: {}", why);
+            true
+        }
+    }
+}
+
+/// Represents an authorized user who has the admin role.
+pub struct AdminUser {
+    /// The database id of the logged in admin.
+    pub id: i32,
+}
+
+/// Controls how an admin's requests are handled. If the user
+/// is authenticated and is an admin, it will succeed. Otherwise
+/// the request will be forwarded to another handler.
+/// 
+/// Impersonated sessions are never treated as admins, even when
+/// the impersonated user is one.
+impl<'a, 'r> FromRequest<'a, 'r> for AdminUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AdminUser, ()> {
+        let user = request.guard::<AuthedUser>()?;
+
+        if user.impersonated_by.is_some() {
+            return Outcome::Forward(());
+        }
+
+        let conn = request.guard::<DbConn>()?;
+
+        let user_role = users
+            .find(user.id)
+            .select(role)
+            .get_result::<String>(&*conn);
+
+        match user_role {
+            Ok(ref user_role) if user_role == ADMIN_ROLE => Outcome::Success(AdminUser { id: user.id }),
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+/// The response given by endpoints that have been turned off
+/// through a feature flag.
+fn feature_disabled() -> JsonValue {
+    json!({
+        "error": "This feature is disabled.",
+        "success": false,
+    })
+}
+
+/// Returns the username of the user making a request, if the
+/// `echo_acting_user` setting is on.
+fn acting_user(conn: &SqliteConnection, user: i32, settings: &Settings) -> Option<String> {
+    if !settings.echo_acting_user {
+        return None;
+    }
+
+    users.find(user)
+        .select(username)
+        .get_result::<String>(conn)
+        .map_err(|why| warn!("Could not find acting user: {}", why))
+        .ok()
+}
+
+/// Adds the user who made a change to its response as `acted_by`,
+/// if they are known.
+fn with_acted_by(mut response: JsonValue, actor: Option<String>) -> JsonValue {
+    if let (Some(actor), Some(fields)) = (actor, response.as_object_mut()) {
+        fields.insert("acted_by".to_string(), actor.into());
+    }
+
+    response
+}
+
+/// Records a change a user made in the audit log. Failing to record
+/// it is logged but doesn't undo the change, since it has already been
+/// saved.
+fn audit(conn: &SqliteConnection, actor: i32, action: &str, node: Option<i32>, detail: Option<String>, now: i64) {
+    if let Err(why) = db::record_audit(conn, Some(actor), action, node, None, detail, now) {
+        warn!("Could not record {} by user {} in the audit log: {}", action, actor, why);
+    }
+}
+
+/// What happened to a broadcast for a single node.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SendStatus {
+    Sent,
+    SkippedDisabled,
+    SkippedCondition,
+}
+
+impl SendStatus {
+    /// Returns the name of the status, as it is stored and returned.
+    fn as_str(self) -> &'static str {
+        match self {
+            SendStatus::Sent => "sent",
+            SendStatus::SkippedDisabled => "skipped_disabled",
+            SendStatus::SkippedCondition => "skipped_condition",
+        }
+    }
+}
+
+/// The outcome of a broadcast for a single node, as it is returned
+/// from the /api/send endpoint.
+#[derive(Serialize)]
+struct BroadcastResult {
+    node_id: u32,
+    status: SendStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Returns what a broadcast does for a single node, and why the node
+/// was skipped if its condition didn't hold. Disabled nodes are
+/// skipped before their condition is checked. Both /api/send and
+/// /api/broadcast/preview use this, so a preview always matches what
+/// a broadcast would do.
+fn broadcast_status(node: &XbeeInfo, configs: &HashMap<u32, Xbees>, condition: Option<&SendCondition>) -> (SendStatus, Option<String>) {
+    if !configs.get(&node.uuid).map_or(true, |config| config.enabled) {
+        return (SendStatus::SkippedDisabled, None);
+    }
+
+    match condition.map_or(Ok(()), |condition| condition.check(Some(node), configs.get(&node.uuid))) {
+        Ok(()) => (SendStatus::Sent, None),
+        Err(reason) => (SendStatus::SkippedCondition, Some(reason)),
+    }
+}
+
+/// Sends the data given to the xbee network.
+/// 
+/// This endpoint takes JSON data that contains both the
+/// destination node's id and the content of the message.
+/// 
+/// Disabled nodes can't be sent messages. Sending to a disabled node
+/// returns an error. Broadcasting to `4294967295` sends to every known
+/// node, skipping disabled ones, and returns each node's `status`:
+/// "sent", "skipped_disabled" or "skipped_condition". A node that was
+/// skipped by its condition has an `error` saying why. Skipping a node
+/// doesn't count as failing.
+/// 
+/// The optional `condition` only sends the message if the node's
+/// current data passes it. It has a `field`, which is `reading` or
+/// `value`, a comparison `op`, which is one of `>`, `>=`, `<`, `<=`,
+/// `==` or `!=`, and the `value` to compare to. If the condition
+/// doesn't hold, nothing is sent and the response has `skipped` set
+/// to true with the `reason`. A node without a reading never passes.
+/// When broadcasting, each node is checked on its own.
+/// 
+/// Messages are only logged for now, since the radio belongs to the
+/// thread reading from it, so sending to a node can't fail and
+/// `all_succeeded` is always true.
+/// 
+/// If the `echo_acting_user` setting is on, the response includes the
+/// username of the sender in `acted_by`.
+/// 
+/// The optional `encoding` field says how `content` is turned into
+/// bytes: `utf8` (the default), `ascii` or `hex`. Content that can't
+/// be represented in the encoding returns an error, and so does content
+/// that is empty or only whitespace, since nothing would be sent.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// It can be turned off with the `send` feature flag.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "content": "0A 1F 42",
+///     "dest": 1234,
+///     "encoding": "hex",
+///     "condition": { "field": "reading", "op": ">", "value": 400 }
+/// }
+/// ```
+#[post("/api/send", format = "application/json", data = "<message>")]
+fn send(message: Json<Message>, info: InfoSet, conn: DbConn, features: State<Features>, settings: State<Settings>, clock: State<SharedClock>, user: AuthedUser) -> JsonValue {
+    if !features.enabled("send") {
+        return feature_disabled();
+    }
+
+    if let Err(why) = message.validate() {
+        return json!({
+            "error": why,
+            "success": false,
+        });
+    }
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let passes = |node: Option<&XbeeInfo>| match message.condition {
+        Some(ref condition) => condition.check(node, configs.get(&message.dest)),
+        None => Ok(()),
+    };
+
+    let enabled = |node: u32| configs.get(&node).map_or(true, |config| config.enabled);
+
+    let bytes = match encode_content(&message.content, message.encoding.as_ref().map(|encoding| encoding.as_str())) {
+        Ok(bytes) => bytes,
+        Err(why) => {
+            return json!({
+                "error": why,
+                "success": false,
+            });
+        }
+    };
+
+    let now = clock.now();
+    let record = |node: u32, status: &str| NewSentMessage {
+        node_id: node as i32,
+        content: message.content.clone(),
+        status: status.to_string(),
+        sender_id: user.id,
+        timestamp: now,
+    };
+
+    if message.dest == BROADCAST {
+        info!("Broadcast: {:?} as {:?}", message, bytes);
+
+        let results = info.nodes().iter()
+            .map(|node| {
+                let (status, error) = broadcast_status(node, &configs, message.condition.as_ref());
+
+                BroadcastResult {
+                    node_id: node.uuid,
+                    status: status,
+                    error: error,
+                }
+            })
+            .collect::<Vec<BroadcastResult>>();
+
+        let records = results.iter()
+            .map(|result| record(result.node_id, result.status.as_str()))
+            .collect::<Vec<NewSentMessage>>();
+        record_sent_messages(&conn, &records);
+
+        return with_acted_by(json!({
+            "content": message.content.clone(),
+            "nodes": results,
+            "all_succeeded": true,
+            "success": true,
+        }), acting_user(&conn, user.id, &settings));
+    }
+
+    if !enabled(message.dest) {
+        return json!({
+            "error": format!("Node {} is disabled.", message.dest),
+            "success": false,
+        });
+    }
+
+    if let Err(reason) = passes(info.get(message.dest).as_ref()) {
+        record_sent_messages(&conn, &[record(message.dest, SendStatus::SkippedCondition.as_str())]);
+        return with_acted_by(json!({
+            "content": message.content.clone(),
+            "skipped": true,
+            "reason": reason,
+            "success": true,
+        }), acting_user(&conn, user.id, &settings));
+    }
+
+    info!("JSON: {:?} as {:?}", message, bytes);
+    record_sent_messages(&conn, &[record(message.dest, SendStatus::Sent.as_str())]);
+    with_acted_by(json!({
+        "content": message.content.clone(),
+        "success": true,
+    }), acting_user(&conn, user.id, &settings))
+}
+
+/// Returns which nodes a broadcast would reach right now, without
+/// sending anything, so a frontend can show how many nodes a command
+/// will go to before it is sent.
+/// 
+/// Nodes are filtered exactly the way a broadcast to /api/send filters
+/// them. A send condition can be given with `?field=<field>`,
+/// `?op=<op>` and `?value=<n>`, which take the same values as the
+/// `condition` of a send. `reached` lists the nodes that would be sent
+/// the message, and `excluded` has every other node with the `status`
+/// it would get and, if its condition didn't hold, an `error` saying
+/// why. `total` is how many nodes a broadcast goes to at all.
+/// 
+/// Muting a node only silences its alerts, so muted nodes are still
+/// reached. There are no per-node permissions yet, so no node is left
+/// out for them.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// It can be turned off with the `send` feature flag.
+/// 
+/// # Errors
+/// If only some of the condition is given, or its field or op isn't
+/// supported, an error saying so is returned.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "reached": [1, 2],
+///     "excluded": [
+///         { "node_id": 3, "status": "skipped_disabled" },
+///         { "node_id": 4, "status": "skipped_condition", "error": "The node has no reading yet." }
+///     ],
+///     "total": 4,
+///     "success": true
+/// }
+/// ```
+#[get("/api/broadcast/preview")]
+fn broadcast_preview(options: Query<BroadcastPreviewOptions>, info: InfoSet, conn: DbConn, features: State<Features>, _user: AuthedUser) -> JsonValue {
+    if !features.enabled("send") {
+        return feature_disabled();
+    }
+
+    let options = options.0;
+    let condition = match (options.field, options.op, options.value) {
+        (None, None, None) => None,
+        (Some(field), Some(op), Some(value)) => Some(SendCondition {
+            field: field,
+            op: op,
+            value: value,
+        }),
+        _ => {
+            return json!({
+                "error": "A condition needs a field, an op and a value.",
+                "success": false,
+            });
+        }
+    };
+
+    if let Some(Err(why)) = condition.as_ref().map(|condition| condition.validate()) {
+        return json!({
+            "error": why,
+            "success": false,
+        });
+    }
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let nodes = info.nodes();
+    let (reached, excluded): (Vec<BroadcastResult>, Vec<BroadcastResult>) = nodes.iter()
+        .map(|node| {
+            let (status, error) = broadcast_status(node, &configs, condition.as_ref());
+
+            BroadcastResult {
+                node_id: node.uuid,
+                status: status,
+                error: error,
+            }
+        })
+        .partition(|result| result.status == SendStatus::Sent);
+
+    json!({
+        "reached": reached.iter().map(|result| result.node_id).collect::<Vec<u32>>(),
+        "excluded": excluded,
+        "total": nodes.len(),
+        "success": true,
+    })
+}
+
+/// Stores messages that were sent so they show up in a node's send
+/// history. Failing to store them doesn't stop the send.
+fn record_sent_messages(conn: &SqliteConnection, records: &[NewSentMessage]) {
+    if let Err(why) = db::create_sent_messages(conn, records) {
+        warn!("Could not record sent messages: {}", why);
+    }
+}
+
+/// A sent message as it is returned from the /api/xbee/<node>/sends
+/// endpoint, with the sender's username.
+#[derive(Serialize)]
+struct SendRecord {
+    id: i32,
+    content: String,
+    status: String,
+    sender: Option<String>,
+    timestamp: i64,
+}
+
+/// Returns the messages sent to a node, newest first, so operators
+/// can see which commands it was given.
+/// 
+/// Each message has its `content`, when it was sent, the username of
+/// its `sender` (or null if they were since deleted), and its
+/// `status`, which is one of the statuses a broadcast returns from
+/// /api/send. Messages sent to only this node are "sent", or
+/// "skipped_condition" if their condition didn't hold.
+/// 
+/// The optional `?from=<time>` and `?to=<time>` Unix timestamps limit
+/// the messages to a time range. Messages are listed in pages of
+/// `?per_page=<n>` (25 by default, at most 100), and `?page=<n>` picks
+/// which page to return starting from 1. A node that was never sent
+/// anything returns an empty page.
+/// 
+/// There are no per-node permissions yet, so any authorized user can
+/// see the history of every node.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "sends": [{
+///         "id": 7,
+///         "content": "Data to send",
+///         "status": "sent",
+///         "sender": "admin",
+///         "timestamp": 1527781200
+///     }, {
+///         ...
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 7,
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>/sends")]
+fn send_history(node: u32, options: Query<SendHistoryOptions>, links: PageLinks, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let options = options.0;
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+
+    let found = db::list_sent_messages(&conn, Some(node as i32), options.from, options.to, (page - 1) * per_page, per_page)
+        .and_then(|(messages, total)| {
+            let mut senders = messages.iter()
+                .map(|message| message.sender_id)
+                .collect::<Vec<i32>>();
+            senders.sort();
+            senders.dedup();
+
+            let names = db::users_by_ids(&conn, &senders)?
+                .into_iter()
+                .map(|user| (user.id, user.username))
+                .collect::<HashMap<i32, String>>();
+
+            Ok((messages, names, total))
+        });
+
+    match found {
+        Ok((messages, names, total)) => {
+            let sends = messages.into_iter()
+                .map(|message| SendRecord {
+                    id: message.id,
+                    sender: names.get(&message.sender_id).cloned(),
+                    content: message.content,
+                    status: message.status,
+                    timestamp: message.timestamp,
+                })
+                .collect::<Vec<SendRecord>>();
+
+            links.add_to(json!({
+                "node_id": node,
+                "sends": sends,
+                "page": page,
+                "per_page": per_page,
+                "total": total,
+                "success": true,
+            }), page, per_page, total)
+        }
+        Err(why) => {
+            warn!("Could not list sent messages: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Checks which of the given destinations a message could be sent
+/// to without sending anything.
+/// 
+/// A destination exists if the node has sent its info to the server,
+/// or if it is the broadcast address. There are no per-node permissions
+/// yet, so any authorized user is permitted to send to every node
+/// that exists.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678]
+/// }
+/// ```
+#[post("/api/send/validate", format = "application/json", data = "<destinations>")]
+fn validate_send(destinations: Json<Destinations>, info: InfoSet, _user: AuthedUser) -> JsonValue {
+    let statuses = destinations.node_ids.iter()
+        .map(|&node| {
+            let exists = info.contains(node);
+
+            DestinationStatus {
+                node_id: node,
+                exists: exists,
+                permitted: exists,
+            }
+        })
+        .collect::<Vec<DestinationStatus>>();
+
+    json!({
+        "destinations": statuses,
+        "success": true,
+    })
+}
+
+/// Gives a new xbee the `default_units` setting if it has no units.
+/// Returns whether they were given.
+fn apply_default_units(xbee: &mut NewXbee, settings: &Settings) -> bool {
+    if xbee.units.trim().is_empty() {
+        if let Some(ref default_units) = settings.default_units {
+            xbee.units = default_units.clone();
+            return true;
+        }
+    }
+
+    false
+}
+
+/// A temporary endpoint that adds the given data to the database.
+/// 
+/// This endpoint takes JSON data that describes an Xbee. 
+/// The body can be sent gzipped with `Content-Encoding: gzip`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// It can be turned off with the `add` feature flag.
+/// 
+/// A node can optionally be given a `report_interval_secs`, which is
+/// how often it is expected to report a reading, a `latitude`,
+/// `longitude` and `location_name` saying where it is, and a longer
+/// `description` with notes about it, such as how it was installed.
+/// 
+/// A `color` and `icon` can be given to change how the node is shown
+/// on the dashboard. The color is a hex color such as `#1e90ff`, and
+/// the icon is one of `db::NODE_ICONS`. They are stored on the server
+/// so the node looks the same for every user.
+/// 
+/// If the units are missing or empty and the `default_units` setting
+/// in Rocket.toml is set, the node is given those units instead and
+/// `default_units_applied` is true in the response.
+/// 
+/// If the `echo_acting_user` setting is on, the response includes the
+/// username of the user who added the node in `acted_by`.
+/// 
+/// # Errors
+/// If the units are longer than `db::MAX_UNITS_LENGTH` characters,
+/// or the description is longer than `db::MAX_DESCRIPTION_LENGTH`,
+/// an error naming the field and its limit will be returned. The same
+/// happens if `report_interval_secs` is not positive, if the location
+/// isn't on Earth, if the color or icon isn't valid, or if the units
+/// are empty and there are no default units.
+/// 
+/// If a node with the same id already exists, an error is returned
+/// with a 409 Conflict status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "name": "Temperature Sensor",
+///     "units": "C",
+///     "report_interval_secs": 60
+/// }
+/// ```
+#[post("/api/add", format = "application/json", data = "<xbee>")]
+fn add(xbee: Decoded<NewXbee>, conn: DbConn, features: State<Features>, settings: State<Settings>, clock: State<SharedClock>, user: AuthedUser) -> status::Custom<JsonValue> {
+    if !features.enabled("add") {
+        return status::Custom(Status::Ok, feature_disabled());
+    }
+
+    let mut xbee = xbee.0;
+    let defaulted = apply_default_units(&mut xbee, &settings);
+
+    match db::create_xbee(&conn, &xbee) {
+        Ok(_) => {
+            audit(&conn, user.id, "node_add", Some(xbee.node_id), None, clock.now());
+            status::Custom(Status::Ok, with_acted_by(json!({
+                "default_units_applied": defaulted,
+                "success": true,
+            }), acting_user(&conn, user.id, &settings)))
+        }
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => {
+                let code = match invalid {
+                    db::ValidationError::NodeExists(_) => Status::Conflict,
+                    _ => Status::Ok,
+                };
+
+                status::Custom(code, json!({
+                    "error": invalid.to_string(),
+                    "success": false,
+                }))
+            }
+            Err(why) => {
+                warn!("Could not save xbee: {}", why);
+                status::Custom(Status::Ok, json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                }))
+            }
+        },
+    }
+}
+
+/// Checks whether /api/add would accept the given xbee, without
+/// adding it. This takes the same body as /api/add, applies the same
+/// default units and runs the same checks, so a provisioning tool can
+/// give feedback before anything is saved.
+/// 
+/// `valid` is true if the node would be added. Otherwise `errors` has
+/// every problem at once, each with the `field` it is about. Nodes are
+/// added with the ranges they send in their info packet, so there are
+/// no ranges to check here.
+/// 
+/// A node that is valid now can still be refused by /api/add if the
+/// same id is added in the meantime.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// It can be turned off with the `add` feature flag.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "valid": false,
+///     "errors": [{
+///         "field": "units",
+///         "error": "Field 'units' must be at most 10 characters."
+///     }, {
+///         "field": "node_id",
+///         "error": "A node with id 1234 already exists."
+///     }],
+///     "default_units_applied": false,
+///     "success": true
+/// }
+/// ```
+#[post("/api/add/validate", format = "application/json", data = "<xbee>")]
+fn validate_add(xbee: Decoded<NewXbee>, conn: DbConn, features: State<Features>, settings: State<Settings>, _user: AuthedUser) -> JsonValue {
+    if !features.enabled("add") {
+        return feature_disabled();
+    }
+
+    let mut xbee = xbee.0;
+    let defaulted = apply_default_units(&mut xbee, &settings);
+
+    match db::validate_xbee(&conn, &xbee) {
+        Ok(errors) => {
+            let errors = errors.iter()
+                .map(|invalid| json!({
+                    "field": invalid.field(),
+                    "error": invalid.to_string(),
+                }))
+                .collect::<Vec<JsonValue>>();
+
+            json!({
+                "valid": errors.is_empty(),
+                "errors": errors,
+                "default_units_applied": defaulted,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not validate xbee: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Adds a new node with the same configuration as an existing one,
+/// for provisioning identical sensors. The units, display order,
+/// whether it is enabled, report interval, alert thresholds and tags
+/// are copied. Voltage and value ranges come from the node itself, so
+/// they aren't copied.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// It can be turned off with the `add` feature flag.
+/// 
+/// # Errors
+/// If the source node is not in the database, an error is returned
+/// with a 404 Not Found status. If a node with the new id already
+/// exists, an error is returned with a 409 Conflict status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "new_node_id": 5678,
+///     "new_name": "Greenhouse Sensor 2"
+/// }
+/// ```
+#[post("/api/xbee/<node>/clone", format = "application/json", data = "<clone>")]
+fn clone_node(node: u32, clone: Json<NodeClone>, conn: DbConn, features: State<Features>, clock: State<SharedClock>, user: AuthedUser) -> status::Custom<JsonValue> {
+    if !features.enabled("add") {
+        return status::Custom(Status::Ok, feature_disabled());
+    }
+
+    match db::clone_xbee(&conn, node as i32, clone.new_node_id as i32, &clone.new_name) {
+        Ok(_) => {
+            audit(&conn, user.id, "node_add", Some(clone.new_node_id as i32), Some(format!("cloned from {}", node)), clock.now());
+            status::Custom(Status::Ok, json!({
+                "node_id": clone.new_node_id,
+                "success": true,
+            }))
+        }
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => {
+                let code = match invalid {
+                    db::ValidationError::NodeNotFound(_) => Status::NotFound,
+                    db::ValidationError::NodeExists(_) => Status::Conflict,
+                    _ => Status::Ok,
+                };
+
+                status::Custom(code, json!({
+                    "error": invalid.to_string(),
+                    "success": false,
+                }))
+            }
+            Err(why) => {
+                warn!("Could not clone xbee: {}", why);
+                status::Custom(Status::Ok, json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                }))
+            }
+        },
+    }
+}
+
+/// Answers a node deletion that failed with the given error.
+fn deletion_error(why: Error) -> status::Custom<JsonValue> {
+    match why.downcast::<db::ValidationError>() {
+        Ok(invalid) => {
+            let code = match invalid {
+                db::ValidationError::NodeNotFound(_) => Status::NotFound,
+                db::ValidationError::SelfApprovedDeletion => Status::Forbidden,
+                _ => Status::Ok,
+            };
+
+            status::Custom(code, json!({
+                "error": invalid.to_string(),
+                "success": false,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not delete xbee: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Deletes a node's configuration and tags. Its readings are kept,
+/// so they can still be moved to another node with /api/admin/reassign.
+/// 
+/// If the `deletion_approval` setting is on, the node isn't deleted
+/// yet. Instead the deletion waits for a different admin to confirm
+/// it with /api/xbee/<node>/delete/confirm, and `pending` is true in
+/// the response. It expires after `deletion_window_secs`, at the
+/// `expires_at` Unix timestamp.
+/// 
+/// If the `echo_acting_user` setting is on, the response includes the
+/// username of the admin who deleted the node in `acted_by`.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the node is not in the database, an error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "pending": true,
+///     "expires_at": 1528025747,
+///     "success": true
+/// }
+/// ```
+#[delete("/api/xbee/<node>")]
+fn delete_node(node: u32, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    if !settings.deletion_approval {
+        return match db::delete_xbee(&conn, node as i32) {
+            Ok(0) => deletion_error(db::ValidationError::NodeNotFound(node as i32).into()),
+            Ok(_) => {
+                warn!("Admin {} deleted node {}.", admin.id, node);
+                audit(&conn, admin.id, "node_delete", Some(node as i32), None, clock.now());
+                status::Custom(Status::Ok, with_acted_by(json!({
+                    "pending": false,
+                    "success": true,
+                }), acting_user(&conn, admin.id, &settings)))
+            }
+            Err(why) => deletion_error(why.into()),
+        };
+    }
+
+    let now = clock.now();
+
+    match db::request_deletion(&conn, node as i32, admin.id, now) {
+        Ok(_) => {
+            warn!("Admin {} asked to delete node {}.", admin.id, node);
+            status::Custom(Status::Ok, json!({
+                "pending": true,
+                "expires_at": now + settings.deletion_window_secs,
+                "success": true,
+            }))
+        }
+        Err(why) => deletion_error(why),
+    }
+}
+
+/// Confirms a node deletion asked for by another admin, deleting the
+/// node. This is only needed if the `deletion_approval` setting is on.
+/// 
+/// If the `echo_acting_user` setting is on, the response includes the
+/// username of the admin who confirmed the deletion in `acted_by`.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the node has no pending deletion, or it has expired, an error
+/// is returned. If the admin confirming is the one who asked for the
+/// deletion, an error is returned with a 403 Forbidden status.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[post("/api/xbee/<node>/delete/confirm")]
+fn confirm_delete(node: u32, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    if !settings.deletion_approval {
+        return status::Custom(Status::Ok, json!({
+            "error": "Node deletions don't need to be confirmed.",
+            "success": false,
+        }));
+    }
+
+    let requested_after = clock.now() - settings.deletion_window_secs;
+
+    match db::confirm_deletion(&conn, node as i32, admin.id, requested_after) {
+        Ok(_) => {
+            warn!("Admin {} confirmed the deletion of node {}.", admin.id, node);
+            audit(&conn, admin.id, "node_delete", Some(node as i32), Some("confirmed".to_string()), clock.now());
+            status::Custom(Status::Ok, with_acted_by(json!({
+                "success": true,
+            }), acting_user(&conn, admin.id, &settings)))
+        }
+        Err(why) => deletion_error(why),
+    }
+}
+
+/// Changes the configuration of an existing xbee. Only the fields
+/// that are given are changed.
+/// 
+/// If the `echo_acting_user` setting is on, the response includes the
+/// username of the user who made the change in `acted_by`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// A node can be given its own `retention_days`, which is how many
+/// days its readings are kept for instead of the `retention_days`
+/// setting. This lets chatty nodes be purged sooner than others.
+/// 
+/// A node's `latitude` and `longitude` place it on the map returned by
+/// /api/list.geojson, and `location_name` describes where it is.
+/// 
+/// A node's `alert_hysteresis` is how far back within its thresholds
+/// its value has to go before it stops alerting, in the same units as
+/// its values. It replaces the `alert_hysteresis` setting.
+/// 
+/// A node's `description` is free text for notes about it, such as
+/// how it was installed or its quirks.
+/// 
+/// A node's `min_reading_interval_secs` is how far apart readings sent
+/// to /api/readings/batch have to be for it. It replaces the
+/// `min_reading_interval_secs` setting, and 0 allows any interval.
+/// 
+/// A node's `color` and `icon` change how it is shown on the
+/// dashboard, in the same form as when it is added.
+/// 
+/// # Errors
+/// If the new units are longer than `db::MAX_UNITS_LENGTH` characters,
+/// or the new description is longer than `db::MAX_DESCRIPTION_LENGTH`,
+/// an error naming the field and its limit will be returned. The same
+/// happens if the new `report_interval_secs` or `retention_days` is
+/// not positive, if the new `alert_hysteresis` or
+/// `min_reading_interval_secs` is negative, if the new color or icon
+/// isn't valid, or if the latitude isn't between -90 and 90 or the
+/// longitude isn't between -180 and 180.
+/// 
+/// If the node is not in the database, an error noting that will
+/// be returned.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "name": "Greenhouse Sensor",
+///     "enabled": false
+/// }
+/// ```
+#[post("/api/xbee/<node>/update", format = "application/json", data = "<update>")]
+fn update(node: u32, update: Json<XbeeUpdate>, conn: DbConn, settings: State<Settings>, clock: State<SharedClock>, user: AuthedUser) -> JsonValue {
+    match db::update_xbee(&conn, node as i32, &update) {
+        Ok(_) => {
+            audit(&conn, user.id, "node_update", Some(node as i32), serde_json::to_string(&*update).ok(), clock.now());
+            with_acted_by(json!({
+                "success": true,
+            }), acting_user(&conn, user.id, &settings))
+        }
+        Err(why) => {
+            if let Some(invalid) = why.downcast_ref::<db::ValidationError>() {
+                return json!({
+                    "error": invalid.to_string(),
+                    "success": false,
+                });
+            }
+
+            if let Some(&diesel::result::Error::NotFound) = why.downcast_ref::<diesel::result::Error>() {
+                return json!({
+                    "error": "No node with that id found.",
+                    "success": false,
+                });
+            }
+
+            warn!("Could not update xbee: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns a single active node in the same format as each node in
+/// /api/list, under `node` instead of a `nodes` array. It takes the
+/// same options as /api/list except `?units=<units>`, which is
+/// ignored. As CSV it is a header row and a single node's row.
+/// 
+/// The response has a `Last-Modified` header with when anything shown
+/// about the node last changed: its `last_update`, which moves forward
+/// with every reading it sends over the radio, its newest stored
+/// reading, the last change to its configuration or tags, a mute
+/// running out, or it going stale. If the request has an
+/// `If-Modified-Since` header that isn't earlier than that, a 304 Not
+/// Modified is returned with no body, so clients polling a node only
+/// download it when something changed. The check is made before the
+/// node is looked up in full, so a 304 is cheap.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// A node that isn't active returns a 404 Not Found, and bad options
+/// return a 400 Bad Request.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node": {
+///         "last_update": 1523568385,
+///         "name": "Test",
+///         "reading": 413,
+///         "units": "C",
+///         "uuid": 2,
+///         "value": 123.9,
+///         ...
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>", rank = 2)]
+fn node_detail(node: u32, options: Query<ListOptions>, format: Format, since: IfModifiedSince, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Result<Conditional<Negotiated>, status::Custom<JsonValue>> {
+    let node = match info.get(node) {
+        Some(node) => node,
+        None => {
+            return Err(status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            })));
+        }
+    };
+
+    let validators = db::xbee_config(&conn, node.uuid as i32)
+        .and_then(|config| Ok((config, db::newest_reading_time(&conn, node.uuid as i32)?)));
+
+    let last_modified = match validators {
+        Ok((config, newest)) => node_last_modified(&node, config.as_ref(), newest, settings.stale_after_secs, clock.now()),
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return Err(status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    if since.is_current(last_modified) {
+        return Ok(Conditional::not_modified(last_modified));
+    }
+
+    //  Filtering a single node by its units would only ever hide it.
+    let mut options = options.0;
+    options.units = None;
+
+    let mut body = match list_nodes(vec![node], &options, format, &settings, clock.now(), &conn) {
+        Negotiated::Json(body) => body,
+        csv => return Ok(Conditional::new(csv, last_modified, since)),
+    };
+
+    let node = body.0.get_mut("nodes")
+        .and_then(serde_json::Value::as_array_mut)
+        .and_then(|nodes| nodes.pop());
+
+    match node {
+        Some(node) => {
+            let body = json!({
+                "node": node,
+                "success": true,
+            });
+            Ok(Conditional::new(Negotiated::Json(body), last_modified, since))
+        }
+        None => Err(status::Custom(Status::BadRequest, body)),
+    }
+}
+
+/// Returns how a node's readings are scaled to values, so clients
+/// can check the conversion themselves.
+/// 
+/// Readings are in hundredths of a volt and are mapped linearly from
+/// the node's voltage range to its value range. The response includes
+/// the ranges, the resulting value per volt, and the node's current
+/// reading along with the value it scales to.
+/// 
+/// The node's calibration is given too, along with `calibrated_value`,
+/// which is the value after calibration as /api/list shows it.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 2,
+///     "min_voltage": 0.0,
+///     "max_voltage": 5.0,
+///     "min_value": 0.0,
+///     "max_value": 150.0,
+///     "value_per_volt": 30.0,
+///     "formula": "value = min_value + (reading / 100 - min_voltage) * value_per_volt",
+///     "reading": 413,
+///     "value": 123.9,
+///     "calibration_scale": 1.0,
+///     "calibration_offset": -0.5,
+///     "calibrated_value": 123.4,
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>/scaling")]
+fn scaling(node: u32, info: InfoSet, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let node = match info.get(node) {
+        Some(node) => node,
+        None => {
+            return json!({
+                "error": "No node with that id found.",
+                "success": false,
+            });
+        }
+    };
+
+    let config = match db::xbee_config_map(&conn) {
+        Ok(mut configs) => configs.remove(&node.uuid),
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let (calibration_scale, calibration_offset) = calibration(config.as_ref());
+
+    json!({
+        "node_id": node.uuid,
+        "min_voltage": node.min_voltage,
+        "max_voltage": node.max_voltage,
+        "min_value": node.min_value,
+        "max_value": node.max_value,
+        "value_per_volt": node.value_per_volt(),
+        "formula": "value = min_value + (reading / 100 - min_voltage) * value_per_volt",
+        "reading": node.reading,
+        "value": node.value(),
+        "calibration_scale": calibration_scale,
+        "calibration_offset": calibration_offset,
+        "calibrated_value": calibrated(config.as_ref(), node.value()),
+        "success": true,
+    })
+}
+
+/// Sets the calibration of a node, which corrects known error in its
+/// sensor without changing its stored readings. Its values in
+/// /api/list become `value * scale + offset`. Both are optional, and
+/// a missing `offset` is 0 and a missing `scale` is 1, so sending an
+/// empty object removes the calibration.
+/// 
+/// Alert thresholds are checked against the calibrated values,
+/// along with send conditions on `value` and the values in alert
+/// summaries, alert history and node reports.
+/// 
+/// The calibration is logged with who set it.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If `scale` is 0, an error saying so is returned. If the node isn't
+/// in the database, an error saying so is returned with a 404 Not
+/// Found status.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "offset": -0.5,
+///     "scale": 1.02
+/// }
+/// ```
+#[post("/api/xbee/<node>/calibrate", format = "application/json", data = "<calibration>")]
+fn calibrate(node: u32, calibration: Json<CalibrationRequest>, conn: DbConn, clock: State<SharedClock>, user: AuthedUser) -> status::Custom<JsonValue> {
+    let calibration = calibration.into_inner();
+
+    match db::set_calibration(&conn, node as i32, calibration.offset, calibration.scale) {
+        Ok(0) => status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        })),
+        Ok(_) => {
+            info!("User {} calibrated node {} with {:?}.", user.id, node, calibration);
+            audit(&conn, user.id, "node_calibrate", Some(node as i32), Some(format!("{:?}", calibration)), clock.now());
+            status::Custom(Status::Ok, json!({
+                "node_id": node,
+                "calibration_scale": calibration.scale.unwrap_or(1.0),
+                "calibration_offset": calibration.offset.unwrap_or(0.0),
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            if let Some(invalid) = why.downcast_ref::<db::ValidationError>() {
+                return status::Custom(Status::Ok, json!({
+                    "error": invalid.to_string(),
+                    "success": false,
+                }));
+            }
+
+            warn!("Could not calibrate node: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Changes the units of a node that was set up with the wrong ones.
+/// By default the units are only relabeled, for when the values were
+/// right and only their label was wrong. With `convert` set to true,
+/// the node's values are converted from the old units to the new ones
+/// as well.
+/// 
+/// A node's value range comes from its sensor, and its stored readings
+/// are raw voltages, so neither can be converted. Instead the
+/// conversion is folded into the node's calibration, which its values
+/// in /api/list then come out in the new units through. Its alert
+/// thresholds are converted the same way, and its alert hysteresis is
+/// scaled, since it is a difference between two values rather than a
+/// value.
+/// 
+/// `changed` has the old and new value of everything that changed.
+/// The change is logged with who made it.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If `convert` is true and the units can't be converted, such as
+/// when they measure different things, an error saying so is returned
+/// and nothing is changed. If the units are too long, an error saying
+/// so is returned. If the node isn't in the database, an error saying
+/// so is returned with a 404 Not Found status.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "units": "F",
+///     "convert": true
+/// }
+/// ```
+/// 
+/// Which returns, for a node that was in Celsius:
+/// ```json
+/// {
+///     "node_id": 4,
+///     "converted": true,
+///     "changed": {
+///         "units": { "from": "C", "to": "F" },
+///         "calibration_scale": { "from": 1.0, "to": 1.8 },
+///         "calibration_offset": { "from": 0.0, "to": 32.0 },
+///         "max_alert": { "from": 30.0, "to": 86.0 }
+///     },
+///     "success": true
+/// }
+/// ```
+#[post("/api/xbee/<node>/units", format = "application/json", data = "<request>")]
+fn change_units(node: u32, request: Json<UnitsRequest>, conn: DbConn, clock: State<SharedClock>, user: AuthedUser) -> status::Custom<JsonValue> {
+    let request = request.into_inner();
+
+    let config = match db::xbee_config_map(&conn) {
+        Ok(mut configs) => configs.remove(&node),
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let config = match config {
+        Some(config) => config,
+        None => {
+            return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            }));
+        }
+    };
+
+    let mut changed = serde_json::Map::new();
+    changed.insert("units".to_string(), json!({ "from": config.units, "to": request.units }));
+
+    let calibrated = if request.convert {
+        let conversion = match convert::conversion(&config.units, &request.units) {
+            Some(conversion) => conversion,
+            None => {
+                return status::Custom(Status::Ok, json!({
+                    "error": format!("Values in {} can't be converted to {}.", config.units, request.units),
+                    "success": false,
+                }));
+            }
+        };
+
+        let (scale, offset) = calibration(Some(&config));
+        let converted = converted_values(&config, conversion);
+
+        changed.insert("calibration_scale".to_string(), json!({ "from": scale, "to": converted.calibration_scale }));
+        changed.insert("calibration_offset".to_string(), json!({ "from": offset, "to": converted.calibration_offset }));
+
+        for &(field, from, to) in &[
+            ("min_alert", config.min_alert, converted.min_alert),
+            ("max_alert", config.max_alert, converted.max_alert),
+            ("alert_hysteresis", config.alert_hysteresis, converted.alert_hysteresis),
+        ] {
+            if from.is_some() {
+                changed.insert(field.to_string(), json!({ "from": from, "to": to }));
+            }
+        }
+
+        Some(converted)
+    } else {
+        None
+    };
+
+    match db::set_units(&conn, node as i32, &request.units, calibrated.as_ref()) {
+        Ok(0) => status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        })),
+        Ok(_) => {
+            info!("User {} changed the units of node {} from {:?} to {:?}, converting: {}.", user.id, node, config.units, request.units, request.convert);
+            audit(&conn, user.id, "node_units", Some(node as i32), Some(format!("{} to {}", config.units, request.units)), clock.now());
+            status::Custom(Status::Ok, json!({
+                "node_id": node,
+                "converted": request.convert,
+                "changed": changed,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            if let Some(invalid) = why.downcast_ref::<db::ValidationError>() {
+                return status::Custom(Status::Ok, json!({
+                    "error": invalid.to_string(),
+                    "success": false,
+                }));
+            }
+
+            warn!("Could not change node units: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Scales the raw reading given by `?reading=<n>` the way the node
+/// would scale it, without waiting for a real reading.
+/// 
+/// Readings are in hundredths of a volt. Readings outside the node's
+/// voltage range are extrapolated along the same line rather than
+/// clamped, and `in_range` is false for them.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node isn't active, an error noting that will be returned.
+/// 
+/// If the node's maximum voltage isn't above its minimum, readings
+/// can't be scaled and an error saying so will be returned.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 2,
+///     "reading": 413,
+///     "value": 123.9,
+///     "in_range": true,
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>/scale")]
+fn scale(node: u32, options: Query<ScaleOptions>, info: InfoSet, _user: AuthedUser) -> JsonValue {
+    let reading = options.0.reading;
+    let node = match info.get(node) {
+        Some(node) => node,
+        None => {
+            return json!({
+                "error": "No node with that id found.",
+                "success": false,
+            });
+        }
+    };
+
+    match node.scale(reading) {
+        Some(value) => {
+            let voltage = f32::from(reading) / 100.0;
+
+            json!({
+                "node_id": node.uuid,
+                "reading": reading,
+                "value": value,
+                "in_range": voltage >= node.min_voltage && voltage <= node.max_voltage,
+                "success": true,
+            })
+        }
+        None => json!({
+            "error": "The node's voltage range is empty or inverted, so readings can't be scaled.",
+            "success": false,
+        }),
+    }
+}
+
+/// Returns whether values in the units given by `?from=<units>` can be
+/// converted to the units given by `?to=<units>`, and how. This lets a
+/// frontend only offer conversions that work.
+/// 
+/// Units are matched by their symbol, such as `C`, `kPa` or `mph`,
+/// and a few other spellings such as `°C` or `feet`. `from` and `to`
+/// in the response are the symbols they were matched to. Units that
+/// measure different things, or that aren't known, can't be
+/// converted. Identical units always can, and `identity` is true for
+/// them.
+/// 
+/// A converted value is `value * factor + offset`, which is also
+/// written out in `formula`. Both are null if the units can't be
+/// converted.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "from": "C",
+///     "to": "F",
+///     "convertible": true,
+///     "identity": false,
+///     "factor": 1.8,
+///     "offset": 32.0,
+///     "formula": "value * 1.8 + 32",
+///     "success": true
+/// }
+/// ```
+#[get("/api/units/convertible")]
+fn units_convertible(options: Query<ConvertibleOptions>, _user: AuthedUser) -> JsonValue {
+    let options = options.0;
+    let found = convert::conversion(&options.from, &options.to);
+
+    json!({
+        "from": convert::normalize(&options.from),
+        "to": convert::normalize(&options.to),
+        "convertible": found.is_some(),
+        "identity": found.map_or(false, |found| found.is_identity()),
+        "factor": found.map(|found| found.factor),
+        "offset": found.map(|found| found.offset),
+        "formula": found.map(|found| found.formula()),
+        "success": true,
+    })
+}
+
+/// Returns the current values of two nodes, given by `?a=<node>` and
+/// `?b=<node>`, and the `difference` between them, which is `a`'s
+/// value minus `b`'s. This is meant for pairs of nodes that measure
+/// the same thing in different places, such as indoor and outdoor
+/// temperature.
+/// 
+/// The difference is null if either node has no value yet. It is
+/// still given if the nodes' units don't match, but `units_match` is
+/// false and a `warning` says so, since the difference is probably
+/// meaningless.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If either node isn't active, an error naming it will be returned.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "a": { "node_id": 1, "name": "Indoor", "units": "F", "value": 71.5 },
+///     "b": { "node_id": 2, "name": "Outdoor", "units": "F", "value": 48.0 },
+///     "difference": 23.5,
+///     "units_match": true,
+///     "success": true
+/// }
+/// ```
+#[get("/api/compare")]
+fn compare(options: Query<CompareOptions>, info: InfoSet, _user: AuthedUser) -> JsonValue {
+    let options = options.0;
+    let (a, b) = match (info.get(options.a), info.get(options.b)) {
+        (Some(a), Some(b)) => (a, b),
+        (None, _) => {
+            return json!({
+                "error": format!("No node with id {} found.", options.a),
+                "success": false,
+            });
+        }
+        (_, None) => {
+            return json!({
+                "error": format!("No node with id {} found.", options.b),
+                "success": false,
+            });
+        }
+    };
+
+    let units_match = a.units == b.units;
+    let difference = match (a.value(), b.value()) {
+        (Some(a), Some(b)) => Some(a - b),
+        _ => None,
+    };
+
+    let mut body = json!({
+        "a": { "node_id": a.uuid, "name": a.name, "units": a.units, "value": a.value() },
+        "b": { "node_id": b.uuid, "name": b.name, "units": b.units, "value": b.value() },
+        "difference": difference,
+        "units_match": units_match,
+        "success": true,
+    });
+
+    if let (false, Some(fields)) = (units_match, body.as_object_mut()) {
+        let warning = format!("The nodes' units differ ({} and {}), so the difference may be meaningless.", a.units, b.units);
+        fields.insert("warning".to_string(), warning.into());
+    }
+
+    body
+}
+
+/// Returns a list of active nodes and their most recent values.
+/// This data will be returned as a JSON object where the xbee
+/// data is stored in an array.
+/// 
+/// Nodes are sorted by their display order, then by id. Nodes
+/// that have not been added to the database are listed last.
+/// 
+/// A different order can be given with `?sort=<keys>`, which is a
+/// comma separated list of keys to sort by in turn. Keys starting with
+/// `-` are sorted in descending order, so `?sort=name,-last_update`
+/// sorts by name and then puts the most recently updated first. Ties
+/// still fall back to the default order. The supported keys are
+/// `display_order`, `node_id`, `name`, `units`, `last_update`,
+/// `reading` and `value`. An unknown key returns an error. If `?sort`
+/// isn't given, the order the user saved through /api/me/prefs is
+/// used, if any.
+/// 
+/// Each node has a `value` which is its reading scaled to its value
+/// range and then calibrated, or null if it has no reading yet. See
+/// /api/xbee/<node>/calibrate for calibration. Nodes that are disabled
+/// have `enabled` set to false and can't be sent messages.
+/// 
+/// If a node's voltage range is empty or inverted, `scaling_valid` is
+/// false and `value` is always null, since it would be meaningless.
+/// 
+/// Each node also has its `report_interval_secs`, or null if it has
+/// none, and whether it is `stale`. A node is stale when it hasn't
+/// reported for longer than its report interval, or the
+/// `stale_after_secs` setting in Rocket.toml if it has no interval.
+/// `created_at` is when the node was added to the database, or null
+/// if it hasn't been. `retention_days` is how long the node's readings
+/// are kept for, which is its own retention period or the
+/// `retention_days` setting, or null if they are kept forever.
+/// 
+/// `power_status` says whether a node's power is failing, from where
+/// its reading falls in its voltage range. It is "critical" below the
+/// `power_critical_fraction` setting in Rocket.toml (0.1 by default),
+/// "low" below `power_low_fraction` (0.25 by default) and "ok" above
+/// that. Nodes without a reading or a valid voltage range are
+/// "unknown".
+/// 
+/// `latitude`, `longitude` and `location_name` say where a node is, or
+/// are null if it hasn't been given a location. `description` is the
+/// node's notes, or null if it has none. `color` and `icon` are how
+/// the dashboard shows the node, or null to use the defaults.
+/// 
+/// A node is `muted` if its alerts were muted through
+/// /api/xbee/<node>/mute, and `muted_until` is when that ends, or
+/// null if it isn't muted. A node is `auto_registered` if it was added
+/// by /api/readings/batch and hasn't been updated since.
+/// 
+/// If `?history=<n>` is given, the last `n` readings of each node
+/// (at most 100) are included newest first in a `history` array.
+/// 
+/// If `?precision=<n>` is given, `value`, `min_value` and `max_value`
+/// are rounded to `n` decimal places (at most 10). Otherwise the
+/// `precision` setting in Rocket.toml is used, if any.
+/// 
+/// If `?tz=<timezone>` is given with an IANA timezone name such as
+/// "America/Chicago", each node also has a `last_update_str` which is
+/// `last_update` formatted in that timezone. Unknown timezones use UTC.
+/// 
+/// If `?units=<units>` is given, only nodes with exactly those units
+/// are listed.
+/// 
+/// If `?fields=<fields>` is given, each node only has the fields in
+/// that comma separated list, such as `?fields=reading,units`, plus
+/// `uuid` so it can be told apart. An unknown field returns an error.
+/// 
+/// Nodes are returned as CSV instead of JSON if the `Accept` header
+/// asks for `text/csv`, with one row per node and a header row naming
+/// the columns. The columns are the fields picked with `?fields=`, or
+/// every field but `history` and the optional ones. Errors are always
+/// JSON. An `Accept` header with no supported type gets a 406 Not
+/// Acceptable.
+/// 
+/// If `?delta=true` is given, each node also has a `previous_reading`,
+/// which is the reading before its latest one, and a `delta`, which is
+/// how much its value changed between the two. Both are null if the
+/// node has fewer than two readings, and `delta` is also null if its
+/// scaling isn't valid. `delta` is rounded like `value`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "nodes": [{
+///         "last_update": 1523568385,
+///         "max_value": 150.0,
+///         "max_voltage": 5.0,
+///         "min_value": 0.0,
+///         "min_voltage": 0.0,
+///         "name": "Test",
+///         "reading": 413,
+///         "units": "C",
+///         "uuid": 2,
+///         "value": 123.9,
+///         "scaling_valid": true,
+///         "enabled": true,
+///         "report_interval_secs": 60,
+///         "retention_days": 30,
+///         "stale": false,
+///         "power_status": "ok",
+///         "latitude": 38.627,
+///         "longitude": -90.199,
+///         "location_name": "Greenhouse 2",
+///         "description": "Mounted under the north vent.",
+///         "color": "#1e90ff",
+///         "icon": "thermometer",
+///         "muted": false,
+///         "muted_until": null,
+///         "auto_registered": false,
+///         "created_at": 1523480000
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/list")]
+fn list_authed(options: Query<ListOptions>, format: Format, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> Negotiated {
+    let mut options = options.0;
+
+    if options.sort.is_none() {
+        match db::user_prefs(&conn, user.id) {
+            Ok(prefs) => {
+                options.sort = prefs.map(|prefs| {
+                    format!("{}{}", if prefs.sort_desc { "-" } else { "" }, prefs.sort_key)
+                });
+            }
+            Err(why) => warn!("Could not get user preferences: {}", why),
+        }
+    }
+
+    list_nodes(info.nodes(), &options, format, &settings, clock.now(), &conn)
+}
+
+/// The columns of /api/list as CSV when no `?fields=<fields>` are
+/// given. History doesn't fit in a column, so it's left out.
+const NODE_CSV_COLUMNS: &[&str] = &[
+    "uuid", "name", "units", "reading", "value", "last_update",
+    "min_value", "max_value", "min_voltage", "max_voltage",
+    "scaling_valid", "enabled", "report_interval_secs", "retention_days",
+    "stale", "power_status", "latitude", "longitude", "location_name",
+    "description", "color", "icon", "muted", "muted_until", "auto_registered",
+    "created_at",
+];
+
+/// A node's raw reading next to its scaled value, as it is returned
+/// from the /api/list/scaled endpoint.
+#[derive(Serialize)]
+struct ScaledReading {
+    node_id: u32,
+    reading: Option<u16>,
+    value: Option<f64>,
+    scaling_valid: bool,
+    units: String,
+}
+
+/// Returns every active node's raw reading and the value it scales
+/// to, for checking calibration across every node at once. Nodes are
+/// sorted by id.
+/// 
+/// `reading` and `value` are null if the node has no reading yet. If
+/// a node's voltage range is empty or inverted, `scaling_valid` is
+/// false and `value` is always null, the same as in /api/list.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "nodes": [{
+///         "node_id": 2,
+///         "reading": 413,
+///         "value": 123.9,
+///         "scaling_valid": true,
+///         "units": "C"
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/list/scaled")]
+fn list_scaled(info: InfoSet, _user: AuthedUser) -> JsonValue {
+    let mut nodes = info.nodes().into_iter()
+        .map(|node| ScaledReading {
+            node_id: node.uuid,
+            reading: node.reading,
+            value: node.value(),
+            scaling_valid: node.scaling_valid(),
+            units: node.units,
+        })
+        .collect::<Vec<ScaledReading>>();
+
+    nodes.sort_by_key(|node| node.node_id);
+
+    json!({
+        "nodes": nodes,
+        "success": true,
+    })
+}
+
+/// Builds the response of /api/list for the given nodes, which
+/// /api/tags/<tag>/nodes shares. See /api/list for the options.
+fn list_nodes(mut nodes: Vec<XbeeInfo>, options: &ListOptions, format: Format, settings: &Settings, now: i64, conn: &SqliteConnection) -> Negotiated {
+    let precision = options.precision
+        .or(settings.precision)
+        .map(|places| places.min(MAX_PRECISION));
+    let configs = db::xbee_config_map(conn).unwrap_or_else(|why| {
+        warn!("Could not get xbee configuration: {}", why);
+        HashMap::new()
+    });
+
+    let spec = match options.sort.as_ref().map(|keys| sort::parse(keys)) {
+        Some(Ok(spec)) => spec,
+        Some(Err(why)) => {
+            return Negotiated::Json(json!({
+                "error": why,
+                "success": false,
+            }));
+        }
+        None => Vec::new(),
+    };
+
+    let fields = match options.fields.as_ref().map(|fields| parse_fields(fields)) {
+        Some(Ok(fields)) => Some(fields),
+        Some(Err(why)) => {
+            return Negotiated::Json(json!({
+                "error": why,
+                "success": false,
+            }));
+        }
+        None => None,
+    };
+
+    if let Some(ref units) = options.units {
+        nodes.retain(|node| &node.units == units);
+    }
+
+    sort::sort_nodes(&mut nodes, &spec, |node| {
+        configs.get(&node)
+            .map(|config| config.display_order)
+            .unwrap_or(i32::max_value())
+    });
+
+    let mut history = match options.history {
+        Some(count) => match db::recent_readings(conn, count.min(MAX_INLINE_HISTORY)) {
+            Ok(history) => Some(history),
+            Err(why) => {
+                warn!("Could not get reading history: {}", why);
+                return Negotiated::Json(json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        },
+        None => None,
+    };
+
+    //  Only the latest two readings are needed to find each delta.
+    let mut latest = if options.delta.unwrap_or(false) {
+        match db::recent_readings(conn, 2) {
+            Ok(latest) => Some(latest),
+            Err(why) => {
+                warn!("Could not get latest readings: {}", why);
+                return Negotiated::Json(json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    } else {
+        None
+    };
+
+    let nodes = nodes.into_iter()
+        .map(|mut node| {
+            let config = configs.get(&node.uuid);
+            let (calibration_scale, _) = calibration(config);
+            let mut value = calibrated(config, node.value());
+            let stale = is_stale(&node, config, settings.stale_after_secs, now);
+            let power = power_status(&node, settings.power_low_fraction, settings.power_critical_fraction);
+
+            //  Readings are newest first, so the previous one is second.
+            let previous = latest.as_mut()
+                .map(|latest| latest.remove(&node.uuid).unwrap_or_default());
+            let previous_reading = previous.as_ref()
+                .map(|readings| readings.get(1).map(|reading| reading.reading));
+            let mut delta = previous.as_ref()
+                .map(|readings| match (readings.get(0), readings.get(1)) {
+                    (Some(newest), Some(previous)) => {
+                        match (node.scale(newest.reading as u16), node.scale(previous.reading as u16)) {
+                            (Some(newest), Some(previous)) => Some((newest - previous) * calibration_scale),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                });
+
+            //  Only the copy being returned is rounded.
+            if let Some(places) = precision {
+                value = value.map(|value| round_to(value, places));
+                delta = delta.map(|delta| delta.map(|delta| round_to(delta, places)));
+                node.min_value = round_to(f64::from(node.min_value), places) as f32;
+                node.max_value = round_to(f64::from(node.max_value), places) as f32;
+            }
+
+            Node {
+                value: value,
+                scaling_valid: node.scaling_valid(),
+                enabled: config.map_or(true, |config| config.enabled),
+                report_interval_secs: config.and_then(|config| config.report_interval_secs),
+                retention_days: config
+                    .and_then(|config| config.retention_days)
+                    .map(i64::from)
+                    .or(settings.retention_days),
+                stale: stale,
+                power_status: power,
+                latitude: config.and_then(|config| config.latitude),
+                longitude: config.and_then(|config| config.longitude),
+                location_name: config.and_then(|config| config.location_name.clone()),
+                description: config.and_then(|config| config.description.clone()),
+                color: config.and_then(|config| config.color.clone()),
+                icon: config.and_then(|config| config.icon.clone()),
+                muted: muted_until(config, now).is_some(),
+                muted_until: muted_until(config, now),
+                auto_registered: config.map_or(false, |config| config.auto_registered),
+                created_at: config.map(|config| config.created_at),
+                last_update_str: options.tz.as_ref()
+                    .map(|timezone| format_timestamp(node.last_update, timezone)),
+                history: history.as_mut()
+                    .map(|history| history.remove(&node.uuid).unwrap_or_default()),
+                previous_reading: previous_reading,
+                delta: delta,
+                info: node,
+            }
+        })
+        .collect::<Vec<Node>>();
+
+    if format == Format::Json && fields.is_none() {
+        return Negotiated::Json(json!({
+            "nodes": Cased::new(&nodes, settings.json_field_case),
+            "success": true,
+        }));
+    }
+
+    let columns = match fields {
+        Some(ref fields) => fields.iter().map(|field| field.as_str()).collect::<Vec<&str>>(),
+        None => NODE_CSV_COLUMNS.to_vec(),
+    };
+
+    //  Fields are picked by their snake_case names, and CSV columns
+    //  always have them.
+    let case = if format == Format::Json { settings.json_field_case } else { FieldCase::Snake };
+
+    //  Only keep the fields that were asked for.
+    let nodes = nodes.iter()
+        .filter_map(|node| match case {
+            FieldCase::Snake => serde_json::to_value(node).ok(),
+            FieldCase::Camel => serde_json::to_value(Camel(node)).ok(),
+        })
+        .map(|node| {
+            columns.iter()
+                .filter_map(|&field| {
+                    let name = case.field_name(field);
+                    node.get(&name).map(|value| (name, value.clone()))
+                })
+                .collect::<serde_json::Map<String, serde_json::Value>>()
+        })
+        .collect::<Vec<serde_json::Map<String, serde_json::Value>>>();
+
+    match format {
+        Format::Json => Negotiated::Json(json!({
+            "nodes": nodes,
+            "success": true,
+        })),
+        Format::Csv => Negotiated::Csv(negotiate::to_csv(&columns, &nodes)),
+    }
+}
+
+/// Returns the active nodes located inside a box, for showing the
+/// nodes in a map's viewport. The box is given by
+/// `?min_lat=<lat>&min_lon=<lon>&max_lat=<lat>&max_lon=<lon>`, and
+/// its edges are inclusive. Nodes are returned in the same format as
+/// /api/list and take the same options. Nodes without a location are
+/// never inside a box.
+/// 
+/// A box whose `min_lon` is greater than its `max_lon` crosses the
+/// antimeridian. For example, `min_lon=170&max_lon=-170` holds the
+/// longitudes from 170 to 180 and from -180 to -170, rather than
+/// everything in between.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If any of the bounds is missing or isn't on Earth, or `min_lat` is
+/// greater than `max_lat`, an error describing why will be returned.
+#[get("/api/list/bbox")]
+fn list_bbox(bounds: Query<BoxOptions>, options: Query<ListOptions>, format: Format, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Negotiated {
+    let bounds = bounds.0;
+
+    let (min_lat, min_lon, max_lat, max_lon) = match (bounds.min_lat, bounds.min_lon, bounds.max_lat, bounds.max_lon) {
+        (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon)) => (min_lat, min_lon, max_lat, max_lon),
+        _ => {
+            return Negotiated::Json(json!({
+                "error": "Fields 'min_lat', 'min_lon', 'max_lat' and 'max_lon' are required.",
+                "success": false,
+            }));
+        }
+    };
+
+    let valid = db::validate_location(Some(min_lat), Some(min_lon))
+        .and_then(|_| db::validate_location(Some(max_lat), Some(max_lon)));
+
+    if let Err(invalid) = valid {
+        return Negotiated::Json(json!({
+            "error": invalid.to_string(),
+            "success": false,
+        }));
+    }
+
+    if min_lat > max_lat {
+        return Negotiated::Json(json!({
+            "error": "Field 'min_lat' must not be greater than 'max_lat'.",
+            "success": false,
+        }));
+    }
+
+    let inside = match db::nodes_in_box(&conn, min_lat, min_lon, max_lat, max_lon) {
+        Ok(inside) => inside,
+        Err(why) => {
+            warn!("Could not get nodes in box: {}", why);
+            return Negotiated::Json(json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let nodes = info.nodes()
+        .into_iter()
+        .filter(|node| inside.contains(&(node.uuid as i32)))
+        .collect::<Vec<XbeeInfo>>();
+
+    list_nodes(nodes, &options.0, format, &settings, clock.now(), &conn)
+}
+
+/// Returns every node with a location as a GeoJSON FeatureCollection,
+/// for showing nodes on a map. Each node is a Point feature at its
+/// `longitude` and `latitude`, with its id, name, `location_name`,
+/// latest `reading`, scaled `value`, `units` and whether it is
+/// `alerting` as properties.
+/// Nodes without both a latitude and longitude are left out.
+/// 
+/// Nodes that haven't sent their info since the server started have a
+/// null reading and value, and aren't alerting. Otherwise `alerting`
+/// is decided the same way as in /api/alerts/summary.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "type": "FeatureCollection",
+///     "features": [{
+///         "type": "Feature",
+///         "geometry": {
+///             "type": "Point",
+///             "coordinates": [-90.199, 38.627]
+///         },
+///         "properties": {
+///             "node_id": 1234,
+///             "name": "Greenhouse Sensor",
+///             "location_name": "Greenhouse 2",
+///             "reading": 413,
+///             "value": 123.9,
+///             "units": "C",
+///             "alerting": false
+///         }
+///     }]
+/// }
+/// ```
+#[get("/api/list.geojson")]
+fn list_geojson(info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let recent = match db::recent_readings(&conn, ALERT_STATE_READINGS) {
+        Ok(recent) => recent,
+        Err(why) => {
+            warn!("Could not get latest readings: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let mut located = configs.values()
+        .filter_map(|config| match (config.latitude, config.longitude) {
+            (Some(lat), Some(long)) => Some((config, lat, long)),
+            _ => None,
+        })
+        .collect::<Vec<(&Xbees, f64, f64)>>();
+    located.sort_by_key(|&(config, _, _)| config.node_id);
+
+    let now = clock.now();
+    let features = located.into_iter()
+        .map(|(config, lat, long)| {
+            let node = info.get(config.node_id as u32);
+            let value = node.as_ref().and_then(|node| node.value());
+            let alerting = node.as_ref().map_or(false, |node| {
+                let readings = recent.get(&node.uuid).map_or(&[][..], |readings| &readings[..]);
+                currently_alerting(node, readings, Some(config), &settings, now)
+            });
+
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [long, lat],
+                },
+                "properties": {
+                    "node_id": config.node_id,
+                    "name": config.name,
+                    "location_name": config.location_name,
+                    "reading": node.as_ref().and_then(|node| node.reading),
+                    "value": value,
+                    "units": config.units,
+                    "alerting": alerting,
+                },
+            })
+        })
+        .collect::<Vec<JsonValue>>();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// Returns the active nodes that have the given tag, in the same
+/// format as /api/list and with the same options. Tags are matched
+/// ignoring case. A tag that no node has gives an empty list.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+#[get("/api/tags/<tag>/nodes")]
+fn tagged_nodes(tag: String, options: Query<ListOptions>, format: Format, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Negotiated {
+    let tagged = match db::normalize_tag(&tag) {
+        Some(tag) => match db::nodes_with_tag(&conn, &tag) {
+            Ok(tagged) => tagged,
+            Err(why) => {
+                warn!("Could not get tagged nodes: {}", why);
+                return Negotiated::Json(json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let nodes = info.nodes()
+        .into_iter()
+        .filter(|node| tagged.contains(&(node.uuid as i32)))
+        .collect::<Vec<XbeeInfo>>();
+
+    list_nodes(nodes, &options.0, format, &settings, clock.now(), &conn)
+}
+
+/// Returns the nodes in the database from the most recently added,
+/// with when each was added in `created_at`.
+/// 
+/// Nodes are listed in pages of `?per_page=<n>` (25 by default, at
+/// most 100), and `?page=<n>` picks which page to return starting
+/// from 1. `total` is how many nodes there are across every page.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "nodes": [{
+///         "id": 3,
+///         "node_id": 1234,
+///         "name": "Temperature Sensor",
+///         "units": "C",
+///         "display_order": 0,
+///         "enabled": true,
+///         "report_interval_secs": null,
+///         "created_at": 1527182400,
+///         "min_alert": null,
+///         "max_alert": null
+///     }, {
+///         ...
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 3,
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/recent")]
+fn recent(options: Query<PageOptions>, links: PageLinks, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let (page, per_page) = page_bounds(options.0.page, options.0.per_page);
+
+    match db::recent_xbees(&conn, (page - 1) * per_page, per_page) {
+        Ok((nodes, total)) => links.add_to(json!({
+            "nodes": nodes,
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "success": true,
+        }), page, per_page, total),
+        Err(why) => {
+            warn!("Could not list recent xbees: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Sets the display order of one or more nodes.
+/// 
+/// This endpoint takes a JSON array of node ids and the position
+/// each should be shown at. Positions do not need to be contiguous;
+/// nodes sharing a position are ordered by their id.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// [
+///     { "node_id": 1234, "order": 0 },
+///     { "node_id": 5678, "order": 1 }
+/// ]
+/// ```
+#[post("/api/xbee/order", format = "application/json", data = "<orders>")]
+fn order(orders: Json<Vec<NodeOrder>>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    match db::set_display_order(&conn, &orders) {
+        Ok(updated) => json!({
+            "updated": updated,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not set display order: {}", why);
+            json!({
+                "error": "Error saving display order.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Enables or disables several nodes at once, such as every node in
+/// an area that is down for maintenance. This is done in a single
+/// transaction, so either every node is changed or none are.
+/// 
+/// The number of nodes whose flag changed is returned in `changed`,
+/// which leaves out nodes that were already enabled or disabled. Node
+/// ids that are not in the database are skipped and returned in
+/// `unknown`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678],
+///     "enabled": false
+/// }
+/// ```
+#[post("/api/xbee/enabled", format = "application/json", data = "<update>")]
+fn set_enabled(update: Json<EnabledUpdate>, conn: DbConn, clock: State<SharedClock>, user: AuthedUser) -> JsonValue {
+    let requested = update.node_ids.iter()
+        .map(|&node| node as i32)
+        .collect::<Vec<i32>>();
+
+    match db::set_enabled(&conn, &requested, update.enabled) {
+        Ok((changed, nodes)) => {
+            let unknown = update.node_ids.iter()
+                .filter(|&&node| !nodes.contains(&(node as i32)))
+                .cloned()
+                .collect::<Vec<u32>>();
+
+            let action = if update.enabled { "node_enable" } else { "node_disable" };
+            for &node in &nodes {
+                audit(&conn, user.id, action, Some(node), None, clock.now());
+            }
+
+            json!({
+                "changed": changed,
+                "unknown": unknown,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not set whether nodes are enabled: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Reduces the given readings to at most `points` readings by
+/// keeping evenly spaced ones. The order of the readings is kept.
+fn downsample(readings: Vec<Reading>, points: usize) -> Vec<Reading> {
+    if points == 0 || readings.len() <= points {
+        return readings;
+    }
+
+    //  Round the step up so no more than `points` readings are kept.
+    let step = (readings.len() + points - 1) / points;
+
+    readings.into_iter()
+        .enumerate()
+        .filter(|&(index, _)| index % step == 0)
+        .map(|(_, reading)| reading)
+        .collect()
+}
+
+/// Returns how many readings `downsample` keeps out of `total` when
+/// asked for at most `points`.
+fn downsampled_len(total: usize, points: usize) -> usize {
+    if points == 0 || total <= points {
+        return total;
+    }
+
+    let step = (total + points - 1) / points;
+    (total + step - 1) / step
+}
+
+/// Returns how much history /api/history/batch would return for a
+/// node, without reading it, so clients can decide whether to ask for
+/// raw or downsampled readings. This takes the same `?from=<time>`,
+/// `?to=<time>` and `?points=<n>` options as /api/history/batch.
+/// 
+/// `total_raw` is how many readings the node has in the range, and
+/// `approx_points` is how many would be returned after downsampling
+/// to at most `buckets` points. `buckets` is `points`, or `total_raw`
+/// if it isn't given. Downsampling keeps evenly spaced readings, so
+/// `approx_points` can be a little under `buckets`. If there are more
+/// readings than /api/history/batch returns at once, only the newest
+/// ones are downsampled, and `truncated` is set.
+/// 
+/// `from` and `to` are the range that was asked for, with an open end
+/// filled in by the oldest or newest reading of the node, or null if
+/// it has none. New readings can arrive before the history is fetched,
+/// so the numbers are only approximate.
+/// 
+/// This only counts readings, so it is cheap however many there are.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, an error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "total_raw": 1440,
+///     "buckets": 100,
+///     "approx_points": 96,
+///     "truncated": false,
+///     "from": 1523568385,
+///     "to": 1523654785,
+///     "success": true
+/// }
+/// ```
+#[get("/api/history/<node>/shape")]
+fn history_shape(node: u32, options: Query<ShapeOptions>, conn: DbConn, _user: AuthedUser) -> status::Custom<JsonValue> {
+    let options = options.0;
+    let node = node as i32;
+
+    let found = db::existing_nodes(&conn, &[node])
+        .and_then(|existing| {
+            if existing.is_empty() {
+                return Ok(None);
+            }
+
+            let stats = db::reading_stats(&conn, &[node], options.from, options.to)?;
+            let range = db::reading_range(&conn, Some(node))?;
+            Ok(Some((stats, range)))
+        });
+
+    let (stats, range) = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            }));
+        }
+        Err(why) => {
+            warn!("Could not get reading counts: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let total = stats.first().map_or(0, |stats| stats.count as usize);
+    let buckets = options.points.unwrap_or(total);
+    let fetched = total.min(MAX_BATCH_READINGS);
+
+    status::Custom(Status::Ok, json!({
+        "total_raw": total,
+        "buckets": buckets,
+        "approx_points": downsampled_len(fetched, buckets),
+        "truncated": total > MAX_BATCH_READINGS,
+        "from": options.from.or(range.oldest),
+        "to": options.to.or(range.newest),
+        "success": true,
+    }))
+}
+
+/// Returns the readings of several nodes in a single request.
+/// 
+/// The `from` and `to` fields are optional Unix timestamps that bound
+/// which readings are returned. If `points` is given, each node's
+/// readings are downsampled to at most that many evenly spaced readings.
+/// Readings are returned oldest first.
+/// 
+/// At most 10000 readings are returned across all nodes. If more
+/// readings match, the newest ones are kept and `truncated` is set.
+/// 
+/// Requests are turned away before reading any history if they cost
+/// more than the `history_cost_budget` setting. The cost is the number
+/// of distinct nodes asked for times the number of days between `from`
+/// and `to`, rounded up. A missing `from` counts from the oldest stored
+/// reading and a missing `to` counts up to now. Downsampling happens
+/// after the readings are read, so `points` doesn't lower the cost.
+/// 
+/// If the `Accept` header asks for `text/csv`, the readings are
+/// returned as CSV instead, with `node_id`, `reading` and `timestamp`
+/// columns and every reading of a node in a row of its own. There is
+/// no `truncated` flag in CSV, so it's best to ask for fewer readings.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the request costs more than the budget, an error saying so is
+/// returned with a 400 Bad Request status, along with the `cost` and
+/// `budget`.
+/// 
+/// If any database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678],
+///     "from": 1523568385,
+///     "to": 1523654785,
+///     "points": 100
+/// }
+/// ```
+#[post("/api/history/batch", format = "application/json", data = "<request>")]
+fn history_batch(request: Json<HistoryRequest>, format: Format, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Result<Negotiated, status::Custom<JsonValue>> {
+    let nodes = request.node_ids.iter()
+        .map(|&node| node as i32)
+        .collect::<Vec<i32>>();
+
+    check_history_cost(&nodes, request.from, request.to, &settings, clock.now(), &conn)?;
+
+    //  Ask for one more reading than the limit to know if any were cut off.
+    let limit = MAX_BATCH_READINGS as i64 + 1;
+
+    let mut rows = match db::readings_for(&conn, &nodes, request.from, request.to, limit) {
+        Ok(rows) => rows,
+        Err(why) => {
+            warn!("Could not get reading history: {}", why);
+            return Ok(Negotiated::Json(json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    let truncated = rows.len() > MAX_BATCH_READINGS;
+    rows.truncate(MAX_BATCH_READINGS);
+
+    //  Rows are newest first, so reverse them to get oldest first.
+    let mut history = request.node_ids.iter()
+        .map(|&node| (node, Vec::new()))
+        .collect::<HashMap<u32, Vec<Reading>>>();
+
+    for row in rows.into_iter().rev() {
+        if let Some(readings) = history.get_mut(&(row.node_id as u32)) {
+            readings.push(row);
+        }
+    }
+
+    if let Some(points) = request.points {
+        history = history.into_iter()
+            .map(|(node, readings)| (node, downsample(readings, points)))
+            .collect();
+    }
+
+    if format == Format::Csv {
+        //  Nodes are kept in the order they were asked for.
+        let rows = request.node_ids.iter()
+            .filter_map(|node| history.remove(node))
+            .flat_map(|readings| readings.into_iter())
+            .filter_map(|reading| match serde_json::to_value(reading) {
+                Ok(serde_json::Value::Object(row)) => Some(row),
+                _ => None,
+            })
+            .collect::<Vec<serde_json::Map<String, serde_json::Value>>>();
+
+        return Ok(Negotiated::Csv(negotiate::to_csv(&["node_id", "reading", "timestamp"], &rows)));
+    }
+
+    Ok(Negotiated::Json(json!({
+        "history": history,
+        "truncated": truncated,
+        "success": true,
+    })))
+}
+
+/// Returns every tag in use with how many nodes have it, the most
+/// used first, for finding rarely used tags to clean up. Tags with the
+/// same count are sorted by name. Tags only exist while a node has
+/// them, so every count is at least 1.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "tags": [{
+///         "tag": "greenhouse",
+///         "node_count": 12
+///     }, {
+///         "tag": "outdoor",
+///         "node_count": 1
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/tags")]
+fn tag_counts(conn: DbConn, _user: AuthedUser) -> JsonValue {
+    match db::tag_counts(&conn) {
+        Ok(counts) => json!({
+            "tags": counts,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not count tags: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns the tags of a node, sorted by name. A node without any
+/// tags has an empty list.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, an error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "tags": ["greenhouse", "temperature"],
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>/tags")]
+fn tags(node: u32, conn: DbConn, _user: AuthedUser) -> status::Custom<JsonValue> {
+    match db::tags_of_node(&conn, node as i32) {
+        Ok(Some(found)) => status::Custom(Status::Ok, json!({
+            "node_id": node,
+            "tags": found,
+            "success": true,
+        })),
+        Ok(None) => status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        })),
+        Err(why) => {
+            warn!("Could not get tags: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Adds and removes tags on several nodes at once. This is done in
+/// a single transaction, so either every change is made or none are.
+/// 
+/// Tag names are trimmed and made lowercase. Adding a tag a node
+/// already has, or removing one it doesn't have, does nothing. If a
+/// tag is both added and removed, it is removed.
+/// 
+/// The resulting tags of each node are returned. Node ids that are
+/// not in the database are skipped and returned in `unknown`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678],
+///     "add": ["greenhouse"],
+///     "remove": ["outdoor"]
+/// }
+/// ```
+#[post("/api/tags/assign", format = "application/json", data = "<assignment>")]
+fn assign_tags(assignment: Json<TagAssignment>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let requested = assignment.node_ids.iter()
+        .map(|&node| node as i32)
+        .collect::<Vec<i32>>();
+
+    let result = db::existing_nodes(&conn, &requested)
+        .and_then(|nodes| {
+            let tags = db::assign_tags(&conn, &nodes, &assignment.add, &assignment.remove)?;
+            Ok((nodes, tags))
+        });
+
+    match result {
+        Ok((nodes, tags)) => {
+            let unknown = assignment.node_ids.iter()
+                .filter(|&&node| !nodes.contains(&(node as i32)))
+                .cloned()
+                .collect::<Vec<u32>>();
+
+            json!({
+                "tags": tags,
+                "unknown": unknown,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not assign tags: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Renames a tag on every node at once, returning how many nodes
+/// were affected. This is done in a single transaction.
+/// 
+/// Both names are trimmed and made lowercase. Nodes that already have
+/// the new tag end up with a single copy of it.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If either name is empty, an error will be returned.
+/// 
+/// If a database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "from": "greenhuose",
+///     "to": "greenhouse"
+/// }
+/// ```
+#[post("/api/tags/rename", format = "application/json", data = "<rename>")]
+fn rename_tag(rename: Json<TagRename>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let (from, to) = match (db::normalize_tag(&rename.from), db::normalize_tag(&rename.to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => {
+            return json!({
+                "error": "Fields 'from' and 'to' must not be empty.",
+                "success": false,
+            });
+        }
+    };
+
+    match db::rename_tag(&conn, &from, &to) {
+        Ok(affected) => json!({
+            "affected": affected,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not rename tag: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns the timestamps of the oldest and newest readings, which
+/// is the window of time there is data for. If `?node=<id>` is given,
+/// only that node's readings are looked at. Both are null if there are
+/// no readings, including for nodes that don't exist.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "oldest": 1523568385,
+///     "newest": 1528880000,
+///     "success": true
+/// }
+/// ```
+#[get("/api/history/range")]
+fn history_range(options: Query<HistoryRangeOptions>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    match db::reading_range(&conn, options.0.node.map(|node| node as i32)) {
+        Ok(range) => json!({
+            "oldest": range.oldest,
+            "newest": range.newest,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not get reading range: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Checks each reading in a batch sent to /api/readings/batch on its
+/// own, returning the ones that can be saved and a result for every
+/// reading, in order.
+/// 
+/// `reading_times` has the stored readings of nodes whose readings
+/// have to be spaced out. Accepted readings are added to it, so later
+/// readings in the batch are spaced from them too.
+fn check_batch(batch: &[IngestReading], configs: &HashMap<u32, Xbees>, reading_times: &mut HashMap<i32, Vec<i64>>, settings: &Settings, now: i64) -> (Vec<NewReading>, Vec<IngestResult>) {
+    let mut valid: Vec<NewReading> = Vec::new();
+    let mut results = Vec::with_capacity(batch.len());
+
+    for (index, entry) in batch.iter().enumerate() {
+        let timestamp = entry.timestamp.unwrap_or(now);
+        let config = configs.get(&entry.node_id);
+        let error = if config.is_none() && !settings.auto_register {
+            Some("No node with that id found.".to_string())
+        } else if entry.reading < 0 || entry.reading > i64::from(u16::max_value()) {
+            Some(format!("Reading must be between 0 and {}.", u16::max_value()))
+        } else if timestamp > now {
+            Some("Timestamp must not be in the future.".to_string())
+        } else if timestamp < 0 {
+            Some("Timestamp must not be before 1970.".to_string())
+        } else {
+            let too_soon = match min_reading_interval(config, settings) {
+                Some(interval) => {
+                    let times = reading_times.entry(entry.node_id as i32).or_insert_with(Vec::new);
+                    !add_if_spaced(times, timestamp, interval)
+                }
+                None => false,
+            };
+
+            if too_soon {
+                Some("Reading is too close to another reading from the same node.".to_string())
+            } else {
+                valid.push(NewReading {
+                    node_id: entry.node_id as i32,
+                    reading: entry.reading as i32,
+                    timestamp: timestamp,
+                });
+                None
+            }
+        };
+
+        results.push(IngestResult {
+            index: index,
+            node_id: entry.node_id,
+            accepted: error.is_none(),
+            error: error,
+        });
+    }
+
+    (valid, results)
+}
+
+/// Marks every reading in a batch as rejected, for when none of it is
+/// saved because `atomic_ingest` is on and some readings were invalid.
+fn reject_batch(results: &mut [IngestResult]) {
+    for result in results {
+        if result.accepted {
+            result.accepted = false;
+            result.error = Some("Another reading in the batch was rejected.".to_string());
+        }
+    }
+}
+
+/// Stores a batch of readings, such as ones a gateway buffered while
+/// the server was unreachable. This endpoint takes a JSON list of
+/// readings, each with a `node_id`, a raw `reading` and an optional
+/// `timestamp`, which is now if it's left out. At most 1000 readings
+/// can be sent at once.
+/// 
+/// Large batches can be sent gzipped with `Content-Encoding: gzip`.
+/// 
+/// Readings are only stored as history. They don't change a node's
+/// current reading, which only comes from the node itself.
+/// 
+/// A reading is rejected if its node isn't in the database, its
+/// reading doesn't fit in 16 bits, or its timestamp is in the future
+/// or before 1970.
+/// Each reading gets an entry in `results`, in the order they were
+/// sent, saying whether it was `accepted` and why not in `error`.
+/// 
+/// A reading is also rejected if it is closer than the node's
+/// `min_reading_interval_secs`, or the setting of the same name in
+/// Rocket.toml, to a stored reading of the same node or to one
+/// accepted earlier in the batch. This drops duplicates, such as
+/// readings a gateway sends twice, and keeps the first reading sent
+/// in each interval. There is no minimum unless one is set.
+/// 
+/// If the `auto_register` setting in Rocket.toml is true, readings
+/// from nodes that aren't in the database are accepted instead, and
+/// each of those nodes is added with its id as its name and the
+/// `default_units`, or "unknown" units if there are none. They have
+/// `auto_registered` set in /api/list until they are updated through
+/// /api/xbee/<node>/update, and their ids are listed in `registered`.
+/// Every registration is recorded in the audit log with the user who
+/// sent the reading.
+/// 
+/// By default the readings that are valid are saved and the rest are
+/// skipped, so one bad reading doesn't hold back the others. If the
+/// `atomic_ingest` setting in Rocket.toml is true, the batch is saved
+/// all together or not at all: if any reading is rejected then none
+/// are saved, and an error is returned along with the results.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If more than 1000 readings are sent, an error is returned and
+/// nothing is saved. If any database error occurs it will return a
+/// generic error, and nothing is saved.
+/// 
+/// # Example
+/// ```json
+/// [
+///     { "node_id": 1234, "reading": 413, "timestamp": 1528880000 },
+///     { "node_id": 9999, "reading": 200 }
+/// ]
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "accepted": 1,
+///     "rejected": 1,
+///     "registered": [],
+///     "results": [
+///         { "index": 0, "node_id": 1234, "accepted": true, "error": null },
+///         { "index": 1, "node_id": 9999, "accepted": false, "error": "No node with that id found." }
+///     ],
+///     "success": true
+/// }
+/// ```
+#[post("/api/readings/batch", format = "application/json", data = "<batch>")]
+fn ingest_readings(batch: Decoded<Vec<IngestReading>>, settings: State<Settings>, clock: State<SharedClock>, metrics: State<Metrics>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    let batch = batch.0;
+
+    if batch.len() > MAX_INGEST_READINGS {
+        return json!({
+            "error": format!("At most {} readings can be sent at once.", MAX_INGEST_READINGS),
+            "success": false,
+        });
+    }
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let now = clock.now();
+
+    //  Find the stored readings near the ones that have to be spaced out
+    //  in one query, rather than one per reading.
+    let spaced = batch.iter()
+        .filter_map(|entry| {
+            min_reading_interval(configs.get(&entry.node_id), &settings)
+                .map(|interval| (entry.node_id as i32, entry.timestamp.unwrap_or(now), interval))
+        })
+        .collect::<Vec<(i32, i64, i64)>>();
+
+    let mut spaced_nodes = spaced.iter().map(|&(node, _, _)| node).collect::<Vec<i32>>();
+    spaced_nodes.sort();
+    spaced_nodes.dedup();
+
+    let window_start = spaced.iter().map(|&(_, time, interval)| time - interval).min().unwrap_or(0);
+    let window_end = spaced.iter().map(|&(_, time, interval)| time + interval).max().unwrap_or(0);
+
+    let mut reading_times = match db::reading_times(&conn, &spaced_nodes, window_start, window_end) {
+        Ok(times) => times,
+        Err(why) => {
+            warn!("Could not check for nearby readings: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let (valid, mut results) = check_batch(&batch, &configs, &mut reading_times, &settings, now);
+    let rejected = batch.len() - valid.len();
+
+    if settings.atomic_ingest && rejected > 0 {
+        reject_batch(&mut results);
+
+        return json!({
+            "error": "No readings were saved because some were rejected.",
+            "accepted": 0,
+            "rejected": batch.len(),
+            "results": results,
+            "success": false,
+        });
+    }
+
+    //  Only nodes with a reading that was accepted are registered.
+    let mut registered = valid.iter()
+        .map(|reading| reading.node_id)
+        .filter(|node| !configs.contains_key(&(*node as u32)))
+        .collect::<Vec<i32>>();
+    registered.sort();
+    registered.dedup();
+
+    let units = settings.default_units.as_ref().map_or(AUTO_REGISTER_UNITS, String::as_str);
+
+    match db::register_and_create_readings(&conn, &registered, units, &valid, user.id, clock.now()) {
+        Ok(accepted) => {
+            metrics.api_readings(accepted);
+
+            for node in &registered {
+                info!("User {} auto-registered node {} by sending it a reading.", user.id, node);
+            }
+
+            info!("User {} sent {} readings, {} of which were rejected.", user.id, batch.len(), rejected);
+            json!({
+                "accepted": accepted,
+                "rejected": rejected,
+                "registered": registered,
+                "results": results,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not save readings: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns how many readings each node has sent, along with the
+/// timestamps of its oldest and newest readings. Nodes without any
+/// readings are not included.
+/// 
+/// The optional `?from=<timestamp>&to=<timestamp>` parameters only
+/// count readings taken within that range.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "counts": [{
+///         "node_id": 1234,
+///         "count": 86400,
+///         "oldest": 1523568385,
+///         "newest": 1523654785
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/readings/count")]
+fn reading_counts(range: Query<RangeOptions>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    match db::reading_counts(&conn, range.0.from, range.0.to) {
+        Ok(counts) => json!({
+            "counts": counts,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not count readings: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// This is an error handler for the /api/list endpoint
+/// that is called when the user is not authorized. No
+/// xbee data will be returned from this endpoint, just
+/// a simple JSON object that indicates failure.
+#[get("/api/list", rank = 2)]
+fn list_invalid() -> JsonValue {
+    json!({
+        "success": false,
+    })
+}
+
+/// Returns everything a page needs to know before starting the
+/// frontend in a single response: whether the user is logged in, who
+/// they are, the API version and which features are enabled. This
+/// saves a round trip for each on page load.
+/// 
+/// `user` has the logged in user's `username` and `role`, and
+/// `impersonated` is true if an admin is acting as them. Callers that
+/// aren't logged in get the same fields with `authenticated` false
+/// and a null `user`.
+/// 
+/// # Errors
+/// If the user's details can't be read from the database, a generic
+/// error is returned.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "authenticated": true,
+///     "user": {
+///         "username": "Admin",
+///         "role": "admin",
+///         "impersonated": false
+///     },
+///     "version": "1",
+///     "features": {
+///         "add": true,
+///         ...
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/bootstrap")]
+fn bootstrap(features: State<Features>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    let found = users
+        .find(user.id)
+        .select((username, role))
+        .get_result::<(String, String)>(&*conn);
+
+    match found {
+        Ok((name, user_role)) => json!({
+            "authenticated": true,
+            "user": {
+                "username": name,
+                "role": user_role,
+                "impersonated": user.impersonated_by.is_some(),
+            },
+            "version": API_VERSION,
+            "features": features.effective(),
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not get user: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Answers /api/bootstrap for callers that aren't logged in, in the
+/// same format with `authenticated` false.
+#[get("/api/bootstrap", rank = 2)]
+fn bootstrap_anonymous(features: State<Features>) -> JsonValue {
+    json!({
+        "authenticated": false,
+        "user": null,
+        "version": API_VERSION,
+        "features": features.effective(),
+        "success": true,
+    })
+}
+
+/// Returns whether a username is free to register, ignoring case,
+/// so a signup form can give feedback as the name is typed.
+/// 
+/// Names in the `reserved_usernames` setting are never available.
+/// 
+/// Each client can check 10 usernames at once, and then one every
+/// two seconds, so this can't be used to find out which usernames
+/// exist in bulk. Clients over that limit get a 429 Too Many Requests.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "available": false
+/// }
+/// ```
+#[get("/api/register/available")]
+fn username_available(options: Query<AvailabilityOptions>, limiter: State<AvailabilityLimiter>, settings: State<Settings>, remote: Option<SocketAddr>, conn: DbConn) -> Result<JsonValue, Limited> {
+    if let Some(seconds) = remote.and_then(|remote| limiter.0.check(remote.ip(), None)) {
+        return Err(Limited { retry_after: seconds });
+    }
+
+    if settings.is_reserved(&options.0.user) {
+        return Ok(json!({
+            "available": false,
+        }));
+    }
+
+    match db::username_taken(&conn, options.0.user.trim()) {
+        Ok(taken) => Ok(json!({
+            "available": !taken,
+        })),
+        Err(why) => {
+            warn!("Could not check username: {}", why);
+            Ok(json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Returns why a username can't be registered, or `None` if it can as
+/// far as the settings go. Whether it is taken is checked separately.
+fn registration_error(name: &str, settings: &Settings) -> Option<&'static str> {
+    if name.trim().is_empty() {
+        Some("Username must not be empty.")
+    } else if settings.is_reserved(name) {
+        Some("That username is reserved.")
+    } else {
+        None
+    }
+}
+
+/// Makes an account for a new user, who can then log in through
+/// /api/login. Usernames are stored in lowercase, and a name that is
+/// taken in any case can't be registered.
+/// 
+/// This is turned off unless the `allow_registration` setting is
+/// true, and can also be turned off with the `registration` feature
+/// flag. Names in the `reserved_usernames` setting can't be
+/// registered, in any case. Every registration is recorded in the
+/// audit log.
+/// 
+/// # Errors
+/// If registration is off, the username is empty, reserved or taken,
+/// or the password is empty, an error saying so is returned. If any
+/// database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "user": "alice",
+///     "pass": "correct horse battery staple"
+/// }
+/// ```
+/// 
+/// Which returns:
+/// ```json
+/// {
+///     "user_id": 7,
+///     "success": true
+/// }
+/// ```
+#[post("/api/register", format = "application/json", data = "<registration>")]
+fn register(registration: Json<Registration>, features: State<Features>, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn) -> JsonValue {
+    if !features.enabled("registration") {
+        return feature_disabled();
+    }
+
+    if !settings.allow_registration {
+        return json!({
+            "error": "Registration is turned off.",
+            "success": false,
+        });
+    }
+
+    let name = registration.user.trim();
+
+    if let Some(error) = registration_error(name, &settings) {
+        return json!({
+            "error": error,
+            "success": false,
+        });
+    }
+
+    if registration.pass.is_empty() {
+        return json!({
+            "error": "Password must not be empty.",
+            "success": false,
+        });
+    }
+
+    match db::register_user(&conn, name, &registration.pass, settings.bcrypt_cost) {
+        Ok(Some(user_id)) => {
+            info!("User {} registered as {:?}.", user_id, db::normalize_username(name));
+            audit(&conn, user_id, "user_register", None, None, clock.now());
+            json!({
+                "user_id": user_id,
+                "success": true,
+            })
+        }
+        Ok(None) => json!({
+            "error": "That username is taken.",
+            "success": false,
+        }),
+        Err(why) => {
+            warn!("Could not register user: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns the preferences the user has saved, which are applied to
+/// their own requests. `sort` is null if they haven't saved one.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "prefs": {
+///         "sort": "last_update",
+///         "descending": true
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/me/prefs")]
+fn prefs(conn: DbConn, user: AuthedUser) -> JsonValue {
+    match db::user_prefs(&conn, user.id) {
+        Ok(prefs) => json!({
+            "prefs": {
+                "sort": prefs.as_ref().map(|prefs| prefs.sort_key.clone()),
+                "descending": prefs.as_ref().map_or(false, |prefs| prefs.sort_desc),
+            },
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not get user preferences: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Saves the user's preferences, replacing any they had before.
+/// 
+/// `sort` is the key /api/list is sorted by when the request doesn't
+/// pass `?sort`, which is either `name` or `last_update`. It is sorted
+/// in descending order if `descending` is true. Preferences are kept
+/// in the database, so they follow the user to other devices.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If `sort` isn't a supported key, an error will be returned and
+/// nothing is saved.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "sort": "last_update",
+///     "descending": true
+/// }
+/// ```
+#[put("/api/me/prefs", format = "application/json", data = "<update>")]
+fn set_prefs(update: Json<PrefsUpdate>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    if !PREF_SORT_KEYS.contains(&update.sort.as_str()) {
+        return json!({
+            "error": format!("Sort must be one of: {}.", PREF_SORT_KEYS.join(", ")),
+            "success": false,
+        });
+    }
+
+    let prefs = UserPrefs {
+        user_id: user.id,
+        sort_key: update.sort.clone(),
+        sort_desc: update.descending,
+    };
+
+    match db::set_user_prefs(&conn, &prefs) {
+        Ok(_) => json!({
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not save user preferences: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns everything the server stores about the user, so they can
+/// get a copy of their data without asking an admin. This is their
+/// profile without the password, their preferences and rate limit
+/// tier, the messages they sent, the alerts they acknowledged, the
+/// node deletions they asked for, their API keys without the keys
+/// themselves, what they did from the audit log and their sessions
+/// that haven't expired. Nothing about other users is included.
+/// 
+/// Sessions are listed with when they started and when they expire
+/// unless they are renewed. Only sessions the server has seen since it
+/// started are listed.
+/// 
+/// **Note**: This endpoint requires that the user is authorized. It
+/// can't be used while an admin is impersonating the user.
+/// 
+/// # Errors
+/// If any database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "export": {
+///         "user": {
+///             "id": 3,
+///             "username": "jsmith",
+///             "role": "user",
+///             "deleted_at": null
+///         },
+///         "prefs": null,
+///         "rate_limit_tier": null,
+///         "sent_messages": [{
+///             "id": 12,
+///             "node_id": 1234,
+///             "content": "R",
+///             "status": "sent",
+///             "sender_id": 3,
+///             "timestamp": 1528900000
+///         }],
+///         "alert_acks": [],
+///         "pending_deletions": [],
+///         "api_keys": [],
+///         "audit_events": [{
+///             "id": 40,
+///             "actor_id": 3,
+///             "action": "login",
+///             "node_id": null,
+///             "target_user_id": null,
+///             "detail": "203.0.113.5",
+///             "timestamp": 1528899000
+///         }],
+///         "sessions": [{
+///             "started": 1528899000,
+///             "expires": 1528902600
+///         }]
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/me/export")]
+fn export_me(sessions: State<ActiveSessions>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    if user.impersonated_by.is_some() {
+        return json!({
+            "error": "A user's data can't be exported while impersonating them.",
+            "success": false,
+        });
+    }
+
+    match db::user_export(&conn, user.id, sessions.for_user(user.id, clock.now())) {
+        Ok(Some(export)) => json!({
+            "export": export,
+            "success": true,
+        }),
+        Ok(None) => json!({
+            "error": "No user with that id found.",
+            "success": false,
+        }),
+        Err(why) => {
+            warn!("Could not export user data: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Lists the user's API keys, oldest first, with when each was made
+/// and last used. `last_used_at` is null for keys that were never
+/// used, and is only updated once a minute. The keys themselves and
+/// their signing secrets are only shown when they are made, so they
+/// aren't listed. `signed` is whether requests made with the key have
+/// to be signed.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If any database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "keys": [{
+///         "id": 4,
+///         "label": "Greenhouse gateway",
+///         "created_at": 1529660000,
+///         "last_used_at": 1529661200,
+///         "signed": false
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/me/keys")]
+fn my_keys(conn: DbConn, user: AuthedUser) -> JsonValue {
+    match db::api_keys_of(&conn, user.id) {
+        Ok(keys) => json!({
+            "keys": keys,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not get API keys: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Makes a new API key for the user, for a machine client to send in
+/// the `X-Api-Key` header instead of logging in. The `label` says what
+/// the key is for. The key is only returned here, so it has to be
+/// saved now. It lasts until it is revoked.
+/// 
+/// If `signed` is true, the key also gets a `signing_secret`, which
+/// is only returned here as well, and every request made with it has
+/// to be signed with HMAC-SHA256 using that secret. The string to sign
+/// is the method, the path with its query, the Unix timestamp and the
+/// SHA-256 of the body as lowercase hex, each on its own line:
+/// 
+/// ```text
+/// POST
+/// /api/readings/batch
+/// 1529660000
+/// 4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945
+/// ```
+/// 
+/// The signature is sent as hex in the `X-Signature` header, the
+/// timestamp in `X-Signature-Timestamp` and the body hash in
+/// `X-Content-Sha256`. Requests without a body sign the hash of an
+/// empty body. A signature more than 5 minutes from the server's time
+/// is rejected, so a caught request can't be replayed later. Trailing
+/// slashes are trimmed from the path before it is checked, so sign it
+/// without them. Endpoints that take gzipped bodies, such as
+/// /api/readings/batch, check the body against its hash; others only
+/// check the signature over the hash that was sent.
+/// 
+/// **Note**: This endpoint requires that the user is authorized. It
+/// can't be used while an admin is impersonating the user.
+/// 
+/// # Errors
+/// If the label is empty or longer than 100 characters, an error
+/// noting that will be returned.
+/// 
+/// If any other error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "label": "Greenhouse gateway",
+///     "signed": true
+/// }
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "id": 4,
+///     "key": "4.9c1e0f...",
+///     "label": "Greenhouse gateway",
+///     "created_at": 1529660000,
+///     "signing_secret": "b27d4a...",
+///     "success": true
+/// }
+/// ```
+#[post("/api/me/keys", format = "application/json", data = "<new_key>")]
+fn create_key(new_key: Json<NewKey>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    if user.impersonated_by.is_some() {
+        return json!({
+            "error": "API keys can't be made while impersonating a user.",
+            "success": false,
+        });
+    }
+
+    let label = new_key.label.trim();
+
+    if label.is_empty() || label.chars().count() > MAX_KEY_LABEL_LENGTH {
+        return json!({
+            "error": format!("Label must be between 1 and {} characters.", MAX_KEY_LABEL_LENGTH),
+            "success": false,
+        });
+    }
+
+    let secret = match api_key::generate_secret().and_then(|secret| api_key::hash(&secret).map(|hash| (secret, hash))) {
+        Ok(secret) => secret,
+        Err(why) => {
+            warn!("Could not make API key: {}", why);
+            return json!({
+                "error": "Could not make an API key.",
+                "success": false,
+            });
+        }
+    };
+
+    let signing_secret = if new_key.signed {
+        match api_key::generate_secret() {
+            Ok(signing_secret) => Some(signing_secret),
+            Err(why) => {
+                warn!("Could not make API key signing secret: {}", why);
+                return json!({
+                    "error": "Could not make an API key.",
+                    "success": false,
+                });
+            }
+        }
+    } else {
+        None
+    };
+
+    let now = clock.now();
+
+    match db::create_api_key(&conn, user.id, label, &secret.1, signing_secret.as_ref().map(String::as_str), now) {
+        Ok(key_id) => {
+            info!("User {} made API key {}.", user.id, key_id);
+            json!({
+                "id": key_id,
+                "key": api_key::format(key_id, &secret.0),
+                "label": label,
+                "created_at": now,
+                "signing_secret": signing_secret,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not save API key: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Revokes one of the user's API keys, so it can't be used anymore.
+/// 
+/// **Note**: This endpoint requires that the user is authorized. It
+/// can't be used while an admin is impersonating the user.
+/// 
+/// # Errors
+/// If the user has no key with the given id, an error noting that
+/// will be returned.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[delete("/api/me/keys/<key_id>")]
+fn revoke_key(key_id: i32, conn: DbConn, user: AuthedUser) -> JsonValue {
+    if user.impersonated_by.is_some() {
+        return json!({
+            "error": "API keys can't be revoked while impersonating a user.",
+            "success": false,
+        });
+    }
+
+    match db::revoke_api_key(&conn, user.id, key_id) {
+        Ok(0) => json!({
+            "error": "No API key with that id found.",
+            "success": false,
+        }),
+        Ok(_) => {
+            info!("User {} revoked API key {}.", user.id, key_id);
+            json!({
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not revoke API key: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// This is a login endpoint for users to authenticate themselves.
+/// A username and password must be supplied in a JSON object as
+/// described below. Once a user is authenticated, a private cookie
+/// will be stored which will allow them to access endpoints that
+/// require authentication.
+/// 
+/// Usernames are not case sensitive, so "Admin" and "admin" will
+/// log in as the same user.
+/// 
+/// Clients that can't hold cookies can pass `?token=true` or send
+/// an `Accept: application/jwt` header to get a signed JWT instead.
+/// It must be sent as an `Authorization: Bearer <token>` header and
+/// is valid for `jwt_lifetime` seconds. The cookie is not set when a
+/// token is issued.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "token": "eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9...",
+///     "expires": 1523654785,
+///     "success": true
+/// }
+/// ```
+/// 
+/// # Errors
+/// If the given username is not in the database, or belongs to a
+/// deleted user, an error noting that will be returned.
+/// 
+/// If a valid username is given but the password is wrong, an error
+/// will be returned.
+/// 
+/// Either way, the attempt is recorded with the username as it was
+/// typed and the client's IP address, and can be reviewed through
+/// /api/admin/failed-logins. The password is never recorded.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[post("/api/login", format = "application/json", data = "<login>")]
+fn login(login: Json<Login>, wants_token: TokenRequest, settings: State<Settings>, clock: State<SharedClock>, sessions: State<ActiveSessions>, conn: DbConn, remote: Option<SocketAddr>, mut cookies: Cookies) -> JsonValue {
+    //  Try to find a user in the database with the given username.
+    //  Usernames are stored lowercase so the lookup ignores case.
+    //  Deleted users are treated as if they didn't exist.
+    //  This query returns at most 1 result.
+    let res = db::find_login_user(&conn, &login.user);
+
+    match res {
+        //  User was found, so now check the password.
+        Ok(user) => {
+            //  Password is stored as a bcrypt hash so we need to
+            //  ensure it is correct.
+            match bcrypt::verify(&login.pass, &user.password) {
+                Ok(true) => {}
+                //  The hash didn't match, so return invalid credentials.
+                Ok(false) => {
+                    record_failed_login(&conn, &login.user, remote, clock.now());
+                    return json!({
+                        "error": "Invalid login credentials.",
+                        "success": false,
+                    });
+                }
+                //  The stored hash couldn't be read, which means the
+                //  data is corrupt rather than the password being wrong.
+                //  Log it without the hash and return a generic error.
+                Err(_) => {
+                    error!("Stored password hash for user {} is malformed.", user.id);
+                    record_failed_login(&conn, &login.user, remote, clock.now());
+                    return json!({
+                        "error": "Invalid login credentials.",
+                        "success": false,
+                    });
+                }
+            }
+
+            //  Password matched hash, so the login is recorded. If the
+            //  client asked for a token, return one instead of setting
+            //  a cookie.
+            audit(&conn, user.id, "login", None, remote.map(|remote| remote.ip().to_string()), clock.now());
+
+            if wants_token.0 {
+                return match token::issue(&user, &settings, clock.now(), None) {
+                    Ok((jwt, expires)) => json!({
+                        "token": jwt,
+                        "expires": expires,
+                        "success": true,
+                    }),
+                    Err(why) => {
+                        warn!("Could not issue token: {}", why);
+                        json!({
+                            "error": "Could not issue a token.",
+                            "success": false,
+                        })
+                    }
+                };
+            }
+
+            //  Otherwise add authenticated cookie. Logging in ends
+            //  any impersonation that was going on.
+            session::start(&mut cookies, &sessions, user.id, None, &settings, clock.now());
+
+            json!({
+                "success": true,
+            })
+        }
+        //  User was not found in the database.
+        Err(diesel::result::Error::NotFound) => {
+            record_failed_login(&conn, &login.user, remote, clock.now());
+            json!({
+                "error": "No user with that name found.",
+                "success": false,
+            })
+        }
+        //  Another database error occurred.
+        Err(_) => {
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Looks up several users by id at once. Password hashes are never
+/// included.
+/// 
+/// This endpoint takes a JSON list of user ids. Users that were found
+/// are returned in `users`, and ids that don't belong to any user are
+/// listed in `missing`.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "ids": [1, 2, 40]
+/// }
+/// ```
+#[post("/api/users/lookup", format = "application/json", data = "<lookup>")]
+fn lookup_users(lookup: Json<UserLookup>, conn: DbConn, _admin: AdminUser) -> JsonValue {
+    match db::users_by_ids(&conn, &lookup.ids) {
+        Ok(found) => {
+            let mut missing = lookup.ids.iter()
+                .filter(|&&user_id| !found.iter().any(|user| user.id == user_id))
+                .cloned()
+                .collect::<Vec<i32>>();
+            missing.sort();
+            missing.dedup();
+
+            json!({
+                "users": found,
+                "missing": missing,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not look up users: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Starts a session as another user so an admin can see what they
+/// see. The session cookie is replaced with one for that user, or a
+/// JWT is returned if one is asked for the same way as /api/login.
+/// 
+/// The session is marked as an impersonation, so it can't be used
+/// for admin endpoints, even if the user is an admin. Every
+/// impersonation is logged and recorded in the audit log before the
+/// session is started. Logging out ends it.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If no user with the given id exists, or they were deleted, an
+/// error noting that will be returned.
+/// 
+/// If the impersonation can't be recorded in the audit log, no session
+/// is started and a generic error is returned, as it is for any other
+/// database error.
+#[post("/api/users/<user_id>/impersonate")]
+fn impersonate(user_id: i32, wants_token: TokenRequest, settings: State<Settings>, clock: State<SharedClock>, sessions: State<ActiveSessions>, conn: DbConn, admin: AdminUser, mut cookies: Cookies) -> JsonValue {
+    let user = match users.find(user_id).filter(deleted_at.is_null()).get_result::<User>(&*conn) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => {
+            return json!({
+                "error": "No user with that id found.",
+                "success": false,
+            });
+        }
+        Err(why) => {
+            warn!("Could not find user to impersonate: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    if let Err(why) = db::record_audit(&conn, Some(admin.id), "impersonate", None, Some(user.id), None, clock.now()) {
+        warn!("Could not record impersonation: {}", why);
+        return json!({
+            "error": "Error saving information to database.",
+            "success": false,
+        });
+    }
+
+    warn!("Admin {} is impersonating user {}.", admin.id, user.id);
+
+    if wants_token.0 {
+        return match token::issue(&user, &settings, clock.now(), Some(admin.id)) {
+            Ok((jwt, expires)) => json!({
+                "token": jwt,
+                "expires": expires,
+                "success": true,
+            }),
+            Err(why) => {
+                warn!("Could not issue token: {}", why);
+                json!({
+                    "error": "Could not issue a token.",
+                    "success": false,
+                })
+            }
+        };
+    }
+
+    session::start(&mut cookies, &sessions, user.id, Some(admin.id), &settings, clock.now());
+
+    json!({
+        "success": true,
+    })
+}
+
+/// Sets a new password for a user who can't log in, such as one who
+/// forgot theirs.
+/// 
+/// By default the user is logged out everywhere: every session they
+/// started and token they were issued before the reset stops working,
+/// including the admin's own if they reset their own password. Setting
+/// `logout` to false keeps them logged in, and the default can be
+/// changed with the `logout_on_password_reset` setting in Rocket.toml.
+/// `logged_out` says whether they were. Every reset is logged with the
+/// admin who made it.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the password is empty, or no user with the given id exists, an
+/// error noting that will be returned.
+/// 
+/// If any other database error occurs it will return a generic error,
+/// and neither the password nor the sessions are changed.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "password": "correct horse battery staple",
+///     "logout": true
+/// }
+/// ```
+#[put("/api/users/<user_id>/password", format = "application/json", data = "<reset>")]
+fn reset_password(user_id: i32, reset: Json<PasswordReset>, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> JsonValue {
+    if reset.password.is_empty() {
+        return json!({
+            "error": "Password must not be empty.",
+            "success": false,
+        });
+    }
+
+    let logout = reset.logout.unwrap_or(settings.logout_on_password_reset);
+    let revoke_at = if logout { Some(clock.now()) } else { None };
+
+    match db::reset_password(&conn, user_id, &reset.password, settings.bcrypt_cost, revoke_at) {
+        Ok(()) => {
+            if logout {
+                warn!("Admin {} reset the password of user {} and logged them out.", admin.id, user_id);
+            } else {
+                warn!("Admin {} reset the password of user {}.", admin.id, user_id);
+            }
+
+            json!({
+                "logged_out": logout,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            if let Some(&db::PasswordError::UserNotFound(_)) = why.downcast_ref::<db::PasswordError>() {
+                return json!({
+                    "error": "No user with that id found.",
+                    "success": false,
+                });
+            }
+
+            warn!("Could not reset password: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Puts a user on a rate limit tier, so trusted clients such as
+/// gateways can make more requests than browsers. `tier` is one of:
+/// 
+/// - `default`: the limit from the `rate_limit` table
+/// - `elevated`: the limit from the `rate_limit.elevated` table
+/// - `unlimited`: never limited
+/// 
+/// Tiers only apply to requests where the user is logged in. The new
+/// tier applies from the user's next request.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If `tier` isn't one of the above, or no user with the given id
+/// exists, an error noting that will be returned.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "tier": "elevated"
+/// }
+/// ```
+#[put("/api/users/<user_id>/rate-limit-tier", format = "application/json", data = "<update>")]
+fn set_rate_limit_tier(user_id: i32, update: Json<TierUpdate>, limiter: State<Option<RateLimiter>>, conn: DbConn, admin: AdminUser) -> JsonValue {
+    let tier = match Tier::parse(&update.tier) {
+        Some(tier) => tier,
+        None => {
+            return json!({
+                "error": format!("Tier must be one of: {}.", rate_limit::TIERS.join(", ")),
+                "success": false,
+            });
+        }
+    };
+
+    match users.find(user_id).select(id).first::<i32>(&*conn).optional() {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return json!({
+                "error": "No user with that id found.",
+                "success": false,
+            });
+        }
+        Err(why) => {
+            warn!("Could not find user to set rate limit tier for: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    }
+
+    let name = if tier == Tier::Default { None } else { Some(tier.as_str()) };
+
+    if let Err(why) = db::set_rate_limit_tier(&conn, user_id, name) {
+        warn!("Could not save rate limit tier: {}", why);
+        return json!({
+            "error": "Error saving information to database.",
+            "success": false,
+        });
+    }
+
+    if let Some(ref limiter) = *limiter {
+        limiter.set_tier(user_id, tier);
+    }
+
+    info!("Admin {} put user {} on the {} rate limit tier.", admin.id, user_id, tier.as_str());
+
+    json!({
+        "tier": tier.as_str(),
+        "success": true,
+    })
+}
+
+/// Stores a failed login attempt so it can be reviewed later.
+/// Failing to store it doesn't stop the login from being answered.
+fn record_failed_login(conn: &SqliteConnection, name: &str, remote: Option<SocketAddr>, now: i64) {
+    let address = remote.map(|remote| remote.ip().to_string());
+
+    if let Err(why) = db::create_failed_login(conn, name, address, now) {
+        warn!("Could not record failed login: {}", why);
+    }
+}
+
+/// Lists failed login attempts, newest first.
+/// 
+/// If `?username=<text>` is given, only attempts whose username
+/// contains that text, ignoring case, are listed. `?from=<time>` and
+/// `?to=<time>` limit the attempts to a time range. Attempts are
+/// listed in pages of `?per_page=<n>` (25 by default, at most 100),
+/// and `?page=<n>` picks which page to return starting from 1.
+/// 
+/// Attempts are purged along with readings, so they are only kept
+/// for `retention_days` if that is set.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "attempts": [{
+///         "id": 12,
+///         "username": "Admin",
+///         "ip": "192.168.1.20",
+///         "timestamp": 1527539000
+///     }, {
+///         ...
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 12,
+///     "success": true
+/// }
+/// ```
+#[get("/api/admin/failed-logins")]
+fn failed_logins(options: Query<FailedLoginOptions>, links: PageLinks, conn: DbConn, _admin: AdminUser) -> JsonValue {
+    let options = options.0;
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+    let search = options.username.as_ref()
+        .map(|search| search.trim())
+        .and_then(|search| if search.is_empty() { None } else { Some(search) });
+
+    match db::list_failed_logins(&conn, search, options.from, options.to, (page - 1) * per_page, per_page) {
+        Ok((attempts, total)) => links.add_to(json!({
+            "attempts": attempts,
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "success": true,
+        }), page, per_page, total),
+        Err(why) => {
+            warn!("Could not list failed logins: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Lists events from the audit log, newest first.
+/// 
+/// Events are recorded for actions that change who can do what or
+/// that operators need to be able to trace back, such as an admin
+/// impersonating a user. `actor_id` is the user who did it, or null if
+/// the server did it on its own, and `node_id` and `target_user_id`
+/// are what it was done to.
+/// 
+/// `?action=<action>` only lists events with that action, and
+/// `?from=<time>` and `?to=<time>` limit the events to a time range.
+/// Events are listed in pages of `?per_page=<n>` (25 by default, at
+/// most 100), and `?page=<n>` picks which page to return starting
+/// from 1.
+/// 
+/// If the `Accept` header asks for `text/csv`, every matching event is
+/// exported as CSV instead, oldest first and without pages, for reading
+/// in a spreadsheet. The export is read from the database a page at a
+/// time while it is sent, so large ranges don't have to fit in memory.
+/// Fields with commas, quotes or line breaks in them are quoted, and
+/// text starting with `=`, `+`, `-` or `@` is prefixed with `'` so
+/// spreadsheets don't run it as a formula. An `Accept` header with no
+/// supported type gets a 406 Not Acceptable.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "events": [{
+///         "id": 41,
+///         "actor_id": 1,
+///         "action": "impersonate",
+///         "node_id": null,
+///         "target_user_id": 7,
+///         "detail": null,
+///         "timestamp": 1530880000
+///     }, {
+///         ...
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 41,
+///     "success": true
+/// }
+/// ```
+/// 
+/// Or as CSV:
+/// 
+/// ```text
+/// id,timestamp,actor_id,action,node_id,target_user_id,detail
+/// 41,1530880000,1,impersonate,,7,
+/// ```
+#[get("/api/audit")]
+fn audit_log(options: Query<AuditOptions>, format: Format, links: PageLinks, conn: DbConn, _admin: AdminUser) -> Negotiated {
+    let options = options.0;
+
+    if format == Format::Csv {
+        let action = options.action().map(String::from);
+        return Negotiated::CsvStream(Box::new(AuditCsv::new(conn, action, options.from, options.to)));
+    }
+
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+    let listed = db::list_audit_events(&conn, options.action(), options.from, options.to, false, (page - 1) * per_page, per_page)
+        .and_then(|events| Ok((events, db::count_audit_events(&conn, options.action(), options.from, options.to)?)));
+
+    match listed {
+        Ok((events, total)) => Negotiated::Json(links.add_to(json!({
+            "events": events,
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "success": true,
+        }), page, per_page, total)),
+        Err(why) => {
+            warn!("Could not list audit events: {}", why);
+            Negotiated::Json(json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Returns the configuration and tags of every xbee in the database
+/// so they can be restored later. Readings are not included.
+/// 
+/// The response can be sent to /api/restore as is.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// It can be turned off with the `backup` feature flag.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "xbees": [{
+///         "node_id": 1234,
+///         "name": "Temperature Sensor",
+///         "units": "C",
+///         "display_order": 0,
+///         "tags": ["greenhouse"]
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/backup")]
+fn backup(conn: DbConn, features: State<Features>, _admin: AdminUser) -> JsonValue {
+    if !features.enabled("backup") {
+        return feature_disabled();
+    }
+
+    match db::xbee_backups(&conn) {
+        Ok(backups) => json!({
+            "xbees": backups,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not create backup: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Lists the users on the server. Password hashes are never included.
+/// 
+/// If `?q=<text>` is given, only users whose username contains that
+/// text, ignoring case, are listed. Users are listed by username in
+/// pages of `?per_page=<n>` (25 by default, at most 100), and
+/// `?page=<n>` picks which page to return starting from 1. `total` is
+/// how many users match across every page.
+/// 
+/// Deleted users are left out unless `?include_deleted=true` is given.
+/// Their `deleted_at` is when they were deleted, and is null for
+/// everyone else.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "users": [{
+///         "id": 1,
+///         "username": "admin",
+///         "role": "admin",
+///         "deleted_at": null
+///     }, {
+///         ...
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 42,
+///     "success": true
+/// }
+/// ```
+#[get("/api/users")]
+fn list_users(options: Query<UserListOptions>, links: PageLinks, conn: DbConn, _admin: AdminUser) -> JsonValue {
+    let options = options.0;
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+    let search = options.q.as_ref()
+        .map(|search| search.trim())
+        .and_then(|search| if search.is_empty() { None } else { Some(search) });
+
+    let include_deleted = options.include_deleted.unwrap_or(false);
+
+    match db::search_users(&conn, search, include_deleted, (page - 1) * per_page, per_page) {
+        Ok((found, total)) => links.add_to(json!({
+            "users": found,
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "success": true,
+        }), page, per_page, total),
+        Err(why) => {
+            warn!("Could not list users: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Deletes a user. They can't log in anymore, and every session and
+/// token they had stops working, as do their API keys. Their account
+/// is kept rather than removed, so sent messages and other records
+/// still show who made them, and it can be brought back with
+/// /api/users/<user_id>/restore. The username stays taken until then,
+/// so it can't be given to someone else by mistake. Every deletion is
+/// logged and recorded in the audit log with the admin who made it.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// Admins can't delete themselves, and users that are already deleted
+/// can't be deleted again. Either returns an error noting that. If no
+/// user with the given id exists, an error noting that will be
+/// returned with a 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[delete("/api/users/<user_id>")]
+fn delete_user(user_id: i32, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    if user_id == admin.id {
+        return status::Custom(Status::Ok, json!({
+            "error": "You can't delete your own account.",
+            "success": false,
+        }));
+    }
+
+    match users.find(user_id).select(deleted_at).first::<Option<i64>>(&*conn) {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return status::Custom(Status::Ok, json!({
+                "error": "That user is already deleted.",
+                "success": false,
+            }));
+        }
+        Err(diesel::result::Error::NotFound) => {
+            return status::Custom(Status::NotFound, json!({
+                "error": "No user with that id found.",
+                "success": false,
+            }));
+        }
+        Err(why) => {
+            warn!("Could not find user to delete: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    }
+
+    let now = clock.now();
+
+    match db::soft_delete_user(&conn, user_id, admin.id, now) {
+        Ok(_) => {
+            warn!("Admin {} deleted user {}.", admin.id, user_id);
+            status::Custom(Status::Ok, json!({
+                "id": user_id,
+                "deleted_at": now,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not delete user: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Brings back a user deleted through `DELETE /api/users/<user_id>`,
+/// with the same username, role and API keys they had. They have to
+/// log in again, since their old sessions stay ended. Every restore is
+/// logged and recorded in the audit log with the admin who made it.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the user isn't deleted, an error noting that will be returned.
+/// If no user with the given id exists, an error noting that will be
+/// returned with a 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[post("/api/users/<user_id>/restore")]
+fn restore_user(user_id: i32, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    match db::restore_user(&conn, user_id, admin.id, clock.now()) {
+        Ok(true) => {
+            warn!("Admin {} restored user {}.", admin.id, user_id);
+            status::Custom(Status::Ok, json!({
+                "id": user_id,
+                "success": true,
+            }))
+        }
+        Ok(false) => match users.find(user_id).select(id).first::<i32>(&*conn) {
+            Ok(_) => status::Custom(Status::Ok, json!({
+                "error": "That user isn't deleted.",
+                "success": false,
+            })),
+            Err(diesel::result::Error::NotFound) => status::Custom(Status::NotFound, json!({
+                "error": "No user with that id found.",
+                "success": false,
+            })),
+            Err(why) => {
+                warn!("Could not find user to restore: {}", why);
+                status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }))
+            }
+        },
+        Err(why) => {
+            warn!("Could not restore user: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Recreates the xbee configurations from a backup made by
+/// /api/backup. This is done in a single transaction, so either
+/// every xbee is restored or none are.
+/// 
+/// Xbees that already exist are overwritten with the backed up
+/// configuration, and their tags are replaced with the backed up
+/// tags. Backups made before tags were included leave tags alone.
+/// Xbees that are not in the backup are kept.
+/// 
+/// Large backups can be sent gzipped with `Content-Encoding: gzip`.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// It can be turned off with the `backup` feature flag.
+/// 
+/// # Errors
+/// If any xbee in the backup is invalid, an error describing why
+/// will be returned.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[post("/api/restore", format = "application/json", data = "<backup>")]
+fn restore(backup: Decoded<Backup>, conn: DbConn, features: State<Features>, clock: State<SharedClock>, admin: AdminUser) -> JsonValue {
+    if !features.enabled("backup") {
+        return feature_disabled();
+    }
+
+    match db::restore_xbees(&conn, &backup.xbees) {
+        Ok(restored) => {
+            for restored_xbee in &backup.xbees {
+                audit(&conn, admin.id, "node_restore", Some(restored_xbee.config.node_id), None, clock.now());
+            }
+
+            json!({
+                "restored": restored,
+                "success": true,
+            })
+        }
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => json!({
+                "error": invalid.to_string(),
+                "success": false,
+            }),
+            Err(why) => {
+                warn!("Could not restore backup: {}", why);
+                json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                })
+            }
+        },
+    }
+}
+
+/// Returns the alert state of every active node, for an overview of
+/// which nodes need attention.
+/// 
+/// A node is `alerting` if its latest value is below its `min_alert`
+/// or above its `max_alert`, and `stale` if it hasn't reported for
+/// longer than its report interval. A node that was alerting keeps
+/// alerting until its value is back within its thresholds by its
+/// `alert_hysteresis`, which is checked over its latest 100 readings.
+/// `latest_reading` is the node's latest value, or null if it has no
+/// reading yet. A node whose alerts are muted isn't alerting until the
+/// mute ends.
+/// 
+/// An alerting node is `acknowledged` if someone acknowledged its alert
+/// through /api/alerts/<node>/ack since it started alerting. Passing
+/// `?acknowledged=false` leaves out acknowledged nodes, so only the
+/// ones that still need someone to look at them are left, and
+/// `?acknowledged=true` lists only them.
+/// 
+/// Alerting nodes are listed first, then stale ones, then the rest.
+/// Each group is in the same order as /api/list.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "nodes": [{
+///         "node_id": 2,
+///         "name": "Test",
+///         "alerting": true,
+///         "acknowledged": false,
+///         "stale": false,
+///         "latest_reading": 123.9,
+///         "thresholds": {
+///             "min_alert": 5.0,
+///             "max_alert": 100.0
+///         }
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/alerts/summary")]
+fn alert_summary(options: Query<AlertSummaryOptions>, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let recent = match db::recent_readings(&conn, ALERT_STATE_READINGS) {
+        Ok(recent) => recent,
+        Err(why) => {
+            warn!("Could not get latest readings: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let acks = match db::alert_acks(&conn) {
+        Ok(acks) => acks,
+        Err(why) => {
+            warn!("Could not get alert acknowledgements: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let mut nodes = info.nodes();
+    sort::sort_nodes(&mut nodes, &[], |node| {
+        configs.get(&node)
+            .map(|config| config.display_order)
+            .unwrap_or(i32::max_value())
+    });
+
+    let now = clock.now();
+    let mut summaries = nodes.into_iter()
+        .map(|node| {
+            let config = configs.get(&node.uuid);
+            let min_alert = config.and_then(|config| config.min_alert);
+            let max_alert = config.and_then(|config| config.max_alert);
+            let value = calibrated(config, node.value());
+
+            let readings = recent.get(&node.uuid).map_or(&[][..], |readings| &readings[..]);
+            let since = alerting_since(&node, readings, config, &settings, now);
+            let acknowledged = match (since, acks.get(&node.uuid)) {
+                (Some(since), Some(ack)) => ack.acknowledged_at >= since,
+                _ => false,
+            };
+
+            AlertSummary {
+                node_id: node.uuid,
+                alerting: since.is_some(),
+                acknowledged: acknowledged,
+                stale: is_stale(&node, config, settings.stale_after_secs, now),
+                latest_reading: value,
+                thresholds: Thresholds {
+                    min_alert: min_alert,
+                    max_alert: max_alert,
+                },
+                name: node.name,
+            }
+        })
+        .filter(|summary| options.0.acknowledged.map_or(true, |acknowledged| summary.acknowledged == acknowledged))
+        .collect::<Vec<AlertSummary>>();
+
+    //  The sort is stable, so each group keeps the list order.
+    summaries.sort_by_key(|summary| (!summary.alerting, !summary.stale));
+
+    json!({
+        "nodes": summaries,
+        "success": true,
+    })
+}
+
+/// Acknowledges a node's current alert, so operators can tell which
+/// alerts someone is already looking at. This doesn't change whether
+/// the node is alerting. The acknowledgement lasts until the node stops
+/// alerting, so it has to be acknowledged again if it starts again.
+/// 
+/// Alerts aren't stored, so they are named by their node's id.
+/// Acknowledgements are logged and recorded in the audit log with who
+/// made them.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, an error is returned with a
+/// 404 Not Found status. If it isn't alerting, an error saying so is
+/// returned.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "acknowledged_at": 1528880000,
+///     "success": true
+/// }
+/// ```
+#[post("/api/alerts/<node>/ack")]
+fn acknowledge_alert(node: u32, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> status::Custom<JsonValue> {
+    let config = match db::xbee_config_map(&conn) {
+        Ok(mut configs) => match configs.remove(&node) {
+            Some(config) => config,
+            None => return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            })),
+        },
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let readings = match db::readings_for(&conn, &[node as i32], None, None, i64::from(ALERT_STATE_READINGS)) {
+        Ok(readings) => readings,
+        Err(why) => {
+            warn!("Could not get latest readings: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let alerting = info.get(node)
+        .map_or(false, |node_info| currently_alerting(&node_info, &readings, Some(&config), &settings, clock.now()));
+
+    if !alerting {
+        return status::Custom(Status::Ok, json!({
+            "error": format!("Node {} is not alerting.", node),
+            "success": false,
+        }));
+    }
+
+    let ack = AlertAck {
+        node_id: node as i32,
+        acknowledged_by: user.id,
+        acknowledged_at: clock.now(),
+    };
+
+    match db::acknowledge_alert(&conn, &ack) {
+        Ok(_) => {
+            info!("User {} acknowledged the alert of node {}.", user.id, node);
+            status::Custom(Status::Ok, json!({
+                "node_id": node,
+                "acknowledged_at": ack.acknowledged_at,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not acknowledge alert: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Acknowledges the current alerts of several nodes at once, such as
+/// when clearing an alert storm. Every acknowledgement is recorded in a
+/// single transaction with who made it and when, so either every alert
+/// is acknowledged or none are. Each one is also recorded in the audit
+/// log.
+/// 
+/// This endpoint takes `alert_ids` or `node_ids`, or both. Alerts
+/// aren't stored, so they are named by their node's id and both lists
+/// hold node ids. A node has at most one current alert, so each id is
+/// only acknowledged once.
+/// 
+/// The number of alerts that were acknowledged is returned in
+/// `acknowledged`. Ids whose alert was already acknowledged are
+/// skipped and returned in `already_acknowledged`, and ids of nodes
+/// that aren't in the database or aren't alerting are skipped and
+/// returned in `missing`.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If no ids are given, an error is returned. If any database error
+/// occurs it will return a generic error, and nothing is acknowledged.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678, 9012]
+/// }
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "acknowledged": 1,
+///     "acknowledged_at": 1528880000,
+///     "already_acknowledged": [5678],
+///     "missing": [9012],
+///     "success": true
+/// }
+/// ```
+#[post("/api/alerts/ack", format = "application/json", data = "<batch>")]
+fn acknowledge_alerts(batch: Json<AlertAckBatch>, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> JsonValue {
+    let batch = batch.into_inner();
+    let mut ids = batch.alert_ids;
+    ids.extend(batch.node_ids);
+    ids.sort();
+    ids.dedup();
+
+    if ids.is_empty() {
+        return json!({
+            "error": "No alert_ids or node_ids were given.",
+            "success": false,
+        });
+    }
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let recent = match db::recent_readings(&conn, ALERT_STATE_READINGS) {
+        Ok(recent) => recent,
+        Err(why) => {
+            warn!("Could not get latest readings: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let acks = match db::alert_acks(&conn) {
+        Ok(acks) => acks,
+        Err(why) => {
+            warn!("Could not get alert acknowledgements: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let now = clock.now();
+    let mut pending = Vec::new();
+    let mut already_acknowledged = Vec::new();
+    let mut missing = Vec::new();
+
+    for id in ids {
+        let since = match (configs.get(&id), info.get(id)) {
+            (Some(config), Some(node)) => {
+                let readings = recent.get(&id).map_or(&[][..], |readings| &readings[..]);
+                alerting_since(&node, readings, Some(config), &settings, clock.now())
+            }
+            _ => None,
+        };
+
+        match since {
+            Some(since) if acks.get(&id).map_or(false, |ack| ack.acknowledged_at >= since) => {
+                already_acknowledged.push(id);
+            }
+            Some(_) => pending.push(AlertAck {
+                node_id: id as i32,
+                acknowledged_by: user.id,
+                acknowledged_at: now,
+            }),
+            None => missing.push(id),
+        }
+    }
+
+    match db::acknowledge_alerts(&conn, &pending) {
+        Ok(acknowledged) => {
+            info!("User {} acknowledged the alerts of {} nodes.", user.id, acknowledged);
+            json!({
+                "acknowledged": acknowledged,
+                "acknowledged_at": now,
+                "already_acknowledged": already_acknowledged,
+                "missing": missing,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not acknowledge alerts: {}", why);
+            json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Mutes a node's alerts until the time given in `until`, such as
+/// during known maintenance. While it is muted the node isn't alerting
+/// anywhere alerts are shown, such as /api/alerts/summary, and its
+/// alerts can't be acknowledged. Its readings are still stored, and it
+/// isn't disabled. Muting a node that is already muted moves the end of
+/// the mute.
+/// 
+/// Mutes are logged and recorded in the audit log with who made them.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If `until` isn't in the future, or is more than a year away, an
+/// error is returned. If the node isn't in the database, an error saying so is returned with a 404
+/// Not Found status.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "until": 1529600000
+/// }
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "muted_until": 1529600000,
+///     "success": true
+/// }
+/// ```
+#[post("/api/xbee/<node>/mute", format = "application/json", data = "<mute>")]
+fn mute(node: u32, mute: Json<MuteRequest>, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> status::Custom<JsonValue> {
+    let until = mute.into_inner().until;
+
+    if until <= clock.now() {
+        return status::Custom(Status::Ok, json!({
+            "error": "Field 'until' must be in the future.",
+            "success": false,
+        }));
+    }
+
+    if until - clock.now() > MAX_MUTE_SECS {
+        return status::Custom(Status::Ok, json!({
+            "error": "Field 'until' must be within a year.",
+            "success": false,
+        }));
+    }
+
+    match db::set_muted_until(&conn, node as i32, Some(until), user.id, clock.now()) {
+        Ok(0) => status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        })),
+        Ok(_) => {
+            info!("User {} muted the alerts of node {} until {}.", user.id, node, until);
+            status::Custom(Status::Ok, json!({
+                "node_id": node,
+                "muted_until": until,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not mute node: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Unmutes a node's alerts before its mute ends. Unmuting a node that
+/// isn't muted does nothing.
+/// 
+/// Unmutes are logged and recorded in the audit log with who made
+/// them.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node isn't in the database, an error saying so is returned
+/// with a 404 Not Found status.
+#[post("/api/xbee/<node>/unmute")]
+fn unmute(node: u32, clock: State<SharedClock>, conn: DbConn, user: AuthedUser) -> status::Custom<JsonValue> {
+    match db::set_muted_until(&conn, node as i32, None, user.id, clock.now()) {
+        Ok(0) => status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        })),
+        Ok(_) => {
+            info!("User {} unmuted the alerts of node {}.", user.id, node);
+            status::Custom(Status::Ok, json!({
+                "node_id": node,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not unmute node: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Lists every webhook, oldest first. Nodes that start or stop
+/// alerting are posted to the hooks for them and the hooks without a
+/// `node_id`, which get every node's alerts.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "webhooks": [{
+///         "id": 1,
+///         "url": "https://example.com/hooks/sparkies",
+///         "node_id": null,
+///         "created_by": 1,
+///         "created_at": 1531384517
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/webhooks")]
+fn webhooks(conn: DbConn, _admin: AdminUser) -> JsonValue {
+    match db::all_webhooks(&conn) {
+        Ok(hooks) => json!({
+            "webhooks": hooks,
+            "success": true,
+        }),
+        Err(why) => {
+            warn!("Could not get webhooks: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Adds a webhook. Whenever a node starts or stops alerting, a JSON
+/// payload like the one below is posted to every webhook for it. Hooks
+/// without a `node_id` get the alerts of every node. Nothing is posted
+/// for a node while its alerts are muted, and a node that changed
+/// during its mute is posted once the mute ends.
+/// 
+/// Requests to a hook time out after 5 seconds. Hooks that can't be
+/// reached or answer with a 5xx status are tried up to 3 times in all.
+/// 
+/// Webhooks are recorded in the audit log with who added them.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If `url` isn't an HTTP or HTTPS URL an error is returned. If
+/// `node_id` is given and the node isn't in the database, an error
+/// saying so is returned with a 404 Not Found status.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "url": "https://example.com/hooks/sparkies",
+///     "node_id": 1234
+/// }
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "id": 1,
+///     "success": true
+/// }
+/// ```
+/// 
+/// And posts payloads like:
+/// 
+/// ```json
+/// {
+///     "event": "alert_started",
+///     "node_id": 1234,
+///     "name": "Greenhouse Sensor",
+///     "alerting": true,
+///     "value": 123.9,
+///     "timestamp": 1531384517,
+///     "test": false
+/// }
+/// ```
+#[post("/api/webhooks", format = "application/json", data = "<hook>")]
+fn create_webhook(hook: Json<WebhookRequest>, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    let hook = hook.into_inner();
+
+    if !webhook::valid_url(&hook.url) {
+        return status::Custom(Status::Ok, json!({
+            "error": "Field 'url' must be an HTTP or HTTPS URL.",
+            "success": false,
+        }));
+    }
+
+    if let Some(node) = hook.node_id {
+        match db::xbee_config(&conn, node) {
+            Ok(Some(_)) => {}
+            Ok(None) => return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            })),
+            Err(why) => {
+                warn!("Could not get xbee configuration: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    match db::create_webhook(&conn, &hook.url, hook.node_id, admin.id, clock.now()) {
+        Ok(hook_id) => {
+            info!("Admin {} added webhook {}.", admin.id, hook_id);
+            audit(&conn, admin.id, "webhook_add", hook.node_id, Some(hook.url), clock.now());
+            status::Custom(Status::Ok, json!({
+                "id": hook_id,
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not save webhook: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Deletes a webhook, so nothing is posted to it anymore.
+/// 
+/// Deletions are recorded in the audit log with who made them.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If there is no webhook with the given id, an error saying so is
+/// returned with a 404 Not Found status.
+#[delete("/api/webhooks/<hook_id>")]
+fn delete_webhook(hook_id: i32, clock: State<SharedClock>, conn: DbConn, admin: AdminUser) -> status::Custom<JsonValue> {
+    let hook = match db::find_webhook(&conn, hook_id) {
+        Ok(Some(hook)) => hook,
+        Ok(None) => return status::Custom(Status::NotFound, json!({
+            "error": "No webhook with that id found.",
+            "success": false,
+        })),
+        Err(why) => {
+            warn!("Could not get webhook: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    match db::delete_webhook(&conn, hook_id) {
+        Ok(_) => {
+            info!("Admin {} deleted webhook {}.", admin.id, hook_id);
+            audit(&conn, admin.id, "webhook_delete", hook.node_id, Some(hook.url), clock.now());
+            status::Custom(Status::Ok, json!({
+                "success": true,
+            }))
+        }
+        Err(why) => {
+            warn!("Could not delete webhook: {}", why);
+            status::Custom(Status::Ok, json!({
+                "error": "Error saving information to database.",
+                "success": false,
+            }))
+        }
+    }
+}
+
+/// Posts a test alert to a webhook and waits for the outcome, so an
+/// admin can check that it is reachable. The payload is the same as a
+/// real alert from the hook's node, or node 0 for hooks without one,
+/// with `test` set to true. It is delivered with the same timeout and
+/// retries as real alerts.
+/// 
+/// `status` is the HTTP status of the last attempt, or null if the hook
+/// couldn't be reached, in which case `error` says why. `latency_ms` is
+/// how long the last attempt took, and `delivered` is whether it got a
+/// 2xx status. Failing to deliver isn't an error, so `success` is
+/// still true.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If there is no webhook with the given id, an error saying so is
+/// returned with a 404 Not Found status.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "id": 1,
+///     "delivered": true,
+///     "status": 200,
+///     "latency_ms": 84,
+///     "attempts": 1,
+///     "error": null,
+///     "success": true
+/// }
+/// ```
+#[post("/api/webhooks/<hook_id>/test")]
+fn test_webhook(hook_id: i32, info: InfoSet, clock: State<SharedClock>, conn: DbConn, _admin: AdminUser) -> status::Custom<JsonValue> {
+    let hook = match db::find_webhook(&conn, hook_id) {
+        Ok(Some(hook)) => hook,
+        Ok(None) => return status::Custom(Status::NotFound, json!({
+            "error": "No webhook with that id found.",
+            "success": false,
+        })),
+        Err(why) => {
+            warn!("Could not get webhook: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let node_id = hook.node_id.map_or(0, |node| node as u32);
+    let name = info.get(node_id).map_or_else(|| "Test".to_string(), |node| node.name);
+    let payload = webhook::alert_payload(node_id, &name, true, None, clock.now(), true);
+
+    let delivery = webhook::deliver(&hook.url, &payload);
+    info!("Test delivery to webhook {} got {:?} after {} attempts.", hook_id, delivery.status, delivery.attempts);
+
+    status::Custom(Status::Ok, json!({
+        "id": hook_id,
+        "delivered": delivery.delivered(),
+        "status": delivery.status,
+        "latency_ms": delivery.latency_ms,
+        "attempts": delivery.attempts,
+        "error": delivery.error,
+        "success": true,
+    }))
+}
+
+/// Returns the times a node started or stopped alerting, oldest first,
+/// by checking the given readings, which are newest first, against
+/// its current thresholds. Readings taken while the node was muted,
+/// within any of the `(start, end)` pairs in `mutes`, are skipped.
+fn alert_transitions(node: &XbeeInfo, rows: Vec<Reading>, config: &Xbees, mutes: &[(i64, i64)], settings: &Settings) -> Vec<AlertTransition> {
+    let margin = alert_margin(Some(config), settings);
+    let mut was_alerting = false;
+    let mut transitions = Vec::new();
+
+    //  Rows are newest first, so walk them oldest first.
+    for row in rows.into_iter().rev() {
+        if mutes.iter().any(|&(start, end)| row.timestamp >= start && row.timestamp < end) {
+            continue;
+        }
+
+        let value = match calibrated(Some(config), node.scale(row.reading as u16)) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let alerting = alert_state(was_alerting, value, config.min_alert, config.max_alert, margin);
+
+        if alerting != was_alerting {
+            transitions.push(AlertTransition {
+                timestamp: row.timestamp,
+                alerting: alerting,
+                reading: value,
+            });
+            was_alerting = alerting;
+        }
+    }
+
+    transitions
+}
+
+/// Returns the times a node started or stopped alerting, newest first.
+/// 
+/// Alerts aren't stored, so they are found by checking the node's
+/// readings against its current thresholds. Only the latest 10000
+/// readings in the range are checked. If the node is alerting at the
+/// first reading checked, that counts as it starting to alert.
+/// Readings taken while the node was muted are skipped.
+/// 
+/// A node stops alerting once its value is back within its thresholds
+/// by its `alert_hysteresis`, or the `alert_hysteresis` setting in
+/// Rocket.toml if it has none, so values hovering around a threshold
+/// don't show up as many short alerts.
+/// 
+/// The optional `?from=<time>` and `?to=<time>` Unix timestamps limit
+/// the readings to a time range. Transitions are listed in pages of
+/// `?per_page=<n>` (25 by default, at most 100), and `?page=<n>` picks
+/// which page to return starting from 1.
+/// 
+/// A node without thresholds, or that hasn't sent its scaling since
+/// the server started, has no alerts and returns an empty page.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, an error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_id": 1234,
+///     "alerts": [{
+///         "timestamp": 1527539000,
+///         "alerting": false,
+///         "reading": 80.2
+///     }, {
+///         "timestamp": 1527538400,
+///         "alerting": true,
+///         "reading": 101.5
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 2,
+///     "success": true
+/// }
+/// ```
+#[get("/api/xbee/<node>/alerts")]
+fn alert_history(node: u32, options: Query<AlertHistoryOptions>, links: PageLinks, info: InfoSet, settings: State<Settings>, conn: DbConn, _user: AuthedUser) -> status::Custom<JsonValue> {
+    let options = options.0;
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+
+    let config = match db::xbee_config_map(&conn) {
+        Ok(mut configs) => match configs.remove(&node) {
+            Some(config) => config,
+            None => return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            })),
+        },
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let rows = match db::readings_for(&conn, &[node as i32], options.from, options.to, MAX_ALERT_READINGS) {
+        Ok(rows) => rows,
+        Err(why) => {
+            warn!("Could not get reading history: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let mutes = match db::mute_windows(&conn) {
+        Ok(mut windows) => windows.remove(&node).unwrap_or_default(),
+        Err(why) => {
+            warn!("Could not get mute history: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let transitions = match info.get(node) {
+        Some(node_info) => alert_transitions(&node_info, rows, &config, &mutes, &settings),
+        None => Vec::new(),
+    };
+
+    let total = transitions.len() as i64;
+    let alerts = transitions.into_iter()
+        .rev()
+        .skip(((page - 1) * per_page) as usize)
+        .take(per_page as usize)
+        .collect::<Vec<AlertTransition>>();
+
+    status::Custom(Status::Ok, links.add_to(json!({
+        "node_id": node,
+        "alerts": alerts,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+        "success": true,
+    }), page, per_page, total))
+}
+
+/// Returns one feed of everything that has been happening, newest
+/// first, for a dashboard that shows it all in one place. Each item
+/// has a `type` saying what it is and a `timestamp`:
+/// 
+/// * `alert` is a node starting or stopping alerting, found the same
+///   way as /api/xbee/<node>/alerts.
+/// * `ack` is a user acknowledging a node's alert. Only the latest
+///   acknowledgement of each node is kept.
+/// * `send` is a message sent to a node through /api/send.
+/// * `config` is a change to a node's configuration, such as adding,
+///   updating, muting or deleting it, from the audit log. `action`
+///   says which change it was and `actor_id` who made it.
+/// * `login` is a successful login, from the audit log.
+/// * `failed_login` is a failed login attempt. Logins and failed
+///   logins are only included for admins, the same as
+///   /api/admin/failed-logins.
+/// 
+/// `?types=<types>` picks which types to include, separated by commas,
+/// and includes every type the user can see by default. `?node=<id>`
+/// only includes events for that node, which leaves out logins and
+/// failed logins.
+/// The optional `?from=<time>` and `?to=<time>` Unix timestamps limit
+/// the events to a time range.
+/// 
+/// Events of every type are merged into one list by their timestamp.
+/// Events at the same second are listed alerts first, then
+/// acknowledgements, sends, configuration changes, logins and failed
+/// logins. They are listed in pages of `?per_page=<n>` (25 by default,
+/// at most 100), and `?page=<n>` picks which page to return starting
+/// from 1. `total` is how many events match across every page. Only
+/// the latest 10000 readings of each node within the time range are
+/// checked for alerts, and they are read for every node at once.
+/// 
+/// There are no per-node permissions yet, so any authorized user can
+/// see the events of every node.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If any of the types isn't known, an error listing the known ones
+/// is returned. If the node is not in the database, an error is
+/// returned with a 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "activity": [{
+///         "type": "send",
+///         "timestamp": 1527539200,
+///         "node_id": 1234,
+///         "content": "R",
+///         "status": "sent",
+///         "sender_id": 2
+///     }, {
+///         "type": "config",
+///         "timestamp": 1527538900,
+///         "node_id": 1234,
+///         "action": "mute",
+///         "actor_id": 2,
+///         "detail": "until 1527542500"
+///     }, {
+///         "type": "alert",
+///         "timestamp": 1527538400,
+///         "node_id": 1234,
+///         "alerting": true,
+///         "reading": 101.5
+///     }],
+///     "page": 1,
+///     "per_page": 25,
+///     "total": 3,
+///     "success": true
+/// }
+/// ```
+#[get("/api/activity")]
+fn activity(options: Query<ActivityOptions>, links: PageLinks, info: InfoSet, settings: State<Settings>, conn: DbConn, admin: Option<AdminUser>, _user: AuthedUser) -> status::Custom<JsonValue> {
+    let options = options.0;
+    let (page, per_page) = page_bounds(options.page, options.per_page);
+
+    let types = match options.types {
+        Some(ref types) => {
+            let types = types.split(',')
+                .map(|kind| kind.trim())
+                .filter(|kind| !kind.is_empty())
+                .collect::<Vec<&str>>();
+
+            if let Some(unknown) = types.iter().find(|kind| !ACTIVITY_TYPES.contains(*kind)) {
+                return status::Custom(Status::Ok, json!({
+                    "error": format!("Unknown activity type '{}'. Types are: {}.", unknown, ACTIVITY_TYPES.join(", ")),
+                    "success": false,
+                }));
+            }
+
+            types
+        }
+        None => ACTIVITY_TYPES.to_vec(),
+    };
+
+    let wants = |kind: &str| types.contains(&kind);
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    if let Some(node) = options.node {
+        if !configs.contains_key(&node) {
+            return status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            }));
+        }
+    }
+
+    let in_range = |timestamp: i64| {
+        options.from.map_or(true, |from| timestamp >= from) && options.to.map_or(true, |to| timestamp <= to)
+    };
+
+    //  Every event up to the end of the page is fetched from each
+    //  source, since any of them could be on the page once merged.
+    let needed = page * per_page;
+    let mut events = Vec::new();
+    let mut total = 0;
+
+    if wants("alert") {
+        let mut history = match db::recent_readings_between(&conn, MAX_ALERT_READINGS as u32, options.from, options.to) {
+            Ok(history) => history,
+            Err(why) => {
+                warn!("Could not get reading history: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        };
+
+        let mut mutes = match db::mute_windows(&conn) {
+            Ok(mutes) => mutes,
+            Err(why) => {
+                warn!("Could not get mute history: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        };
+
+        for (&node, config) in &configs {
+            if options.node.map_or(false, |wanted| wanted != node) {
+                continue;
+            }
+
+            let node_info = match info.get(node) {
+                Some(node_info) => node_info,
+                None => continue,
+            };
+
+            let rows = history.remove(&node).unwrap_or_default();
+            let node_mutes = mutes.remove(&node).unwrap_or_default();
+            let transitions = alert_transitions(&node_info, rows, config, &node_mutes, &settings);
+            total += transitions.len() as i64;
+            events.extend(transitions.into_iter().map(|transition| Activity::Alert {
+                timestamp: transition.timestamp,
+                node_id: node,
+                alerting: transition.alerting,
+                reading: transition.reading,
+            }));
+        }
+    }
+
+    if wants("ack") {
+        match db::alert_acks(&conn) {
+            Ok(acks) => {
+                let acks = acks.into_iter()
+                    .filter(|&(node, ref ack)| options.node.map_or(true, |wanted| wanted == node) && in_range(ack.acknowledged_at))
+                    .map(|(node, ack)| Activity::Ack {
+                        timestamp: ack.acknowledged_at,
+                        node_id: node,
+                        acknowledged_by: ack.acknowledged_by,
+                    })
+                    .collect::<Vec<Activity>>();
+
+                total += acks.len() as i64;
+                events.extend(acks);
+            }
+            Err(why) => {
+                warn!("Could not get alert acknowledgements: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    if wants("send") {
+        match db::list_sent_messages(&conn, options.node.map(|node| node as i32), options.from, options.to, 0, needed) {
+            Ok((sends, count)) => {
+                total += count;
+                events.extend(sends.into_iter().map(|sent| Activity::Send {
+                    timestamp: sent.timestamp,
+                    node_id: sent.node_id as u32,
+                    content: sent.content,
+                    status: sent.status,
+                    sender_id: sent.sender_id,
+                }));
+            }
+            Err(why) => {
+                warn!("Could not list sent messages: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    if wants("config") {
+        match db::list_audit_events_by_action(&conn, CONFIG_ACTIONS, options.node.map(|node| node as i32), options.from, options.to, needed) {
+            Ok((changes, count)) => {
+                total += count;
+                events.extend(changes.into_iter().map(|change| Activity::Config {
+                    timestamp: change.timestamp,
+                    node_id: change.node_id.map(|node| node as u32),
+                    action: change.action,
+                    actor_id: change.actor_id,
+                    detail: change.detail,
+                }));
+            }
+            Err(why) => {
+                warn!("Could not list configuration changes: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    if wants("login") && admin.is_some() && options.node.is_none() {
+        match db::list_audit_events_by_action(&conn, &["login"], None, options.from, options.to, needed) {
+            Ok((logins, count)) => {
+                total += count;
+                events.extend(logins.into_iter().map(|login| Activity::Login {
+                    timestamp: login.timestamp,
+                    user_id: login.actor_id,
+                    ip: login.detail,
+                }));
+            }
+            Err(why) => {
+                warn!("Could not list logins: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    if wants("failed_login") && admin.is_some() && options.node.is_none() {
+        match db::list_failed_logins(&conn, None, options.from, options.to, 0, needed) {
+            Ok((attempts, count)) => {
+                total += count;
+                events.extend(attempts.into_iter().map(|attempt| Activity::FailedLogin {
+                    timestamp: attempt.timestamp,
+                    username: attempt.username,
+                    ip: attempt.ip,
+                }));
+            }
+            Err(why) => {
+                warn!("Could not list failed logins: {}", why);
+                return status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                }));
+            }
+        }
+    }
+
+    //  The sort is stable, so events at the same second keep the order
+    //  their types were added in.
+    events.sort_by(|a, b| b.timestamp().cmp(&a.timestamp()));
+
+    let events = events.into_iter()
+        .skip(((page - 1) * per_page) as usize)
+        .take(per_page as usize)
+        .collect::<Vec<Activity>>();
+
+    status::Custom(Status::Ok, links.add_to(json!({
+        "activity": events,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+        "success": true,
+    }), page, per_page, total))
+}
+
+/// Returns a report on a node as a self-contained HTML page, which
+/// can be saved or emailed as a snapshot of how the node is doing. It
+/// shows the node's configuration, its current reading and value,
+/// whether it is alerting, and its latest readings.
+/// 
+/// Times are shown in UTC unless `?tz=<timezone>` is given with an
+/// IANA timezone name such as "America/Chicago". Values are rounded
+/// by the `precision` setting, if it's set.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, a JSON error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+#[get("/api/xbee/<node>/report")]
+fn node_report(node: u32, options: Query<ReportOptions>, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Result<Html<String>, status::Custom<JsonValue>> {
+    let config = match db::xbee_config_map(&conn) {
+        Ok(mut configs) => match configs.remove(&node) {
+            Some(config) => config,
+            None => return Err(status::Custom(Status::NotFound, json!({
+                "error": "No node with that id found.",
+                "success": false,
+            }))),
+        },
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return Err(status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    let (rows, ack) = match (db::readings_for(&conn, &[node as i32], None, None, i64::from(ALERT_STATE_READINGS)), db::alert_acks(&conn)) {
+        (Ok(rows), Ok(mut acks)) => (rows, acks.remove(&node)),
+        (Err(why), _) | (_, Err(why)) => {
+            warn!("Could not get node report data: {}", why);
+            return Err(status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    let node_info = info.get(node);
+    let timezone = options.0.tz.unwrap_or_else(|| "UTC".to_string());
+    let time = |timestamp: i64| report::escape(&format_timestamp(timestamp, &timezone));
+
+    let precision = settings.precision.map(|places| places.min(MAX_PRECISION));
+    let units = report::escape(&config.units);
+    let value = |value: f64| match precision {
+        Some(places) => format!("{} {}", round_to(value, places), units),
+        None => format!("{} {}", value, units),
+    };
+
+    let thresholds = match (config.min_alert, config.max_alert) {
+        (Some(min), Some(max)) => format!("Below {} or above {}", value(min), value(max)),
+        (Some(min), None) => format!("Below {}", value(min)),
+        (None, Some(max)) => format!("Above {}", value(max)),
+        (None, None) => "None".to_string(),
+    };
+
+    let coordinates = match (config.latitude, config.longitude) {
+        (Some(latitude), Some(longitude)) => Some(format!("{}, {}", latitude, longitude)),
+        _ => None,
+    };
+
+    let location = match (config.location_name.as_ref(), coordinates) {
+        (Some(name), Some(coordinates)) => format!("{} ({})", report::escape(name), coordinates),
+        (Some(name), None) => report::escape(name),
+        (None, Some(coordinates)) => coordinates,
+        (None, None) => "None".to_string(),
+    };
+
+    let (reading, current, last_update) = match node_info {
+        Some(ref node_info) => (
+            node_info.reading.map_or("None".to_string(), |reading| reading.to_string()),
+            calibrated(Some(&config), node_info.value()).map_or("None".to_string(), &value),
+            time(node_info.last_update),
+        ),
+        None => ("None".to_string(), "None".to_string(), "Not since the server started".to_string()),
+    };
+
+    let (alert_class, alert_status) = match node_info {
+        _ if config.min_alert.is_none() && config.max_alert.is_none() => ("", "No alert thresholds are set.".to_string()),
+        None => ("", "Unknown, since the node hasn't reported since the server started.".to_string()),
+        _ if muted_until(Some(&config), clock.now()).is_some() => {
+            ("", format!("Muted until {}.", time(config.muted_until.unwrap_or(0))))
+        }
+        Some(ref node_info) => match alerting_since(node_info, &rows, Some(&config), &settings, clock.now()) {
+            Some(since) => {
+                let status = match ack {
+                    Some(ref ack) if ack.acknowledged_at >= since => format!(
+                        "Alerting since {}, acknowledged by user {} at {}.",
+                        time(since), ack.acknowledged_by, time(ack.acknowledged_at),
+                    ),
+                    _ => format!("Alerting since {}.", time(since)),
+                };
+
+                ("alerting", status)
+            }
+            None => ("ok", "Not alerting.".to_string()),
+        },
+    };
+
+    let history = if rows.is_empty() {
+        "<tr><td colspan=\"3\">No readings yet.</td></tr>".to_string()
+    } else {
+        rows.iter()
+            .take(REPORT_READINGS)
+            .map(|row| {
+                let scaled = node_info.as_ref()
+                    .and_then(|node_info| calibrated(Some(&config), node_info.scale(row.reading as u16)))
+                    .map_or(String::new(), &value);
+
+                format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>", time(row.timestamp), row.reading, scaled)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    Ok(Html(report::render(report::NODE_REPORT, &[
+        ("name", report::escape(&config.name)),
+        ("node_id", node.to_string()),
+        ("generated", time(clock.now())),
+        ("units", units.clone()),
+        ("enabled", if config.enabled { "Yes" } else { "No" }.to_string()),
+        ("report_interval", config.report_interval_secs.map_or("None".to_string(), |secs| format!("{} seconds", secs))),
+        ("thresholds", thresholds),
+        ("hysteresis", value(alert_margin(Some(&config), &settings))),
+        ("location", location),
+        ("description", config.description.as_ref().map_or("None".to_string(), |description| report::escape(description))),
+        ("reading", reading),
+        ("value", current),
+        ("last_update", last_update),
+        ("alert_class", alert_class.to_string()),
+        ("alert_status", alert_status),
+        ("history", history),
+    ])))
+}
+
+/// How long clients of /api/xbee/<node>/tail wait before asking for
+/// new readings, in milliseconds.
+const TAIL_RETRY_MILLIS: u64 = 2000;
+
+/// The most readings sent in one response of /api/xbee/<node>/tail.
+/// Clients get the rest when they reconnect.
+const MAX_TAIL_READINGS: i64 = 100;
+
+/// Returns a node's new readings as server-sent events, so they can be
+/// watched live with an `EventSource` in the browser, such as while
+/// debugging a sensor.
+/// 
+/// The stream starts from now, so a client's first response only has
+/// the id of the latest reading and no readings. Each response after
+/// that has the node's readings stored since the `Last-Event-ID` the
+/// client sends, at most 100 at a time, then ends. Browsers reconnect
+/// after two seconds, so they get new readings as they come in without
+/// tying up a worker thread, and nothing is left open when they go
+/// away. Readings sent through /api/readings/batch are included when
+/// they are stored, whatever their timestamp.
+/// 
+/// Each event's data is the reading as JSON, with its calibrated
+/// `value`, which is null if the node hasn't sent its scaling since
+/// the server started. Clients following a node are counted in
+/// /api/subscribers.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the node is not in the database, a JSON error is returned with a
+/// 404 Not Found status.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```text
+/// retry: 2000
+/// 
+/// id: 5120
+/// data: {"node_id":1234,"reading":413,"value":123.9,"timestamp":1529660000}
+/// 
+/// ```
+#[get("/api/xbee/<node>/tail")]
+fn tail(node: u32, last: LastEventId, info: InfoSet, subscribers: State<Subscribers>, remote: Option<SocketAddr>, conn: DbConn, user: AuthedUser) -> Result<Content<String>, status::Custom<JsonValue>> {
+    let config = match db::xbee_config(&conn, node as i32) {
+        Ok(Some(config)) => config,
+        Ok(None) => return Err(status::Custom(Status::NotFound, json!({
+            "error": "No node with that id found.",
+            "success": false,
+        }))),
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return Err(status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    subscribers.touch(TAIL_STREAM, Some(node), user.id, remote.map(|remote| remote.ip()));
+
+    let mut events = format!("retry: {}\n\n", TAIL_RETRY_MILLIS);
+
+    //  A first connection only learns where the stream starts.
+    let after = match last.0 {
+        Some(after) => after.min(i32::max_value() as u64) as i32,
+        None => match db::latest_reading_id(&conn) {
+            Ok(latest) => {
+                events.push_str(&format!("id: {}\n\n", latest));
+                return Ok(Content(ContentType::new("text", "event-stream"), events));
+            }
+            Err(why) => {
+                warn!("Could not get the latest reading: {}", why);
+                return Err(status::Custom(Status::Ok, json!({
+                    "error": "Error getting information from database.",
+                    "success": false,
+                })));
+            }
+        },
+    };
+
+    let rows = match db::readings_after(&conn, node as i32, after, MAX_TAIL_READINGS) {
+        Ok(rows) => rows,
+        Err(why) => {
+            warn!("Could not get new readings: {}", why);
+            return Err(status::Custom(Status::Ok, json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })));
+        }
+    };
+
+    let node_info = info.get(node);
+
+    for row in rows {
+        let value = node_info.as_ref()
+            .and_then(|node_info| calibrated(Some(&config), node_info.scale(row.reading as u16)));
+        let data = json!({
+            "node_id": node,
+            "reading": row.reading,
+            "value": value,
+            "timestamp": row.timestamp,
+        });
+
+        events.push_str(&format!("id: {}\ndata: {}\n\n", row.id, data.0));
+    }
+
+    Ok(Content(ContentType::new("text", "event-stream"), events))
+}
+
+/// Returns the active nodes that haven't reported since the Unix
+/// timestamp given by `?since=<time>`, oldest update first, so quiet
+/// sensors can be found and checked.
+/// 
+/// If `since` isn't given, the `stale_after_secs` setting is used, so
+/// nodes that haven't reported for that long are listed. Unlike
+/// `stale` in /api/list, node report intervals aren't used here.
+/// `silent_secs` is how long ago each node last reported.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "since": 1527700000,
+///     "nodes": [{
+///         "node_id": 1234,
+///         "name": "Greenhouse",
+///         "units": "C",
+///         "last_update": 1527610000,
+///         "silent_secs": 96000
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/nodes/silent")]
+fn silent_nodes(options: Query<SilentOptions>, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, _user: AuthedUser) -> JsonValue {
+    let now = clock.now();
+    let since = options.0.since.unwrap_or(now - settings.stale_after_secs);
+
+    let mut nodes = info.nodes().into_iter()
+        .filter(|node| node.last_update < since)
+        .map(|node| SilentNode {
+            node_id: node.uuid,
+            silent_secs: now - node.last_update,
+            last_update: node.last_update,
+            name: node.name,
+            units: node.units,
+        })
+        .collect::<Vec<SilentNode>>();
+
+    nodes.sort_by_key(|node| (node.last_update, node.node_id));
+
+    json!({
+        "since": since,
+        "nodes": nodes,
+        "success": true,
+    })
+}
+
+/// Returns the nodes in the database that have never sent a reading,
+/// from the longest ago added, for finding nodes that were set up
+/// wrong. Unlike /api/nodes/silent, these nodes may never have
+/// reported at all. `silent_secs` is how long ago each node was added.
+/// 
+/// Readings older than their node's retention period are purged, so a
+/// node whose readings have all been purged is listed as well.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "nodes": [{
+///         "node_id": 1234,
+///         "name": "Greenhouse",
+///         "units": "C",
+///         "created_at": 1527610000,
+///         "silent_secs": 96000
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/nodes/never-reported")]
+fn unreported_nodes(clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let now = clock.now();
+
+    match db::unreported_nodes(&conn) {
+        Ok(nodes) => {
+            let nodes = nodes.into_iter()
+                .map(|node| json!({
+                    "node_id": node.node_id,
+                    "name": node.name,
+                    "units": node.units,
+                    "created_at": node.created_at,
+                    "silent_secs": now - node.created_at,
+                }))
+                .collect::<Vec<JsonValue>>();
+
+            json!({
+                "nodes": nodes,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not get unreported nodes: {}", why);
+            json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            })
+        }
+    }
+}
+
+/// Returns the nodes in the database that are missing metadata, for
+/// finishing setting them up. `?require=<fields>` is a comma separated
+/// list of what each node should have, out of `name`, `units`,
+/// `location`, `thresholds`, `report_interval` and `description`. It
+/// defaults to `name,units,location,thresholds`.
+/// 
+/// A node is missing its `location` unless it has both a latitude and
+/// longitude, and its `thresholds` unless it has a `min_alert` or a
+/// `max_alert`. Names, units and descriptions that are only whitespace
+/// count as missing. Each node lists what it is `missing`, in the order
+/// the fields were asked for. Nodes are in order of their ids.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If any required field isn't known, an error naming it is returned.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "required": ["name", "units", "location", "thresholds"],
+///     "nodes": [{
+///         "node_id": 1234,
+///         "name": "Greenhouse",
+///         "missing": ["location", "thresholds"]
+///     }, {
+///         ...
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/nodes/incomplete")]
+fn incomplete_nodes(options: Query<IncompleteOptions>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let mut required = Vec::new();
+
+    match options.0.require {
+        Some(ref require) => {
+            for field in require.split(',').map(|field| field.trim()).filter(|field| !field.is_empty()) {
+                if !METADATA_FIELDS.contains(&field) {
+                    return json!({
+                        "error": format!("Unknown field '{}'.", field),
+                        "success": false,
+                    });
+                }
+
+                if !required.contains(&field) {
+                    required.push(field);
+                }
+            }
+        }
+        None => required.extend_from_slice(DEFAULT_REQUIRED_FIELDS),
+    }
+
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let mut configs = configs.values().collect::<Vec<&Xbees>>();
+    configs.sort_by_key(|config| config.node_id);
+
+    let nodes = configs.into_iter()
+        .filter_map(|config| {
+            let missing = required.iter()
+                .filter(|field| missing_metadata(config, field))
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                return None;
+            }
+
+            Some(json!({
+                "node_id": config.node_id,
+                "name": config.name,
+                "missing": missing,
+            }))
+        })
+        .collect::<Vec<JsonValue>>();
+
+    json!({
+        "required": required,
+        "nodes": nodes,
+        "success": true,
+    })
+}
+
+/// How many nodes have the same units, as it is returned from the
+/// /api/stats/by-units endpoint.
+#[derive(Serialize)]
+struct UnitsCount {
+    units: String,
+    count: usize,
+    alerting: usize,
+    stale: usize,
+}
+
+/// Returns how many nodes in the database have each units, sorted by
+/// units, for showing what kinds of sensors there are.
+/// 
+/// Each group also counts how many of its nodes are `alerting` or
+/// `stale`, the same way /api/alerts/summary decides. Nodes that
+/// haven't sent their info since the server started are counted,
+/// but are neither.
+/// 
+/// This only takes two database queries, however many nodes there
+/// are.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "units": [{
+///         "units": "C",
+///         "count": 12,
+///         "alerting": 1,
+///         "stale": 2
+///     }, {
+///         "units": "kPa",
+///         "count": 3,
+///         "alerting": 0,
+///         "stale": 0
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/stats/by-units")]
+fn units_stats(info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    let configs = match db::xbee_config_map(&conn) {
+        Ok(configs) => configs,
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let recent = match db::recent_readings(&conn, ALERT_STATE_READINGS) {
+        Ok(recent) => recent,
+        Err(why) => {
+            warn!("Could not get latest readings: {}", why);
+            return json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            });
+        }
+    };
+
+    let now = clock.now();
+    let mut groups = HashMap::new();
+
+    for (&node, config) in &configs {
+        let group = groups.entry(config.units.clone())
+            .or_insert(UnitsCount {
+                units: config.units.clone(),
+                count: 0,
+                alerting: 0,
+                stale: 0,
+            });
+
+        group.count += 1;
+
+        if let Some(node) = info.get(node) {
+            let readings = recent.get(&node.uuid).map_or(&[][..], |readings| &readings[..]);
+
+            if currently_alerting(&node, readings, Some(config), &settings, clock.now()) {
+                group.alerting += 1;
+            }
+
+            if is_stale(&node, Some(config), settings.stale_after_secs, now) {
+                group.stale += 1;
+            }
+        }
+    }
+
+    let mut groups = groups.into_iter()
+        .map(|(_, group)| group)
+        .collect::<Vec<UnitsCount>>();
+    groups.sort_by(|a, b| a.units.cmp(&b.units));
+
+    json!({
+        "units": groups,
+        "success": true,
+    })
+}
+
+/// The lowest, highest and mean value of a set of nodes, as it is
+/// returned from the /api/stats/batch endpoint.
+#[derive(Default, Serialize)]
+struct GroupStats {
+    count: i64,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    units: Option<String>,
+}
+
+/// A node's stats along with the raw readings they were scaled from,
+/// as it is returned from the /api/stats/batch endpoint.
+#[derive(Default, Serialize)]
+struct NodeStats {
+    node_id: u32,
+    count: i64,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    units: Option<String>,
+    min_reading: Option<i32>,
+    max_reading: Option<i32>,
+    mean_reading: Option<f64>,
+}
+
+/// Returns the lowest, highest and mean value of each of a group of
+/// nodes, and of the whole group, for comparing sensors side by side.
+/// This endpoint takes the `node_ids` of the nodes and an optional
+/// `from` and `to` to only count readings taken within that range.
+/// 
+/// The stats are worked out by the database from the raw readings, and
+/// are then scaled to values the same way as in /api/list. The raw
+/// stats are in `min_reading`, `max_reading` and `mean_reading`. The
+/// scaled ones are null if the node isn't active or its scaling isn't
+/// valid, and every stat is null for nodes without any readings in the
+/// range. Nodes are in the order they were asked for.
+/// 
+/// `overall` combines every node in the group, with `mean` weighted by
+/// how many readings each has. Values with different units can't be
+/// combined, so its `min`, `max` and `mean` are only given if every
+/// node with readings has the same units and can be scaled. `count`
+/// is always the total number of readings.
+/// 
+/// Requests cost the same as in /api/history/batch, and are turned away
+/// if they cost more than the `history_cost_budget` setting.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If the request costs more than the budget, an error saying so is
+/// returned with a 400 Bad Request status, along with the `cost` and
+/// `budget`.
+/// 
+/// If any database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "node_ids": [1234, 5678],
+///     "from": 1523568385,
+///     "to": 1523654785
+/// }
+/// ```
+/// 
+/// Which returns:
+/// 
+/// ```json
+/// {
+///     "nodes": [{
+///         "node_id": 1234,
+///         "count": 1440,
+///         "min": 18.3,
+///         "max": 27.9,
+///         "mean": 22.4,
+///         "units": "C",
+///         "min_reading": 61,
+///         "max_reading": 93,
+///         "mean_reading": 74.67
+///     }, {
+///         ...
+///     }],
+///     "overall": {
+///         "count": 2880,
+///         "min": 17.1,
+///         "max": 27.9,
+///         "mean": 22.0,
+///         "units": "C"
+///     },
+///     "success": true
+/// }
+/// ```
+#[post("/api/stats/batch", format = "application/json", data = "<request>")]
+fn batch_stats(request: Json<StatsRequest>, info: InfoSet, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _user: AuthedUser) -> Result<JsonValue, status::Custom<JsonValue>> {
+    let nodes = request.node_ids.iter()
+        .map(|&node| node as i32)
+        .collect::<Vec<i32>>();
+
+    check_history_cost(&nodes, request.from, request.to, &settings, clock.now(), &conn)?;
+
+    let rows = match db::reading_stats(&conn, &nodes, request.from, request.to) {
+        Ok(rows) => rows.into_iter()
+            .map(|row| (row.node_id as u32, row))
+            .collect::<HashMap<u32, ReadingStats>>(),
+        Err(why) => {
+            warn!("Could not get reading stats: {}", why);
+            return Ok(json!({
+                "error": "Error getting information from database.",
+                "success": false,
+            }));
+        }
+    };
+
+    let mut distinct = request.node_ids.clone();
+    distinct.sort();
+    distinct.dedup();
+
+    let stats = request.node_ids.iter()
+        .map(|&node| {
+            let row = match rows.get(&node) {
+                Some(row) => row,
+                None => {
+                    return NodeStats {
+                        node_id: node,
+                        ..NodeStats::default()
+                    };
+                }
+            };
+
+            //  Scaling is linear, so the mean reading scales to the mean
+            //  value, but a falling value range swaps the lowest and highest.
+            let scaled = info.get(node).and_then(|node| {
+                match (node.scale(row.min_reading as u16), node.scale(row.max_reading as u16), node.scale(0)) {
+                    (Some(low), Some(high), Some(zero)) => Some((
+                        low.min(high),
+                        low.max(high),
+                        zero + row.mean_reading / 100.0 * node.value_per_volt(),
+                        node.units,
+                    )),
+                    _ => None,
+                }
+            });
+
+            NodeStats {
+                node_id: node,
+                count: row.count,
+                min: scaled.as_ref().map(|scaled| scaled.0),
+                max: scaled.as_ref().map(|scaled| scaled.1),
+                mean: scaled.as_ref().map(|scaled| scaled.2),
+                units: scaled.map(|scaled| scaled.3),
+                min_reading: Some(row.min_reading),
+                max_reading: Some(row.max_reading),
+                mean_reading: Some(row.mean_reading),
+            }
+        })
+        .collect::<Vec<NodeStats>>();
+
+    //  Each node only counts once towards the group, however many
+    //  times it was asked for.
+    let counted = distinct.iter()
+        .filter_map(|node| stats.iter().find(|stats| stats.node_id == *node))
+        .filter(|stats| stats.count > 0)
+        .collect::<Vec<&NodeStats>>();
+
+    let mut overall = GroupStats {
+        count: counted.iter().map(|stats| stats.count).sum(),
+        ..GroupStats::default()
+    };
+
+    //  Only nodes that can be scaled have units, so if they all share
+    //  them then every node has a min, max and mean.
+    let units = counted.first().and_then(|stats| stats.units.clone());
+
+    if units.is_some() && counted.iter().all(|stats| stats.units == units) {
+        overall.min = Some(counted.iter()
+            .filter_map(|stats| stats.min)
+            .fold(::std::f64::INFINITY, f64::min));
+        overall.max = Some(counted.iter()
+            .filter_map(|stats| stats.max)
+            .fold(::std::f64::NEG_INFINITY, f64::max));
+        overall.mean = Some(counted.iter()
+            .filter_map(|stats| stats.mean.map(|mean| mean * stats.count as f64))
+            .sum::<f64>() / overall.count as f64);
+        overall.units = units;
+    }
+
+    Ok(json!({
+        "nodes": stats,
+        "overall": overall,
+        "success": true,
+    }))
+}
+
+/// Sets the alert thresholds of every node with the given units at
+/// once, returning how many nodes were updated.
+/// 
+/// Thresholds are in the same units as the nodes' values. A threshold
+/// that is null or missing is cleared. Units must match exactly.
+/// 
+/// **Note**: This endpoint requires that the user is authorized.
+/// 
+/// # Errors
+/// If `min_alert` is greater than `max_alert`, an error will be
+/// returned and no nodes are updated.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "units": "C",
+///     "min_alert": 5.0,
+///     "max_alert": 35.0
+/// }
+/// ```
+#[post("/api/alerts/by-units", format = "application/json", data = "<thresholds>")]
+fn thresholds_by_units(thresholds: Json<ThresholdsByUnits>, conn: DbConn, _user: AuthedUser) -> JsonValue {
+    match db::set_thresholds_by_units(&conn, &thresholds.units, thresholds.min_alert, thresholds.max_alert) {
+        Ok(updated) => json!({
+            "updated": updated,
+            "success": true,
+        }),
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => json!({
+                "error": invalid.to_string(),
+                "success": false,
+            }),
+            Err(why) => {
+                warn!("Could not set alert thresholds: {}", why);
+                json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                })
+            }
+        },
+    }
+}
+
+/// Moves readings that were saved under the wrong node to the right
+/// one, returning how many readings were moved.
+/// 
+/// This endpoint takes the node the readings are under now, the node
+/// they should be under, and the time range they were taken in. Both
+/// ends of the range are inclusive. The readings are moved in a single
+/// transaction.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the nodes are the same, the range ends before it starts, or
+/// either node is not in the database, an error describing why will
+/// be returned and nothing is moved.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "from_node": 1234,
+///     "to_node": 5678,
+///     "from_time": 1525132800,
+///     "to_time": 1525219200
+/// }
+/// ```
+#[post("/api/readings/reassign", format = "application/json", data = "<reassignment>")]
+fn reassign(reassignment: Json<Reassignment>, conn: DbConn, _admin: AdminUser) -> JsonValue {
+    if reassignment.from_node == reassignment.to_node {
+        return json!({
+            "error": "Fields 'from_node' and 'to_node' must be different.",
+            "success": false,
+        });
+    }
+
+    if reassignment.from_time > reassignment.to_time {
+        return json!({
+            "error": "Field 'from_time' must not be after 'to_time'.",
+            "success": false,
+        });
+    }
+
+    let result = db::reassign_readings(
+        &conn,
+        reassignment.from_node as i32,
+        reassignment.to_node as i32,
+        reassignment.from_time,
+        reassignment.to_time);
+
+    match result {
+        Ok(moved) => json!({
+            "moved": moved,
+            "success": true,
+        }),
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => json!({
+                "error": invalid.to_string(),
+                "success": false,
+            }),
+            Err(why) => {
+                warn!("Could not reassign readings: {}", why);
+                json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                })
+            }
+        },
+    }
+}
+
+/// Merges two nodes that turned out to be the same sensor. Everything
+/// of the source node is moved to the target, and then the source is
+/// deleted. This is done in a single transaction, so either the whole
+/// merge happens or none of it does.
+/// 
+/// The rules for what the target ends up with are:
+/// 
+/// * Readings and sent messages of the source are moved to it, and
+///   how many were moved is returned in `moved_readings` and
+///   `moved_sends`.
+/// * It keeps its own name, units, display order and whether it is
+///   enabled.
+/// * It takes the source's report interval and retention period if it
+///   has none, and the source's alert thresholds if it has neither.
+/// * It gets every tag the source had.
+/// * A pending deletion of the source is dropped.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// If the nodes are the same or either node is not in the database,
+/// an error describing why will be returned and nothing is changed.
+/// 
+/// If any other database error occurs it will return a generic error.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "source": 1234,
+///     "target": 5678
+/// }
+/// ```
+#[post("/api/xbee/merge", format = "application/json", data = "<merge>")]
+fn merge_nodes(merge: Json<NodeMerge>, conn: DbConn, clock: State<SharedClock>, admin: AdminUser) -> JsonValue {
+    if merge.source == merge.target {
+        return json!({
+            "error": "Fields 'source' and 'target' must be different.",
+            "success": false,
+        });
+    }
+
+    match db::merge_xbees(&conn, merge.source as i32, merge.target as i32) {
+        Ok((moved_readings, moved_sends)) => {
+            audit(&conn, admin.id, "node_merge", Some(merge.source as i32), Some(format!("into {}", merge.target)), clock.now());
+            json!({
+                "moved_readings": moved_readings,
+                "moved_sends": moved_sends,
+                "success": true,
+            })
+        }
+        Err(why) => match why.downcast::<db::ValidationError>() {
+            Ok(invalid) => json!({
+                "error": invalid.to_string(),
+                "success": false,
+            }),
+            Err(why) => {
+                warn!("Could not merge nodes: {}", why);
+                json!({
+                    "error": "Error saving information to database.",
+                    "success": false,
+                })
+            }
+        },
+    }
+}
+
+/// Returns the settings the server is running with, so operators
+/// can check that their configuration took effect.
+/// 
+/// Secrets are never included. Whether tokens can be issued is
+/// reported instead of the JWT secret, and the database URL and
+/// cookie secret key are left out entirely.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "config": {
+///         "features": {
+///             "add": true,
+///             "backup": true,
+///             "registration": true,
+///             "send": false
+///         },
+///         "tokens_enabled": true,
+///         "jwt_lifetime": 86400,
+///         "precision": 2,
+///         "retention_days": 30,
+///         "stale_after_secs": 5,
+///         "default_units": "C",
+///         "log_request_bodies": false,
+///         "echo_acting_user": false,
+///         "require_https": true,
+///         "static_dir": "static",
+///         "reserved_usernames": ["admin", "root", "system", "api"],
+///         "allow_registration": false,
+///         "session_window_secs": 604800,
+///         "session_max_secs": 2592000,
+///         "legacy_sessions_until": null,
+///         "logout_on_password_reset": true,
+///         "deletion_approval": false,
+///         "deletion_window_secs": 86400,
+///         "history_cost_budget": 36500,
+///         "power_low_fraction": 0.25,
+///         "power_critical_fraction": 0.1,
+///         "alert_hysteresis": 0.0,
+///         "bcrypt_cost": 12,
+///         "atomic_ingest": false,
+///         "auto_register": false,
+///         "min_reading_interval_secs": null,
+///         "json_field_case": "snake_case"
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/config")]
+fn config(features: State<Features>, settings: State<Settings>, _admin: AdminUser) -> JsonValue {
+    json!({
+        "config": {
+            "features": features.effective(),
+            "tokens_enabled": settings.jwt_secret.is_some(),
+            "jwt_lifetime": settings.jwt_lifetime,
+            "precision": settings.precision,
+            "retention_days": settings.retention_days,
+            "stale_after_secs": settings.stale_after_secs,
+            "default_units": settings.default_units,
+            "log_request_bodies": settings.log_request_bodies,
+            "echo_acting_user": settings.echo_acting_user,
+            "require_https": settings.require_https,
+            "static_dir": settings.static_dir,
+            "reserved_usernames": settings.reserved_usernames,
+            "allow_registration": settings.allow_registration,
+            "session_window_secs": settings.session_window_secs,
+            "session_max_secs": settings.session_max_secs,
+            "legacy_sessions_until": settings.legacy_sessions_until,
+            "logout_on_password_reset": settings.logout_on_password_reset,
+            "deletion_approval": settings.deletion_approval,
+            "deletion_window_secs": settings.deletion_window_secs,
+            "history_cost_budget": settings.history_cost_budget,
+            "power_low_fraction": settings.power_low_fraction,
+            "power_critical_fraction": settings.power_critical_fraction,
+            "alert_hysteresis": settings.alert_hysteresis,
+            "bcrypt_cost": settings.bcrypt_cost,
+            "atomic_ingest": settings.atomic_ingest,
+            "auto_register": settings.auto_register,
+            "min_reading_interval_secs": settings.min_reading_interval_secs,
+            "json_field_case": settings.json_field_case.as_str(),
+        },
+        "success": true,
+    })
+}
+
+/// Checks the scaling of every active node and lists the ones that
+/// are misconfigured, so they can be fixed before they show up as
+/// null values on the dashboard. Nothing is changed.
+/// 
+/// Each node in `invalid` has the `problems` found with it:
+/// `empty_voltage_range` or `inverted_voltage_range` mean its readings
+/// can't be scaled, the same check that makes `scaling_valid` false
+/// in /api/list. `inverted_value_range` means its values go down as
+/// its readings go up, which is usually a mistake. Nodes are checked
+/// as their info packets arrive too, and problems are logged then.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "checked": 5,
+///     "invalid": [{
+///         "node_id": 3,
+///         "name": "Soil Moisture",
+///         "problems": ["inverted_voltage_range"]
+///     }],
+///     "success": true
+/// }
+/// ```
+#[get("/api/admin/validate")]
+fn validate_nodes(info: InfoSet, _admin: AdminUser) -> JsonValue {
+    let mut nodes = info.nodes();
+    nodes.sort_by_key(|node| node.uuid);
+
+    let mut invalid = Vec::new();
+    let mut ids = Vec::new();
+
+    for node in &nodes {
+        let problems = node.scaling_problems();
+        if problems.is_empty() {
+            continue;
+        }
+
+        ids.push(node.uuid.to_string());
+        invalid.push(json!({
+            "node_id": node.uuid,
+            "name": node.name,
+            "problems": problems,
+        }));
+    }
+
+    if !ids.is_empty() {
+        warn!("Nodes with bad scaling: {}", ids.join(", "));
+    }
+
+    json!({
+        "checked": nodes.len(),
+        "invalid": invalid,
+        "success": true,
+    })
+}
+
+/// Deletes every reading and failed login attempt older than a
+/// number of days, returning how many of each were removed.
+/// 
+/// The number of days is given by `?days=<n>`, or by the
+/// `retention_days` setting in Rocket.toml if it isn't passed.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Errors
+/// Every node is purged the same way here, so nodes' own retention
+/// periods are ignored. They are only used by the hourly purge.
+/// 
+/// If no number of days is passed or configured, or it isn't
+/// positive, an error will be returned.
+/// 
+/// If a database error occurs it will return a generic error.
+#[post("/api/admin/purge")]
+fn purge(options: Query<PurgeOptions>, settings: State<Settings>, clock: State<SharedClock>, conn: DbConn, _admin: AdminUser) -> JsonValue {
+    let days = match options.0.days.or(settings.retention_days) {
+        Some(days) if days > 0 => days,
+        _ => {
+            return json!({
+                "error": "A positive number of days must be given.",
+                "success": false,
+            });
+        }
+    };
+
+    let cutoff = clock.now() - days * 60 * 60 * 24;
 
-/// Controls how an authorized user's requests are handled.
-/// If a user is authenticated, it will succeed. Otherwise
-/// the request will be forwarded to another handler.
-impl<'a, 'r> FromRequest<'a, 'r> for AuthedUser {
-    type Error = ();
+    let purged = db::purge_readings_older_than(&conn, cutoff)
+        .and_then(|removed| {
+            db::purge_failed_logins_older_than(&conn, cutoff)
+                .map(|logins_removed| (removed, logins_removed))
+        });
 
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<AuthedUser, ()> {
-        match request.cookies().get_private("auth") {
-            Some(_) => Outcome::Success(AuthedUser),
-            None => Outcome::Forward(()),
+    match purged {
+        Ok((removed, logins_removed)) => {
+            info!("Purged {} readings and {} failed logins older than {} days.", removed, logins_removed, days);
+            json!({
+                "removed": removed,
+                "failed_logins_removed": logins_removed,
+                "success": true,
+            })
+        }
+        Err(why) => {
+            warn!("Could not purge readings: {}", why);
+            json!({
+                "error": "Error removing information from database.",
+                "success": false,
+            })
         }
     }
 }
 
-/// Sends the data given to the xbee network.
-/// 
-/// This endpoint takes JSON data that contains both the
-/// destination node's id and the content of the message.
+/// This endpoint removes the authentication cookie. Once
+/// called, a user can no longer access authenticated endpoints.
 /// 
-/// **Note**: This endpoint requires that the user is authorized.
+/// Logging out is idempotent. It always succeeds, even if the
+/// user is not logged in or has already logged out.
 /// 
-/// # Example
-/// ```json
-/// {
-///     "content": "Data to send",
-///     "dest": 1234
-/// }
-/// ```
-#[post("/api/send", format = "application/json", data = "<message>")]
-fn send(message: Json<Message>, _user: AuthedUser) -> JsonValue {
-    info!("JSON: {:?}", message);
+/// Logging out also ends an impersonation. JWTs are not stored by
+/// the server, so they stay valid until they expire.
+#[get("/api/logout")]
+fn logout(sessions: State<ActiveSessions>, mut cookies: Cookies) -> JsonValue {
+    session::end(&mut cookies, &sessions);
     json!({
-        "content": message.content.clone(),
         "success": true,
     })
 }
 
-/// A temporary endpoint that adds the given data to the database.
+/// Returns how many login sessions haven't expired. Each login counts
+/// as its own session, so a user logged in from two browsers is
+/// counted twice. JWTs and API keys aren't sessions, and aren't
+/// counted.
 /// 
-/// This endpoint takes JSON data that describes an Xbee. 
+/// Sessions are only known to the server once they have made a
+/// request since it started, and a session whose cookie was thrown
+/// away without logging out is counted until it would have expired.
 /// 
-/// **Note**: This endpoint requires that the user is authorized.
+/// **Note**: This endpoint requires that the user is an admin.
 /// 
 /// # Example
 /// ```json
 /// {
-///     "node_id": 1234,
-///     "name": "Temperature Sensor",
-///     "units": "C"
+///     "count": 3,
+///     "success": true
 /// }
 /// ```
-#[post("/api/add", format = "application/json", data = "<xbee>")]
-fn add(xbee: Json<NewXbee>, conn: DbConn, _user: AuthedUser) -> JsonValue {
-    db::create_xbee(&conn, xbee.node_id, &xbee.name, &xbee.units);
-
+#[get("/api/sessions/count")]
+fn session_count(sessions: State<ActiveSessions>, clock: State<SharedClock>, _admin: AdminUser) -> JsonValue {
     json!({
+        "count": sessions.count(clock.now()),
         "success": true,
     })
 }
 
-/// Returns a list of active nodes and their most recent values.
-/// This data will be returned as a JSON object where the xbee
-/// data is stored in an array.
+/// The name of the cookie used to check that private cookies work.
+const AUTH_PROBE_COOKIE: &str = "auth_probe";
+
+/// Checks that private cookies, which logins depend on, can be
+/// encrypted and read back with the configured `secret_key`.
 /// 
-/// **Note**: This endpoint requires that the user is authorized.
+/// A probe cookie holding only the current time is set and then read
+/// back straight away. The probe is removed again before responding,
+/// so clients only see it expire.
+/// 
+/// # Errors
+/// If the probe can't be read back, an error saying so is returned
+/// with a 503 Service Unavailable status.
 /// 
 /// # Example
 /// ```json
 /// {
-///     "nodes": [{
-///         "last_update": 1523568385,
-///         "max_value": 150.0,
-///         "max_voltage": 5.0,
-///         "min_value": 0.0,
-///         "min_voltage": 0.0,
-///         "name": "Test",
-///         "reading": 413,
-///         "units": "C",
-///         "uuid": 2
-///     }, {
-///         ...
-///     }],
 ///     "success": true
 /// }
 /// ```
-#[get("/api/list")]
-fn list_authed(info: InfoSet, _user: AuthedUser) -> JsonValue {
-    json!({
-        "nodes": info.nodes(),
-        "success": true,
-    })
-}
+#[get("/api/health/auth")]
+fn health_auth(clock: State<SharedClock>, mut cookies: Cookies) -> status::Custom<JsonValue> {
+    let probe = clock.now().to_string();
 
-/// This is an error handler for the /api/list endpoint
-/// that is called when the user is not authorized. No
-/// xbee data will be returned from this endpoint, just
-/// a simple JSON object that indicates failure.
-#[get("/api/list", rank = 2)]
-fn list_invalid() -> JsonValue {
-    json!({
-        "success": false,
-    })
+    cookies.add_private(Cookie::new(AUTH_PROBE_COOKIE, probe.clone()));
+    let read_back = cookies.get_private(AUTH_PROBE_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    cookies.remove_private(Cookie::named(AUTH_PROBE_COOKIE));
+
+    if read_back.as_ref() == Some(&probe) {
+        status::Custom(Status::Ok, json!({
+            "success": true,
+        }))
+    } else {
+        error!("Private cookies could not be read back. Check the secret key.");
+        status::Custom(Status::ServiceUnavailable, json!({
+            "error": "Private cookies could not be read back. Check `secret_key` in Rocket.toml.",
+            "success": false,
+        }))
+    }
 }
 
-/// This is a login endpoint for users to authenticate themselves.
-/// A username and password must be supplied in a JSON object as
-/// described below. Once a user is authenticated, a private cookie
-/// will be stored which will allow them to access endpoints that
-/// require authentication.
-/// 
-/// # Errors
-/// If the given username is not in the database, an error noting
-/// that will be returned.
-/// 
-/// If a valid username is given but the password is wrong, an error
-/// will be returned.
-/// 
-/// If any other database error occurs it will return a generic error.
-#[post("/api/login", format = "application/json", data = "<login>")]
-fn login(login: Json<Login>, conn: DbConn, mut cookies: Cookies) -> JsonValue {
-    //  Try to find a user in the database with the given username.
-    //  This query returns at most 1 result.
-    let res = users
-        .filter(username.eq(&login.user))
-        .get_result::<User>(&*conn);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+    use rocket;
+    use rocket::local::Client;
 
-    match res {
-        //  User was found, so now check the password.
-        Ok(user) => {
-            //  Password is stored as a bcrypt hash so we need to
-            //  ensure it is correct.
-            if let Ok(true) = bcrypt::verify(&login.pass, &user.password) {
-                //  Password matched hash, add authenticated cookie.
-                cookies.add_private(Cookie::new("auth", "true"));
+    use std::sync::Arc;
 
-                json!({
-                    "success": true,
-                })
-            } else {
-                //  Either the hash check failed, or the hash didn't match.
-                //  Either way, return invalid credentials.
-                json!({
-                    "error": "Invalid login credentials.",
-                    "success": false,
-                })
-            }
+    fn node_info(last_update: i64) -> XbeeInfo {
+        XbeeInfo {
+            uuid: 2,
+            name: "Test".to_string(),
+            units: "C".to_string(),
+            max_voltage: 5.0,
+            min_voltage: 0.0,
+            max_value: 150.0,
+            min_value: 0.0,
+            last_update: last_update,
+            reading: Some(413),
         }
-        //  User was not found in the database.
-        Err(diesel::result::Error::NotFound) => {
-            json!({
-                "error": "No user with that name found.",
-                "success": false,
+    }
+
+    fn config() -> Xbees {
+        Xbees {
+            id: 1,
+            node_id: 2,
+            name: "Test".to_string(),
+            units: "C".to_string(),
+            display_order: 0,
+            enabled: true,
+            report_interval_secs: None,
+            created_at: 1000,
+            min_alert: None,
+            max_alert: None,
+            retention_days: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            alert_hysteresis: None,
+            description: None,
+            min_reading_interval_secs: None,
+            color: None,
+            icon: None,
+            muted_until: None,
+            calibration_offset: None,
+            calibration_scale: None,
+            auto_registered: false,
+            updated_at: 1000,
+        }
+    }
+
+    #[test]
+    fn last_modified_follows_readings() {
+        let config = config();
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2010), 2000);
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), Some(2005), 60, 2010), 2005);
+    }
+
+    #[test]
+    fn last_modified_follows_config_changes() {
+        let mut config = config();
+        config.updated_at = 2008;
+
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2010), 2008);
+    }
+
+    #[test]
+    fn last_modified_follows_mutes_running_out() {
+        let mut config = config();
+        config.muted_until = Some(2009);
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2010), 2009);
+
+        //  A mute that is still going was set by a config change.
+        config.muted_until = Some(3000);
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2010), 2000);
+    }
+
+    #[test]
+    fn last_modified_follows_going_stale() {
+        let mut config = config();
+        assert!(!is_stale(&node_info(2000), Some(&config), 60, 2060));
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2060), 2000);
+
+        assert!(is_stale(&node_info(2000), Some(&config), 60, 2061));
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2100), 2061);
+
+        config.report_interval_secs = Some(10);
+        assert_eq!(node_last_modified(&node_info(2000), Some(&config), None, 60, 2100), 2011);
+    }
+
+    #[test]
+    fn far_off_timestamps_are_formatted_as_they_are() {
+        assert_eq!(format_timestamp(1523568385, "UTC"), "2018-04-12 21:26:25 UTC");
+        assert_eq!(format_timestamp(i64::max_value(), "UTC"), i64::max_value().to_string());
+    }
+
+    fn close(value: Option<f64>, expected: f64) -> bool {
+        value.map_or(false, |value| (value - expected).abs() < 1e-6)
+    }
+
+    #[test]
+    fn unit_conversion_converts_thresholds() {
+        let mut config = config();
+        config.min_alert = Some(10.0);
+        config.max_alert = Some(30.0);
+        config.alert_hysteresis = Some(2.0);
+
+        let converted = converted_values(&config, convert::conversion("C", "F").unwrap());
+        assert!(close(converted.min_alert, 50.0));
+        assert!(close(converted.max_alert, 86.0));
+        assert!(close(converted.alert_hysteresis, 3.6));
+        assert!(close(Some(converted.calibration_scale), 1.8));
+        assert!(close(Some(converted.calibration_offset), 32.0));
+    }
+
+    #[test]
+    fn unit_conversion_only_scales_hysteresis() {
+        let mut config = config();
+        config.max_alert = Some(212.0);
+        config.alert_hysteresis = Some(9.0);
+        config.calibration_scale = Some(2.0);
+        config.calibration_offset = Some(32.0);
+
+        let converted = converted_values(&config, convert::conversion("F", "C").unwrap());
+        assert_eq!(converted.min_alert, None);
+        assert!(close(converted.max_alert, 100.0));
+        assert!(close(converted.alert_hysteresis, 5.0));
+        assert!(close(Some(converted.calibration_scale), 2.0 * 5.0 / 9.0));
+        assert!(close(Some(converted.calibration_offset), 0.0));
+    }
+
+    #[test]
+    fn bursts_keep_the_first_reading_of_each_interval() {
+        let mut times = Vec::new();
+        let kept = (100..112)
+            .filter(|&time| add_if_spaced(&mut times, time, 5))
+            .collect::<Vec<i64>>();
+
+        assert_eq!(kept, vec![100, 105, 110]);
+        assert_eq!(times, vec![100, 105, 110]);
+    }
+
+    #[test]
+    fn readings_are_spaced_from_stored_ones_on_both_sides() {
+        let mut times = vec![100, 200];
+
+        assert!(!add_if_spaced(&mut times, 200, 10));
+        assert!(!add_if_spaced(&mut times, 95, 10));
+        assert!(!add_if_spaced(&mut times, 191, 10));
+        assert!(add_if_spaced(&mut times, 150, 10));
+        assert!(add_if_spaced(&mut times, 90, 10));
+        assert_eq!(times, vec![90, 100, 150, 200]);
+    }
+
+    fn alert_states(values: &[f64], margin: f64) -> Vec<bool> {
+        let mut alerting = false;
+
+        values.iter()
+            .map(|&value| {
+                alerting = alert_state(alerting, value, Some(10.0), Some(30.0), margin);
+                alerting
             })
+            .collect()
+    }
+
+    #[test]
+    fn hysteresis_stops_alerts_flapping() {
+        let values = [31.0, 29.5, 30.5, 29.0, 30.2, 27.9, 29.0, 30.1];
+
+        assert_eq!(alert_states(&values, 0.0), vec![true, false, true, false, true, false, false, true]);
+        assert_eq!(alert_states(&values, 2.0), vec![true, true, true, true, true, false, false, true]);
+    }
+
+    #[test]
+    fn hysteresis_applies_to_both_thresholds() {
+        let values = [9.0, 11.0, 11.9, 12.1, 11.0];
+
+        assert_eq!(alert_states(&values, 2.0), vec![true, true, true, false, false]);
+    }
+
+    #[test]
+    fn hysteresis_doesnt_start_alerts() {
+        assert_eq!(alert_states(&[29.0, 30.0, 28.5], 2.0), vec![false, false, false]);
+    }
+
+    fn settings(extra: &[(&str, Vec<&str>)]) -> Settings {
+        use rocket::config::{Config, Environment};
+
+        let mut config = Config::build(Environment::Development);
+
+        for &(name, ref value) in extra {
+            config = config.extra(name, value.clone());
         }
-        //  Another database error occurred.
-        Err(_) => {
-            json!({
-                "error": "Error getting information from database.",
-                "success": false,
+
+        Settings::from_config(&config.finalize().unwrap())
+    }
+
+    #[test]
+    fn registration_blocks_reserved_names_in_any_case() {
+        let settings = settings(&[]);
+
+        for name in &["admin", "ADMIN", " Root ", "sYsTeM", "api"] {
+            assert_eq!(registration_error(name, &settings), Some("That username is reserved."));
+        }
+
+        assert_eq!(registration_error("alice", &settings), None);
+        assert_eq!(registration_error("administrator", &settings), None);
+    }
+
+    #[test]
+    fn registration_uses_the_configured_reserved_names() {
+        let settings = settings(&[("reserved_usernames", vec!["Support"])]);
+
+        assert_eq!(registration_error("support", &settings), Some("That username is reserved."));
+        assert_eq!(registration_error("admin", &settings), None);
+    }
+
+    #[test]
+    fn registration_needs_a_username() {
+        assert_eq!(registration_error("  ", &settings(&[])), Some("Username must not be empty."));
+    }
+
+    #[get("/start")]
+    fn start_session(sessions: State<ActiveSessions>, settings: State<Settings>, clock: State<SharedClock>, mut cookies: Cookies) {
+        session::start(&mut cookies, &sessions, 1, None, &settings, clock.now());
+    }
+
+    #[get("/count")]
+    fn count_sessions(sessions: State<ActiveSessions>, clock: State<SharedClock>) -> String {
+        sessions.count(clock.now()).to_string()
+    }
+
+    fn session_client() -> Client {
+        session_client_at(&Arc::new(MockClock::new(0)))
+    }
+
+    fn session_client_at(mock: &Arc<MockClock>) -> Client {
+        let clock: SharedClock = mock.clone();
+        let rocket = rocket::ignite()
+            .mount("/", routes![start_session, count_sessions, logout])
+            .manage(ActiveSessions::default())
+            .manage(settings(&[]))
+            .manage(clock);
+
+        Client::new(rocket).unwrap()
+    }
+
+    fn counted_sessions(client: &Client) -> String {
+        client.get("/count").dispatch().body_string().unwrap()
+    }
+
+    fn log_out(client: &Client) -> serde_json::Value {
+        let mut response = client.get("/api/logout").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn logout_without_login_succeeds() {
+        let client = session_client();
+
+        assert_eq!(log_out(&client), json!({ "success": true }).0);
+        assert_eq!(counted_sessions(&client), "0");
+    }
+
+    #[test]
+    fn logging_out_twice_succeeds() {
+        let client = session_client();
+
+        client.get("/start").dispatch();
+        assert_eq!(counted_sessions(&client), "1");
+
+        assert_eq!(log_out(&client), json!({ "success": true }).0);
+        assert_eq!(counted_sessions(&client), "0");
+
+        assert_eq!(log_out(&client), json!({ "success": true }).0);
+        assert_eq!(counted_sessions(&client), "0");
+    }
+
+    #[test]
+    fn sessions_stop_counting_once_they_expire() {
+        let settings = settings(&[]);
+        let mock = Arc::new(MockClock::new(1000));
+        let client = session_client_at(&mock);
+
+        client.get("/start").dispatch();
+        mock.advance(settings.session_window_secs - 1);
+        assert_eq!(counted_sessions(&client), "1");
+
+        mock.advance(1);
+        assert_eq!(counted_sessions(&client), "0");
+    }
+
+    fn message(body: serde_json::Value) -> Message {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn empty_messages_are_rejected() {
+        for content in &["", " ", "\t\n "] {
+            let empty = message(json!({ "content": content, "dest": 2 }).0);
+            assert_eq!(empty.validate(), Err("Empty message".to_string()));
+        }
+
+        assert_eq!(message(json!({ "content": "hi", "dest": 2 }).0).validate(), Ok(()));
+    }
+
+    #[test]
+    fn messages_with_bad_conditions_are_rejected() {
+        let conditional = message(json!({
+            "content": "hi",
+            "dest": 2,
+            "condition": { "field": "name", "op": ">", "value": 1 },
+        }).0);
+
+        assert!(conditional.validate().is_err());
+    }
+
+    fn batch(entries: serde_json::Value) -> Vec<IngestReading> {
+        serde_json::from_value(entries).unwrap()
+    }
+
+    fn mixed_batch() -> Vec<IngestReading> {
+        batch(json!([
+            { "node_id": 2, "reading": 413, "timestamp": 1000 },
+            { "node_id": 9999, "reading": 200, "timestamp": 1000 },
+            { "node_id": 2, "reading": 70000, "timestamp": 1000 },
+            { "node_id": 2, "reading": -1, "timestamp": 1000 },
+            { "node_id": 2, "reading": 200, "timestamp": 3000 },
+            { "node_id": 2, "reading": 420 },
+        ]).0)
+    }
+
+    fn configs() -> HashMap<u32, Xbees> {
+        let mut configs = HashMap::new();
+        configs.insert(2, config());
+        configs
+    }
+
+    #[test]
+    fn mixed_batches_keep_the_valid_readings() {
+        let (valid, results) = check_batch(&mixed_batch(), &configs(), &mut HashMap::new(), &settings(&[]), 2000);
+
+        let saved = valid.iter()
+            .map(|reading| (reading.node_id, reading.reading, reading.timestamp))
+            .collect::<Vec<_>>();
+        assert_eq!(saved, vec![(2, 413, 1000), (2, 420, 2000)]);
+
+        let outcomes = results.iter()
+            .map(|result| (result.index, result.node_id, result.accepted, result.error.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, vec![
+            (0, 2, true, None),
+            (1, 9999, false, Some("No node with that id found.".to_string())),
+            (2, 2, false, Some("Reading must be between 0 and 65535.".to_string())),
+            (3, 2, false, Some("Reading must be between 0 and 65535.".to_string())),
+            (4, 2, false, Some("Timestamp must not be in the future.".to_string())),
+            (5, 2, true, None),
+        ]);
+    }
+
+    #[test]
+    fn mixed_batches_can_register_unknown_nodes() {
+        let mut settings = settings(&[]);
+        settings.auto_register = true;
+
+        let (valid, results) = check_batch(&mixed_batch(), &configs(), &mut HashMap::new(), &settings, 2000);
+
+        assert_eq!(valid.len(), 3);
+        assert!(results[1].accepted);
+    }
+
+    #[test]
+    fn atomic_batches_reject_every_reading() {
+        let (valid, mut results) = check_batch(&mixed_batch(), &configs(), &mut HashMap::new(), &settings(&[]), 2000);
+        assert_eq!(valid.len(), 2);
+
+        reject_batch(&mut results);
+
+        assert!(results.iter().all(|result| !result.accepted));
+        assert_eq!(results[0].error, Some("Another reading in the batch was rejected.".to_string()));
+        assert_eq!(results[1].error, Some("No node with that id found.".to_string()));
+    }
+
+    fn stored_readings(readings: &[i32]) -> Vec<Reading> {
+        //  Stored readings are newest first, one a minute.
+        readings.iter()
+            .rev()
+            .enumerate()
+            .map(|(index, &reading)| Reading {
+                id: index as i32,
+                node_id: 2,
+                reading: reading,
+                timestamp: 1000 - index as i64 * 60,
             })
+            .collect()
+    }
+
+    #[test]
+    fn alerts_check_calibrated_values() {
+        let settings = settings(&[]);
+        let node = node_info(1000);
+        let mut config = config();
+        config.max_alert = Some(100.0);
+
+        //  The reading of 413 scales to 123.9, which is above the
+        //  threshold, but the calibration brings it down to 23.9.
+        assert!(currently_alerting(&node, &[], Some(&config), &settings, 1000));
+        config.calibration_offset = Some(-100.0);
+        assert!(!currently_alerting(&node, &[], Some(&config), &settings, 1000));
+
+        //  100 and 413 scale to 30 and 123.9, so calibrated they are
+        //  -70 and 23.9, and neither is above the threshold.
+        let rows = stored_readings(&[100, 413]);
+        assert!(!currently_alerting(&node, &rows, Some(&config), &settings, 1000));
+        assert!(alert_transitions(&node, rows, &config, &[], &settings).is_empty());
+    }
+
+    #[test]
+    fn send_conditions_check_calibrated_values() {
+        let mut config = config();
+        config.calibration_scale = Some(2.0);
+
+        let condition = SendCondition {
+            field: "value".to_string(),
+            op: ">".to_string(),
+            value: 200.0,
+        };
+        let node = node_info(1000);
+
+        assert!(condition.check(Some(&node), None).is_err());
+        assert!(condition.check(Some(&node), Some(&config)).is_ok());
+    }
+
+    #[test]
+    fn alert_history_skips_muted_readings() {
+        let settings = settings(&[]);
+        let node = node_info(1000);
+        let mut config = config();
+        config.min_alert = Some(10.0);
+        config.max_alert = Some(30.0);
+        config.alert_hysteresis = Some(2.0);
+
+        //  These scale to 27, 31.5, 29.7, 28.5, 36 and 18 degrees C,
+        //  taken at 1000 back to 700.
+        let readings = [90, 105, 99, 95, 120, 60];
+        let states = |mutes: &[(i64, i64)]| alert_transitions(&node, stored_readings(&readings), &config, mutes, &settings)
+            .into_iter()
+            .map(|transition| (transition.timestamp, transition.alerting))
+            .collect::<Vec<(i64, bool)>>();
+
+        assert_eq!(states(&[]), vec![(760, true), (1000, false)]);
+        assert_eq!(states(&[(750, 800)]), vec![(940, true), (1000, false)]);
+        assert!(states(&[(750, 950)]).is_empty());
+    }
+
+    #[test]
+    fn muted_nodes_have_no_alert_state_for_webhooks() {
+        let settings = settings(&[]);
+        let node = node_info(1000);
+        let mut config = config();
+        config.max_alert = Some(30.0);
+
+        //  This scales to 36 degrees C.
+        let readings = stored_readings(&[120]);
+        assert_eq!(unmuted_alerting(&node, &readings, Some(&config), &settings, 1000), Some(true));
+
+        config.muted_until = Some(2000);
+        assert_eq!(unmuted_alerting(&node, &readings, Some(&config), &settings, 1000), None);
+        assert_eq!(unmuted_alerting(&node, &readings, Some(&config), &settings, 2000), Some(true));
+    }
+
+    #[test]
+    fn unit_conversion_keeps_the_alert_state() {
+        let settings = settings(&[]);
+        let node = node_info(1000);
+        let mut celsius = config();
+        celsius.min_alert = Some(10.0);
+        celsius.max_alert = Some(30.0);
+        celsius.alert_hysteresis = Some(2.0);
+
+        let converted = converted_values(&celsius, convert::conversion("C", "F").unwrap());
+        let mut fahrenheit = config();
+        fahrenheit.units = "F".to_string();
+        fahrenheit.calibration_scale = Some(converted.calibration_scale);
+        fahrenheit.calibration_offset = Some(converted.calibration_offset);
+        fahrenheit.min_alert = converted.min_alert;
+        fahrenheit.max_alert = converted.max_alert;
+        fahrenheit.alert_hysteresis = converted.alert_hysteresis;
+
+        //  These scale to 27, 31.5, 29.7, 28.5, 36 and 18 degrees C.
+        let readings = [90, 105, 99, 95, 120, 60];
+        let rows = stored_readings(&readings);
+        let states = |config: &Xbees| alert_transitions(&node, stored_readings(&readings), config, &[], &settings)
+            .into_iter()
+            .map(|transition| (transition.timestamp, transition.alerting))
+            .collect::<Vec<(i64, bool)>>();
+
+        assert_eq!(states(&celsius), vec![(760, true), (1000, false)]);
+        assert_eq!(states(&fahrenheit), states(&celsius));
+
+        for count in 1..rows.len() + 1 {
+            let latest = &rows[..count];
+            assert_eq!(
+                currently_alerting(&node, latest, Some(&fahrenheit), &settings, 1000),
+                currently_alerting(&node, latest, Some(&celsius), &settings, 1000),
+            );
         }
     }
-}
 
-/// This endpoint removes the authentication cookie. Once
-/// called, a user can no longer access authenticated endpoints.
-#[get("/api/logout")]
-fn logout(mut cookies: Cookies) -> JsonValue {
-    cookies.remove_private(Cookie::new("auth", "true"));
-    json!({
-        "success": true,
-    })
-}
\ No newline at end of file
+    #[test]
+    fn batches_reject_timestamps_before_1970() {
+        let entries = batch(json!([
+            { "node_id": 2, "reading": 413, "timestamp": -1 },
+            { "node_id": 2, "reading": 413, "timestamp": -9000000000000000000i64 },
+            { "node_id": 2, "reading": 413, "timestamp": 0 },
+        ]).0);
+        let (valid, results) = check_batch(&entries, &configs(), &mut HashMap::new(), &settings(&[]), 2000);
+
+        assert_eq!(valid.len(), 1);
+        assert_eq!(valid[0].timestamp, 0);
+        assert_eq!(results[0].error, Some("Timestamp must not be before 1970.".to_string()));
+        assert_eq!(results[1].error, Some("Timestamp must not be before 1970.".to_string()));
+    }
+}
@@ -0,0 +1,237 @@
+use bcrypt;
+use failure::Error;
+use hmac::{Hmac, Mac};
+use rand::{OsRng, Rng};
+use rocket::Request;
+use sha2::{Digest, Sha256};
+
+use db::MIN_BCRYPT_COST;
+
+/// The header API keys are sent in.
+pub const HEADER: &str = "X-Api-Key";
+
+/// The header the signature of a signed request is sent in, as hex.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// The header the time a signed request was signed is sent in, as a
+/// Unix timestamp.
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+/// The header the SHA-256 of a signed request's body is sent in, as
+/// hex. Bodies that are read with `Decoded` are checked against it.
+pub const BODY_HASH_HEADER: &str = "X-Content-Sha256";
+
+/// How far the time a request was signed can be from the server's
+/// time, in seconds. Older signatures are rejected, so a request that
+/// is caught can only be replayed for this long.
+pub const SIGNATURE_WINDOW: i64 = 5 * 60;
+
+/// How many random bytes are in the secret part of a key.
+const SECRET_BYTES: usize = 24;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Writes bytes as lowercase hex.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Reads hex into bytes, or returns `None` if it isn't hex.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len() / 2)
+        .map(|index| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok())
+        .collect()
+}
+
+/// Makes a new random secret for a key, as hex. Signing secrets are
+/// made the same way.
+pub fn generate_secret() -> Result<String, Error> {
+    let mut rng = OsRng::new()?;
+
+    Ok(to_hex(&(0..SECRET_BYTES)
+        .map(|_| rng.gen::<u8>())
+        .collect::<Vec<u8>>()))
+}
+
+/// Hashes a key's secret to be stored. Secrets are long and random, so
+/// unlike passwords they don't need a slow hash to be safe from
+/// guessing, and the lowest cost keeps checking them on every request
+/// cheap.
+pub fn hash(secret: &str) -> Result<String, Error> {
+    Ok(bcrypt::hash(secret, MIN_BCRYPT_COST)?)
+}
+
+/// Returns whether a secret matches the stored hash.
+pub fn verify(secret: &str, hash: &str) -> bool {
+    bcrypt::verify(secret, hash).unwrap_or(false)
+}
+
+/// Returns the key given to the user, which is the key's id and its
+/// secret, such as "12.3f9a...". The id lets the key be found without
+/// checking every hash.
+pub fn format(id: i32, secret: &str) -> String {
+    format!("{}.{}", id, secret)
+}
+
+/// Splits a key into its id and secret, or returns `None` if it isn't
+/// in the format made by `format`.
+pub fn parse(key: &str) -> Option<(i32, &str)> {
+    let mut parts = key.trim().splitn(2, '.');
+    let id = parts.next()?.parse().ok()?;
+    let secret = parts.next()?;
+
+    if secret.is_empty() {
+        return None;
+    }
+
+    Some((id, secret))
+}
+
+/// Returns the SHA-256 of a request body, as hex, the way it is sent
+/// in `BODY_HASH_HEADER`.
+pub fn body_hash(body: &[u8]) -> String {
+    to_hex(&Sha256::digest(body))
+}
+
+/// The parts of a signed request that its signature covers.
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub timestamp: Option<&'a str>,
+    pub body_hash: Option<&'a str>,
+    pub signature: Option<&'a str>,
+}
+
+impl<'a> SignedRequest<'a> {
+    /// Reads the signed parts of a request. The path includes the
+    /// query, if there is one.
+    pub fn from_request(request: &'a Request) -> SignedRequest<'a> {
+        let headers = request.headers();
+
+        SignedRequest {
+            method: request.method().as_str(),
+            path: request.uri().as_str(),
+            timestamp: headers.get_one(TIMESTAMP_HEADER),
+            body_hash: headers.get_one(BODY_HASH_HEADER),
+            signature: headers.get_one(SIGNATURE_HEADER),
+        }
+    }
+
+    /// Returns the string that is signed, which is the method, path,
+    /// timestamp and body hash on their own lines, or `None` if the
+    /// timestamp or body hash is missing.
+    pub fn canonical(&self) -> Option<String> {
+        Some(format!("{}\n{}\n{}\n{}", self.method, self.path, self.timestamp?.trim(), self.body_hash?.trim().to_lowercase()))
+    }
+
+    /// Returns whether the request was signed with `secret` within
+    /// `SIGNATURE_WINDOW` of `now`. The signature is compared in
+    /// constant time.
+    pub fn verify(&self, secret: &str, now: i64) -> bool {
+        let signed_at = match self.timestamp.and_then(|timestamp| timestamp.trim().parse::<i64>().ok()) {
+            Some(signed_at) => signed_at,
+            None => return false,
+        };
+
+        //  The timestamp comes from the client, so it could be far
+        //  enough out to overflow.
+        let recent = now.checked_sub(signed_at)
+            .map_or(false, |age| age >= -SIGNATURE_WINDOW && age <= SIGNATURE_WINDOW);
+
+        if !recent {
+            return false;
+        }
+
+        let (canonical, signature) = match (self.canonical(), self.signature.and_then(|signature| from_hex(signature.trim()))) {
+            (Some(canonical), Some(signature)) => (canonical, signature),
+            _ => return false,
+        };
+
+        let mut mac = match HmacSha256::new_varkey(secret.as_bytes()) {
+            Ok(mac) => mac,
+            Err(_) => return false,
+        };
+
+        mac.input(canonical.as_bytes());
+        mac.verify(&signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "4f1c2a";
+
+    fn sign(secret: &str, canonical: &str) -> String {
+        let mut mac = HmacSha256::new_varkey(secret.as_bytes()).unwrap();
+        mac.input(canonical.as_bytes());
+        to_hex(&mac.result().code())
+    }
+
+    fn request<'a>(timestamp: &'a str, body: &'a str, signature: &'a str) -> SignedRequest<'a> {
+        SignedRequest {
+            method: "POST",
+            path: "/api/readings/batch",
+            timestamp: Some(timestamp),
+            body_hash: Some(body),
+            signature: Some(signature),
+        }
+    }
+
+    #[test]
+    fn the_canonical_string_has_one_part_per_line() {
+        let hash = body_hash(b"[]");
+        let canonical = request("1000", &hash, "").canonical().unwrap();
+
+        assert_eq!(canonical, format!("POST\n/api/readings/batch\n1000\n{}", hash));
+    }
+
+    #[test]
+    fn empty_bodies_hash_like_sha256() {
+        assert_eq!(body_hash(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn signed_requests_are_verified() {
+        let hash = body_hash(b"[]");
+        let canonical = request("1000", &hash, "").canonical().unwrap();
+        let signature = sign(SECRET, &canonical);
+
+        assert!(request("1000", &hash, &signature).verify(SECRET, 1000));
+        assert!(!request("1000", &hash, &signature).verify("other", 1000));
+        assert!(!request("1001", &hash, &signature).verify(SECRET, 1000));
+        assert!(!request("1000", &body_hash(b"[1]"), &signature).verify(SECRET, 1000));
+        assert!(!request("1000", &hash, "not hex").verify(SECRET, 1000));
+    }
+
+    #[test]
+    fn stale_signatures_are_rejected() {
+        let hash = body_hash(b"[]");
+        let signature = sign(SECRET, &request("1000", &hash, "").canonical().unwrap());
+
+        assert!(request("1000", &hash, &signature).verify(SECRET, 1000 + SIGNATURE_WINDOW));
+        assert!(!request("1000", &hash, &signature).verify(SECRET, 1001 + SIGNATURE_WINDOW));
+        assert!(!request("1000", &hash, &signature).verify(SECRET, 999 - SIGNATURE_WINDOW));
+        assert!(!request(&i64::min_value().to_string(), &hash, &signature).verify(SECRET, 1000));
+    }
+
+    #[test]
+    fn unsigned_requests_are_rejected() {
+        let unsigned = SignedRequest {
+            method: "GET",
+            path: "/api/list",
+            timestamp: None,
+            body_hash: None,
+            signature: None,
+        };
+
+        assert!(!unsigned.verify(SECRET, 1000));
+    }
+}
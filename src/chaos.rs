@@ -0,0 +1,97 @@
+use rand;
+use rocket::{Data, Outcome, Request, State};
+use rocket::config::{Config, Value};
+use rocket::http::{Method, Status};
+use rocket::response::status;
+use rocket_contrib::JsonValue;
+
+use std::thread;
+use std::time::Duration;
+
+/// The path requests failed by chaos mode are rerouted to.
+pub const FAILED_PATH: &str = "/api/chaos-failure";
+
+/// Delays and failures added to API requests on purpose, configured
+/// by the `chaos` table in Rocket.toml. This is only for testing how
+/// clients handle a slow or failing server.
+/// 
+/// Chaos mode is off unless `enabled` is set, and release builds
+/// ignore it unless `allow_release` is set as well.
+pub struct Chaos {
+    delay_ms: u64,
+    failure_rate: f64,
+}
+
+impl Chaos {
+    /// Reads chaos mode from the given config, or returns `None` if
+    /// it is not turned on.
+    pub fn from_config(config: &Config) -> Option<Chaos> {
+        let table = config.get_table("chaos").ok()?;
+
+        if !table.get("enabled").and_then(Value::as_bool).unwrap_or(false) {
+            return None;
+        }
+
+        if !cfg!(debug_assertions) && !table.get("allow_release").and_then(Value::as_bool).unwrap_or(false) {
+            warn!("Chaos mode is ignored in release builds unless allow_release is set.");
+            return None;
+        }
+
+        let chaos = Chaos {
+            delay_ms: table.get("delay_ms")
+                .and_then(Value::as_integer)
+                .map_or(0, |delay| delay.max(0) as u64),
+            failure_rate: table.get("failure_rate")
+                .and_then(Value::as_float)
+                .map_or(0.0, |rate| rate.max(0.0).min(1.0)),
+        };
+
+        warn!("Chaos mode is on. API requests are delayed by {}ms and {}% of them fail.",
+              chaos.delay_ms, chaos.failure_rate * 100.0);
+
+        Some(chaos)
+    }
+}
+
+/// Delays API requests and reroutes some of them to `FAILED_PATH`
+/// if chaos mode is on. Health checks are left alone, so monitoring
+/// doesn't think the server is down.
+pub fn disrupt_request(request: &mut Request, _: &Data) {
+    let fail = {
+        let path = request.uri().path();
+
+        if !path.starts_with("/api/") || path.starts_with("/api/health") {
+            return;
+        }
+
+        let chaos = match request.guard::<State<Option<Chaos>>>() {
+            Outcome::Success(chaos) => chaos,
+            _ => return,
+        };
+
+        let chaos = match chaos.as_ref() {
+            Some(chaos) => chaos,
+            None => return,
+        };
+
+        if chaos.delay_ms > 0 {
+            thread::sleep(Duration::from_millis(chaos.delay_ms));
+        }
+
+        rand::random::<f64>() < chaos.failure_rate
+    };
+
+    if fail {
+        request.set_method(Method::Get);
+        request.set_uri(FAILED_PATH);
+    }
+}
+
+/// Answers requests that chaos mode chose to fail.
+#[get("/api/chaos-failure")]
+pub fn failure() -> status::Custom<JsonValue> {
+    status::Custom(Status::ServiceUnavailable, json!({
+        "error": "This request was failed on purpose by chaos mode.",
+        "success": false,
+    }))
+}
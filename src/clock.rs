@@ -0,0 +1,83 @@
+use chrono::Utc;
+#[cfg(test)]
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+
+/// A source of the current wall-clock time.
+/// 
+/// Everything that compares against timestamps, such as staleness,
+/// token expiry and purging, reads the time through a `Clock` instead
+/// of reading the system time directly. This keeps the time used
+/// consistent and lets it be replaced with a fixed time when checking
+/// expiry logic.
+/// 
+/// Elapsed durations, such as rate limit windows and request latency,
+/// are measured with `Instant` instead, since they mustn't jump when
+/// the system time is changed. Log lines are stamped with the system
+/// time as well, so they line up with other logs on the machine.
+pub trait Clock: Send + Sync {
+    /// Returns the current time as a Unix timestamp.
+    fn now(&self) -> i64;
+}
+
+/// A clock that reads the system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+/// The clock shared by the server and its background threads.
+pub type SharedClock = Arc<Clock>;
+
+/// A clock that only moves when it is told to, for tests.
+#[cfg(test)]
+pub struct MockClock(Mutex<i64>);
+
+#[cfg(test)]
+impl MockClock {
+    /// Returns a clock stopped at the given time.
+    pub fn new(now: i64) -> MockClock {
+        MockClock(Mutex::new(now))
+    }
+
+    /// Moves the clock to the given time.
+    pub fn set(&self, now: i64) {
+        *self.0.lock() = now;
+    }
+
+    /// Moves the clock forward by the given number of seconds.
+    pub fn advance(&self, secs: i64) {
+        *self.0.lock() += secs;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        *self.0.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_only_moves_when_told() {
+        let mock = Arc::new(MockClock::new(1000));
+        let clock: SharedClock = mock.clone();
+
+        assert_eq!(clock.now(), 1000);
+        assert_eq!(clock.now(), 1000);
+
+        mock.advance(30);
+        assert_eq!(clock.now(), 1030);
+
+        mock.set(500);
+        assert_eq!(clock.now(), 500);
+    }
+}
@@ -0,0 +1,174 @@
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+/// The format of dates in HTTP headers, which is always in GMT.
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Formats a Unix timestamp as an HTTP date, such as
+/// "Thu, 12 Apr 2018 21:26:25 GMT". Timestamps too far out to be a
+/// date are formatted as the Unix epoch.
+pub fn http_date(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0).single()
+        .unwrap_or_else(|| Utc.timestamp(0, 0))
+        .format(HTTP_DATE)
+        .to_string()
+}
+
+/// The time given in a request's `If-Modified-Since` header, as a
+/// Unix timestamp. It is `None` if the header is missing or isn't a
+/// valid date, in which case the request is answered in full.
+#[derive(Clone, Copy, Debug)]
+pub struct IfModifiedSince(pub Option<i64>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfModifiedSince {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<IfModifiedSince, ()> {
+        let since = request.headers().get_one("If-Modified-Since")
+            .and_then(|since| DateTime::parse_from_rfc2822(since.trim()).ok())
+            .map(|since| since.timestamp());
+
+        Outcome::Success(IfModifiedSince(since))
+    }
+}
+
+impl IfModifiedSince {
+    /// Returns whether the client's copy is still current for a
+    /// resource last modified at the given Unix timestamp. HTTP dates
+    /// only have whole seconds, so a copy from the same second as
+    /// `last_modified` is current.
+    pub fn is_current(&self, last_modified: i64) -> bool {
+        self.0.map_or(false, |since| last_modified <= since)
+    }
+}
+
+/// A response that is sent with a `Last-Modified` header, or a
+/// bodyless 304 Not Modified if the client's copy is still current.
+/// 
+/// Building the body can be skipped altogether by checking
+/// `IfModifiedSince::is_current` first and answering with
+/// `Conditional::not_modified`.
+pub struct Conditional<R> {
+    body: Option<R>,
+    last_modified: i64,
+    since: IfModifiedSince,
+}
+
+impl<R> Conditional<R> {
+    /// Makes a response last modified at the given Unix timestamp.
+    pub fn new(body: R, last_modified: i64, since: IfModifiedSince) -> Conditional<R> {
+        Conditional {
+            body: Some(body),
+            last_modified: last_modified,
+            since: since,
+        }
+    }
+
+    /// Makes a 304 Not Modified for a client whose copy of a resource
+    /// last modified at the given Unix timestamp is still current.
+    pub fn not_modified(last_modified: i64) -> Conditional<R> {
+        Conditional {
+            body: None,
+            last_modified: last_modified,
+            since: IfModifiedSince(Some(last_modified)),
+        }
+    }
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for Conditional<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let last_modified = http_date(self.last_modified);
+
+        match self.body {
+            Some(body) if !self.since.is_current(self.last_modified) => {
+                Response::build_from(body.respond_to(request)?)
+                    .raw_header("Last-Modified", last_modified)
+                    .ok()
+            }
+            _ => {
+                Response::build()
+                    .status(Status::NotModified)
+                    .raw_header("Last-Modified", last_modified)
+                    .ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket;
+    use rocket::http::Header;
+    use rocket::local::Client;
+
+    #[get("/")]
+    fn resource(since: IfModifiedSince) -> Conditional<&'static str> {
+        Conditional::new("body", 1523568385, since)
+    }
+
+    #[get("/skipped")]
+    fn skipped(since: IfModifiedSince) -> Conditional<&'static str> {
+        if since.is_current(1523568385) {
+            return Conditional::not_modified(1523568385);
+        }
+
+        Conditional::new("body", 1523568385, since)
+    }
+
+    fn client() -> Client {
+        Client::new(rocket::ignite().mount("/", routes![resource, skipped])).unwrap()
+    }
+
+    #[test]
+    fn http_dates_are_gmt() {
+        assert_eq!(http_date(1523568385), "Thu, 12 Apr 2018 21:26:25 GMT");
+        assert_eq!(http_date(i64::max_value()), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn last_modified_round_trips() {
+        let client = client();
+
+        for path in &["/", "/skipped"] {
+            let mut first = client.get(*path).dispatch();
+            assert_eq!(first.status(), Status::Ok);
+            assert_eq!(first.body_string(), Some("body".to_string()));
+
+            let last_modified = first.headers().get_one("Last-Modified").unwrap().to_string();
+            assert_eq!(last_modified, http_date(1523568385));
+
+            let mut second = client.get(*path)
+                .header(Header::new("If-Modified-Since", last_modified.clone()))
+                .dispatch();
+            assert_eq!(second.status(), Status::NotModified);
+            assert_eq!(second.body_string(), None);
+            assert_eq!(second.headers().get_one("Last-Modified"), Some(last_modified.as_str()));
+        }
+    }
+
+    #[test]
+    fn older_copies_get_the_body() {
+        let client = client();
+        let mut response = client.get("/")
+            .header(Header::new("If-Modified-Since", http_date(1523568384)))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some("body".to_string()));
+    }
+
+    #[test]
+    fn bad_dates_are_ignored() {
+        let client = client();
+        let response = client.get("/")
+            .header(Header::new("If-Modified-Since", "yesterday"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
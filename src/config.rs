@@ -0,0 +1,272 @@
+use bcrypt;
+use rocket::config::Config;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use db::{MAX_BCRYPT_COST, MIN_BCRYPT_COST};
+use naming::FieldCase;
+
+/// Feature flags read from the `features` table in Rocket.toml.
+/// 
+/// Each flag turns a group of endpoints on or off. Flags that
+/// are not set are enabled, so existing deployments keep every
+/// endpoint unless they opt out.
+/// 
+/// | Flag           | Endpoints                                           |
+/// |----------------|-----------------------------------------------------|
+/// | `send`         | /api/send                                           |
+/// | `add`          | /api/add, /api/add/validate, /api/xbee/<node>/clone |
+/// | `backup`       | /api/backup, /api/restore                           |
+/// | `registration` | /api/register                                       |
+pub struct Features(HashMap<String, bool>);
+
+/// The names of every feature flag.
+pub const FEATURE_FLAGS: &[&str] = &["send", "add", "backup", "registration"];
+
+impl Features {
+    /// Reads the feature flags from the given config. Flags that
+    /// are not booleans are ignored with a warning.
+    pub fn from_config(config: &Config) -> Features {
+        let mut flags = HashMap::new();
+
+        if let Ok(table) = config.get_table("features") {
+            for (name, value) in table {
+                match value.as_bool() {
+                    Some(enabled) => {
+                        flags.insert(name.clone(), enabled);
+                    }
+                    None => warn!("Feature flag '{}' is not a boolean, ignoring it.", name),
+                }
+            }
+        }
+
+        Features(flags)
+    }
+
+    /// Returns whether the feature with the given name is enabled.
+    pub fn enabled(&self, name: &str) -> bool {
+        self.0.get(name).cloned().unwrap_or(true)
+    }
+
+    /// Returns whether each known feature is enabled, by name.
+    pub fn effective(&self) -> HashMap<&'static str, bool> {
+        FEATURE_FLAGS.iter()
+            .map(|&name| (name, self.enabled(name)))
+            .collect()
+    }
+}
+
+/// How long issued JWTs are valid for by default, in seconds.
+const DEFAULT_JWT_LIFETIME: i64 = 60 * 60 * 24;
+
+/// How long a node can go without an update before it is stale by
+/// default, in seconds. This matches what the frontend used to use.
+const DEFAULT_STALE_AFTER: i64 = 5;
+
+/// How long a login session lasts without activity by default, in
+/// seconds. This is how long Rocket kept private cookies for.
+const DEFAULT_SESSION_WINDOW: i64 = 60 * 60 * 24 * 7;
+
+/// The longest a login session can last by default, however active
+/// it is, in seconds.
+const DEFAULT_SESSION_MAX: i64 = 60 * 60 * 24 * 30;
+
+/// How long a node deletion waits for a second admin by default,
+/// in seconds.
+const DEFAULT_DELETION_WINDOW: i64 = 60 * 60 * 24;
+
+/// The most node-days of history a request can read by default, which
+/// is a year of 100 nodes.
+const DEFAULT_HISTORY_COST_BUDGET: i64 = 365 * 100;
+
+/// The fraction of its voltage range below which a node's power is
+/// low by default.
+const DEFAULT_POWER_LOW: f64 = 0.25;
+
+/// The fraction of its voltage range below which a node's power is
+/// critical by default.
+const DEFAULT_POWER_CRITICAL: f64 = 0.1;
+
+/// How far back past a threshold a node's value has to go to stop
+/// alerting by default. No margin means it stops as soon as it is back
+/// within its thresholds.
+const DEFAULT_ALERT_HYSTERESIS: f64 = 0.0;
+
+/// The bcrypt cost passwords are hashed with by default.
+const DEFAULT_BCRYPT_COST: u32 = bcrypt::DEFAULT_COST;
+
+/// The usernames reserved by default.
+const DEFAULT_RESERVED_USERNAMES: &[&str] = &["admin", "root", "system", "api"];
+
+/// The directory the frontend's files are served from by default.
+const DEFAULT_STATIC_DIR: &str = "static";
+
+/// General settings read from Rocket.toml.
+#[derive(Clone)]
+pub struct Settings {
+    /// The secret used to sign JWTs. If this is not set, the
+    /// server will not issue or accept tokens.
+    pub jwt_secret: Option<String>,
+    /// How long issued JWTs are valid for, in seconds.
+    pub jwt_lifetime: i64,
+    /// The number of decimal places values in /api/list are rounded
+    /// to when the request doesn't say. Values are not rounded if
+    /// this is not set.
+    pub precision: Option<u32>,
+    /// How many days readings are kept for. If this is set, older
+    /// readings are purged every hour. Otherwise they are kept
+    /// until an admin purges them.
+    pub retention_days: Option<i64>,
+    /// How long a node can go without an update before it is stale,
+    /// in seconds. Nodes with a report interval use that instead.
+    pub stale_after_secs: i64,
+    /// The units given to nodes that are added without any. If this
+    /// is not set, nodes must be added with units.
+    pub default_units: Option<String>,
+    /// Whether the bodies of API requests are logged, with sensitive
+    /// fields redacted. This is only meant for development, so it is
+    /// always off in release builds.
+    pub log_request_bodies: bool,
+    /// Whether responses to changes include the username of the
+    /// user who made them. This costs an extra lookup per change.
+    pub echo_acting_user: bool,
+    /// Whether requests a proxy says were made without HTTPS are
+    /// refused, based on the `X-Forwarded-Proto` header.
+    pub require_https: bool,
+    /// The directory the frontend's files are served from, or `None`
+    /// if `static_dir` is set to false to serve only the API.
+    pub static_dir: Option<PathBuf>,
+    /// Usernames that users can't take for themselves, stored
+    /// lowercase. The bootstrap admin may still use one.
+    pub reserved_usernames: Vec<String>,
+    /// Whether anyone can make themselves an account through
+    /// /api/register.
+    pub allow_registration: bool,
+    /// How long a login session lasts without activity, in seconds.
+    /// Sessions are renewed once they are halfway through it.
+    pub session_window_secs: i64,
+    /// The longest a login session can last, in seconds. After this
+    /// the user has to log in again, even if they are active.
+    pub session_max_secs: i64,
+    /// The Unix timestamp until which cookies from before sessions
+    /// were renewed are still accepted. They are rejected after it, or
+    /// always if this is not set.
+    pub legacy_sessions_until: Option<i64>,
+    /// Whether an admin resetting a user's password logs the user out
+    /// everywhere, unless the reset says otherwise.
+    pub logout_on_password_reset: bool,
+    /// Whether deleting a node has to be confirmed by a second admin.
+    pub deletion_approval: bool,
+    /// How long a node deletion waits for a second admin before it
+    /// expires, in seconds.
+    pub deletion_window_secs: i64,
+    /// The most node-days of history a single /api/history/batch
+    /// request can read, or `None` if `history_cost_budget` is set to
+    /// false to allow any range.
+    pub history_cost_budget: Option<i64>,
+    /// The fraction of a node's voltage range below which its power
+    /// is reported as low in /api/list.
+    pub power_low_fraction: f64,
+    /// The fraction of a node's voltage range below which its power
+    /// is reported as critical in /api/list.
+    pub power_critical_fraction: f64,
+    /// How far back within its thresholds an alerting node's value has
+    /// to go before it stops alerting, for nodes without their own.
+    pub alert_hysteresis: f64,
+    /// The bcrypt cost passwords are hashed with. Existing hashes
+    /// keep the cost they were made with.
+    pub bcrypt_cost: u32,
+    /// Whether /api/readings/batch saves all of a batch or none of it,
+    /// rather than saving the readings that are valid.
+    pub atomic_ingest: bool,
+    /// Whether /api/readings/batch adds placeholder nodes for readings
+    /// from nodes that aren't in the database, rather than rejecting
+    /// them.
+    pub auto_register: bool,
+    /// How many seconds apart readings sent to /api/readings/batch
+    /// have to be for the same node, for nodes without their own
+    /// interval, or `None` to allow readings at any interval.
+    pub min_reading_interval_secs: Option<i64>,
+    /// How the fields of nodes in /api/list, and the endpoints that
+    /// share its format, are named. No other response is renamed.
+    pub json_field_case: FieldCase,
+}
+
+impl Settings {
+    /// Reads the settings from the given config, using the
+    /// defaults for any that are not set.
+    pub fn from_config(config: &Config) -> Settings {
+        Settings {
+            jwt_secret: config.get_str("jwt_secret").ok().map(String::from),
+            jwt_lifetime: config.get_int("jwt_lifetime").unwrap_or(DEFAULT_JWT_LIFETIME),
+            precision: config.get_int("precision").ok().map(|places| places.max(0) as u32),
+            retention_days: config.get_int("retention_days").ok()
+                .and_then(|days| if days > 0 { Some(days) } else { None }),
+            stale_after_secs: config.get_int("stale_after_secs").unwrap_or(DEFAULT_STALE_AFTER),
+            default_units: config.get_str("default_units").ok().map(String::from),
+            log_request_bodies: request_body_logging(config),
+            echo_acting_user: config.get_bool("echo_acting_user").unwrap_or(false),
+            require_https: config.get_bool("require_https").unwrap_or(false),
+            static_dir: match config.get_bool("static_dir") {
+                Ok(false) => None,
+                _ => Some(PathBuf::from(config.get_str("static_dir").unwrap_or(DEFAULT_STATIC_DIR))),
+            },
+            reserved_usernames: match config.get_slice("reserved_usernames") {
+                Ok(names) => names.iter()
+                    .filter_map(|name| name.as_str())
+                    .map(|name| name.trim().to_lowercase())
+                    .collect(),
+                Err(_) => DEFAULT_RESERVED_USERNAMES.iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            },
+            allow_registration: config.get_bool("allow_registration").unwrap_or(false),
+            session_window_secs: config.get_int("session_window_secs").unwrap_or(DEFAULT_SESSION_WINDOW),
+            session_max_secs: config.get_int("session_max_secs").unwrap_or(DEFAULT_SESSION_MAX),
+            legacy_sessions_until: config.get_int("legacy_sessions_until").ok(),
+            logout_on_password_reset: config.get_bool("logout_on_password_reset").unwrap_or(true),
+            deletion_approval: config.get_bool("deletion_approval").unwrap_or(false),
+            deletion_window_secs: config.get_int("deletion_window_secs").unwrap_or(DEFAULT_DELETION_WINDOW),
+            history_cost_budget: match config.get_bool("history_cost_budget") {
+                Ok(false) => None,
+                _ => Some(config.get_int("history_cost_budget").unwrap_or(DEFAULT_HISTORY_COST_BUDGET)),
+            },
+            power_low_fraction: config.get_float("power_low_fraction").unwrap_or(DEFAULT_POWER_LOW),
+            power_critical_fraction: config.get_float("power_critical_fraction").unwrap_or(DEFAULT_POWER_CRITICAL),
+            alert_hysteresis: config.get_float("alert_hysteresis")
+                .map(|margin| margin.max(0.0))
+                .unwrap_or(DEFAULT_ALERT_HYSTERESIS),
+            bcrypt_cost: config.get_int("bcrypt_cost").ok()
+                .map(|cost| cost.max(i64::from(MIN_BCRYPT_COST)).min(i64::from(MAX_BCRYPT_COST)) as u32)
+                .unwrap_or(DEFAULT_BCRYPT_COST),
+            atomic_ingest: config.get_bool("atomic_ingest").unwrap_or(false),
+            auto_register: config.get_bool("auto_register").unwrap_or(false),
+            min_reading_interval_secs: config.get_int("min_reading_interval_secs").ok()
+                .and_then(|secs| if secs > 0 { Some(secs) } else { None }),
+            json_field_case: config.get_str("json_field_case").ok()
+                .and_then(FieldCase::parse)
+                .unwrap_or(FieldCase::Snake),
+        }
+    }
+
+    /// Returns whether the given username is reserved, ignoring case.
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let name = name.trim().to_lowercase();
+        self.reserved_usernames.iter().any(|reserved| *reserved == name)
+    }
+}
+
+/// Returns whether request bodies should be logged. Bodies can hold
+/// private data that redacting misses, so the setting is ignored in
+/// release builds.
+fn request_body_logging(config: &Config) -> bool {
+    let enabled = config.get_bool("log_request_bodies").unwrap_or(false);
+
+    if enabled && !cfg!(debug_assertions) {
+        warn!("log_request_bodies is ignored in release builds.");
+        return false;
+    }
+
+    enabled
+}
@@ -0,0 +1,126 @@
+/// A unit that values can be converted to and from. A value in the
+/// unit is turned into its dimension's base unit as
+/// `value * scale + offset`.
+struct Unit {
+    symbol: &'static str,
+    dimension: &'static str,
+    scale: f64,
+    offset: f64,
+}
+
+/// Every unit that can be converted, grouped by dimension. The first
+/// unit of each dimension is its base unit.
+const UNITS: &[Unit] = &[
+    Unit { symbol: "C", dimension: "temperature", scale: 1.0, offset: 0.0 },
+    Unit { symbol: "F", dimension: "temperature", scale: 5.0 / 9.0, offset: -32.0 * 5.0 / 9.0 },
+    Unit { symbol: "K", dimension: "temperature", scale: 1.0, offset: -273.15 },
+    Unit { symbol: "m", dimension: "length", scale: 1.0, offset: 0.0 },
+    Unit { symbol: "mm", dimension: "length", scale: 0.001, offset: 0.0 },
+    Unit { symbol: "cm", dimension: "length", scale: 0.01, offset: 0.0 },
+    Unit { symbol: "km", dimension: "length", scale: 1000.0, offset: 0.0 },
+    Unit { symbol: "in", dimension: "length", scale: 0.0254, offset: 0.0 },
+    Unit { symbol: "ft", dimension: "length", scale: 0.3048, offset: 0.0 },
+    Unit { symbol: "Pa", dimension: "pressure", scale: 1.0, offset: 0.0 },
+    Unit { symbol: "hPa", dimension: "pressure", scale: 100.0, offset: 0.0 },
+    Unit { symbol: "kPa", dimension: "pressure", scale: 1000.0, offset: 0.0 },
+    Unit { symbol: "bar", dimension: "pressure", scale: 100_000.0, offset: 0.0 },
+    Unit { symbol: "psi", dimension: "pressure", scale: 6894.757, offset: 0.0 },
+    Unit { symbol: "m/s", dimension: "speed", scale: 1.0, offset: 0.0 },
+    Unit { symbol: "km/h", dimension: "speed", scale: 1.0 / 3.6, offset: 0.0 },
+    Unit { symbol: "mph", dimension: "speed", scale: 0.44704, offset: 0.0 },
+    Unit { symbol: "kn", dimension: "speed", scale: 0.514_444, offset: 0.0 },
+    Unit { symbol: "V", dimension: "voltage", scale: 1.0, offset: 0.0 },
+    Unit { symbol: "mV", dimension: "voltage", scale: 0.001, offset: 0.0 },
+];
+
+/// Other names units are written with, and the symbol they stand for.
+/// These are matched ignoring case.
+const ALIASES: &[(&str, &str)] = &[
+    ("°c", "C"), ("degc", "C"), ("celsius", "C"),
+    ("°f", "F"), ("degf", "F"), ("fahrenheit", "F"),
+    ("kelvin", "K"),
+    ("meters", "m"), ("metres", "m"),
+    ("inches", "in"), ("feet", "ft"),
+    ("mbar", "hPa"),
+    ("kph", "km/h"), ("knots", "kn"),
+];
+
+/// Returns the symbol a unit is known by, such as "C" for "°C", or
+/// the unit trimmed if it isn't known. Symbols are matched exactly,
+/// since "m" and "M" aren't the same unit.
+pub fn normalize(units: &str) -> String {
+    let units = units.trim();
+
+    if UNITS.iter().any(|unit| unit.symbol == units) {
+        return units.to_string();
+    }
+
+    let lower = units.to_lowercase();
+    ALIASES.iter()
+        .find(|&&(alias, _)| alias == lower)
+        .map_or(units.to_string(), |&(_, symbol)| symbol.to_string())
+}
+
+/// How to convert a value from one unit to another, which is
+/// `value * factor + offset`.
+#[derive(Clone, Copy, Debug)]
+pub struct Conversion {
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Conversion {
+    /// Returns whether the conversion leaves values as they are.
+    pub fn is_identity(&self) -> bool {
+        (self.factor - 1.0).abs() < ::std::f64::EPSILON && self.offset.abs() < ::std::f64::EPSILON
+    }
+
+    /// Returns the conversion written out, such as
+    /// "value * 1.8 + 32".
+    pub fn formula(&self) -> String {
+        if self.is_identity() {
+            return "value".to_string();
+        }
+
+        let mut formula = format!("value * {}", self.factor);
+
+        if self.offset > 0.0 {
+            formula.push_str(&format!(" + {}", self.offset));
+        } else if self.offset < 0.0 {
+            formula.push_str(&format!(" - {}", -self.offset));
+        }
+
+        formula
+    }
+}
+
+/// Returns how to convert values from one unit to another, or `None`
+/// if they can't be converted, such as when they measure different
+/// things or either is unknown. Identical units always convert, even
+/// if they aren't known.
+pub fn conversion(from: &str, to: &str) -> Option<Conversion> {
+    let (from, to) = (normalize(from), normalize(to));
+
+    if from == to {
+        return Some(Conversion { factor: 1.0, offset: 0.0 });
+    }
+
+    let from = UNITS.iter().find(|unit| unit.symbol == from)?;
+    let to = UNITS.iter().find(|unit| unit.symbol == to)?;
+
+    if from.dimension != to.dimension {
+        return None;
+    }
+
+    Some(Conversion {
+        factor: round(from.scale / to.scale),
+        offset: round((from.offset - to.offset) / to.scale),
+    })
+}
+
+/// Rounds away the floating point error left by converting through
+/// the base unit, so that Celsius to Fahrenheit is exactly
+/// `value * 1.8 + 32`.
+fn round(number: f64) -> f64 {
+    (number * 1e9).round() / 1e9
+}
@@ -0,0 +1,232 @@
+use rocket::{Outcome, Request, Response, State};
+use rocket::config::{Config, Value};
+use rocket::http::{Method, Status};
+
+/// The methods browsers are told they can use in cross-origin requests.
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+
+/// The request headers browsers are told they can send cross-origin.
+const ALLOWED_HEADERS: &str = "Accept, Authorization, Content-Type, Content-Encoding, X-Api-Key";
+
+/// The response headers cross-origin clients are allowed to read.
+const EXPOSED_HEADERS: &str = "X-Api-Version, X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset, X-RateLimit-Warning, Retry-After";
+
+/// An origin that is allowed to make cross-origin requests, such as
+/// "https://app.example.com", or "https://*.example.com" for any of
+/// its subdomains. Patterns without a scheme match both http and https.
+struct OriginPattern {
+    scheme: Option<String>,
+    host: String,
+    wildcard: bool,
+}
+
+impl OriginPattern {
+    /// Parses an origin pattern. Patterns with a path, or with a
+    /// wildcard anywhere but the start of the host, are not valid.
+    fn parse(pattern: &str) -> Option<OriginPattern> {
+        let pattern = pattern.trim().trim_right_matches('/').to_lowercase();
+        let (scheme, host) = match pattern.find("://") {
+            Some(end) => (Some(pattern[..end].to_string()), pattern[end + 3..].to_string()),
+            None => (None, pattern.clone()),
+        };
+
+        let (wildcard, host) = if host.starts_with("*.") {
+            (true, host[2..].to_string())
+        } else {
+            (false, host)
+        };
+
+        if host.is_empty() || host.contains('/') || host.contains('*') {
+            return None;
+        }
+
+        Some(OriginPattern {
+            scheme: scheme,
+            host: host,
+            wildcard: wildcard,
+        })
+    }
+
+    /// Returns whether the given `Origin` header matches the pattern.
+    /// A wildcard only matches subdomains, not the domain itself.
+    fn matches(&self, origin: &str) -> bool {
+        let origin = origin.trim().to_lowercase();
+        let end = match origin.find("://") {
+            Some(end) => end,
+            None => return false,
+        };
+
+        let (scheme, host) = (&origin[..end], &origin[end + 3..]);
+
+        if host.is_empty() || host.contains('/') {
+            return false;
+        }
+
+        if self.scheme.as_ref().map_or(false, |allowed| allowed != scheme) {
+            return false;
+        }
+
+        if self.wildcard {
+            host.len() > self.host.len() + 1 && host.ends_with(&format!(".{}", self.host))
+        } else {
+            host == self.host
+        }
+    }
+}
+
+/// The origins allowed to make cross-origin API requests, set by the
+/// `cors_origins` list in Rocket.toml.
+/// 
+/// Only origins in the list are ever echoed back in the
+/// `Access-Control-Allow-Origin` header, so no other site can read
+/// responses with a user's cookies.
+pub struct Cors {
+    origins: Vec<OriginPattern>,
+}
+
+impl Cors {
+    /// Reads the allowed origins from the given config, or returns
+    /// `None` if there are none. A single origin can be given as a
+    /// string. Patterns that can't be parsed are ignored with a warning.
+    pub fn from_config(config: &Config) -> Option<Cors> {
+        let patterns = match config.get_slice("cors_origins") {
+            Ok(patterns) => patterns.iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect(),
+            Err(_) => vec![config.get_str("cors_origins").ok()?.to_string()],
+        };
+
+        let origins = patterns.iter()
+            .filter_map(|pattern| {
+                let parsed = OriginPattern::parse(pattern);
+                if parsed.is_none() {
+                    warn!("Allowed origin {} is not valid, ignoring it.", pattern);
+                }
+                parsed
+            })
+            .collect::<Vec<OriginPattern>>();
+
+        if origins.is_empty() {
+            return None;
+        }
+
+        Some(Cors { origins: origins })
+    }
+
+    /// Returns whether the given origin is allowed.
+    pub fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|pattern| pattern.matches(origin))
+    }
+}
+
+/// Lets allowed origins read API responses by echoing their `Origin`
+/// header back. Preflight `OPTIONS` requests from them, which have no
+/// route, are answered with a 204 No Content instead of a 404.
+/// Requests from any other origin get no CORS headers, so browsers
+/// keep them from reading the response.
+pub fn add_headers(request: &Request, response: &mut Response) {
+    if !request.uri().path().starts_with("/api/") {
+        return;
+    }
+
+    let origin = match request.headers().get_one("Origin") {
+        Some(origin) => origin,
+        None => return,
+    };
+
+    let allowed = match request.guard::<State<Option<Cors>>>() {
+        Outcome::Success(cors) => cors.as_ref().map_or(false, |cors| cors.allows(origin)),
+        _ => false,
+    };
+
+    //  Responses differ by origin, so caches must not mix them up.
+    response.set_raw_header("Vary", "Origin");
+
+    if !allowed {
+        return;
+    }
+
+    response.set_raw_header("Access-Control-Allow-Origin", origin.to_string());
+    response.set_raw_header("Access-Control-Allow-Credentials", "true");
+    response.set_raw_header("Access-Control-Expose-Headers", EXPOSED_HEADERS);
+
+    if request.method() == Method::Options && response.status() == Status::NotFound {
+        response.set_status(Status::NoContent);
+        response.take_body();
+        response.set_raw_header("Access-Control-Allow-Methods", ALLOWED_METHODS);
+        response.set_raw_header("Access-Control-Allow-Headers", ALLOWED_HEADERS);
+        response.set_raw_header("Access-Control-Max-Age", "86400");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::config::Environment;
+
+    fn pattern(pattern: &str) -> OriginPattern {
+        OriginPattern::parse(pattern).unwrap()
+    }
+
+    #[test]
+    fn exact_origins_match_themselves() {
+        let allowed = pattern("https://app.example.com");
+
+        assert!(allowed.matches("https://app.example.com"));
+        assert!(allowed.matches("HTTPS://App.Example.com"));
+        assert!(!allowed.matches("http://app.example.com"));
+        assert!(!allowed.matches("https://app.example.com.evil.net"));
+        assert!(!allowed.matches("https://evilapp.example.com"));
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_only() {
+        let allowed = pattern("https://*.example.com");
+
+        assert!(allowed.matches("https://app.example.com"));
+        assert!(allowed.matches("https://staging.app.example.com"));
+        assert!(!allowed.matches("https://example.com"));
+        assert!(!allowed.matches("https://evilexample.com"));
+        assert!(!allowed.matches("https://example.com.evil.net"));
+    }
+
+    #[test]
+    fn patterns_without_a_scheme_match_either() {
+        let allowed = pattern("localhost:8000");
+
+        assert!(allowed.matches("http://localhost:8000"));
+        assert!(allowed.matches("https://localhost:8000"));
+        assert!(!allowed.matches("http://localhost:8001"));
+    }
+
+    #[test]
+    fn malformed_origins_never_match() {
+        let allowed = pattern("https://*.example.com");
+
+        assert!(!allowed.matches("app.example.com"));
+        assert!(!allowed.matches("https://app.example.com/path"));
+        assert!(!allowed.matches("null"));
+    }
+
+    #[test]
+    fn bad_patterns_are_ignored() {
+        assert!(OriginPattern::parse("https://app.*.example.com").is_none());
+        assert!(OriginPattern::parse("https://example.com/path").is_none());
+        assert!(OriginPattern::parse("https://").is_none());
+    }
+
+    #[test]
+    fn unlisted_origins_are_rejected() {
+        let config = Config::build(Environment::Development)
+            .extra("cors_origins", vec!["https://app.example.com", "https://*.staging.example.com", "bogus/path"])
+            .finalize()
+            .unwrap();
+        let cors = Cors::from_config(&config).unwrap();
+
+        assert!(cors.allows("https://app.example.com"));
+        assert!(cors.allows("https://one.staging.example.com"));
+        assert!(!cors.allows("https://other.example.com"));
+        assert!(!cors.allows("https://evil.net"));
+    }
+}
@@ -1,16 +1,23 @@
+use bcrypt;
 use diesel;
 use diesel::prelude::*;
 use dotenv::dotenv;
+use failure::Error;
 use r2d2;
 use r2d2_diesel::ConnectionManager;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::{Outcome, Request, State};
+use std::collections::HashMap;
 use std::env;
 use std::ops::Deref;
 
+use session::ActiveSession;
+
 pub mod schema;
 pub mod models;
+#[cfg(test)]
+pub mod testing;
 
 pub type SqlitePool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
 pub struct DbConn(r2d2::PooledConnection<ConnectionManager<SqliteConnection>>);
@@ -53,22 +60,2665 @@ pub fn establish_connection() -> SqlitePool {
     r2d2::Pool::new(manager).expect("Could not initialize db pool")
 }
 
+/// Returns the form a username is stored in.
+/// 
+/// Usernames are stored lowercase so that names differing only
+/// by case are treated as the same user. The database also has
+/// a case-insensitive unique index on `username` as a backstop.
+pub fn normalize_username(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Finds the user logging in with the given username, ignoring case.
+/// Deleted users are treated as if they didn't exist.
+pub fn find_login_user(conn: &SqliteConnection, name: &str) -> QueryResult<models::User> {
+    use self::schema::users::dsl::*;
+
+    users
+        .filter(username.eq(normalize_username(name)))
+        .filter(deleted_at.is_null())
+        .get_result::<models::User>(conn)
+}
+
+/// Returns the users that were renamed when usernames were made
+/// case-insensitive, because their name only differed in case from an
+/// older user's.
+pub fn username_renames(conn: &SqliteConnection) -> QueryResult<Vec<models::UsernameRename>> {
+    use self::schema::username_renames::dsl::*;
+
+    username_renames
+        .order(user_id)
+        .load::<models::UsernameRename>(conn)
+}
+
+/// The lowest and highest costs bcrypt can hash passwords with.
+pub const MIN_BCRYPT_COST: u32 = 4;
+pub const MAX_BCRYPT_COST: u32 = 31;
+
+/// Errors caused by a password that couldn't be stored.
+#[derive(Debug, Fail)]
+pub enum PasswordError {
+    #[fail(display = "Password hash is not a bcrypt hash with cost {}.", _0)]
+    InvalidHash(u32),
+    #[fail(display = "No user with id {} exists.", _0)]
+    UserNotFound(i32),
+}
+
+/// Returns whether the given hash looks like a bcrypt hash made with
+/// the given cost, such as "$2y$12$" followed by the salt and hash.
+pub fn is_bcrypt_hash(hash: &str, cost: u32) -> bool {
+    let parts = hash.split('$').collect::<Vec<&str>>();
+
+    parts.len() == 4
+        && parts[0].is_empty()
+        && ["2a", "2b", "2y"].contains(&parts[1])
+        && parts[2].parse::<u32>().ok() == Some(cost)
+        && parts[3].len() == 53
+}
+
+/// Hashes a user's password with the given bcrypt cost and stores it.
+/// Every write to the `password` column should go through this, so a
+/// plaintext password or a hash with the wrong cost is never stored.
+/// 
+/// # Errors
+/// If the password can't be hashed, or the hash isn't a valid bcrypt
+/// hash of the given cost, nothing is stored and an error is returned.
+/// A `PasswordError::UserNotFound` is returned if there is no such user.
+pub fn set_password(conn: &SqliteConnection, user: i32, plaintext: &str, cost: u32) -> Result<(), Error> {
+    use self::schema::users::dsl::*;
+
+    let hash = bcrypt::hash(plaintext, cost)?;
+    ensure!(is_bcrypt_hash(&hash, cost), PasswordError::InvalidHash(cost));
+
+    let updated = diesel::update(users.find(user))
+        .set(password.eq(&hash))
+        .execute(conn)?;
+
+    ensure!(updated == 1, PasswordError::UserNotFound(user));
+
+    Ok(())
+}
+
+/// Sets a user's password for them, the same way as `set_password`.
+/// If `revoke_at` is given, every session the user started and token
+/// they were issued up to then is revoked in the same transaction, so
+/// the old password can't keep them logged in.
+/// 
+/// # Errors
+/// The same as `set_password`. Nothing is changed if any of it fails.
+pub fn reset_password(conn: &SqliteConnection, user: i32, plaintext: &str, cost: u32, revoke_at: Option<i64>) -> Result<(), Error> {
+    use self::schema::session_revocations::dsl::*;
+
+    conn.transaction::<_, Error, _>(|| {
+        set_password(conn, user, plaintext, cost)?;
+
+        if let Some(time) = revoke_at {
+            diesel::replace_into(session_revocations)
+                .values(&models::SessionRevocation { user_id: user, revoked_at: time })
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Returns when a user's sessions were last revoked, or `None` if they
+/// never have been.
+pub fn sessions_revoked_at(conn: &SqliteConnection, user: i32) -> QueryResult<Option<i64>> {
+    use self::schema::session_revocations::dsl::*;
+
+    session_revocations
+        .find(user)
+        .select(revoked_at)
+        .first::<i64>(conn)
+        .optional()
+}
+
+/// The longest units string that can be stored for an xbee.
+/// 
+/// SQLite does not enforce `VARCHAR` widths, so this is checked before
+/// writing to the `units` column instead. It matches the size of the
+/// units field in the info packet sent by the nodes.
+pub const MAX_UNITS_LENGTH: usize = 10;
+
+/// The longest description that can be stored for an xbee.
+pub const MAX_DESCRIPTION_LENGTH: usize = 2000;
+
+/// The icons a node can be shown with on the dashboard.
+pub const NODE_ICONS: &[&str] = &[
+    "thermometer", "droplet", "sun", "wind", "gauge", "bolt",
+    "battery", "leaf", "home", "flame", "snowflake", "alert",
+];
+
+/// Errors caused by invalid data being written to the database.
+#[derive(Debug, Fail)]
+pub enum ValidationError {
+    #[fail(display = "Field 'units' must be at most {} characters.", _0)]
+    UnitsTooLong(usize),
+    #[fail(display = "Field 'units' must not be empty.")]
+    MissingUnits,
+    #[fail(display = "Field 'description' must be at most {} characters.", _0)]
+    DescriptionTooLong(usize),
+    #[fail(display = "Field 'color' must be a hex color such as #1e90ff.")]
+    InvalidColor,
+    #[fail(display = "Field 'icon' must be one of: {}.", _0)]
+    UnknownIcon(String),
+    #[fail(display = "A node with id {} already exists.", _0)]
+    NodeExists(i32),
+    #[fail(display = "Field 'report_interval_secs' must be positive.")]
+    InvalidReportInterval,
+    #[fail(display = "No node with id {} exists.", _0)]
+    NodeNotFound(i32),
+    #[fail(display = "Field 'min_alert' must not be greater than 'max_alert'.")]
+    InvalidThresholds,
+    #[fail(display = "Field 'retention_days' must be positive.")]
+    InvalidRetention,
+    #[fail(display = "Field 'alert_hysteresis' must not be negative.")]
+    InvalidHysteresis,
+    #[fail(display = "Field 'min_reading_interval_secs' must not be negative.")]
+    InvalidReadingInterval,
+    #[fail(display = "Field 'calibration_scale' must not be 0.")]
+    InvalidCalibrationScale,
+    #[fail(display = "Field 'latitude' must be between -90 and 90.")]
+    InvalidLatitude,
+    #[fail(display = "Field 'longitude' must be between -180 and 180.")]
+    InvalidLongitude,
+    #[fail(display = "Node {} has no pending deletion to confirm.", _0)]
+    NoPendingDeletion(i32),
+    #[fail(display = "A deletion must be confirmed by a different admin than the one who asked for it.")]
+    SelfApprovedDeletion,
+}
+
+impl ValidationError {
+    /// Returns the name of the field the error is about, if it is
+    /// about a single field.
+    pub fn field(&self) -> Option<&'static str> {
+        match *self {
+            ValidationError::UnitsTooLong(_) | ValidationError::MissingUnits => Some("units"),
+            ValidationError::DescriptionTooLong(_) => Some("description"),
+            ValidationError::InvalidColor => Some("color"),
+            ValidationError::UnknownIcon(_) => Some("icon"),
+            ValidationError::NodeExists(_) | ValidationError::NodeNotFound(_) => Some("node_id"),
+            ValidationError::InvalidReportInterval => Some("report_interval_secs"),
+            ValidationError::InvalidThresholds => Some("min_alert"),
+            ValidationError::InvalidRetention => Some("retention_days"),
+            ValidationError::InvalidHysteresis => Some("alert_hysteresis"),
+            ValidationError::InvalidReadingInterval => Some("min_reading_interval_secs"),
+            ValidationError::InvalidCalibrationScale => Some("calibration_scale"),
+            ValidationError::InvalidLatitude => Some("latitude"),
+            ValidationError::InvalidLongitude => Some("longitude"),
+            ValidationError::NoPendingDeletion(_) | ValidationError::SelfApprovedDeletion => None,
+        }
+    }
+}
+
+/// Ensures the given units will fit in the `units` column.
+pub fn validate_units(units: &str) -> Result<(), ValidationError> {
+    if units.chars().count() > MAX_UNITS_LENGTH {
+        return Err(ValidationError::UnitsTooLong(MAX_UNITS_LENGTH));
+    }
+
+    Ok(())
+}
+
+/// Ensures a node's description, if it has one, will fit within
+/// `MAX_DESCRIPTION_LENGTH`.
+pub fn validate_description(description: Option<&str>) -> Result<(), ValidationError> {
+    if description.map_or(false, |description| description.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(ValidationError::DescriptionTooLong(MAX_DESCRIPTION_LENGTH));
+    }
+
+    Ok(())
+}
+
+/// Ensures a node's color, if it has one, is a hex color in the form
+/// `#rrggbb`.
+pub fn validate_color(color: Option<&str>) -> Result<(), ValidationError> {
+    match color {
+        Some(color) if color.len() != 7
+            || !color.starts_with('#')
+            || !color[1..].chars().all(|c| c.is_digit(16)) => Err(ValidationError::InvalidColor),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's icon, if it has one, is in `NODE_ICONS`.
+pub fn validate_icon(icon: Option<&str>) -> Result<(), ValidationError> {
+    match icon {
+        Some(icon) if !NODE_ICONS.contains(&icon) => Err(ValidationError::UnknownIcon(NODE_ICONS.join(", "))),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's report interval, if it has one, is positive.
+pub fn validate_report_interval(interval: Option<i32>) -> Result<(), ValidationError> {
+    match interval {
+        Some(secs) if secs <= 0 => Err(ValidationError::InvalidReportInterval),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's retention period, if it has one, is positive.
+pub fn validate_retention_days(days: Option<i32>) -> Result<(), ValidationError> {
+    match days {
+        Some(days) if days <= 0 => Err(ValidationError::InvalidRetention),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's alert hysteresis, if it has one, isn't negative.
+pub fn validate_hysteresis(margin: Option<f64>) -> Result<(), ValidationError> {
+    match margin {
+        Some(margin) if margin < 0.0 => Err(ValidationError::InvalidHysteresis),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's minimum reading interval, if it has one, isn't
+/// negative. An interval of 0 turns the check off for the node.
+pub fn validate_reading_interval(interval: Option<i32>) -> Result<(), ValidationError> {
+    match interval {
+        Some(secs) if secs < 0 => Err(ValidationError::InvalidReadingInterval),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's calibration scale, if it has one, isn't 0, which
+/// would make every value the same.
+pub fn validate_calibration_scale(scale: Option<f64>) -> Result<(), ValidationError> {
+    match scale {
+        Some(scale) if scale == 0.0 => Err(ValidationError::InvalidCalibrationScale),
+        _ => Ok(()),
+    }
+}
+
+/// Ensures a node's location, if it has one, is a place on Earth.
+pub fn validate_location(lat: Option<f64>, long: Option<f64>) -> Result<(), ValidationError> {
+    if lat.map_or(false, |lat| lat < -90.0 || lat > 90.0) {
+        return Err(ValidationError::InvalidLatitude);
+    }
+
+    if long.map_or(false, |long| long < -180.0 || long > 180.0) {
+        return Err(ValidationError::InvalidLongitude);
+    }
+
+    Ok(())
+}
+
+/// Ensures a node's alert thresholds, if it has both, are in order.
+pub fn validate_thresholds(min: Option<f64>, max: Option<f64>) -> Result<(), ValidationError> {
+    match (min, max) {
+        (Some(min), Some(max)) if min > max => Err(ValidationError::InvalidThresholds),
+        _ => Ok(()),
+    }
+}
+
+/// Returns every problem with the fields of a new xbee, without
+/// looking at the database.
+fn new_xbee_errors(new: &models::NewXbee) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    if new.units.trim().is_empty() {
+        errors.push(ValidationError::MissingUnits);
+    }
+
+    if let Err(invalid) = validate_units(&new.units) {
+        errors.push(invalid);
+    }
+
+    if let Err(invalid) = validate_report_interval(new.report_interval_secs) {
+        errors.push(invalid);
+    }
+
+    if let Err(invalid) = validate_description(new.description.as_ref().map(String::as_str)) {
+        errors.push(invalid);
+    }
+
+    if let Err(invalid) = validate_color(new.color.as_ref().map(String::as_str)) {
+        errors.push(invalid);
+    }
+
+    if let Err(invalid) = validate_icon(new.icon.as_ref().map(String::as_str)) {
+        errors.push(invalid);
+    }
+
+    //  Checked one at a time so both can be reported.
+    if let Err(invalid) = validate_location(new.latitude, None) {
+        errors.push(invalid);
+    }
+
+    if let Err(invalid) = validate_location(None, new.longitude) {
+        errors.push(invalid);
+    }
+
+    errors
+}
+
+/// Returns every reason `create_xbee` would refuse the given xbee,
+/// including a node with the same id already existing, without
+/// saving anything. An empty list means it would be created.
+pub fn validate_xbee(conn: &SqliteConnection, new: &models::NewXbee) -> QueryResult<Vec<ValidationError>> {
+    let mut errors = new_xbee_errors(new);
+
+    if !existing_nodes(conn, &[new.node_id])?.is_empty() {
+        errors.push(ValidationError::NodeExists(new.node_id));
+    }
+
+    Ok(errors)
+}
+
 /// Creates a new row in the database with the given xbee information.
 /// 
 /// This information will be used to keep track of which nodes have 
 /// connected to our main server at least once.
-pub fn create_xbee(conn: &SqliteConnection, node_id: i32, name: &String, units: &String) -> usize {
+/// 
+/// # Errors
+/// Returns a `ValidationError` if any field is invalid or a node
+/// with the same id already exists, or the underlying database error
+/// if the row could not be saved.
+/// 
+/// Duplicates are caught by the unique index on `node_id` rather than
+/// by checking first, so two requests adding the same node at the
+/// same time can't both succeed.
+pub fn create_xbee(conn: &SqliteConnection, new: &models::NewXbee) -> Result<usize, Error> {
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
     use self::schema::xbees;
-    use self::models::NewXbee;
 
-    let new = NewXbee {
-        node_id: node_id,
-        name: name.clone(),
-        units: units.clone(),
+    if let Some(invalid) = new_xbee_errors(new).into_iter().next() {
+        return Err(invalid.into());
+    }
+
+    let result = diesel::insert_into(xbees::table)
+        .values(new)
+        .execute(conn);
+
+    match result {
+        Ok(inserted) => Ok(inserted),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(ValidationError::NodeExists(new.node_id).into())
+        }
+        Err(why) => Err(why.into()),
+    }
+}
+
+/// Creates a node with the configuration of an existing one, under a
+/// new id and name. Its units, display order, whether it is enabled,
+/// report interval, alert thresholds and hysteresis, retention period
+/// and tags are all copied.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if the source node doesn't exist or a
+/// node with the new id already exists, or the underlying database
+/// error if the node could not be saved.
+pub fn clone_xbee(conn: &SqliteConnection, source: i32, new_node_id: i32, new_name: &str) -> Result<usize, Error> {
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+    use self::schema::xbees::dsl::*;
+    use self::schema::node_tags;
+    use self::models::NodeTag;
+
+    conn.transaction::<_, Error, _>(|| {
+        let original = match xbees.filter(node_id.eq(source)).first::<models::Xbees>(conn) {
+            Ok(original) => original,
+            Err(DieselError::NotFound) => return Err(ValidationError::NodeNotFound(source).into()),
+            Err(why) => return Err(why.into()),
+        };
+
+        let result = diesel::insert_into(xbees)
+            .values((
+                node_id.eq(new_node_id),
+                name.eq(new_name),
+                units.eq(original.units.as_str()),
+                display_order.eq(original.display_order),
+                enabled.eq(original.enabled),
+                report_interval_secs.eq(original.report_interval_secs),
+                min_alert.eq(original.min_alert),
+                max_alert.eq(original.max_alert),
+                alert_hysteresis.eq(original.alert_hysteresis),
+                retention_days.eq(original.retention_days),
+                min_reading_interval_secs.eq(original.min_reading_interval_secs),
+            ))
+            .execute(conn);
+
+        let inserted = match result {
+            Ok(inserted) => inserted,
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                return Err(ValidationError::NodeExists(new_node_id).into());
+            }
+            Err(why) => return Err(why.into()),
+        };
+
+        let tags = node_tags::table
+            .filter(node_tags::node_id.eq(source))
+            .select(node_tags::tag)
+            .load::<String>(conn)?
+            .into_iter()
+            .map(|copied| NodeTag {
+                node_id: new_node_id,
+                tag: copied,
+            })
+            .collect::<Vec<NodeTag>>();
+
+        diesel::insert_into(node_tags::table)
+            .values(&tags)
+            .execute(conn)?;
+
+        Ok(inserted)
+    })
+}
+
+/// Applies the given changes to an existing xbee.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if any new field is invalid, a
+/// `NotFound` error if the node is not in the database, or the
+/// underlying database error if the row could not be saved.
+pub fn update_xbee(conn: &SqliteConnection, node: i32, update: &models::XbeeUpdate) -> Result<usize, Error> {
+    use self::schema::xbees::dsl::*;
+
+    if let Some(ref new_units) = update.units {
+        validate_units(new_units)?;
+    }
+
+    validate_report_interval(update.report_interval_secs)?;
+    validate_retention_days(update.retention_days)?;
+    validate_location(update.latitude, update.longitude)?;
+    validate_hysteresis(update.alert_hysteresis)?;
+    validate_description(update.description.as_ref().map(String::as_str))?;
+    validate_reading_interval(update.min_reading_interval_secs)?;
+    validate_color(update.color.as_ref().map(String::as_str))?;
+    validate_icon(update.icon.as_ref().map(String::as_str))?;
+
+    //  Diesel refuses to run an update with nothing to set, so
+    //  check the node exists instead.
+    if update.is_empty() {
+        xbees.filter(node_id.eq(node)).select(id).first::<i32>(conn)?;
+        return Ok(0);
+    }
+
+    //  Any change means someone has looked at the node, so it no
+    //  longer counts as auto-registered.
+    let updated = diesel::update(xbees.filter(node_id.eq(node)))
+        .set((update, auto_registered.eq(false)))
+        .execute(conn)?;
+
+    if updated == 0 {
+        return Err(diesel::result::Error::NotFound.into());
+    }
+
+    Ok(updated)
+}
+
+/// Assigns display positions to the given nodes in a single transaction.
+/// 
+/// Returns the number of rows that were updated. Nodes that are not in
+/// the database are ignored.
+pub fn set_display_order(conn: &SqliteConnection, orders: &[models::NodeOrder]) -> QueryResult<usize> {
+    use self::schema::xbees::dsl::*;
+
+    conn.transaction(|| {
+        let mut updated = 0;
+
+        for entry in orders {
+            updated += diesel::update(xbees.filter(node_id.eq(entry.node_id)))
+                .set(display_order.eq(entry.order))
+                .execute(conn)?;
+        }
+
+        Ok(updated)
+    })
+}
+
+/// Sets whether each of the given nodes is enabled in a single
+/// transaction. Returns how many nodes changed, which leaves out those
+/// that already had the flag, and which of the given nodes are in the
+/// database.
+pub fn set_enabled(conn: &SqliteConnection, nodes: &[i32], value: bool) -> QueryResult<(usize, Vec<i32>)> {
+    use self::schema::xbees::dsl::*;
+
+    conn.transaction(|| {
+        let existing = existing_nodes(conn, nodes)?;
+
+        let changed = diesel::update(xbees
+                .filter(node_id.eq_any(&existing))
+                .filter(enabled.ne(value)))
+            .set(enabled.eq(value))
+            .execute(conn)?;
+
+        Ok((changed, existing))
+    })
+}
+
+/// Mutes a node's alerts until the given time, or unmutes it if there
+/// is none. The change is recorded in the audit log as made by `actor`
+/// at `now`, in the same transaction. Returns how many nodes were
+/// changed, which is 0 if the node isn't in the database.
+pub fn set_muted_until(conn: &SqliteConnection, node: i32, until: Option<i64>, actor: i32, now: i64) -> QueryResult<usize> {
+    use self::schema::xbees::dsl::*;
+
+    conn.transaction(|| {
+        let changed = diesel::update(xbees.filter(node_id.eq(node)))
+            .set(muted_until.eq(until))
+            .execute(conn)?;
+
+        if changed > 0 {
+            let (kind, detail) = match until {
+                Some(until) => ("mute", Some(format!("until {}", until))),
+                None => ("unmute", None),
+            };
+
+            record_audit(conn, Some(actor), kind, Some(node), None, detail, now)?;
+        }
+
+        Ok(changed)
+    })
+}
+
+/// Returns the times each node's alerts were muted, keyed by node id,
+/// as `(start, end)` pairs oldest first. They are found from the mutes
+/// and unmutes recorded in the audit log. A mute ends when it runs out
+/// or when the node is unmuted or muted again, whichever is first.
+pub fn mute_windows(conn: &SqliteConnection) -> QueryResult<HashMap<u32, Vec<(i64, i64)>>> {
+    use self::schema::audit_events::dsl::*;
+
+    let events = audit_events
+        .filter(action.eq_any(vec!["mute", "unmute"]))
+        .filter(node_id.is_not_null())
+        .order((timestamp.asc(), id.asc()))
+        .load::<models::AuditEvent>(conn)?;
+
+    let mut windows: HashMap<u32, Vec<(i64, i64)>> = HashMap::new();
+    let mut open: HashMap<u32, (i64, i64)> = HashMap::new();
+
+    for event in events {
+        let node = match event.node_id {
+            Some(node) => node as u32,
+            None => continue,
+        };
+
+        if let Some((start, until)) = open.remove(&node) {
+            windows.entry(node).or_insert_with(Vec::new).push((start, until.min(event.timestamp)));
+        }
+
+        if event.action == "mute" {
+            let until = event.detail.as_ref()
+                .and_then(|detail| detail.trim_left_matches("until ").parse::<i64>().ok());
+
+            if let Some(until) = until {
+                open.insert(node, (event.timestamp, until));
+            }
+        }
+    }
+
+    for (node, window) in open {
+        windows.entry(node).or_insert_with(Vec::new).push(window);
+    }
+
+    Ok(windows)
+}
+
+/// Sets the calibration applied to a node's values, replacing the one
+/// it had. A missing offset or scale leaves values as they are.
+/// Returns how many nodes were changed, which is 0 if the node isn't
+/// in the database.
+pub fn set_calibration(conn: &SqliteConnection, node: i32, offset: Option<f64>, scale: Option<f64>) -> Result<usize, Error> {
+    use self::schema::xbees::dsl::*;
+
+    validate_calibration_scale(scale)?;
+
+    let updated = diesel::update(xbees.filter(node_id.eq(node)))
+        .set((calibration_offset.eq(offset), calibration_scale.eq(scale)))
+        .execute(conn)?;
+
+    Ok(updated)
+}
+
+/// Changes a node's units. If converted values are given, they replace
+/// the node's calibration and alert thresholds in the same update, so
+/// its values are never shown or checked in the new units without
+/// them. Returns how many nodes were changed, which is 0 if the node
+/// isn't in the database.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if the units are too long, the scale
+/// is 0, or the thresholds or hysteresis are invalid, or the
+/// underlying database error if the row could not be saved.
+pub fn set_units(conn: &SqliteConnection, node: i32, new_units: &str, converted: Option<&models::ConvertedValues>) -> Result<usize, Error> {
+    use self::schema::xbees::dsl::*;
+
+    validate_units(new_units)?;
+
+    let target = xbees.filter(node_id.eq(node));
+    let updated = match converted {
+        Some(converted) => {
+            validate_calibration_scale(Some(converted.calibration_scale))?;
+            validate_thresholds(converted.min_alert, converted.max_alert)?;
+            validate_hysteresis(converted.alert_hysteresis)?;
+            diesel::update(target)
+                .set((
+                    units.eq(new_units),
+                    calibration_scale.eq(Some(converted.calibration_scale)),
+                    calibration_offset.eq(Some(converted.calibration_offset)),
+                    min_alert.eq(converted.min_alert),
+                    max_alert.eq(converted.max_alert),
+                    alert_hysteresis.eq(converted.alert_hysteresis),
+                    auto_registered.eq(false),
+                ))
+                .execute(conn)?
+        }
+        None => {
+            diesel::update(target)
+                .set((units.eq(new_units), auto_registered.eq(false)))
+                .execute(conn)?
+        }
+    };
+
+    Ok(updated)
+}
+
+/// Returns every node in the database, keyed by node id.
+pub fn xbee_config_map(conn: &SqliteConnection) -> QueryResult<HashMap<u32, models::Xbees>> {
+    use self::schema::xbees::dsl::*;
+
+    let rows = xbees.load::<models::Xbees>(conn)?;
+
+    Ok(rows.into_iter().map(|row| (row.node_id as u32, row)).collect())
+}
+
+/// Returns a single node in the database, or `None` if it isn't there.
+pub fn xbee_config(conn: &SqliteConnection, node: i32) -> QueryResult<Option<models::Xbees>> {
+    use self::schema::xbees::dsl::*;
+
+    xbees
+        .filter(node_id.eq(node))
+        .first::<models::Xbees>(conn)
+        .optional()
+}
+
+/// Returns the nodes in the database from the most recently added,
+/// along with how many nodes there are in total. Only `limit` nodes
+/// are returned, starting after the first `offset`.
+pub fn recent_xbees(conn: &SqliteConnection, offset: i64, limit: i64) -> QueryResult<(Vec<models::Xbees>, i64)> {
+    use self::schema::xbees::dsl::*;
+
+    let found = xbees
+        .order((created_at.desc(), id.desc()))
+        .offset(offset)
+        .limit(limit)
+        .load::<models::Xbees>(conn)?;
+
+    Ok((found, xbees.count().get_result(conn)?))
+}
+
+/// Stores a reading that was received from a node.
+pub fn create_reading(conn: &SqliteConnection, node: u32, value: u16, time: i64) -> QueryResult<usize> {
+    use self::schema::readings;
+    use self::models::NewReading;
+
+    let new = NewReading {
+        node_id: node as i32,
+        reading: value as i32,
+        timestamp: time,
     };
 
-    diesel::replace_into(xbees::table)
+    diesel::insert_into(readings::table)
         .values(&new)
         .execute(conn)
-        .expect("Error saving new xbee")
-}
\ No newline at end of file
+}
+
+/// Stores several readings in a single transaction, so either every
+/// reading is saved or none are. Returns how many were saved.
+pub fn create_readings(conn: &SqliteConnection, new: &[models::NewReading]) -> QueryResult<usize> {
+    use self::schema::readings;
+
+    conn.transaction(|| {
+        let mut saved = 0;
+
+        for reading in new {
+            saved += diesel::insert_into(readings::table)
+                .values(reading)
+                .execute(conn)?;
+        }
+
+        Ok(saved)
+    })
+}
+
+/// Adds placeholder nodes for the given ids and stores readings in a
+/// single transaction, so either all of them are saved or none are.
+/// The nodes are named after their ids, given `node_units`, and
+/// marked as auto-registered. Each registration is recorded in the
+/// audit log as made by `actor` at `now`. Returns how many readings
+/// were saved.
+pub fn register_and_create_readings(conn: &SqliteConnection, nodes: &[i32], node_units: &str, new: &[models::NewReading], actor: i32, now: i64) -> QueryResult<usize> {
+    use self::schema::xbees::dsl::*;
+
+    conn.transaction(|| {
+        for &node in nodes {
+            diesel::insert_into(xbees)
+                .values((
+                    node_id.eq(node),
+                    name.eq(format!("Node {}", node)),
+                    units.eq(node_units),
+                    auto_registered.eq(true),
+                ))
+                .execute(conn)?;
+
+            record_audit(conn, Some(actor), "auto_register", Some(node), None, None, now)?;
+        }
+
+        create_readings(conn, new)
+    })
+}
+
+/// Returns the timestamps of the given nodes' stored readings taken
+/// within the given time range, inclusive, keyed by node id and
+/// ordered oldest first. Nodes without readings in the range are left
+/// out.
+pub fn reading_times(conn: &SqliteConnection, nodes: &[i32], from: i64, to: i64) -> QueryResult<HashMap<i32, Vec<i64>>> {
+    use self::schema::readings::dsl::*;
+
+    let mut times = HashMap::new();
+
+    if nodes.is_empty() {
+        return Ok(times);
+    }
+
+    let rows = readings
+        .filter(node_id.eq_any(nodes))
+        .filter(timestamp.between(from, to))
+        .order((node_id, timestamp))
+        .select((node_id, timestamp))
+        .load::<(i32, i64)>(conn)?;
+
+    for (node, time) in rows {
+        times.entry(node).or_insert_with(Vec::new).push(time);
+    }
+
+    Ok(times)
+}
+
+/// Returns the newest timestamp of a node's stored readings, or `None`
+/// if it has none.
+pub fn newest_reading_time(conn: &SqliteConnection, node: i32) -> QueryResult<Option<i64>> {
+    reading_range(conn, Some(node)).map(|range| range.newest)
+}
+
+/// Returns up to `count` of the most recent readings for every node,
+/// keyed by node id and ordered newest first.
+/// 
+/// This is done with a single windowed query so the cost does not
+/// grow with the number of nodes.
+pub fn recent_readings(conn: &SqliteConnection, count: u32) -> QueryResult<HashMap<u32, Vec<models::Reading>>> {
+    recent_readings_between(conn, count, None, None)
+}
+
+/// Returns up to `count` of the most recent readings for every node
+/// taken within the given time range, keyed by node id and ordered
+/// newest first. Either end of the range may be left open.
+/// 
+/// Like `recent_readings`, this is a single windowed query however
+/// many nodes there are.
+pub fn recent_readings_between(conn: &SqliteConnection, count: u32, from: Option<i64>, to: Option<i64>) -> QueryResult<HashMap<u32, Vec<models::Reading>>> {
+    use diesel::sql_types::{BigInt, Integer, Nullable};
+
+    let rows = diesel::sql_query(
+            "SELECT id, node_id, reading, timestamp FROM (
+                SELECT *, ROW_NUMBER() OVER (
+                    PARTITION BY node_id ORDER BY timestamp DESC
+                ) AS row_num
+                FROM readings
+                WHERE (? IS NULL OR timestamp >= ?)
+                AND (? IS NULL OR timestamp <= ?)
+            )
+            WHERE row_num <= ?
+            ORDER BY node_id, timestamp DESC")
+        .bind::<Nullable<BigInt>, _>(from)
+        .bind::<Nullable<BigInt>, _>(from)
+        .bind::<Nullable<BigInt>, _>(to)
+        .bind::<Nullable<BigInt>, _>(to)
+        .bind::<Integer, _>(count as i32)
+        .load::<models::Reading>(conn)?;
+
+    let mut history = HashMap::new();
+    for row in rows {
+        history.entry(row.node_id as u32)
+            .or_insert_with(Vec::new)
+            .push(row);
+    }
+
+    Ok(history)
+}
+
+/// Returns the configuration of every xbee in the database,
+/// ordered by node id.
+pub fn xbee_configs(conn: &SqliteConnection) -> QueryResult<Vec<models::XbeeConfig>> {
+    use self::schema::xbees::dsl::*;
+
+    xbees
+        .select((node_id, name, units, display_order, enabled, report_interval_secs, min_alert, max_alert, retention_days, latitude, longitude, location_name, alert_hysteresis, description, min_reading_interval_secs, color, icon, calibration_offset, calibration_scale))
+        .order(node_id)
+        .load::<models::XbeeConfig>(conn)
+}
+
+/// Returns the configuration and tags of every xbee in the database,
+/// ordered by node id, for a backup.
+pub fn xbee_backups(conn: &SqliteConnection) -> QueryResult<Vec<models::XbeeBackup>> {
+    let configs = xbee_configs(conn)?;
+    let nodes = configs.iter().map(|config| config.node_id).collect::<Vec<i32>>();
+    let mut tags = tags_for(conn, &nodes)?;
+
+    Ok(configs.into_iter()
+        .map(|config| {
+            let node_tags = tags.remove(&(config.node_id as u32)).unwrap_or_default();
+            models::XbeeBackup {
+                config: config,
+                tags: Some(node_tags),
+            }
+        })
+        .collect())
+}
+
+/// Recreates the given xbee configurations and their tags in a single
+/// transaction.
+/// 
+/// Restoring is idempotent: a node that is already in the database
+/// has its configuration overwritten, and any other node is inserted.
+/// A node's tags are replaced with the ones in the backup, unless the
+/// backup has none for it because it was made before tags were backed
+/// up. Nodes in the database that are not in the backup are left alone.
+/// 
+/// # Errors
+/// If any configuration is invalid or cannot be saved, nothing is
+/// restored and the error is returned.
+pub fn restore_xbees(conn: &SqliteConnection, backups: &[models::XbeeBackup]) -> Result<usize, Error> {
+    use self::schema::xbees::dsl::*;
+    use self::schema::node_tags;
+    use self::models::NodeTag;
+
+    conn.transaction::<_, Error, _>(|| {
+        for backup in backups {
+            let config = &backup.config;
+            validate_units(&config.units)?;
+            validate_report_interval(config.report_interval_secs)?;
+            validate_thresholds(config.min_alert, config.max_alert)?;
+            validate_retention_days(config.retention_days)?;
+            validate_location(config.latitude, config.longitude)?;
+            validate_hysteresis(config.alert_hysteresis)?;
+            validate_description(config.description.as_ref().map(String::as_str))?;
+            validate_reading_interval(config.min_reading_interval_secs)?;
+            validate_color(config.color.as_ref().map(String::as_str))?;
+            validate_icon(config.icon.as_ref().map(String::as_str))?;
+            validate_calibration_scale(config.calibration_scale)?;
+
+            let updated = diesel::update(xbees.filter(node_id.eq(config.node_id)))
+                .set((
+                    name.eq(&config.name),
+                    units.eq(&config.units),
+                    display_order.eq(config.display_order),
+                    enabled.eq(config.enabled),
+                    report_interval_secs.eq(config.report_interval_secs),
+                    min_alert.eq(config.min_alert),
+                    max_alert.eq(config.max_alert),
+                    retention_days.eq(config.retention_days),
+                    latitude.eq(config.latitude),
+                    longitude.eq(config.longitude),
+                    location_name.eq(&config.location_name),
+                    alert_hysteresis.eq(config.alert_hysteresis),
+                    description.eq(&config.description),
+                    min_reading_interval_secs.eq(config.min_reading_interval_secs),
+                    color.eq(&config.color),
+                    icon.eq(&config.icon),
+                    calibration_offset.eq(config.calibration_offset),
+                    calibration_scale.eq(config.calibration_scale),
+                ))
+                .execute(conn)?;
+
+            if updated == 0 {
+                diesel::insert_into(xbees)
+                    .values(config)
+                    .execute(conn)?;
+            }
+
+            if let Some(ref tags) = backup.tags {
+                let mut restored = tags.iter()
+                    .filter_map(|name| normalize_tag(name))
+                    .collect::<Vec<String>>();
+                restored.sort();
+                restored.dedup();
+
+                diesel::delete(node_tags::table.filter(node_tags::node_id.eq(config.node_id)))
+                    .execute(conn)?;
+
+                let rows = restored.into_iter()
+                    .map(|name| NodeTag {
+                        node_id: config.node_id,
+                        tag: name,
+                    })
+                    .collect::<Vec<NodeTag>>();
+
+                diesel::insert_into(node_tags::table)
+                    .values(&rows)
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(backups.len())
+    })
+}
+
+/// Sets the alert thresholds of every node with the given units,
+/// returning how many nodes were updated. A threshold that is `None`
+/// is cleared. This is a single statement, so either every matching
+/// node is updated or none are.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if the minimum is above the maximum,
+/// or the underlying database error if the nodes could not be saved.
+pub fn set_thresholds_by_units(conn: &SqliteConnection, matching: &str, min: Option<f64>, max: Option<f64>) -> Result<usize, Error> {
+    use self::schema::xbees::dsl::*;
+
+    validate_thresholds(min, max)?;
+
+    let updated = diesel::update(xbees.filter(units.eq(matching)))
+        .set((min_alert.eq(min), max_alert.eq(max)))
+        .execute(conn)?;
+
+    Ok(updated)
+}
+
+/// Returns the readings of the given nodes taken within the given
+/// time range, newest first. Either end of the range may be left
+/// open. At most `limit` readings are returned.
+pub fn readings_for(conn: &SqliteConnection, nodes: &[i32], from: Option<i64>, to: Option<i64>, limit: i64) -> QueryResult<Vec<models::Reading>> {
+    use self::schema::readings::dsl::*;
+
+    let mut query = readings
+        .filter(node_id.eq_any(nodes))
+        .into_boxed();
+
+    if let Some(from) = from {
+        query = query.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        query = query.filter(timestamp.le(to));
+    }
+
+    query
+        .order(timestamp.desc())
+        .limit(limit)
+        .load::<models::Reading>(conn)
+}
+
+/// Returns the id of the newest reading in the database, or 0 if there
+/// are none. Each reading stored gets a higher id than every reading
+/// already in the database.
+pub fn latest_reading_id(conn: &SqliteConnection) -> QueryResult<i32> {
+    use self::schema::readings::dsl::*;
+
+    readings
+        .select(diesel::dsl::max(id))
+        .first::<Option<i32>>(conn)
+        .map(|latest| latest.unwrap_or(0))
+}
+
+/// Returns the readings of the given node stored after the reading
+/// with the id `after`, oldest first. At most `limit` readings are
+/// returned.
+pub fn readings_after(conn: &SqliteConnection, node: i32, after: i32, limit: i64) -> QueryResult<Vec<models::Reading>> {
+    use self::schema::readings::dsl::*;
+
+    readings
+        .filter(node_id.eq(node))
+        .filter(id.gt(after))
+        .order(id)
+        .limit(limit)
+        .load::<models::Reading>(conn)
+}
+
+/// Returns the form a tag is stored in, or `None` if the tag
+/// is empty. Tags are trimmed and lowercase.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+/// Returns which of the given node ids are in the database.
+pub fn existing_nodes(conn: &SqliteConnection, nodes: &[i32]) -> QueryResult<Vec<i32>> {
+    use self::schema::xbees::dsl::*;
+
+    xbees
+        .select(node_id)
+        .filter(node_id.eq_any(nodes))
+        .distinct()
+        .load::<i32>(conn)
+}
+
+/// Returns the tags of each of the given nodes, keyed by node id.
+/// Every node is in the map, even if it has no tags.
+pub fn tags_for(conn: &SqliteConnection, nodes: &[i32]) -> QueryResult<HashMap<u32, Vec<String>>> {
+    use self::schema::node_tags::dsl::*;
+
+    let rows = node_tags
+        .filter(node_id.eq_any(nodes))
+        .order((node_id, tag))
+        .load::<models::NodeTag>(conn)?;
+
+    let mut tags = nodes.iter()
+        .map(|&node| (node as u32, Vec::new()))
+        .collect::<HashMap<u32, Vec<String>>>();
+
+    for row in rows {
+        if let Some(list) = tags.get_mut(&(row.node_id as u32)) {
+            list.push(row.tag);
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Returns every tag with how many nodes have it, the most used
+/// first. Tags with the same count are sorted by name.
+/// 
+/// This is a single grouped query, so no nodes are loaded.
+pub fn tag_counts(conn: &SqliteConnection) -> QueryResult<Vec<models::TagCount>> {
+    diesel::sql_query(
+            "SELECT tag, COUNT(*) AS node_count
+            FROM node_tags
+            GROUP BY tag
+            ORDER BY node_count DESC, tag")
+        .load::<models::TagCount>(conn)
+}
+
+/// Returns the nodes in the database that don't have any readings,
+/// from the longest ago added.
+/// 
+/// This is a single joined query, so no readings are loaded.
+pub fn unreported_nodes(conn: &SqliteConnection) -> QueryResult<Vec<models::UnreportedNode>> {
+    diesel::sql_query(
+            "SELECT xbees.node_id AS node_id, xbees.name AS name,
+                xbees.units AS units, xbees.created_at AS created_at
+            FROM xbees
+            LEFT JOIN readings ON readings.node_id = xbees.node_id
+            WHERE readings.id IS NULL
+            ORDER BY xbees.created_at, xbees.node_id")
+        .load::<models::UnreportedNode>(conn)
+}
+
+/// Returns the ids of the nodes that have the given tag, which
+/// must already be normalized.
+pub fn nodes_with_tag(conn: &SqliteConnection, name: &str) -> QueryResult<Vec<i32>> {
+    use self::schema::node_tags::dsl::*;
+
+    node_tags
+        .select(node_id)
+        .filter(tag.eq(name))
+        .load::<i32>(conn)
+}
+
+/// Returns the ids of the nodes located inside the given box. The
+/// bounds are inclusive. If `min_long` is greater than `max_long`, the
+/// box is taken to cross the antimeridian, so it holds the longitudes
+/// from `min_long` east to 180 and from -180 east to `max_long`.
+pub fn nodes_in_box(conn: &SqliteConnection, min_lat: f64, min_long: f64, max_lat: f64, max_long: f64) -> QueryResult<Vec<i32>> {
+    use self::schema::xbees::dsl::*;
+
+    let query = xbees
+        .select(node_id)
+        .filter(latitude.ge(min_lat))
+        .filter(latitude.le(max_lat))
+        .into_boxed();
+
+    let query = if min_long <= max_long {
+        query.filter(longitude.ge(min_long).and(longitude.le(max_long)))
+    } else {
+        query.filter(longitude.ge(min_long).or(longitude.le(max_long)))
+    };
+
+    query
+        .order(node_id)
+        .load::<i32>(conn)
+}
+
+/// Records that the given user acknowledged a node's alert, replacing
+/// any earlier acknowledgement of it. The acknowledgement is recorded
+/// in the audit log in the same transaction.
+pub fn acknowledge_alert(conn: &SqliteConnection, ack: &models::AlertAck) -> QueryResult<usize> {
+    use self::schema::alert_acks::dsl::*;
+
+    conn.transaction(|| {
+        let recorded = diesel::replace_into(alert_acks)
+            .values(ack)
+            .execute(conn)?;
+
+        record_audit(conn, Some(ack.acknowledged_by), "alert_ack", Some(ack.node_id), None, None, ack.acknowledged_at)?;
+        Ok(recorded)
+    })
+}
+
+/// Records several acknowledgements in a single transaction, so either
+/// every alert is acknowledged or none are. Earlier acknowledgements
+/// of the same nodes are replaced. Each one is recorded in the audit
+/// log as well.
+/// 
+/// Returns the number of acknowledgements that were recorded.
+pub fn acknowledge_alerts(conn: &SqliteConnection, acks: &[models::AlertAck]) -> QueryResult<usize> {
+    use self::schema::alert_acks::dsl::*;
+
+    conn.transaction(|| {
+        let mut recorded = 0;
+
+        for ack in acks {
+            recorded += diesel::replace_into(alert_acks)
+                .values(ack)
+                .execute(conn)?;
+
+            record_audit(conn, Some(ack.acknowledged_by), "alert_ack", Some(ack.node_id), None, None, ack.acknowledged_at)?;
+        }
+
+        Ok(recorded)
+    })
+}
+
+/// Returns the latest acknowledgement of each node's alert, keyed by
+/// node id.
+pub fn alert_acks(conn: &SqliteConnection) -> QueryResult<HashMap<u32, models::AlertAck>> {
+    use self::schema::alert_acks::dsl::*;
+
+    let rows = alert_acks.load::<models::AlertAck>(conn)?;
+
+    Ok(rows.into_iter().map(|row| (row.node_id as u32, row)).collect())
+}
+
+/// Returns the tags of a single node in order, or `None` if the
+/// node is not in the database.
+/// 
+/// This is a single query, so the node can't be removed between
+/// checking that it exists and reading its tags.
+pub fn tags_of_node(conn: &SqliteConnection, node: i32) -> QueryResult<Option<Vec<String>>> {
+    use diesel::sql_types::Integer;
+
+    let rows = diesel::sql_query(
+            "SELECT node_tags.tag AS tag
+            FROM xbees
+            LEFT JOIN node_tags ON node_tags.node_id = xbees.node_id
+            WHERE xbees.node_id = ?
+            ORDER BY node_tags.tag")
+        .bind::<Integer, _>(node)
+        .load::<models::MaybeTag>(conn)?;
+
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(rows.into_iter().filter_map(|row| row.tag).collect()))
+}
+
+/// Adds and removes tags on the given nodes in a single transaction,
+/// returning the resulting tags of each node.
+/// 
+/// Tags are normalized first. Adding a tag a node already has, or
+/// removing one it doesn't have, does nothing. If a tag is both added
+/// and removed, it is removed.
+pub fn assign_tags(conn: &SqliteConnection, nodes: &[i32], add: &[String], remove: &[String]) -> QueryResult<HashMap<u32, Vec<String>>> {
+    use self::schema::node_tags::dsl::*;
+    use self::models::NodeTag;
+
+    let add = add.iter().filter_map(|name| normalize_tag(name)).collect::<Vec<String>>();
+    let remove = remove.iter().filter_map(|name| normalize_tag(name)).collect::<Vec<String>>();
+
+    conn.transaction(|| {
+        let new = nodes.iter()
+            .flat_map(|&node| add.iter().map(move |name| NodeTag {
+                node_id: node,
+                tag: name.clone(),
+            }))
+            .collect::<Vec<NodeTag>>();
+
+        //  Replacing keeps adds of existing tags from failing on
+        //  the primary key.
+        diesel::replace_into(node_tags)
+            .values(&new)
+            .execute(conn)?;
+
+        diesel::delete(node_tags.filter(node_id.eq_any(nodes)).filter(tag.eq_any(&remove)))
+            .execute(conn)?;
+
+        tags_for(conn, nodes)
+    })
+}
+
+/// Renames a tag on every node that has it in a single transaction,
+/// returning how many nodes were affected. Nodes that already have
+/// the new tag keep a single copy of it. Both names must already
+/// be normalized.
+pub fn rename_tag(conn: &SqliteConnection, from: &str, to: &str) -> QueryResult<usize> {
+    use self::schema::node_tags::dsl::*;
+    use self::models::NodeTag;
+
+    if from == to {
+        return Ok(0);
+    }
+
+    conn.transaction(|| {
+        let nodes = node_tags
+            .select(node_id)
+            .filter(tag.eq(from))
+            .load::<i32>(conn)?;
+
+        let renamed = nodes.iter()
+            .map(|&node| NodeTag {
+                node_id: node,
+                tag: to.to_string(),
+            })
+            .collect::<Vec<NodeTag>>();
+
+        //  Replacing merges the tag into nodes that already have it.
+        diesel::replace_into(node_tags)
+            .values(&renamed)
+            .execute(conn)?;
+
+        diesel::delete(node_tags.filter(tag.eq(from)))
+            .execute(conn)?;
+
+        Ok(nodes.len())
+    })
+}
+
+/// Returns the timestamps of the oldest and newest readings, of only
+/// the given node if there is one.
+/// 
+/// This is a single aggregate query, so no readings are loaded.
+pub fn reading_range(conn: &SqliteConnection, node: Option<i32>) -> QueryResult<models::ReadingRange> {
+    use diesel::sql_types::{Integer, Nullable};
+
+    diesel::sql_query(
+            "SELECT MIN(timestamp) AS oldest, MAX(timestamp) AS newest
+            FROM readings
+            WHERE ?1 IS NULL OR node_id = ?1")
+        .bind::<Nullable<Integer>, _>(node)
+        .get_result::<models::ReadingRange>(conn)
+}
+
+/// Counts the readings of every node taken within the given time
+/// range, along with the oldest and newest timestamps. Either end of
+/// the range may be left open.
+/// 
+/// This is a single grouped query, so no readings are loaded.
+pub fn reading_counts(conn: &SqliteConnection, from: Option<i64>, to: Option<i64>) -> QueryResult<Vec<models::ReadingCount>> {
+    use diesel::sql_types::{BigInt, Nullable};
+
+    diesel::sql_query(
+            "SELECT node_id, COUNT(*) AS count,
+                MIN(timestamp) AS oldest, MAX(timestamp) AS newest
+            FROM readings
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+                AND (?2 IS NULL OR timestamp <= ?2)
+            GROUP BY node_id
+            ORDER BY node_id")
+        .bind::<Nullable<BigInt>, _>(from)
+        .bind::<Nullable<BigInt>, _>(to)
+        .load::<models::ReadingCount>(conn)
+}
+
+/// Finds the lowest, highest and mean reading of each of the given
+/// nodes within the given time range. Either end of the range may be
+/// left open. Nodes without any readings in the range are left out.
+/// 
+/// This is a single grouped query, so no readings are loaded.
+pub fn reading_stats(conn: &SqliteConnection, nodes: &[i32], from: Option<i64>, to: Option<i64>) -> QueryResult<Vec<models::ReadingStats>> {
+    use diesel::sql_types::{BigInt, Nullable};
+
+    if nodes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    //  Node ids are integers, so they are safe to put in the query.
+    let ids = nodes.iter()
+        .map(|node| node.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    diesel::sql_query(format!(
+            "SELECT node_id, COUNT(*) AS count,
+                MIN(reading) AS min_reading, MAX(reading) AS max_reading,
+                AVG(reading) AS mean_reading
+            FROM readings
+            WHERE node_id IN ({})
+                AND (?1 IS NULL OR timestamp >= ?1)
+                AND (?2 IS NULL OR timestamp <= ?2)
+            GROUP BY node_id
+            ORDER BY node_id", ids))
+        .bind::<Nullable<BigInt>, _>(from)
+        .bind::<Nullable<BigInt>, _>(to)
+        .load::<models::ReadingStats>(conn)
+}
+
+/// Returns a `LIKE` pattern matching text that contains the given
+/// text. `%`, `_` and `\` in the text are escaped, so it has to be
+/// used with `ESCAPE '\'`.
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+
+        pattern.push(c);
+    }
+
+    pattern.push('%');
+    pattern
+}
+
+/// Lists users whose username contains the given text, ordered by
+/// username, along with how many users match in total. Only `limit`
+/// users are returned, starting after the first `offset`. Deleted
+/// users are left out unless `include_deleted` is true.
+/// 
+/// Usernames are stored in lowercase, so the search is case-insensitive.
+pub fn search_users(conn: &SqliteConnection, search: Option<&str>, include_deleted: bool, offset: i64, limit: i64) -> QueryResult<(Vec<models::UserSummary>, i64)> {
+    use self::schema::users::dsl::*;
+
+    let pattern = search.map(|search| contains_pattern(&normalize_username(search)));
+
+    let mut matching = users.into_boxed();
+    let mut total = users.count().into_boxed();
+
+    if let Some(ref pattern) = pattern {
+        matching = matching.filter(username.like(pattern.clone()).escape('\\'));
+        total = total.filter(username.like(pattern.clone()).escape('\\'));
+    }
+
+    if !include_deleted {
+        matching = matching.filter(deleted_at.is_null());
+        total = total.filter(deleted_at.is_null());
+    }
+
+    let found = matching
+        .select((id, username, role, deleted_at))
+        .order(username)
+        .offset(offset)
+        .limit(limit)
+        .load::<models::UserSummary>(conn)?;
+
+    Ok((found, total.get_result(conn)?))
+}
+
+/// Moves the readings of one node taken within the given time range
+/// to another node, returning how many readings were moved. Both ends
+/// of the range are inclusive.
+/// 
+/// # Errors
+/// If either node is not in the database, a `ValidationError` naming
+/// it is returned and no readings are moved.
+pub fn reassign_readings(conn: &SqliteConnection, from: i32, to: i32, from_time: i64, to_time: i64) -> Result<usize, Error> {
+    use self::schema::readings::dsl::*;
+
+    conn.transaction::<_, Error, _>(|| {
+        let existing = existing_nodes(conn, &[from, to])?;
+
+        for &node in &[from, to] {
+            if !existing.contains(&node) {
+                return Err(ValidationError::NodeNotFound(node).into());
+            }
+        }
+
+        let moved = diesel::update(readings
+                .filter(node_id.eq(from))
+                .filter(timestamp.ge(from_time))
+                .filter(timestamp.le(to_time)))
+            .set(node_id.eq(to))
+            .execute(conn)?;
+
+        Ok(moved)
+    })
+}
+
+/// Creates an admin with the given username and password, hashed
+/// with the given bcrypt cost, but only if there are no users yet.
+/// Returns whether it was created.
+pub fn bootstrap_admin(conn: &SqliteConnection, name: &str, plaintext: &str, cost: u32) -> Result<bool, Error> {
+    use self::schema::users::dsl::*;
+    use self::models::{NewUser, ADMIN_ROLE};
+
+    conn.transaction(|| {
+        let existing = users.count().get_result::<i64>(conn)?;
+
+        if existing > 0 {
+            return Ok(false);
+        }
+
+        //  The password is set right after in the same transaction,
+        //  so the empty one is never seen.
+        let admin = NewUser {
+            username: normalize_username(name),
+            password: String::new(),
+            role: ADMIN_ROLE.to_string(),
+        };
+
+        diesel::insert_into(users)
+            .values(&admin)
+            .execute(conn)?;
+
+        let admin_id = users
+            .filter(username.eq(&admin.username))
+            .select(id)
+            .first::<i32>(conn)?;
+
+        set_password(conn, admin_id, plaintext, cost)?;
+
+        Ok(true)
+    })
+}
+
+/// Creates a user with the given username and password, hashed with
+/// the given bcrypt cost, and the `USER_ROLE`. Returns the new user's
+/// id, or `None` if the username is taken, ignoring case.
+/// 
+/// # Errors
+/// If the user can't be saved or the password can't be hashed, nothing
+/// is saved and the error is returned.
+pub fn register_user(conn: &SqliteConnection, name: &str, plaintext: &str, cost: u32) -> Result<Option<i32>, Error> {
+    use self::schema::users::dsl::*;
+    use self::models::{NewUser, USER_ROLE};
+
+    conn.transaction(|| {
+        if username_taken(conn, name)? {
+            return Ok(None);
+        }
+
+        //  The password is set right after in the same transaction,
+        //  so the empty one is never seen.
+        let user = NewUser {
+            username: normalize_username(name),
+            password: String::new(),
+            role: USER_ROLE.to_string(),
+        };
+
+        diesel::insert_into(users)
+            .values(&user)
+            .execute(conn)?;
+
+        let user_id = users
+            .filter(username.eq(&user.username))
+            .select(id)
+            .first::<i32>(conn)?;
+
+        set_password(conn, user_id, plaintext, cost)?;
+
+        Ok(Some(user_id))
+    })
+}
+
+/// Marks a user as deleted at the given time, and revokes every
+/// session they started and token they were issued up to then in the
+/// same transaction, along with an audit event for `actor`. Their row
+/// is kept, so what they did can still be traced to them and they can
+/// be brought back with `restore_user`. Returns whether the user was
+/// deleted, which is false if there is no such user or they already
+/// were.
+pub fn soft_delete_user(conn: &SqliteConnection, user: i32, actor: i32, now: i64) -> QueryResult<bool> {
+    use self::schema::{session_revocations, users};
+
+    conn.transaction(|| {
+        let updated = diesel::update(users::table.find(user).filter(users::deleted_at.is_null()))
+            .set(users::deleted_at.eq(Some(now)))
+            .execute(conn)?;
+
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        diesel::replace_into(session_revocations::table)
+            .values(&models::SessionRevocation { user_id: user, revoked_at: now })
+            .execute(conn)?;
+
+        record_audit(conn, Some(actor), "user_delete", None, Some(user), None, now)?;
+        Ok(true)
+    })
+}
+
+/// Brings back a user deleted with `soft_delete_user`. Their sessions
+/// stay revoked, so they have to log in again. The restore is recorded
+/// in the audit log as made by `actor` at `now`. Returns whether the
+/// user was restored, which is false if there is no such user or they
+/// weren't deleted.
+pub fn restore_user(conn: &SqliteConnection, user: i32, actor: i32, now: i64) -> QueryResult<bool> {
+    use self::schema::users::dsl::*;
+
+    conn.transaction(|| {
+        let updated = diesel::update(users.find(user).filter(deleted_at.is_not_null()))
+            .set(deleted_at.eq(None::<i64>))
+            .execute(conn)?;
+
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        record_audit(conn, Some(actor), "user_restore", None, Some(user), None, now)?;
+        Ok(true)
+    })
+}
+
+/// Returns whether a user has been deleted. Users that don't exist
+/// count as deleted.
+pub fn user_deleted(conn: &SqliteConnection, user: i32) -> QueryResult<bool> {
+    use self::schema::users::dsl::*;
+
+    let found = users
+        .find(user)
+        .select(deleted_at)
+        .first::<Option<i64>>(conn)
+        .optional()?;
+
+    Ok(found.map_or(true, |deleted| deleted.is_some()))
+}
+
+/// Returns whether a user with the given username exists, ignoring case.
+/// Deleted users still hold on to their username.
+pub fn username_taken(conn: &SqliteConnection, name: &str) -> QueryResult<bool> {
+    use self::schema::users::dsl::*;
+
+    let matching = users
+        .filter(username.eq(normalize_username(name)))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(matching > 0)
+}
+
+/// Returns the preferences the given user has saved, if any.
+pub fn user_prefs(conn: &SqliteConnection, user: i32) -> QueryResult<Option<models::UserPrefs>> {
+    use self::schema::user_prefs::dsl::*;
+
+    user_prefs
+        .find(user)
+        .first::<models::UserPrefs>(conn)
+        .optional()
+}
+
+/// Saves a user's preferences, replacing any they had before.
+pub fn set_user_prefs(conn: &SqliteConnection, prefs: &models::UserPrefs) -> QueryResult<usize> {
+    use self::schema::user_prefs::dsl::*;
+
+    diesel::replace_into(user_prefs)
+        .values(prefs)
+        .execute(conn)
+}
+
+/// Returns every user with a rate limit tier other than the default.
+pub fn rate_limit_tiers(conn: &SqliteConnection) -> QueryResult<Vec<models::RateLimitTier>> {
+    use self::schema::rate_limit_tiers::dsl::*;
+
+    rate_limit_tiers
+        .order(user_id)
+        .load::<models::RateLimitTier>(conn)
+}
+
+/// Sets a user's rate limit tier, replacing the one they had before.
+/// Passing `None` puts them back on the default tier.
+pub fn set_rate_limit_tier(conn: &SqliteConnection, user: i32, name: Option<&str>) -> QueryResult<usize> {
+    use self::schema::rate_limit_tiers::dsl::*;
+
+    match name {
+        Some(name) => {
+            diesel::replace_into(rate_limit_tiers)
+                .values(&models::RateLimitTier { user_id: user, tier: name.to_string() })
+                .execute(conn)
+        }
+        None => diesel::delete(rate_limit_tiers.find(user)).execute(conn),
+    }
+}
+
+/// Returns the users with the given ids, ordered by id. Ids that
+/// don't belong to any user are left out, but deleted users are
+/// included, so what they did can still be traced to them.
+pub fn users_by_ids(conn: &SqliteConnection, ids: &[i32]) -> QueryResult<Vec<models::UserSummary>> {
+    use self::schema::users::dsl::*;
+
+    users
+        .select((id, username, role, deleted_at))
+        .filter(id.eq_any(ids))
+        .order(id)
+        .load::<models::UserSummary>(conn)
+}
+
+/// Returns everything stored about the given user, or `None` if there
+/// is no such user. Only rows belonging to the user are included, and
+/// audit events are the ones the user made. Sessions aren't stored in
+/// the database, so the user's are given as `sessions`.
+pub fn user_export(conn: &SqliteConnection, user: i32, sessions: Vec<ActiveSession>) -> QueryResult<Option<models::UserExport>> {
+    use self::schema::{alert_acks, api_keys, audit_events, pending_deletions, rate_limit_tiers, sent_messages, users};
+
+    conn.transaction(|| {
+        let profile = users::table
+            .find(user)
+            .select((users::id, users::username, users::role, users::deleted_at))
+            .first::<models::UserSummary>(conn)
+            .optional()?;
+
+        let profile = match profile {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+
+        Ok(Some(models::UserExport {
+            user: profile,
+            prefs: user_prefs(conn, user)?,
+            rate_limit_tier: rate_limit_tiers::table
+                .find(user)
+                .select(rate_limit_tiers::tier)
+                .first::<String>(conn)
+                .optional()?,
+            sent_messages: sent_messages::table
+                .filter(sent_messages::sender_id.eq(user))
+                .order(sent_messages::timestamp)
+                .load::<models::SentMessage>(conn)?,
+            alert_acks: alert_acks::table
+                .filter(alert_acks::acknowledged_by.eq(user))
+                .order(alert_acks::acknowledged_at)
+                .load::<models::AlertAck>(conn)?,
+            pending_deletions: pending_deletions::table
+                .filter(pending_deletions::requested_by.eq(user))
+                .order(pending_deletions::requested_at)
+                .load::<models::PendingDeletion>(conn)?,
+            api_keys: api_keys::table
+                .filter(api_keys::user_id.eq(user))
+                .select((api_keys::id, api_keys::label, api_keys::created_at, api_keys::last_used_at, api_keys::signing_secret.is_not_null()))
+                .order(api_keys::id)
+                .load::<models::ApiKeySummary>(conn)?,
+            audit_events: audit_events::table
+                .filter(audit_events::actor_id.eq(user))
+                .order((audit_events::timestamp, audit_events::id))
+                .load::<models::AuditEvent>(conn)?,
+            sessions: sessions,
+        }))
+    })
+}
+
+/// Stores a new API key for the given user, returning its id. Keys
+/// with a signing secret only accept signed requests.
+pub fn create_api_key(conn: &SqliteConnection, user: i32, name: &str, hash: &str, secret: Option<&str>, now: i64) -> QueryResult<i32> {
+    use self::schema::api_keys::dsl::*;
+
+    let new = models::NewApiKey {
+        user_id: user,
+        label: name.to_string(),
+        key_hash: hash.to_string(),
+        created_at: now,
+        signing_secret: secret.map(String::from),
+    };
+
+    //  SQLite can't return the new id, so it's read back in the same
+    //  transaction. Ids only ever go up, so it's the highest one.
+    conn.transaction(|| {
+        diesel::insert_into(api_keys)
+            .values(&new)
+            .execute(conn)?;
+
+        api_keys
+            .select(id)
+            .order(id.desc())
+            .first::<i32>(conn)
+    })
+}
+
+/// Returns the API keys of the given user, oldest first, without
+/// their hashes.
+pub fn api_keys_of(conn: &SqliteConnection, user: i32) -> QueryResult<Vec<models::ApiKeySummary>> {
+    use self::schema::api_keys::dsl::*;
+
+    api_keys
+        .filter(user_id.eq(user))
+        .select((id, label, created_at, last_used_at, signing_secret.is_not_null()))
+        .order(id)
+        .load::<models::ApiKeySummary>(conn)
+}
+
+/// Returns the API key with the given id, or `None` if there is none.
+pub fn find_api_key(conn: &SqliteConnection, key: i32) -> QueryResult<Option<models::ApiKey>> {
+    use self::schema::api_keys::dsl::*;
+
+    api_keys
+        .find(key)
+        .first::<models::ApiKey>(conn)
+        .optional()
+}
+
+/// Records that an API key was used at the given time.
+pub fn touch_api_key(conn: &SqliteConnection, key: i32, now: i64) -> QueryResult<usize> {
+    use self::schema::api_keys::dsl::*;
+
+    diesel::update(api_keys.find(key))
+        .set(last_used_at.eq(Some(now)))
+        .execute(conn)
+}
+
+/// Deletes one of the given user's API keys, returning how many were
+/// deleted. Keys of other users are never deleted.
+pub fn revoke_api_key(conn: &SqliteConnection, user: i32, key: i32) -> QueryResult<usize> {
+    use self::schema::api_keys::dsl::*;
+
+    diesel::delete(api_keys.filter(id.eq(key)).filter(user_id.eq(user)))
+        .execute(conn)
+}
+
+/// Stores a new webhook, returning its id. Hooks without a node get
+/// the alerts of every node.
+pub fn create_webhook(conn: &SqliteConnection, hook_url: &str, node: Option<i32>, user: i32, now: i64) -> QueryResult<i32> {
+    use self::schema::webhooks::dsl::*;
+
+    let new = models::NewWebhook {
+        url: hook_url.to_string(),
+        node_id: node,
+        created_by: user,
+        created_at: now,
+    };
+
+    //  Read back the same way as in `create_api_key`.
+    conn.transaction(|| {
+        diesel::insert_into(webhooks)
+            .values(&new)
+            .execute(conn)?;
+
+        webhooks
+            .select(id)
+            .order(id.desc())
+            .first::<i32>(conn)
+    })
+}
+
+/// Returns every webhook, oldest first.
+pub fn all_webhooks(conn: &SqliteConnection) -> QueryResult<Vec<models::Webhook>> {
+    use self::schema::webhooks::dsl::*;
+
+    webhooks
+        .order(id)
+        .load::<models::Webhook>(conn)
+}
+
+/// Returns the webhooks that get the given node's alerts, which are
+/// its own and the ones for every node, oldest first.
+pub fn webhooks_for(conn: &SqliteConnection, node: i32) -> QueryResult<Vec<models::Webhook>> {
+    use self::schema::webhooks::dsl::*;
+
+    webhooks
+        .filter(node_id.eq(node).or(node_id.is_null()))
+        .order(id)
+        .load::<models::Webhook>(conn)
+}
+
+/// Returns the webhook with the given id, or `None` if there is none.
+pub fn find_webhook(conn: &SqliteConnection, hook: i32) -> QueryResult<Option<models::Webhook>> {
+    use self::schema::webhooks::dsl::*;
+
+    webhooks
+        .find(hook)
+        .first::<models::Webhook>(conn)
+        .optional()
+}
+
+/// Deletes a webhook, returning how many were deleted.
+pub fn delete_webhook(conn: &SqliteConnection, hook: i32) -> QueryResult<usize> {
+    use self::schema::webhooks::dsl::*;
+
+    diesel::delete(webhooks.find(hook))
+        .execute(conn)
+}
+
+/// How many readings are deleted per statement when purging.
+const PURGE_BATCH_SIZE: i64 = 1000;
+
+/// Deletes every reading taken before the given Unix timestamp,
+/// returning how many were removed.
+/// 
+/// Readings are deleted in batches so that a large purge doesn't
+/// hold a lock on the readings table for too long at once.
+pub fn purge_readings_older_than(conn: &SqliteConnection, cutoff: i64) -> QueryResult<usize> {
+    purge_readings_matching(conn, cutoff, None, &[])
+}
+
+/// Deletes readings taken before the given Unix timestamp in batches,
+/// from only the given node if there is one, and never from the
+/// excluded nodes. Returns how many were removed.
+fn purge_readings_matching(conn: &SqliteConnection, cutoff: i64, only: Option<i32>, excluded: &[i32]) -> QueryResult<usize> {
+    use self::schema::readings::dsl::*;
+    use diesel::dsl::not;
+
+    let mut removed = 0;
+
+    loop {
+        let mut batch = readings
+            .select(id)
+            .filter(timestamp.lt(cutoff))
+            .into_boxed();
+
+        if let Some(node) = only {
+            batch = batch.filter(node_id.eq(node));
+        }
+
+        if !excluded.is_empty() {
+            batch = batch.filter(not(node_id.eq_any(excluded)));
+        }
+
+        let deleted = diesel::delete(readings.filter(id.eq_any(batch.limit(PURGE_BATCH_SIZE))))
+            .execute(conn)?;
+
+        removed += deleted;
+
+        if (deleted as i64) < PURGE_BATCH_SIZE {
+            return Ok(removed);
+        }
+    }
+}
+
+/// Deletes every reading older than its node's retention period as of
+/// `now`, returning how many were removed. Nodes without a retention
+/// period of their own use `default_days`, or keep every reading if
+/// that isn't set either.
+pub fn purge_expired_readings(conn: &SqliteConnection, now: i64, default_days: Option<i64>) -> QueryResult<usize> {
+    use self::schema::xbees::dsl::*;
+
+    let overrides = xbees
+        .select((node_id, retention_days))
+        .filter(retention_days.is_not_null())
+        .load::<(i32, Option<i32>)>(conn)?;
+
+    let mut removed = 0;
+
+    for &(node, days) in &overrides {
+        if let Some(days) = days {
+            removed += purge_readings_matching(conn, now - i64::from(days) * 60 * 60 * 24, Some(node), &[])?;
+        }
+    }
+
+    if let Some(days) = default_days {
+        let overridden = overrides.iter().map(|&(node, _)| node).collect::<Vec<i32>>();
+        removed += purge_readings_matching(conn, now - days * 60 * 60 * 24, None, &overridden)?;
+    }
+
+    Ok(removed)
+}
+
+/// Stores a failed login attempt with the username as it was typed.
+pub fn create_failed_login(conn: &SqliteConnection, name: &str, address: Option<String>, time: i64) -> QueryResult<usize> {
+    use self::schema::failed_logins;
+    use self::models::NewFailedLogin;
+
+    let new = NewFailedLogin {
+        username: name.to_string(),
+        ip: address,
+        timestamp: time,
+    };
+
+    diesel::insert_into(failed_logins::table)
+        .values(&new)
+        .execute(conn)
+}
+
+/// Lists failed login attempts made within the given time range,
+/// newest first, along with how many match in total. Either end of
+/// the range may be left open. If a username is given, only attempts
+/// whose username contains it, ignoring case, are listed. Only `limit`
+/// attempts are returned, starting after the first `offset`.
+pub fn list_failed_logins(conn: &SqliteConnection, search: Option<&str>, from: Option<i64>, to: Option<i64>, offset: i64, limit: i64) -> QueryResult<(Vec<models::FailedLogin>, i64)> {
+    use self::schema::failed_logins::dsl::*;
+
+    let mut matching = failed_logins.into_boxed();
+    let mut total = failed_logins.count().into_boxed();
+
+    if let Some(search) = search {
+        let pattern = contains_pattern(search);
+        matching = matching.filter(username.like(pattern.clone()).escape('\\'));
+        total = total.filter(username.like(pattern).escape('\\'));
+    }
+
+    if let Some(from) = from {
+        matching = matching.filter(timestamp.ge(from));
+        total = total.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        matching = matching.filter(timestamp.le(to));
+        total = total.filter(timestamp.le(to));
+    }
+
+    let found = matching
+        .order((timestamp.desc(), id.desc()))
+        .offset(offset)
+        .limit(limit)
+        .load::<models::FailedLogin>(conn)?;
+
+    Ok((found, total.get_result(conn)?))
+}
+
+/// Deletes every failed login attempt made before the given Unix
+/// timestamp, returning how many were removed.
+pub fn purge_failed_logins_older_than(conn: &SqliteConnection, cutoff: i64) -> QueryResult<usize> {
+    use self::schema::failed_logins::dsl::*;
+
+    diesel::delete(failed_logins.filter(timestamp.lt(cutoff)))
+        .execute(conn)
+}
+
+/// Stores an event in the audit log. `actor` is the user who did it,
+/// or `None` if the server did it on its own. `node` and `target_user`
+/// are what it was done to, if anything.
+pub fn record_audit(conn: &SqliteConnection, actor: Option<i32>, action: &str, node: Option<i32>, target_user: Option<i32>, detail: Option<String>, time: i64) -> QueryResult<usize> {
+    use self::schema::audit_events;
+    use self::models::NewAuditEvent;
+
+    let new = NewAuditEvent {
+        actor_id: actor,
+        action: action.to_string(),
+        node_id: node,
+        target_user_id: target_user,
+        detail: detail,
+        timestamp: time,
+    };
+
+    diesel::insert_into(audit_events::table)
+        .values(&new)
+        .execute(conn)
+}
+
+/// Lists audit events made within the given time range, newest first
+/// unless `oldest_first` is set. Either end of the range may be left
+/// open. If an action is given, only events with that action are
+/// listed. Only `limit` events are returned, starting after the first
+/// `offset`.
+pub fn list_audit_events(conn: &SqliteConnection, kind: Option<&str>, from: Option<i64>, to: Option<i64>, oldest_first: bool, offset: i64, limit: i64) -> QueryResult<Vec<models::AuditEvent>> {
+    use self::schema::audit_events::dsl::*;
+
+    let mut matching = audit_events.into_boxed();
+
+    if let Some(kind) = kind {
+        matching = matching.filter(action.eq(kind));
+    }
+
+    if let Some(from) = from {
+        matching = matching.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        matching = matching.filter(timestamp.le(to));
+    }
+
+    matching = if oldest_first {
+        matching.order((timestamp.asc(), id.asc()))
+    } else {
+        matching.order((timestamp.desc(), id.desc()))
+    };
+
+    matching
+        .offset(offset)
+        .limit(limit)
+        .load::<models::AuditEvent>(conn)
+}
+
+/// Lists audit events with any of the given actions made within the
+/// given time range, newest first, along with how many match in total.
+/// Either end of the range may be left open. If a node is given, only
+/// events about it are listed. At most `limit` events are returned.
+pub fn list_audit_events_by_action(conn: &SqliteConnection, kinds: &[&str], node: Option<i32>, from: Option<i64>, to: Option<i64>, limit: i64) -> QueryResult<(Vec<models::AuditEvent>, i64)> {
+    use self::schema::audit_events::dsl::*;
+
+    let mut matching = audit_events.filter(action.eq_any(kinds.to_vec())).into_boxed();
+    let mut total = audit_events.filter(action.eq_any(kinds.to_vec())).count().into_boxed();
+
+    if let Some(node) = node {
+        matching = matching.filter(node_id.eq(node));
+        total = total.filter(node_id.eq(node));
+    }
+
+    if let Some(from) = from {
+        matching = matching.filter(timestamp.ge(from));
+        total = total.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        matching = matching.filter(timestamp.le(to));
+        total = total.filter(timestamp.le(to));
+    }
+
+    let found = matching
+        .order((timestamp.desc(), id.desc()))
+        .limit(limit)
+        .load::<models::AuditEvent>(conn)?;
+
+    Ok((found, total.get_result(conn)?))
+}
+
+/// Counts the audit events `list_audit_events` would list for the
+/// same filters.
+pub fn count_audit_events(conn: &SqliteConnection, kind: Option<&str>, from: Option<i64>, to: Option<i64>) -> QueryResult<i64> {
+    use self::schema::audit_events::dsl::*;
+
+    let mut total = audit_events.count().into_boxed();
+
+    if let Some(kind) = kind {
+        total = total.filter(action.eq(kind));
+    }
+
+    if let Some(from) = from {
+        total = total.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        total = total.filter(timestamp.le(to));
+    }
+
+    total.get_result(conn)
+}
+
+/// Stores messages that were sent to nodes, returning how many
+/// were stored.
+pub fn create_sent_messages(conn: &SqliteConnection, messages: &[models::NewSentMessage]) -> QueryResult<usize> {
+    use self::schema::sent_messages;
+
+    diesel::insert_into(sent_messages::table)
+        .values(messages)
+        .execute(conn)
+}
+
+/// Returns a page of the messages sent to a node, or to every node if
+/// none is given, newest first, along with how many there are in
+/// total. Either end of the time range may be left open.
+pub fn list_sent_messages(conn: &SqliteConnection, node: Option<i32>, from: Option<i64>, to: Option<i64>, offset: i64, limit: i64) -> QueryResult<(Vec<models::SentMessage>, i64)> {
+    use self::schema::sent_messages::dsl::*;
+
+    let mut matching = sent_messages.into_boxed();
+    let mut total = sent_messages.count().into_boxed();
+
+    if let Some(node) = node {
+        matching = matching.filter(node_id.eq(node));
+        total = total.filter(node_id.eq(node));
+    }
+
+    if let Some(from) = from {
+        matching = matching.filter(timestamp.ge(from));
+        total = total.filter(timestamp.ge(from));
+    }
+
+    if let Some(to) = to {
+        matching = matching.filter(timestamp.le(to));
+        total = total.filter(timestamp.le(to));
+    }
+
+    let found = matching
+        .order((timestamp.desc(), id.desc()))
+        .offset(offset)
+        .limit(limit)
+        .load::<models::SentMessage>(conn)?;
+
+    Ok((found, total.get_result(conn)?))
+}
+
+/// Deletes a node's configuration and tags, along with any pending
+/// deletion or alert acknowledgement of it. Its readings are kept, so
+/// they can still be reassigned. Returns how many nodes were deleted.
+pub fn delete_xbee(conn: &SqliteConnection, node: i32) -> QueryResult<usize> {
+    use self::schema::{alert_acks, node_tags, pending_deletions, xbees};
+
+    conn.transaction(|| {
+        diesel::delete(alert_acks::table.filter(alert_acks::node_id.eq(node)))
+            .execute(conn)?;
+
+        diesel::delete(node_tags::table.filter(node_tags::node_id.eq(node)))
+            .execute(conn)?;
+
+        diesel::delete(pending_deletions::table.filter(pending_deletions::node_id.eq(node)))
+            .execute(conn)?;
+
+        diesel::delete(xbees::table.filter(xbees::node_id.eq(node)))
+            .execute(conn)
+    })
+}
+
+/// Merges the source node into the target node in a single transaction
+/// and deletes the source. Returns how many readings and sent messages
+/// were moved to the target.
+/// 
+/// The target keeps its own configuration. Its report interval and
+/// retention period are only taken from the source if it has none, and
+/// the same goes for its alert thresholds if it has neither of them.
+/// The target gets every tag of the source. A pending deletion of the
+/// source is dropped along with it.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if either node doesn't exist, or the
+/// underlying database error if the merge could not be saved.
+pub fn merge_xbees(conn: &SqliteConnection, source: i32, target: i32) -> Result<(usize, usize), Error> {
+    use self::schema::{node_tags, readings, sent_messages, xbees};
+    use self::models::NodeTag;
+
+    conn.transaction::<_, Error, _>(|| {
+        let mut nodes = xbees::table
+            .filter(xbees::node_id.eq_any(&[source, target]))
+            .load::<models::Xbees>(conn)?;
+
+        let position = |node: i32, nodes: &[models::Xbees]| nodes.iter().position(|row| row.node_id == node);
+        let original = match position(source, &nodes) {
+            Some(index) => nodes.remove(index),
+            None => return Err(ValidationError::NodeNotFound(source).into()),
+        };
+        let merged = match position(target, &nodes) {
+            Some(index) => nodes.remove(index),
+            None => return Err(ValidationError::NodeNotFound(target).into()),
+        };
+
+        let (min_alert, max_alert) = if merged.min_alert.is_none() && merged.max_alert.is_none() {
+            (original.min_alert, original.max_alert)
+        } else {
+            (merged.min_alert, merged.max_alert)
+        };
+
+        diesel::update(xbees::table.filter(xbees::node_id.eq(target)))
+            .set((
+                xbees::report_interval_secs.eq(merged.report_interval_secs.or(original.report_interval_secs)),
+                xbees::retention_days.eq(merged.retention_days.or(original.retention_days)),
+                xbees::min_alert.eq(min_alert),
+                xbees::max_alert.eq(max_alert),
+            ))
+            .execute(conn)?;
+
+        let tags = node_tags::table
+            .filter(node_tags::node_id.eq(source))
+            .select(node_tags::tag)
+            .load::<String>(conn)?
+            .into_iter()
+            .map(|name| NodeTag {
+                node_id: target,
+                tag: name,
+            })
+            .collect::<Vec<NodeTag>>();
+
+        //  Replacing keeps tags the target already has from failing
+        //  on the primary key.
+        diesel::replace_into(node_tags::table)
+            .values(&tags)
+            .execute(conn)?;
+
+        let moved_readings = diesel::update(readings::table.filter(readings::node_id.eq(source)))
+            .set(readings::node_id.eq(target))
+            .execute(conn)?;
+
+        let moved_sends = diesel::update(sent_messages::table.filter(sent_messages::node_id.eq(source)))
+            .set(sent_messages::node_id.eq(target))
+            .execute(conn)?;
+
+        delete_xbee(conn, source)?;
+
+        Ok((moved_readings, moved_sends))
+    })
+}
+
+/// Asks for a node to be deleted once another admin confirms it.
+/// Asking again restarts the wait, even if another admin asked first.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if the node doesn't exist, or the
+/// underlying database error if the request could not be saved.
+pub fn request_deletion(conn: &SqliteConnection, node: i32, admin: i32, now: i64) -> Result<usize, Error> {
+    use self::schema::pending_deletions;
+    use self::models::PendingDeletion;
+
+    if existing_nodes(conn, &[node])?.is_empty() {
+        return Err(ValidationError::NodeNotFound(node).into());
+    }
+
+    let pending = PendingDeletion {
+        node_id: node,
+        requested_by: admin,
+        requested_at: now,
+    };
+
+    Ok(diesel::replace_into(pending_deletions::table)
+        .values(&pending)
+        .execute(conn)?)
+}
+
+/// Deletes a node whose deletion was asked for by a different admin
+/// at or after `requested_after`. Older requests have expired, and are
+/// removed. Returns how many nodes were deleted.
+/// 
+/// # Errors
+/// Returns a `ValidationError` if there is no unexpired request for the
+/// node, or if the admin confirming it is the one who asked for it.
+pub fn confirm_deletion(conn: &SqliteConnection, node: i32, admin: i32, requested_after: i64) -> Result<usize, Error> {
+    use self::schema::pending_deletions::dsl::*;
+    use self::models::PendingDeletion;
+
+    let pending = match pending_deletions.find(node).first::<PendingDeletion>(conn).optional()? {
+        Some(pending) => pending,
+        None => return Err(ValidationError::NoPendingDeletion(node).into()),
+    };
+
+    if pending.requested_at < requested_after {
+        //  Remove the expired request so it doesn't linger.
+        diesel::delete(pending_deletions.find(node)).execute(conn)?;
+        return Err(ValidationError::NoPendingDeletion(node).into());
+    }
+
+    if pending.requested_by == admin {
+        return Err(ValidationError::SelfApprovedDeletion.into());
+    }
+
+    Ok(delete_xbee(conn, node)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use self::models::{NewUser, User};
+
+    fn add_user(conn: &SqliteConnection, name: &str) -> i32 {
+        use self::schema::users::dsl::*;
+
+        diesel::insert_into(users)
+            .values(&NewUser {
+                username: normalize_username(name),
+                password: String::new(),
+                role: "user".to_string(),
+            })
+            .execute(conn)
+            .unwrap();
+
+        users.filter(username.eq(normalize_username(name))).select(id).first(conn).unwrap()
+    }
+
+    #[test]
+    fn login_ignores_case() {
+        let conn = testing::connection();
+        let alice = add_user(&conn, "Alice");
+
+        for name in &["alice", "ALICE", "aLiCe"] {
+            let found: User = find_login_user(&conn, name).unwrap();
+            assert_eq!(found.id, alice);
+        }
+
+        assert!(username_taken(&conn, "ALICE").unwrap());
+    }
+
+    #[test]
+    fn login_skips_deleted_users() {
+        let conn = testing::connection();
+        let bob = add_user(&conn, "bob");
+        soft_delete_user(&conn, bob, 1, 1530000000).unwrap();
+
+        match find_login_user(&conn, "Bob") {
+            Err(diesel::result::Error::NotFound) => {}
+            _ => panic!("Deleted users shouldn't be able to log in."),
+        }
+    }
+
+    #[test]
+    fn usernames_differing_by_case_collide() {
+        let conn = testing::connection();
+        add_user(&conn, "carol");
+
+        let duplicate = conn.batch_execute("INSERT INTO users (username, password) VALUES ('CAROL', '')");
+        assert!(duplicate.is_err());
+    }
+
+    #[test]
+    fn case_migration_renames_collisions() {
+        use self::schema::users::dsl::*;
+
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        testing::migrate(&conn, |migration| migration < "2018-05-11-152840");
+        conn.batch_execute("
+            INSERT INTO users (id, username, password) VALUES (10, 'Alice', '');
+            INSERT INTO users (id, username, password) VALUES (11, 'alice', '');
+            INSERT INTO users (id, username, password) VALUES (12, 'ALICE', '');
+            INSERT INTO users (id, username, password) VALUES (13, 'Bob', '');
+        ").unwrap();
+        testing::migrate(&conn, |migration| migration >= "2018-05-11-152840");
+
+        let names = users
+            .filter(id.ge(10))
+            .order(id)
+            .select(username)
+            .load::<String>(&conn)
+            .unwrap();
+        assert_eq!(names, vec!["alice", "alice-11", "alice-12", "bob"]);
+
+        let renames = username_renames(&conn).unwrap();
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames[0].user_id, 11);
+        assert_eq!(renames[0].old_username, "alice");
+        assert_eq!(renames[1].new_username, "alice-12");
+    }
+
+    fn backup(tags: &str) -> models::XbeeBackup {
+        let json = format!(r#"{{
+            "node_id": 5, "name": "Roof", "units": "C", "display_order": 0,
+            "report_interval_secs": null, "min_alert": null, "max_alert": null,
+            "retention_days": null, "latitude": null, "longitude": null,
+            "location_name": null, "alert_hysteresis": null, "description": null,
+            "min_reading_interval_secs": null, "color": null, "icon": null,
+            "calibration_offset": null, "calibration_scale": null{}
+        }}"#, tags);
+
+        ::serde_json::from_str(&json).unwrap()
+    }
+
+    fn backed_up_tags(conn: &SqliteConnection) -> Option<Vec<String>> {
+        xbee_backups(conn).unwrap().into_iter()
+            .find(|backup| backup.config.node_id == 5)
+            .unwrap()
+            .tags
+    }
+
+    #[test]
+    fn restore_replaces_tags() {
+        let conn = testing::connection();
+
+        restore_xbees(&conn, &[backup(r#", "tags": ["Greenhouse", "greenhouse ", "roof"]"#)]).unwrap();
+        assert_eq!(backed_up_tags(&conn), Some(vec!["greenhouse".to_string(), "roof".to_string()]));
+
+        restore_xbees(&conn, &[backup(r#", "tags": ["shed"]"#)]).unwrap();
+        assert_eq!(backed_up_tags(&conn), Some(vec!["shed".to_string()]));
+
+        restore_xbees(&conn, &[backup(r#", "tags": []"#)]).unwrap();
+        assert_eq!(backed_up_tags(&conn), Some(vec![]));
+    }
+
+    #[test]
+    fn restore_without_tags_keeps_them() {
+        let conn = testing::connection();
+
+        restore_xbees(&conn, &[backup(r#", "tags": ["roof"]"#)]).unwrap();
+        restore_xbees(&conn, &[backup("")]).unwrap();
+        assert_eq!(backed_up_tags(&conn), Some(vec!["roof".to_string()]));
+    }
+
+    #[test]
+    fn failed_restore_keeps_tags() {
+        let conn = testing::connection();
+        restore_xbees(&conn, &[backup(r#", "tags": ["roof"]"#)]).unwrap();
+
+        let mut invalid = backup(r#", "tags": ["shed"]"#);
+        invalid.config.min_alert = Some(10.0);
+        invalid.config.max_alert = Some(0.0);
+
+        assert!(restore_xbees(&conn, &[backup(r#", "tags": ["shed"]"#), invalid]).is_err());
+        assert_eq!(backed_up_tags(&conn), Some(vec!["roof".to_string()]));
+    }
+
+    fn found_users(conn: &SqliteConnection, search: &str) -> Vec<String> {
+        let (found, total) = search_users(conn, Some(search), false, 0, 50).unwrap();
+        assert_eq!(found.len() as i64, total);
+        found.into_iter().map(|user| user.username).collect()
+    }
+
+    #[test]
+    fn user_search_narrows_by_username() {
+        let conn = testing::connection();
+        add_user(&conn, "alice");
+        add_user(&conn, "malice");
+        add_user(&conn, "bob");
+
+        assert_eq!(found_users(&conn, "LIC"), vec!["alice", "malice"]);
+        assert_eq!(found_users(&conn, "mal"), vec!["malice"]);
+        assert!(found_users(&conn, "carol").is_empty());
+    }
+
+    #[test]
+    fn user_search_treats_wildcards_as_text() {
+        let conn = testing::connection();
+        add_user(&conn, "a_b");
+        add_user(&conn, "axb");
+        add_user(&conn, "50%off");
+        add_user(&conn, "back\\slash");
+
+        assert_eq!(found_users(&conn, "_"), vec!["a_b"]);
+        assert_eq!(found_users(&conn, "%"), vec!["50%off"]);
+        assert_eq!(found_users(&conn, "\\"), vec!["back\\slash"]);
+        assert!(found_users(&conn, "' OR 1=1 --").is_empty());
+    }
+
+    #[test]
+    fn registered_users_can_log_in() {
+        use self::models::USER_ROLE;
+
+        let conn = testing::connection();
+        let user_id = register_user(&conn, "Carol", "hunter2", MIN_BCRYPT_COST).unwrap().unwrap();
+
+        let user: User = find_login_user(&conn, "carol").unwrap();
+        assert_eq!(user.id, user_id);
+        assert_eq!(user.role, USER_ROLE);
+        assert!(bcrypt::verify("hunter2", &user.password).unwrap());
+    }
+
+    #[test]
+    fn registration_refuses_taken_names_in_any_case() {
+        let conn = testing::connection();
+        register_user(&conn, "carol", "hunter2", MIN_BCRYPT_COST).unwrap().unwrap();
+
+        assert_eq!(register_user(&conn, "CAROL", "other", MIN_BCRYPT_COST).unwrap(), None);
+        assert_eq!(register_user(&conn, "Admin", "other", MIN_BCRYPT_COST).unwrap(), None);
+    }
+
+    fn new_xbee(node: i32, new_units: &str) -> models::NewXbee {
+        models::NewXbee {
+            node_id: node,
+            name: "Roof".to_string(),
+            units: new_units.to_string(),
+            report_interval_secs: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            description: None,
+            color: None,
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn over_length_units_are_refused() {
+        let conn = testing::connection();
+        let too_long = "x".repeat(MAX_UNITS_LENGTH + 1);
+
+        let why = create_xbee(&conn, &new_xbee(5, &too_long)).unwrap_err();
+        match why.downcast_ref::<ValidationError>() {
+            Some(&ValidationError::UnitsTooLong(limit)) => assert_eq!(limit, MAX_UNITS_LENGTH),
+            _ => panic!("expected UnitsTooLong, got {}", why),
+        }
+        assert!(xbee_config(&conn, 5).unwrap().is_none());
+
+        create_xbee(&conn, &new_xbee(5, &"x".repeat(MAX_UNITS_LENGTH))).unwrap();
+
+        let update = models::XbeeUpdate {
+            name: None,
+            units: Some(too_long),
+            enabled: None,
+            report_interval_secs: None,
+            retention_days: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            alert_hysteresis: None,
+            description: None,
+            min_reading_interval_secs: None,
+            color: None,
+            icon: None,
+        };
+        let why = update_xbee(&conn, 5, &update).unwrap_err();
+        match why.downcast_ref::<ValidationError>() {
+            Some(&ValidationError::UnitsTooLong(limit)) => assert_eq!(limit, MAX_UNITS_LENGTH),
+            _ => panic!("expected UnitsTooLong, got {}", why),
+        }
+        assert_eq!(xbee_config(&conn, 5).unwrap().unwrap().units, "x".repeat(MAX_UNITS_LENGTH));
+    }
+
+    #[test]
+    fn racing_adds_of_one_node_conflict() {
+        let conn = testing::connection();
+        let first = new_xbee(5, "C");
+        let second = new_xbee(5, "F");
+
+        //  Both requests check before either saves, so both pass.
+        assert!(validate_xbee(&conn, &first).unwrap().is_empty());
+        assert!(validate_xbee(&conn, &second).unwrap().is_empty());
+
+        create_xbee(&conn, &first).unwrap();
+
+        let why = create_xbee(&conn, &second).unwrap_err();
+        match why.downcast_ref::<ValidationError>() {
+            Some(&ValidationError::NodeExists(node)) => assert_eq!(node, 5),
+            _ => panic!("expected NodeExists, got {}", why),
+        }
+        assert_eq!(xbee_config(&conn, 5).unwrap().unwrap().units, "C");
+
+        let errors = validate_xbee(&conn, &second).unwrap();
+        assert_eq!(errors.len(), 1);
+        match errors[0] {
+            ValidationError::NodeExists(5) => {}
+            ref other => panic!("expected NodeExists, got {}", other),
+        }
+    }
+
+    fn stored_password(conn: &SqliteConnection, user: i32) -> String {
+        use self::schema::users::dsl::*;
+
+        users.find(user)
+            .select(password)
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn passwords_are_stored_as_hashes_of_the_cost() {
+        let conn = testing::connection();
+        let user_id = add_user(&conn, "carol");
+
+        set_password(&conn, user_id, "hunter2", MIN_BCRYPT_COST).unwrap();
+
+        let hash = stored_password(&conn, user_id);
+        assert!(is_bcrypt_hash(&hash, MIN_BCRYPT_COST));
+        assert!(!is_bcrypt_hash(&hash, MIN_BCRYPT_COST + 1));
+        assert!(bcrypt::verify("hunter2", &hash).unwrap());
+    }
+
+    #[test]
+    fn plaintext_isnt_a_bcrypt_hash() {
+        assert!(!is_bcrypt_hash("hunter2", MIN_BCRYPT_COST));
+        assert!(!is_bcrypt_hash("", MIN_BCRYPT_COST));
+        assert!(!is_bcrypt_hash("$2y$04$tooshort", MIN_BCRYPT_COST));
+    }
+
+    #[test]
+    fn passwords_of_missing_users_arent_set() {
+        let conn = testing::connection();
+
+        let why = set_password(&conn, 404, "hunter2", MIN_BCRYPT_COST).unwrap_err();
+        match why.downcast_ref::<PasswordError>() {
+            Some(&PasswordError::UserNotFound(user)) => assert_eq!(user, 404),
+            _ => panic!("expected UserNotFound, got {}", why),
+        }
+    }
+
+    #[test]
+    fn mute_windows_end_at_the_next_change() {
+        let conn = testing::connection();
+        let admin = add_user(&conn, "admin");
+        create_xbee(&conn, &new_xbee(5, "C")).unwrap();
+        create_xbee(&conn, &new_xbee(6, "C")).unwrap();
+
+        set_muted_until(&conn, 5, Some(200), admin, 100).unwrap();
+        set_muted_until(&conn, 5, Some(400), admin, 150).unwrap();
+        set_muted_until(&conn, 5, None, admin, 300).unwrap();
+        set_muted_until(&conn, 5, Some(600), admin, 500).unwrap();
+        set_muted_until(&conn, 6, Some(250), admin, 50).unwrap();
+
+        let windows = mute_windows(&conn).unwrap();
+        assert_eq!(windows[&5], vec![(100, 150), (150, 300), (500, 600)]);
+        assert_eq!(windows[&6], vec![(50, 250)]);
+    }
+
+    #[test]
+    fn exports_include_the_users_audit_events_and_sessions() {
+        let conn = testing::connection();
+        let alice = add_user(&conn, "alice");
+        let bob = add_user(&conn, "bob");
+        create_xbee(&conn, &new_xbee(5, "C")).unwrap();
+
+        set_muted_until(&conn, 5, Some(200), alice, 100).unwrap();
+        set_muted_until(&conn, 5, None, bob, 150).unwrap();
+
+        let sessions = vec![ActiveSession { started: 90, expires: 190 }];
+        let export = user_export(&conn, alice, sessions).unwrap().unwrap();
+
+        assert_eq!(export.audit_events.len(), 1);
+        assert_eq!(export.audit_events[0].action, "mute");
+        assert_eq!(export.audit_events[0].actor_id, Some(alice));
+        assert_eq!(export.sessions, vec![ActiveSession { started: 90, expires: 190 }]);
+    }
+
+    #[test]
+    fn keys_with_signing_secrets_are_listed_as_signed() {
+        let conn = testing::connection();
+        let alice = add_user(&conn, "alice");
+
+        let plain = create_api_key(&conn, alice, "plain", "hash", None, 100).unwrap();
+        let signed = create_api_key(&conn, alice, "signed", "hash", Some("secret"), 100).unwrap();
+
+        let keys = api_keys_of(&conn, alice).unwrap();
+        assert_eq!(keys.iter().map(|key| (key.id, key.signed)).collect::<Vec<(i32, bool)>>(), vec![(plain, false), (signed, true)]);
+        assert_eq!(find_api_key(&conn, signed).unwrap().unwrap().signing_secret, Some("secret".to_string()));
+    }
+
+    #[test]
+    fn readings_after_an_id_are_oldest_first() {
+        use self::schema::readings::dsl::*;
+
+        let conn = testing::connection();
+        assert_eq!(latest_reading_id(&conn).unwrap(), 0);
+
+        for &(node, value) in &[(5, 10), (6, 20), (5, 30), (5, 40)] {
+            diesel::insert_into(readings)
+                .values(&models::NewReading { node_id: node, reading: value, timestamp: 100 })
+                .execute(&conn)
+                .unwrap();
+        }
+
+        let latest = latest_reading_id(&conn).unwrap();
+        let after = |first: i32, limit: i64| readings_after(&conn, 5, first, limit).unwrap()
+            .into_iter()
+            .map(|row| row.reading)
+            .collect::<Vec<i32>>();
+
+        assert_eq!(after(0, 10), vec![10, 30, 40]);
+        assert_eq!(after(0, 2), vec![10, 30]);
+        assert_eq!(after(latest - 1, 10), vec![40]);
+        assert!(after(latest, 10).is_empty());
+    }
+
+    #[test]
+    fn webhooks_for_a_node_include_the_ones_for_every_node() {
+        let conn = testing::connection();
+        let admin = add_user(&conn, "admin");
+
+        let every = create_webhook(&conn, "http://example.com/all", None, admin, 100).unwrap();
+        let five = create_webhook(&conn, "http://example.com/5", Some(5), admin, 100).unwrap();
+        create_webhook(&conn, "http://example.com/6", Some(6), admin, 100).unwrap();
+
+        let ids = webhooks_for(&conn, 5).unwrap().into_iter().map(|hook| hook.id).collect::<Vec<i32>>();
+        assert_eq!(ids, vec![every, five]);
+        assert_eq!(all_webhooks(&conn).unwrap().len(), 3);
+
+        assert_eq!(delete_webhook(&conn, five).unwrap(), 1);
+        assert!(find_webhook(&conn, five).unwrap().is_none());
+        assert_eq!(find_webhook(&conn, every).unwrap().unwrap().url, "http://example.com/all");
+    }
+}
@@ -1,4 +1,6 @@
-use db::schema::xbees;
+use db::schema::{alert_acks, api_keys, audit_events, failed_logins, node_tags, pending_deletions, rate_limit_tiers, readings, sent_messages, session_revocations, user_prefs, users, webhooks, xbees};
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+use session::ActiveSession;
 
 /// Represents a row in the database that stores xbee data.
 #[derive(Queryable, Deserialize, Serialize)]
@@ -7,6 +9,26 @@ pub struct Xbees {
     pub node_id: i32,
     pub name: String,
     pub units: String,
+    pub display_order: i32,
+    pub enabled: bool,
+    pub report_interval_secs: Option<i32>,
+    pub created_at: i64,
+    pub min_alert: Option<f64>,
+    pub max_alert: Option<f64>,
+    pub retention_days: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub alert_hysteresis: Option<f64>,
+    pub description: Option<String>,
+    pub min_reading_interval_secs: Option<i32>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub muted_until: Option<i64>,
+    pub calibration_offset: Option<f64>,
+    pub calibration_scale: Option<f64>,
+    pub auto_registered: bool,
+    pub updated_at: i64,
 }
 
 /// Represents information needed to make a new xbee entry
@@ -16,13 +38,442 @@ pub struct Xbees {
 pub struct NewXbee {
     pub node_id: i32,
     pub name: String,
+    #[serde(default)]
     pub units: String,
+    pub report_interval_secs: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
 }
 
-/// Represents a user in the database.
+/// The role given to users who can manage the server.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// The role given to everyone else, including users who register
+/// themselves.
+pub const USER_ROLE: &str = "user";
+
+/// Represents a user in the database. Deleted users keep their row,
+/// with when they were deleted in `deleted_at`.
 #[derive(Queryable, Deserialize, Serialize)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub password: String,
-}
\ No newline at end of file
+    pub role: String,
+    pub deleted_at: Option<i64>,
+}
+
+/// Represents information needed to make a new user. The
+/// password must already be hashed, so it should be set with
+/// `db::set_password` after the user is made.
+#[derive(Insertable)]
+#[table_name = "users"]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+}
+
+/// Represents a user without their password hash. This is the
+/// format users are listed in.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+    pub deleted_at: Option<i64>,
+}
+
+/// Represents a user who was renamed when usernames were made
+/// case-insensitive, since their name collided with an older user's.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct UsernameRename {
+    pub user_id: i32,
+    pub old_username: String,
+    pub new_username: String,
+}
+
+/// Represents a requested position for a node in the
+/// user-defined display order.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NodeOrder {
+    pub node_id: i32,
+    pub order: i32,
+}
+
+/// Represents a single reading received from a node.
+#[derive(Queryable, QueryableByName, Deserialize, Serialize)]
+#[table_name = "readings"]
+pub struct Reading {
+    pub id: i32,
+    pub node_id: i32,
+    pub reading: i32,
+    pub timestamp: i64,
+}
+
+/// Represents information needed to store a new reading.
+#[derive(Insertable, Deserialize, Serialize)]
+#[table_name = "readings"]
+pub struct NewReading {
+    pub node_id: i32,
+    pub reading: i32,
+    pub timestamp: i64,
+}
+
+/// Represents the configuration of an xbee without its database id.
+/// This is the format used for backups.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "xbees"]
+pub struct XbeeConfig {
+    pub node_id: i32,
+    pub name: String,
+    pub units: String,
+    pub display_order: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub report_interval_secs: Option<i32>,
+    pub min_alert: Option<f64>,
+    pub max_alert: Option<f64>,
+    pub retention_days: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub alert_hysteresis: Option<f64>,
+    pub description: Option<String>,
+    pub min_reading_interval_secs: Option<i32>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub calibration_offset: Option<f64>,
+    pub calibration_scale: Option<f64>,
+}
+
+/// Represents a node's configuration along with its tags. This is
+/// the format nodes are backed up in.
+#[derive(Deserialize, Serialize)]
+pub struct XbeeBackup {
+    #[serde(flatten)]
+    pub config: XbeeConfig,
+    /// The node's tags. Backups made before tags were backed up don't
+    /// have them, and restoring those leaves the node's tags alone.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+/// Nodes are enabled unless they are explicitly disabled.
+fn default_enabled() -> bool {
+    true
+}
+
+/// Represents changes to an existing xbee. Fields that are
+/// not set are left as they are.
+#[derive(AsChangeset, Deserialize, Serialize)]
+#[table_name = "xbees"]
+pub struct XbeeUpdate {
+    pub name: Option<String>,
+    pub units: Option<String>,
+    pub enabled: Option<bool>,
+    pub report_interval_secs: Option<i32>,
+    pub retention_days: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub alert_hysteresis: Option<f64>,
+    pub description: Option<String>,
+    pub min_reading_interval_secs: Option<i32>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+impl XbeeUpdate {
+    /// Returns whether the update doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.units.is_none()
+            && self.enabled.is_none()
+            && self.report_interval_secs.is_none()
+            && self.retention_days.is_none()
+            && self.latitude.is_none()
+            && self.longitude.is_none()
+            && self.location_name.is_none()
+            && self.alert_hysteresis.is_none()
+            && self.description.is_none()
+            && self.min_reading_interval_secs.is_none()
+            && self.color.is_none()
+            && self.icon.is_none()
+    }
+}
+
+/// Represents the values of a node that change when its values are
+/// converted to other units.
+pub struct ConvertedValues {
+    pub calibration_scale: f64,
+    pub calibration_offset: f64,
+    pub min_alert: Option<f64>,
+    pub max_alert: Option<f64>,
+    pub alert_hysteresis: Option<f64>,
+}
+
+/// Represents a tag attached to a node.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "node_tags"]
+pub struct NodeTag {
+    pub node_id: i32,
+    pub tag: String,
+}
+
+/// Represents a node joined with one of its tags, if it has any.
+#[derive(QueryableByName)]
+pub struct MaybeTag {
+    #[sql_type = "Nullable<Text>"]
+    pub tag: Option<String>,
+}
+
+/// Represents the timestamps of the oldest and newest readings, which
+/// are both `None` if there are no readings.
+#[derive(QueryableByName, Deserialize, Serialize)]
+pub struct ReadingRange {
+    #[sql_type = "Nullable<BigInt>"]
+    pub oldest: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub newest: Option<i64>,
+}
+
+/// Represents a tag and how many nodes have it.
+#[derive(QueryableByName, Deserialize, Serialize)]
+pub struct TagCount {
+    #[sql_type = "Text"]
+    pub tag: String,
+    #[sql_type = "BigInt"]
+    pub node_count: i64,
+}
+
+/// Represents how many readings a node has sent and the time
+/// range they cover.
+#[derive(QueryableByName, Deserialize, Serialize)]
+pub struct ReadingCount {
+    #[sql_type = "Integer"]
+    pub node_id: i32,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+    #[sql_type = "BigInt"]
+    pub oldest: i64,
+    #[sql_type = "BigInt"]
+    pub newest: i64,
+}
+
+/// Represents the lowest, highest and mean raw reading of a node,
+/// and how many readings they were taken from.
+#[derive(QueryableByName, Deserialize, Serialize)]
+pub struct ReadingStats {
+    #[sql_type = "Integer"]
+    pub node_id: i32,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+    #[sql_type = "Integer"]
+    pub min_reading: i32,
+    #[sql_type = "Integer"]
+    pub max_reading: i32,
+    #[sql_type = "Double"]
+    pub mean_reading: f64,
+}
+
+/// Represents a node in the database that has no readings.
+#[derive(QueryableByName, Deserialize, Serialize)]
+pub struct UnreportedNode {
+    #[sql_type = "Integer"]
+    pub node_id: i32,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Text"]
+    pub units: String,
+    #[sql_type = "BigInt"]
+    pub created_at: i64,
+}
+
+/// Represents a login attempt that failed. The password that was
+/// tried is never stored.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct FailedLogin {
+    pub id: i32,
+    pub username: String,
+    pub ip: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Represents information needed to store a failed login attempt.
+#[derive(Insertable, Deserialize, Serialize)]
+#[table_name = "failed_logins"]
+pub struct NewFailedLogin {
+    pub username: String,
+    pub ip: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Represents a message that was sent, or skipped, to a node.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct SentMessage {
+    pub id: i32,
+    pub node_id: i32,
+    pub content: String,
+    pub status: String,
+    pub sender_id: i32,
+    pub timestamp: i64,
+}
+
+/// Represents information needed to store a sent message.
+#[derive(Insertable, Deserialize, Serialize)]
+#[table_name = "sent_messages"]
+pub struct NewSentMessage {
+    pub node_id: i32,
+    pub content: String,
+    pub status: String,
+    pub sender_id: i32,
+    pub timestamp: i64,
+}
+
+/// Represents something a user did that is kept in the audit log.
+/// `actor_id` is the user who did it, or `None` for the server itself.
+/// `node_id` and `target_user_id` are the node or user it was done to,
+/// if any.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct AuditEvent {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub node_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub detail: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Represents information needed to store an audit event.
+#[derive(Insertable, Deserialize, Serialize)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent {
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub node_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub detail: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Represents a node deletion waiting for a second admin to confirm it.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "pending_deletions"]
+pub struct PendingDeletion {
+    pub node_id: i32,
+    pub requested_by: i32,
+    pub requested_at: i64,
+}
+
+/// Represents the preferences a user has saved for the dashboard.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "user_prefs"]
+pub struct UserPrefs {
+    pub user_id: i32,
+    pub sort_key: String,
+    pub sort_desc: bool,
+}
+
+/// Represents a user acknowledging a node's alert.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "alert_acks"]
+pub struct AlertAck {
+    pub node_id: i32,
+    pub acknowledged_by: i32,
+    pub acknowledged_at: i64,
+}
+
+/// Represents a user with a rate limit tier other than the default.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "rate_limit_tiers"]
+pub struct RateLimitTier {
+    pub user_id: i32,
+    pub tier: String,
+}
+
+/// Represents when a user's sessions and tokens were last revoked.
+/// Sessions started and tokens issued up to then are rejected.
+#[derive(Queryable, Insertable, Deserialize, Serialize)]
+#[table_name = "session_revocations"]
+pub struct SessionRevocation {
+    pub user_id: i32,
+    pub revoked_at: i64,
+}
+
+/// Represents an API key a user made for a machine client. Only the
+/// hash of the key's secret is stored.
+#[derive(Queryable)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub label: String,
+    pub key_hash: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub signing_secret: Option<String>,
+}
+
+/// Represents information needed to store a new API key.
+#[derive(Insertable)]
+#[table_name = "api_keys"]
+pub struct NewApiKey {
+    pub user_id: i32,
+    pub label: String,
+    pub key_hash: String,
+    pub created_at: i64,
+    pub signing_secret: Option<String>,
+}
+
+/// Represents an API key without its hash or signing secret. This is
+/// the format keys are listed in. `signed` is whether requests made
+/// with the key have to be signed.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct ApiKeySummary {
+    pub id: i32,
+    pub label: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub signed: bool,
+}
+
+/// Represents a URL that alerts are posted to. Hooks without a
+/// `node_id` get the alerts of every node.
+#[derive(Queryable, Deserialize, Serialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub node_id: Option<i32>,
+    pub created_by: i32,
+    pub created_at: i64,
+}
+
+/// Represents information needed to store a new webhook.
+#[derive(Insertable)]
+#[table_name = "webhooks"]
+pub struct NewWebhook {
+    pub url: String,
+    pub node_id: Option<i32>,
+    pub created_by: i32,
+    pub created_at: i64,
+}
+
+/// Represents everything stored about a user, for them to download.
+/// The password hash, API key hashes and signing secrets are left out.
+#[derive(Deserialize, Serialize)]
+pub struct UserExport {
+    pub user: UserSummary,
+    pub prefs: Option<UserPrefs>,
+    pub rate_limit_tier: Option<String>,
+    pub sent_messages: Vec<SentMessage>,
+    pub alert_acks: Vec<AlertAck>,
+    pub pending_deletions: Vec<PendingDeletion>,
+    pub api_keys: Vec<ApiKeySummary>,
+    pub audit_events: Vec<AuditEvent>,
+    pub sessions: Vec<ActiveSession>,
+}
@@ -4,6 +4,26 @@ table! {
         node_id -> Integer,
         name -> Text,
         units -> Text,
+        display_order -> Integer,
+        enabled -> Bool,
+        report_interval_secs -> Nullable<Integer>,
+        created_at -> BigInt,
+        min_alert -> Nullable<Double>,
+        max_alert -> Nullable<Double>,
+        retention_days -> Nullable<Integer>,
+        latitude -> Nullable<Double>,
+        longitude -> Nullable<Double>,
+        location_name -> Nullable<Text>,
+        alert_hysteresis -> Nullable<Double>,
+        description -> Nullable<Text>,
+        min_reading_interval_secs -> Nullable<Integer>,
+        color -> Nullable<Text>,
+        icon -> Nullable<Text>,
+        muted_until -> Nullable<BigInt>,
+        calibration_offset -> Nullable<Double>,
+        calibration_scale -> Nullable<Double>,
+        auto_registered -> Bool,
+        updated_at -> BigInt,
     }
 }
 
@@ -12,5 +32,123 @@ table! {
         id -> Integer,
         username -> Text,
         password -> Text,
+        role -> Text,
+        deleted_at -> Nullable<BigInt>,
     }
-}
\ No newline at end of file
+}
+
+table! {
+    username_renames (user_id) {
+        user_id -> Integer,
+        old_username -> Text,
+        new_username -> Text,
+    }
+}
+
+table! {
+    readings (id) {
+        id -> Integer,
+        node_id -> Integer,
+        reading -> Integer,
+        timestamp -> BigInt,
+    }
+}
+
+table! {
+    node_tags (node_id, tag) {
+        node_id -> Integer,
+        tag -> Text,
+    }
+}
+
+table! {
+    failed_logins (id) {
+        id -> Integer,
+        username -> Text,
+        ip -> Nullable<Text>,
+        timestamp -> BigInt,
+    }
+}
+
+table! {
+    sent_messages (id) {
+        id -> Integer,
+        node_id -> Integer,
+        content -> Text,
+        status -> Text,
+        sender_id -> Integer,
+        timestamp -> BigInt,
+    }
+}
+
+table! {
+    pending_deletions (node_id) {
+        node_id -> Integer,
+        requested_by -> Integer,
+        requested_at -> BigInt,
+    }
+}
+
+table! {
+    user_prefs (user_id) {
+        user_id -> Integer,
+        sort_key -> Text,
+        sort_desc -> Bool,
+    }
+}
+
+table! {
+    alert_acks (node_id) {
+        node_id -> Integer,
+        acknowledged_by -> Integer,
+        acknowledged_at -> BigInt,
+    }
+}
+
+table! {
+    rate_limit_tiers (user_id) {
+        user_id -> Integer,
+        tier -> Text,
+    }
+}
+
+table! {
+    session_revocations (user_id) {
+        user_id -> Integer,
+        revoked_at -> BigInt,
+    }
+}
+
+table! {
+    api_keys (id) {
+        id -> Integer,
+        user_id -> Integer,
+        label -> Text,
+        key_hash -> Text,
+        created_at -> BigInt,
+        last_used_at -> Nullable<BigInt>,
+        signing_secret -> Nullable<Text>,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Integer,
+        actor_id -> Nullable<Integer>,
+        action -> Text,
+        node_id -> Nullable<Integer>,
+        target_user_id -> Nullable<Integer>,
+        detail -> Nullable<Text>,
+        timestamp -> BigInt,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Integer,
+        url -> Text,
+        node_id -> Nullable<Integer>,
+        created_by -> Integer,
+        created_at -> BigInt,
+    }
+}
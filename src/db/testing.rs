@@ -0,0 +1,52 @@
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Returns the directory of every migration, oldest first.
+fn migrations() -> Vec<PathBuf> {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    let mut found = fs::read_dir(root)
+        .expect("Could not read migrations")
+        .map(|entry| entry.expect("Could not read migration").path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<PathBuf>>();
+
+    //  Migrations are named after when they were made, so sorting by
+    //  name puts them in the order they have to run in.
+    found.sort();
+    found
+}
+
+/// Runs `up.sql` of every migration whose directory name `wanted`
+/// returns true for, oldest first.
+pub fn migrate<F: Fn(&str) -> bool>(conn: &SqliteConnection, wanted: F) {
+    for migration in migrations() {
+        let name = migration.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("")
+            .to_string();
+
+        if !wanted(&name) {
+            continue;
+        }
+
+        let mut sql = String::new();
+        File::open(migration.join("up.sql"))
+            .and_then(|mut file| file.read_to_string(&mut sql))
+            .expect("Could not read up.sql");
+
+        conn.batch_execute(&sql)
+            .unwrap_or_else(|why| panic!("Migration {} failed: {}", name, why));
+    }
+}
+
+/// Returns a new in-memory database with every migration run.
+pub fn connection() -> SqliteConnection {
+    let conn = SqliteConnection::establish(":memory:")
+        .expect("Could not open in-memory database");
+
+    migrate(&conn, |_| true);
+    conn
+}
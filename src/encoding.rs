@@ -0,0 +1,205 @@
+use std::io::Read;
+use std::ops::Deref;
+
+use flate2::read::GzDecoder;
+use rocket::{Data, Outcome, Request};
+use rocket::data::{self, FromData};
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use api_key;
+
+/// The most bytes a JSON body can be after it has been decoded,
+/// unless the `json` limit in Rocket.toml says otherwise.
+const DEFAULT_LIMIT: u64 = 1 << 20;
+
+/// Parses a JSON request body into `T`, decompressing it first
+/// if it was sent with a `Content-Encoding` of `gzip`.
+/// 
+/// The size limit applies to the decompressed body, so a small
+/// compressed body can't expand into more than the limit allows.
+/// 
+/// Requests with any other encoding fail with a 415 Unsupported
+/// Media Type. Bodies over the limit fail with a 413 Payload Too
+/// Large, and bodies that aren't valid JSON with a 400 Bad Request.
+/// 
+/// If the request has an `X-Content-Sha256` header, as signed API key
+/// requests do, the body as it was sent has to match it, or the
+/// request fails with a 400 Bad Request.
+pub struct Decoded<T>(pub T);
+
+impl<T: DeserializeOwned> FromData for Decoded<T> {
+    type Error = ();
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Decoded<T>, ()> {
+        let limit = request.limits().get("json").unwrap_or(DEFAULT_LIMIT);
+        let encoding = request.headers()
+            .get_one("Content-Encoding")
+            .unwrap_or("identity")
+            .trim()
+            .to_lowercase();
+
+        let gzipped = match encoding.as_str() {
+            "identity" => false,
+            "gzip" | "x-gzip" => true,
+            _ => return Outcome::Failure((Status::UnsupportedMediaType, ())),
+        };
+
+        //  Read one byte past the limit to know if the body is too large.
+        let mut sent = Vec::new();
+        if data.open().take(limit + 1).read_to_end(&mut sent).is_err() {
+            return Outcome::Failure((Status::BadRequest, ()));
+        }
+
+        if sent.len() as u64 > limit {
+            return Outcome::Failure((Status::PayloadTooLarge, ()));
+        }
+
+        if let Some(expected) = request.headers().get_one(api_key::BODY_HASH_HEADER) {
+            if api_key::body_hash(&sent) != expected.trim().to_lowercase() {
+                return Outcome::Failure((Status::BadRequest, ()));
+            }
+        }
+
+        let body = if gzipped {
+            let mut body = Vec::new();
+            if GzDecoder::new(&sent[..]).take(limit + 1).read_to_end(&mut body).is_err() {
+                return Outcome::Failure((Status::BadRequest, ()));
+            }
+
+            if body.len() as u64 > limit {
+                return Outcome::Failure((Status::PayloadTooLarge, ()));
+            }
+
+            body
+        } else {
+            sent
+        };
+
+        match serde_json::from_slice(&body) {
+            Ok(value) => Outcome::Success(Decoded(value)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
+
+/// Implement Deref to make it easier to use.
+impl<T> Deref for Decoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use rocket;
+    use rocket::http::Header;
+    use rocket::local::Client;
+
+    use std::io::Write;
+
+    #[post("/batch", data = "<batch>")]
+    fn batch(batch: Decoded<Vec<i32>>) -> String {
+        batch.iter().sum::<i32>().to_string()
+    }
+
+    fn client() -> Client {
+        Client::new(rocket::ignite().mount("/", routes![batch])).unwrap()
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzipped_batches_are_decoded() {
+        let client = client();
+        let mut response = client.post("/batch")
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(gzip(b"[1, 2, 3, 4]"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some("10".to_string()));
+    }
+
+    #[test]
+    fn plain_batches_are_still_accepted() {
+        let client = client();
+        let mut response = client.post("/batch")
+            .body("[1, 2, 3, 4]")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some("10".to_string()));
+    }
+
+    #[test]
+    fn unknown_encodings_are_unsupported() {
+        let client = client();
+        let response = client.post("/batch")
+            .header(Header::new("Content-Encoding", "br"))
+            .body("[1, 2, 3, 4]")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn the_limit_applies_after_decoding() {
+        let mut body = "[0".to_string();
+        while (body.len() as u64) <= DEFAULT_LIMIT {
+            body.push_str(",0");
+        }
+        body.push(']');
+
+        let compressed = gzip(body.as_bytes());
+        assert!((compressed.len() as u64) < DEFAULT_LIMIT);
+
+        let client = client();
+        let response = client.post("/batch")
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(compressed)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+
+    #[test]
+    fn broken_gzip_is_a_bad_request() {
+        let client = client();
+        let response = client.post("/batch")
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body("[1, 2, 3, 4]")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn bodies_have_to_match_their_hash() {
+        let client = client();
+        let body = gzip(b"[1, 2, 3, 4]");
+
+        let response = client.post("/batch")
+            .header(Header::new("Content-Encoding", "gzip"))
+            .header(Header::new(api_key::BODY_HASH_HEADER, api_key::body_hash(&body)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.post("/batch")
+            .header(Header::new(api_key::BODY_HASH_HEADER, api_key::body_hash(b"[1]")))
+            .body("[1, 2, 3, 4]")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,115 @@
+use rocket::{Data, Outcome, Request, State};
+use rocket::http::{Method, Status};
+use rocket::response::status;
+use rocket_contrib::JsonValue;
+
+use config::Settings;
+
+/// The path requests that didn't use HTTPS are rerouted to.
+pub const INSECURE_PATH: &str = "/api/https-required";
+
+/// Returns whether a proxy in front of the server says the client
+/// connected without HTTPS, through the `X-Forwarded-Proto` header.
+/// 
+/// Requests without the header are connections straight to the
+/// server, which only accepts them over TLS.
+fn forwarded_insecure(request: &Request) -> bool {
+    match request.headers().get_one("X-Forwarded-Proto") {
+        Some(proto) => !proto.split(',').next().unwrap_or("").trim().eq_ignore_ascii_case("https"),
+        None => false,
+    }
+}
+
+/// Reroutes requests that didn't use HTTPS to `INSECURE_PATH` if the
+/// `require_https` setting is on. Private cookies are marked secure,
+/// so without HTTPS logins silently fail instead.
+pub fn require_https(request: &mut Request, _: &Data) {
+    let required = match request.guard::<State<Settings>>() {
+        Outcome::Success(settings) => settings.require_https,
+        _ => false,
+    };
+
+    if required && forwarded_insecure(request) {
+        request.set_method(Method::Get);
+        request.set_uri(INSECURE_PATH);
+    }
+}
+
+/// Answers requests that were rerouted because they didn't use HTTPS.
+#[get("/api/https-required")]
+pub fn insecure() -> status::Custom<JsonValue> {
+    status::Custom(Status::Forbidden, json!({
+        "error": "This server requires HTTPS. Connect with https:// instead.",
+        "success": false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket;
+    use rocket::config::{Config, Environment};
+    use rocket::fairing::AdHoc;
+    use rocket::http::Header;
+    use rocket::local::Client;
+
+    #[get("/api/list")]
+    fn list() -> &'static str {
+        "list"
+    }
+
+    fn client(required: bool) -> Client {
+        let config = Config::build(Environment::Development)
+            .extra("require_https", required)
+            .finalize()
+            .unwrap();
+
+        let rocket = rocket::ignite()
+            .mount("/", routes![list, insecure])
+            .manage(Settings::from_config(&config))
+            .attach(AdHoc::on_request(require_https));
+
+        Client::new(rocket).unwrap()
+    }
+
+    fn status(client: &Client, proto: Option<&'static str>) -> Status {
+        let mut request = client.get("/api/list");
+        if let Some(proto) = proto {
+            request = request.header(Header::new("X-Forwarded-Proto", proto));
+        }
+
+        request.dispatch().status()
+    }
+
+    #[test]
+    fn forwarded_https_is_let_through() {
+        let client = client(true);
+
+        assert_eq!(status(&client, Some("https")), Status::Ok);
+        assert_eq!(status(&client, Some("HTTPS")), Status::Ok);
+        assert_eq!(status(&client, Some("https, http")), Status::Ok);
+    }
+
+    #[test]
+    fn forwarded_http_is_refused() {
+        let client = client(true);
+
+        let mut response = client.get("/api/list")
+            .header(Header::new("X-Forwarded-Proto", "http"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response.body_string().unwrap().contains("requires HTTPS"));
+
+        assert_eq!(status(&client, Some("http, https")), Status::Forbidden);
+    }
+
+    #[test]
+    fn requests_without_a_proxy_are_let_through() {
+        assert_eq!(status(&client(true), None), Status::Ok);
+    }
+
+    #[test]
+    fn http_is_allowed_unless_required() {
+        assert_eq!(status(&client(false), Some("http")), Status::Ok);
+    }
+}
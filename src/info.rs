@@ -1,6 +1,5 @@
 use xbee::packet::Packet;
 use byteorder::{ByteOrder, LittleEndian};
-use chrono::Utc;
 use failure::Error;
 use parking_lot::RwLock;
 use rocket::http::Status;
@@ -10,6 +9,9 @@ use rocket::{Outcome, Request, State};
 use std::collections::HashMap;
 use std::sync::Arc;
 
+/// The node id that sends a packet to every node on the network.
+pub const BROADCAST: u32 = 0xFFFFFFFF;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct XbeeInfo {
     pub uuid: u32,
@@ -34,7 +36,7 @@ enum InfoError {
 }
 
 impl XbeeInfo {
-    pub fn new(packet: &Packet) -> Result<Self, Error> {
+    pub fn new(packet: &Packet, now: i64) -> Result<Self, Error> {
         ensure!(packet.length == 50, InfoError::NoInfo);
         let bytes = packet.data();
 
@@ -46,14 +48,74 @@ impl XbeeInfo {
             max_value: LittleEndian::read_f32(&bytes[16..]),
             name: String::from_utf8(bytes[20..39].to_vec())?.replace("\x00", ""),
             units: String::from_utf8(bytes[40..].to_vec())?.replace("\x00", ""),
-            last_update: Utc::now().timestamp(),
+            last_update: now,
             reading: None,
         })
     }
 
-    pub fn set_reading(&mut self, value: u16) {
+    /// Returns the current reading scaled from the node's voltage
+    /// range to its value range, or `None` if there is no reading yet.
+    /// 
+    /// Readings are reported in hundredths of a volt, and are mapped
+    /// linearly so that `min_voltage` gives `min_value` and
+    /// `max_voltage` gives `max_value`.
+    pub fn value(&self) -> Option<f64> {
+        self.reading.and_then(|reading| self.scale(reading))
+    }
+
+    /// Scales the given reading the same way as `value`. Readings
+    /// outside the voltage range are extrapolated along the same line.
+    /// 
+    /// Returns `None` if the node's scaling isn't valid, since the
+    /// result would be meaningless.
+    pub fn scale(&self, reading: u16) -> Option<f64> {
+        if !self.scaling_valid() {
+            return None;
+        }
+
+        let voltage = f64::from(reading) / 100.0;
+
+        Some(f64::from(self.min_value) + (voltage - f64::from(self.min_voltage)) * self.value_per_volt())
+    }
+
+    /// Returns whether readings can be scaled, which is the case when
+    /// the maximum voltage is above the minimum. An empty or inverted
+    /// voltage range would give infinite or backwards values.
+    pub fn scaling_valid(&self) -> bool {
+        self.max_voltage > self.min_voltage
+    }
+
+    /// Returns what is wrong with the node's scaling, which is empty if
+    /// nothing is. An empty or inverted voltage range means readings
+    /// can't be scaled at all, while an inverted value range scales
+    /// them backwards, which is usually a mistake.
+    pub fn scaling_problems(&self) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+
+        if self.max_voltage < self.min_voltage {
+            problems.push("inverted_voltage_range");
+        } else if !self.scaling_valid() {
+            problems.push("empty_voltage_range");
+        }
+
+        if self.max_value < self.min_value {
+            problems.push("inverted_value_range");
+        }
+
+        problems
+    }
+
+    /// Returns how much the scaled value changes per volt.
+    pub fn value_per_volt(&self) -> f64 {
+        let voltage_range = f64::from(self.max_voltage - self.min_voltage);
+        let value_range = f64::from(self.max_value - self.min_value);
+
+        value_range / voltage_range
+    }
+
+    pub fn set_reading(&mut self, value: u16, now: i64) {
         self.reading = Some(value);
-        self.last_update = Utc::now().timestamp();
+        self.last_update = now;
     }
 }
 
@@ -62,7 +124,7 @@ pub struct InfoSet(pub Arc<RwLock<HashMap<u32, XbeeInfo>>>);
 
 impl InfoSet {
     pub fn contains(&self, uuid: u32) -> bool {
-        if uuid == 0xFFFFFFFF {
+        if uuid == BROADCAST {
             return true
         }
 
@@ -71,7 +133,12 @@ impl InfoSet {
         values.contains_key(&uuid)
     }
 
-    pub fn set_reading(&self, packet: Packet) -> Result<u16, Error> {
+    /// Returns a copy of the info of the node with the given id.
+    pub fn get(&self, uuid: u32) -> Option<XbeeInfo> {
+        self.0.read().get(&uuid).cloned()
+    }
+
+    pub fn set_reading(&self, packet: Packet, now: i64) -> Result<u16, Error> {
         let mut values = self.0.write();
         let entry = values.get_mut(&packet.origin)
             .ok_or(InfoError::NoNode)?;
@@ -80,7 +147,7 @@ impl InfoSet {
         ensure!(data.len() == 2, InfoError::NotEnoughData);
 
         let value = LittleEndian::read_u16(data);
-        entry.set_reading(value);
+        entry.set_reading(value, now);
 
         Ok(value)
     }
@@ -107,4 +174,64 @@ impl <'a, 'r> FromRequest<'a, 'r> for InfoSet {
             _ => Outcome::Failure((Status::ServiceUnavailable, ())),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(min_voltage: f32, max_voltage: f32) -> XbeeInfo {
+        XbeeInfo {
+            uuid: 2,
+            name: "Test".to_string(),
+            units: "C".to_string(),
+            max_voltage: max_voltage,
+            min_voltage: min_voltage,
+            max_value: 150.0,
+            min_value: 0.0,
+            last_update: 0,
+            reading: Some(250),
+        }
+    }
+
+    #[test]
+    fn readings_scale_across_the_ranges() {
+        let node = node(0.0, 5.0);
+
+        assert!(node.scaling_valid());
+        assert!(node.scaling_problems().is_empty());
+        assert_eq!(node.value(), Some(75.0));
+        assert_eq!(node.scale(500), Some(150.0));
+    }
+
+    #[test]
+    fn zero_voltage_ranges_have_no_value() {
+        let node = node(2.5, 2.5);
+
+        assert!(!node.scaling_valid());
+        assert_eq!(node.scaling_problems(), vec!["empty_voltage_range"]);
+        assert_eq!(node.value(), None);
+        assert_eq!(node.scale(250), None);
+    }
+
+    #[test]
+    fn inverted_voltage_ranges_have_no_value() {
+        let node = node(5.0, 0.0);
+
+        assert!(!node.scaling_valid());
+        assert_eq!(node.scaling_problems(), vec!["inverted_voltage_range"]);
+        assert_eq!(node.value(), None);
+    }
+
+    #[test]
+    fn inverted_value_ranges_still_scale() {
+        let node = XbeeInfo {
+            max_value: 0.0,
+            min_value: 150.0,
+            ..node(0.0, 5.0)
+        };
+
+        assert!(node.scaling_valid());
+        assert_eq!(node.scaling_problems(), vec!["inverted_value_range"]);
+        assert_eq!(node.value(), Some(75.0));
+    }
+}
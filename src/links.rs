@@ -0,0 +1,82 @@
+use rocket::Outcome;
+use rocket::request::{self, FormItems, FromRequest, Request};
+use rocket_contrib::JsonValue;
+use serde_json::{Map, Value};
+
+/// Links to the other pages of a paginated response, which are only
+/// added when the request asks for them with `?links=true`.
+/// 
+/// Links are the request's own path with every query parameter but
+/// `page` kept as it was sent, so filters and the page size carry
+/// over from page to page.
+pub struct PageLinks {
+    path: String,
+    params: Vec<String>,
+    wanted: bool,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PageLinks {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<PageLinks, ()> {
+        let mut wanted = false;
+        let mut params = Vec::new();
+
+        for (key, value) in FormItems::from(request.uri().query().unwrap_or("")) {
+            if key.as_str() == "page" {
+                continue;
+            }
+
+            if key.as_str() == "links" {
+                wanted = value.as_str() == "true";
+            }
+
+            params.push(format!("{}={}", key, value));
+        }
+
+        Outcome::Success(PageLinks {
+            path: request.uri().path().to_string(),
+            params: params,
+            wanted: wanted,
+        })
+    }
+}
+
+impl PageLinks {
+    /// Returns the URL of the given page.
+    fn url(&self, page: i64) -> Value {
+        let mut params = self.params.clone();
+        params.push(format!("page={}", page));
+
+        Value::String(format!("{}?{}", self.path, params.join("&")))
+    }
+
+    /// Adds `links` to a page of results if they were asked for. There
+    /// are always `first` and `last` links, while `prev` is left out on
+    /// the first page and `next` on the last.
+    pub fn add_to(&self, mut response: JsonValue, page: i64, per_page: i64, total: i64) -> JsonValue {
+        if !self.wanted {
+            return response;
+        }
+
+        let last = ((total + per_page - 1) / per_page).max(1);
+        let mut links = Map::new();
+        links.insert("first".to_string(), self.url(1));
+
+        if page > 1 {
+            links.insert("prev".to_string(), self.url((page - 1).min(last)));
+        }
+
+        if page < last {
+            links.insert("next".to_string(), self.url(page + 1));
+        }
+
+        links.insert("last".to_string(), self.url(last));
+
+        if let Some(fields) = response.as_object_mut() {
+            fields.insert("links".to_string(), Value::Object(links));
+        }
+
+        response
+    }
+}
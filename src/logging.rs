@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use chrono::Utc;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use rocket::{Data, Outcome, Request, State};
+use rocket::http::ContentType;
+use rocket::request::{self, FromRequest};
+use rocket::response::content::Content;
+use serde_json::{self, Value};
+
+use api::AdminUser;
+use config::Settings;
+use subscribers::{Subscribers, LOG_STREAM};
+
+/// How many of the latest log lines are kept in memory.
+pub const LOG_BUFFER_LINES: usize = 1000;
+
+/// How long clients of /api/admin/logs wait before asking for new
+/// lines, in milliseconds.
+const LOG_RETRY_MILLIS: u64 = 2000;
+
+/// The latest log lines, each with an increasing sequence number.
+pub struct LogBuffer {
+    lines: Mutex<(u64, VecDeque<(u64, String)>)>,
+}
+
+impl LogBuffer {
+    fn new() -> LogBuffer {
+        LogBuffer { lines: Mutex::new((0, VecDeque::with_capacity(LOG_BUFFER_LINES))) }
+    }
+
+    /// Adds a line, dropping the oldest one if the buffer is full.
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock();
+        let (ref mut next, ref mut buffer) = *lines;
+
+        if buffer.len() == LOG_BUFFER_LINES {
+            buffer.pop_front();
+        }
+
+        *next += 1;
+        buffer.push_back((*next, line));
+    }
+
+    /// Returns the lines with sequence numbers after `after`, oldest first.
+    pub fn after(&self, after: u64) -> Vec<(u64, String)> {
+        self.lines.lock().1.iter()
+            .filter(|&&(seq, _)| seq > after)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Logs to stderr like Rocket does, and keeps the latest lines in a
+/// `LogBuffer` so admins can read them from /api/admin/logs.
+struct BufferLogger {
+    buffer: Arc<LogBuffer>,
+}
+
+impl Log for BufferLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        //  Hyper is too noisy to be useful at info level.
+        metadata.level() <= Level::Info && !metadata.target().starts_with("hyper")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!("{} [{}] {}", Utc::now().format("%Y-%m-%d %H:%M:%S"), record.level(), record.args());
+        eprintln!("{}", line);
+        self.buffer.push(line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging to stderr and to the returned buffer. This has to
+/// be called before Rocket starts, since only one logger can be set.
+/// Rocket will say its own logger failed to initialize, which is
+/// expected.
+pub fn init() -> Arc<LogBuffer> {
+    let buffer = Arc::new(LogBuffer::new());
+    let logger = BufferLogger { buffer: buffer.clone() };
+
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+
+    buffer
+}
+
+/// Parts of the names of fields whose values are never logged. A field
+/// is redacted if its name contains any of them, such as
+/// `new_password` or `refreshToken`. Names are matched without regard
+/// to case, at any depth of the body.
+pub const REDACTED_FIELDS: &[&str] = &["pass", "secret", "token", "api_key", "apikey"];
+
+/// Replaces the value of every redacted field in the given JSON,
+/// however deeply it is nested.
+pub fn redact(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut fields) => {
+            for (name, field) in fields.iter_mut() {
+                let name = name.to_lowercase();
+
+                if REDACTED_FIELDS.iter().any(|redacted| name.contains(redacted)) {
+                    *field = Value::String("***".to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(ref mut items) => {
+            for item in items.iter_mut() {
+                redact(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Logs the body of each API request with sensitive fields redacted,
+/// if the `log_request_bodies` setting is on. This is only meant to be
+/// used while debugging clients during development, and never happens
+/// in release builds.
+/// 
+/// Only bodies small enough for Rocket to peek at without reading
+/// them are logged. Bodies that aren't JSON are never logged, since
+/// they can't be redacted.
+pub fn log_request_body(request: &mut Request, data: &Data) {
+    let enabled = match request.guard::<State<Settings>>() {
+        Outcome::Success(settings) => settings.log_request_bodies,
+        _ => false,
+    };
+
+    if !enabled || !request.uri().path().starts_with("/api/") || data.peek().is_empty() {
+        return;
+    }
+
+    if !data.peek_complete() {
+        info!("{} {}: body too large to log", request.method(), request.uri());
+        return;
+    }
+
+    match serde_json::from_slice::<Value>(data.peek()) {
+        Ok(mut body) => {
+            redact(&mut body);
+            info!("{} {}: {}", request.method(), request.uri(), body);
+        }
+        Err(_) => info!("{} {}: body is not JSON", request.method(), request.uri()),
+    }
+}
+
+/// The id of the last event a client got, sent back by browsers in
+/// the `Last-Event-ID` header when they reconnect to an event stream.
+/// It is `None` on the first connection. For /api/admin/logs it is the
+/// sequence number of the last log line.
+pub struct LastEventId(pub Option<u64>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LastEventId, ()> {
+        let last = request.headers()
+            .get_one("Last-Event-ID")
+            .and_then(|last| last.trim().parse::<u64>().ok());
+
+        Outcome::Success(LastEventId(last))
+    }
+}
+
+/// Returns the latest server log lines as server-sent events, so they
+/// can be followed with an `EventSource` in the browser.
+/// 
+/// Only the latest 1000 lines are kept in memory, and they are lost
+/// when the server restarts. Each response has the lines buffered
+/// since the `Last-Event-ID` the client sends, then ends. Browsers
+/// reconnect after two seconds, so they get new lines as they come in
+/// without tying up a worker thread.
+/// 
+/// Request bodies are only logged with sensitive fields redacted,
+/// but anything else a handler logs is included as is.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```text
+/// retry: 2000
+/// 
+/// id: 41
+/// data: 2018-05-30 14:02:11 [INFO] Purged 120 readings older than 30 days.
+/// 
+/// ```
+#[get("/api/admin/logs")]
+pub fn logs(buffer: State<Arc<LogBuffer>>, last: LastEventId, subscribers: State<Subscribers>, remote: Option<SocketAddr>, admin: AdminUser) -> Content<String> {
+    subscribers.touch(LOG_STREAM, None, admin.id, remote.map(|remote| remote.ip()));
+
+    let mut events = format!("retry: {}\n\n", LOG_RETRY_MILLIS);
+
+    for (seq, line) in buffer.after(last.0.unwrap_or(0)) {
+        events.push_str(&format!("id: {}\ndata: {}\n\n", seq, line));
+    }
+
+    Content(ContentType::new("text", "event-stream"), events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_fields_containing_sensitive_names() {
+        let mut body = json!({
+            "username": "alice",
+            "new_password": "hunter2",
+            "jwt_secret": "abc",
+            "api_key": "def",
+            "refreshToken": "ghi",
+        }).0;
+        redact(&mut body);
+
+        assert_eq!(body, json!({
+            "username": "alice",
+            "new_password": "***",
+            "jwt_secret": "***",
+            "api_key": "***",
+            "refreshToken": "***",
+        }).0);
+    }
+
+    #[test]
+    fn redacts_nested_fields() {
+        let mut body = json!({
+            "users": [{ "name": "bob", "Password": "x" }],
+            "auth": { "token": { "value": "y" } },
+        }).0;
+        redact(&mut body);
+
+        assert_eq!(body, json!({
+            "users": [{ "name": "bob", "Password": "***" }],
+            "auth": { "token": "***" },
+        }).0);
+    }
+}
@@ -4,48 +4,108 @@
 extern crate bcrypt;
 extern crate byteorder;
 extern crate chrono;
+extern crate chrono_tz;
 #[macro_use] extern crate diesel;
 extern crate dotenv;
 #[macro_use] extern crate dotenv_codegen;
 #[macro_use] extern crate failure;
+extern crate flate2;
+extern crate hmac;
+extern crate hyper;
+extern crate hyper_sync_rustls;
+extern crate jsonwebtoken;
 #[macro_use] extern crate log;
 extern crate parking_lot;
 extern crate r2d2;
 extern crate r2d2_diesel;
+extern crate rand;
 extern crate rocket;
 #[macro_use] extern crate rocket_contrib;
+extern crate serde;
 #[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+extern crate time;
 extern crate xbee;
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use failure::Error;
 use parking_lot::RwLock;
+use rocket::{Data, Request, State};
+use rocket::fairing::AdHoc;
 use rocket::response::NamedFile;
+use rocket_contrib::JsonValue;
 
 mod api;
+mod api_key;
+mod audit;
+mod chaos;
+mod clock;
+mod conditional;
+mod config;
+mod convert;
+mod cors;
 mod db;
+mod encoding;
 mod errors;
+mod https;
 mod info;
+mod links;
+mod logging;
+mod metrics;
+mod naming;
+mod negotiate;
+mod query;
+mod rate_limit;
+mod report;
+mod session;
+mod sort;
+mod subscribers;
+mod token;
+mod webhook;
+
+/// Returns a pointer to the API, for when the server is deployed
+/// without the frontend's static files.
+fn api_pointer() -> JsonValue {
+    json!({
+        "api": "/api",
+        "version": api::API_VERSION,
+        "success": true,
+    })
+}
+
+/// Opens the given file from the `static_dir` setting, if the
+/// frontend is being served and the file exists.
+fn static_file<P: AsRef<Path>>(settings: &config::Settings, file: P) -> Option<NamedFile> {
+    settings.static_dir.as_ref()
+        .and_then(|dir| NamedFile::open(dir.join(file)).ok())
+}
 
 /// This will return the homepage for an authorized user.
 /// It is the base for the frontend and is where the user 
 /// will be able to interact with and view data from the 
 /// backend.
+/// 
+/// If the frontend isn't being served, a pointer to the API
+/// is returned instead.
 #[get("/")]
-fn index_authed(_user: api::AuthedUser) -> Result<NamedFile, Error> {
-    Ok(NamedFile::open("static/index.html")?)
+fn index_authed(_user: api::AuthedUser, settings: State<config::Settings>) -> Result<NamedFile, JsonValue> {
+    static_file(&settings, "index.html").ok_or_else(api_pointer)
 }
 
 /// This will return the homepage for an unauthorized user.
 /// Since the data being shown and modified is private,
 /// this will instead redirect to a login page.
+/// 
+/// If the frontend isn't being served, a pointer to the API
+/// is returned instead.
 #[get("/", rank = 2)]
-fn index_login() -> Result<NamedFile, Error> {
-    Ok(NamedFile::open("static/login.html")?)
+fn index_login(settings: State<config::Settings>) -> Result<NamedFile, JsonValue> {
+    static_file(&settings, "login.html").ok_or_else(api_pointer)
 }
 
 /// This is a wildcard route. It will attempt to send a file
@@ -54,17 +114,229 @@ fn index_login() -> Result<NamedFile, Error> {
 /// For example, if the file ./static/test.jpg exists, then
 /// going to https://localhost:8000/test.jpg should return
 /// that file.
+/// 
+/// Any other path outside of /api is routed on the client, so
+/// the homepage is sent instead, the same way `/` would send it.
+/// Unknown /api paths still get a 404.
 #[get("/<file..>", rank = 99)]
-fn files(file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new("static/").join(file)).ok()
+fn files(file: PathBuf, user: Option<api::AuthedUser>, settings: State<config::Settings>) -> Option<NamedFile> {
+    if file.starts_with("api") {
+        return None;
+    }
+
+    static_file(&settings, &file).or_else(|| {
+        let homepage = if user.is_some() { "index.html" } else { "login.html" };
+        static_file(&settings, homepage)
+    })
+}
+
+/// Removes any trailing slashes from the paths of API requests,
+/// so that `/api/list/` is handled exactly like `/api/list`.
+/// The query string is kept as it is.
+fn trim_trailing_slash(request: &mut Request, _: &Data) {
+    let trimmed = {
+        let uri = request.uri();
+        let path = uri.path();
+
+        if path.starts_with("/api/") && path.ends_with('/') {
+            let path = path.trim_right_matches('/');
+
+            Some(match uri.query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path.to_string(),
+            })
+        } else {
+            None
+        }
+    };
+
+    if let Some(uri) = trimmed {
+        request.set_uri(uri);
+    }
+}
+
+/// Creates an admin from the `bootstrap_admin_user` and
+/// `bootstrap_admin_pass` settings if there are no users yet, so a
+/// fresh deployment has someone who can log in. The password is
+/// hashed with the given bcrypt cost.
+fn bootstrap_admin(pool: &db::SqlitePool, config: &rocket::Config, cost: u32) {
+    let (name, pass) = match (config.get_str("bootstrap_admin_user"), config.get_str("bootstrap_admin_pass")) {
+        (Ok(name), Ok(pass)) => (name, pass),
+        _ => return,
+    };
+
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(why) => {
+            warn!("Could not get database connection: {}", why);
+            return;
+        }
+    };
+
+    match db::bootstrap_admin(&conn, name, pass, cost) {
+        Ok(true) => warn!("Created admin '{}' since there were no users. Remove bootstrap_admin_pass \
+                           from Rocket.toml now.", name),
+        Ok(false) => {}
+        Err(why) => error!("Could not create bootstrap admin: {}", why),
+    }
+}
+
+/// Logs every user who was renamed when usernames were made
+/// case-insensitive, so they can be told their new username. The
+/// renames stay in the `username_renames` table until it is cleared.
+fn log_username_renames(pool: &db::SqlitePool) {
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(why) => {
+            warn!("Could not get database connection: {}", why);
+            return;
+        }
+    };
+
+    match db::username_renames(&conn) {
+        Ok(renames) => {
+            for rename in renames {
+                warn!("User {} was renamed from '{}' to '{}' since another user had the same name in a different case.",
+                      rename.user_id, rename.old_username, rename.new_username);
+            }
+        }
+        Err(why) => error!("Could not load username renames: {}", why),
+    }
+}
+
+/// Puts users on the rate limit tiers saved in the database, since
+/// the rate limiter only keeps them in memory.
+fn load_rate_limit_tiers(pool: &db::SqlitePool, limiter: &rate_limit::RateLimiter) {
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(why) => {
+            warn!("Could not get database connection: {}", why);
+            return;
+        }
+    };
+
+    match db::rate_limit_tiers(&conn) {
+        Ok(tiers) => {
+            for saved in tiers {
+                match rate_limit::Tier::parse(&saved.tier) {
+                    Some(tier) => limiter.set_tier(saved.user_id, tier),
+                    None => warn!("User {} has unknown rate limit tier '{}', ignoring it.", saved.user_id, saved.tier),
+                }
+            }
+        }
+        Err(why) => error!("Could not load rate limit tiers: {}", why),
+    }
+}
+
+/// Purges readings older than their node's retention period once an
+/// hour, forever. Nodes without one use the given number of days, which
+/// failed login attempts are purged after as well. This is meant to be
+/// run on its own thread.
+fn purge_readings(pool: db::SqlitePool, clock: clock::SharedClock, retention_days: Option<i64>) {
+    loop {
+        let now = clock.now();
+
+        match pool.get() {
+            Ok(conn) => {
+                match db::purge_expired_readings(&conn, now, retention_days) {
+                    Ok(removed) => info!("Purged {} expired readings.", removed),
+                    Err(why) => warn!("Could not purge readings: {}", why),
+                }
+
+                if let Some(days) = retention_days {
+                    match db::purge_failed_logins_older_than(&conn, now - days * 60 * 60 * 24) {
+                        Ok(removed) => info!("Purged {} failed logins older than {} days.", removed, days),
+                        Err(why) => warn!("Could not purge failed logins: {}", why),
+                    }
+                }
+            }
+            Err(why) => warn!("Could not get database connection: {}", why),
+        }
+
+        thread::sleep(Duration::from_secs(60 * 60));
+    }
+}
+
+/// How often nodes are checked for alerts to post to webhooks, in
+/// seconds.
+const WEBHOOK_CHECK_SECS: u64 = 10;
+
+/// Checks whether each node started or stopped alerting every
+/// `WEBHOOK_CHECK_SECS`, forever, and posts the ones that did to their
+/// webhooks. Muted nodes are left alone until their mute ends. This is
+/// meant to be run on its own thread.
+fn watch_alerts(pool: db::SqlitePool, xbees: info::InfoSet, clock: clock::SharedClock, settings: config::Settings) {
+    let mut previous = HashMap::new();
+
+    loop {
+        thread::sleep(Duration::from_secs(WEBHOOK_CHECK_SECS));
+
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(why) => {
+                warn!("Could not get database connection: {}", why);
+                continue;
+            }
+        };
+
+        let now = clock.now();
+        let nodes = match api::alert_states(&conn, &xbees, &settings, now) {
+            Ok(nodes) => nodes,
+            Err(why) => {
+                warn!("Could not check alerts for webhooks: {}", why);
+                continue;
+            }
+        };
+
+        let states = nodes.iter()
+            .map(|&(ref node, _, alerting)| (node.uuid, alerting))
+            .collect::<Vec<(u32, Option<bool>)>>();
+
+        for (node_id, alerting) in webhook::alert_changes(&mut previous, &states) {
+            let hooks = match db::webhooks_for(&conn, node_id as i32) {
+                Ok(hooks) => hooks,
+                Err(why) => {
+                    warn!("Could not get webhooks: {}", why);
+                    continue;
+                }
+            };
+
+            if let Some(&(ref node, value, _)) = nodes.iter().find(|&&(ref node, _, _)| node.uuid == node_id) {
+                let payload = webhook::alert_payload(node_id, &node.name, alerting, value, now, false);
+
+                for hook in hooks {
+                    let delivery = webhook::deliver(&hook.url, &payload);
+                    if !delivery.delivered() {
+                        warn!("Could not deliver alert for node {} to webhook {}: {:?}", node_id, hook.id, delivery);
+                    }
+                }
+            }
+        }
+    }
 }
 
 fn main() {
+    //  Keep the latest log lines in memory for /api/admin/logs
+    let log_buffer = logging::init();
+
     //  Establish a connection with the local database
     let conn = db::establish_connection();
+    let pool = conn.clone();
+    let purge_pool = conn.clone();
+    let bootstrap_pool = conn.clone();
+
+    let clock: clock::SharedClock = Arc::new(clock::SystemClock);
+    let xbee_clock = clock.clone();
+    let purge_clock = clock.clone();
+    let webhook_clock = clock.clone();
+    let webhook_pool = conn.clone();
+
+    let metrics = metrics::Metrics::default();
+    let radio_metrics = metrics.clone();
 
     let xbees = info::InfoSet(Arc::new(RwLock::new(HashMap::new())));
     let rocket_xbees = info::InfoSet(xbees.0.clone());
+    let webhook_xbees = info::InfoSet(xbees.0.clone());
 
     //let (tx, rx) = mpsc::channel();
 
@@ -72,7 +344,7 @@ fn main() {
         let mut xbee = xbee::Xbee::new(dotenv!("XBEE_PORT"))
             .expect("No Xbee found.");
 
-        if let Err(why) = xbee.send_packet(0xFFFFFFFF, b"I") {
+        if let Err(why) = xbee.send_packet(info::BROADCAST, b"I") {
             error!("Could not send broadcast packet: {}", why);
         }
 
@@ -81,8 +353,13 @@ fn main() {
                 Ok(packet) => {
                     trace!("Got packet: {:#?}", packet);
                     if packet.length == 50 {
-                        if let Ok(info) =  info::XbeeInfo::new(&packet) {
+                        if let Ok(info) =  info::XbeeInfo::new(&packet, xbee_clock.now()) {
                             debug!("New Xbee: {:?}", info);
+                            let problems = info.scaling_problems();
+                            if !problems.is_empty() {
+                                warn!("Node {} has bad scaling: {}", info.uuid, problems.join(", "));
+                            }
+
                             let mut handle = xbees.0.write();
                             (*handle).insert(packet.origin, info);
                             
@@ -92,8 +369,21 @@ fn main() {
 
                     if xbees.contains(packet.origin) {
                         if packet.length == 2 {
-                            if let Err(why) = xbees.set_reading(packet) {
-                                warn!("Could not set reading: {:?}", why);
+                            let origin = packet.origin;
+
+                            let now = xbee_clock.now();
+
+                            match xbees.set_reading(packet, now) {
+                                Ok(value) => match pool.get() {
+                                    Ok(conn) => {
+                                        match db::create_reading(&conn, origin, value, now) {
+                                            Ok(_) => radio_metrics.radio_readings(1),
+                                            Err(why) => warn!("Could not save reading: {}", why),
+                                        }
+                                    }
+                                    Err(why) => warn!("Could not get database connection: {}", why),
+                                },
+                                Err(why) => warn!("Could not set reading: {:?}", why),
                             }
                         }
                     } else {
@@ -116,16 +406,218 @@ fn main() {
             index_login,
             files,
             api::add,
+            api::validate_add,
+            api::update,
+            api::clone_node,
+            api::delete_node,
+            api::confirm_delete,
+            api::node_detail,
+            api::scaling,
+            api::calibrate,
+            api::change_units,
+            api::scale,
+            api::units_convertible,
+            api::compare,
+            api::recent,
             api::send,
+            api::send_history,
+            api::broadcast_preview,
+            api::validate_send,
             api::list_authed,
+            api::list_scaled,
+            api::list_geojson,
+            api::list_bbox,
             api::list_invalid,
+            api::bootstrap,
+            api::bootstrap_anonymous,
+            api::order,
+            api::set_enabled,
+            api::history_batch,
+            api::history_shape,
+            api::history_range,
+            api::tags,
+            api::tag_counts,
+            api::tagged_nodes,
+            api::assign_tags,
+            api::rename_tag,
+            api::alert_summary,
+            api::alert_history,
+            api::activity,
+            api::node_report,
+            api::tail,
+            api::acknowledge_alert,
+            api::acknowledge_alerts,
+            api::mute,
+            api::unmute,
+            api::webhooks,
+            api::create_webhook,
+            api::delete_webhook,
+            api::test_webhook,
+            api::silent_nodes,
+            api::unreported_nodes,
+            api::incomplete_nodes,
+            api::thresholds_by_units,
+            api::reading_counts,
+            api::ingest_readings,
+            api::units_stats,
+            api::batch_stats,
+            api::username_available,
+            api::register,
+            api::prefs,
+            api::set_prefs,
+            api::export_me,
+            api::my_keys,
+            api::create_key,
+            api::revoke_key,
             api::login,
             api::logout,
+            api::session_count,
+            api::health_auth,
+            rate_limit::limited,
+            rate_limit::status,
+            chaos::failure,
+            https::insecure,
+            api::backup,
+            api::purge,
+            api::failed_logins,
+            api::audit_log,
+            api::reassign,
+            api::merge_nodes,
+            api::restore,
+            api::list_users,
+            api::lookup_users,
+            api::delete_user,
+            api::restore_user,
+            api::impersonate,
+            api::set_rate_limit_tier,
+            api::reset_password,
+            api::config,
+            api::validate_nodes,
+            logging::logs,
+            metrics::metrics,
+            subscribers::subscribers,
         ])
         //  Add the 404 handler
         .catch(catchers![errors::not_found])
         //  Manage the database connection
         .manage(conn)
         .manage(rocket_xbees)
+        .manage(clock)
+        .manage(log_buffer)
+        .manage(subscribers::Subscribers::default())
+        .manage(session::ActiveSessions::default())
+        .manage(metrics)
+        .manage(api::AvailabilityLimiter::default())
+        //  Read the feature flags, settings and rate limit from Rocket.toml
+        .attach(AdHoc::on_attach(move |rocket| {
+            let features = config::Features::from_config(rocket.config());
+            let settings = config::Settings::from_config(rocket.config());
+
+            //  Start purging old readings. Nodes can have their own
+            //  retention period even if there is no default.
+            let pool = purge_pool.clone();
+            let clock = purge_clock.clone();
+            let days = settings.retention_days;
+            thread::spawn(move || purge_readings(pool, clock, days));
+
+            //  Start posting alerts to webhooks
+            let pool = webhook_pool.clone();
+            let clock = webhook_clock.clone();
+            let xbees = webhook_xbees.clone();
+            let watch_settings = settings.clone();
+            thread::spawn(move || watch_alerts(pool, xbees, clock, watch_settings));
+
+            let limiter = rate_limit::RateLimiter::from_config(rocket.config());
+            if let Some(ref limiter) = limiter {
+                load_rate_limit_tiers(&bootstrap_pool, limiter);
+            }
+            let chaos = chaos::Chaos::from_config(rocket.config());
+            let cors = cors::Cors::from_config(rocket.config());
+            let metrics = metrics::MetricsConfig::from_config(rocket.config());
+
+            if !settings.require_https {
+                warn!("require_https is off. Logins will fail if a proxy serves the site over plain HTTP.");
+            }
+
+            //  Create the first admin if this is a fresh deployment
+            bootstrap_admin(&bootstrap_pool, rocket.config(), settings.bcrypt_cost);
+            log_username_renames(&bootstrap_pool);
+
+            Ok(rocket.manage(features).manage(settings).manage(limiter).manage(chaos).manage(cors).manage(metrics))
+        }))
+        //  Note when requests arrive, so their latency can be measured
+        .attach(AdHoc::on_request(metrics::start_timer))
+        //  Treat API paths with and without a trailing slash the same
+        .attach(AdHoc::on_request(trim_trailing_slash))
+        //  Turn away requests that a proxy says didn't use HTTPS
+        .attach(AdHoc::on_request(https::require_https))
+        //  Find who is making API requests once, for the routes and
+        //  the rate limiter
+        .attach(AdHoc::on_request(api::resolve_caller))
+        //  Turn away clients making too many requests
+        .attach(AdHoc::on_request(rate_limit::limit_request))
+        //  Slow down and fail requests on purpose if chaos mode is on
+        .attach(AdHoc::on_request(chaos::disrupt_request))
+        //  Log request bodies if debugging is turned on
+        .attach(AdHoc::on_request(logging::log_request_body))
+        //  Tell clients which API version they are talking to, even
+        //  on responses from the error catchers
+        .attach(AdHoc::on_response(|_, response| {
+            response.set_raw_header("X-Api-Version", api::API_VERSION);
+        }))
+        //  Tell clients how close they are to the rate limit
+        .attach(AdHoc::on_response(rate_limit::limit_headers))
+        //  Let the allowed origins make cross-origin requests
+        .attach(AdHoc::on_response(cors::add_headers))
+        //  Count responses and how long they took for /metrics
+        .attach(AdHoc::on_response(metrics::record_response))
         .launch();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::Client;
+
+    #[get("/api/list")]
+    fn list() -> &'static str {
+        "list"
+    }
+
+    #[get("/api/nodes/<id>?<query>")]
+    fn node(id: i32, query: &str) -> String {
+        format!("node {} {}", id, query)
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::ignite()
+            .mount("/", routes![list, node])
+            .attach(AdHoc::on_request(trim_trailing_slash));
+
+        Client::new(rocket).unwrap()
+    }
+
+    fn body(client: &Client, path: &str) -> (Status, Option<String>) {
+        let mut response = client.get(path).dispatch();
+        (response.status(), response.body_string())
+    }
+
+    #[test]
+    fn api_paths_match_with_or_without_a_trailing_slash() {
+        let client = client();
+
+        assert_eq!(body(&client, "/api/list"), (Status::Ok, Some("list".to_string())));
+        assert_eq!(body(&client, "/api/list/"), (Status::Ok, Some("list".to_string())));
+        assert_eq!(body(&client, "/api/list//"), (Status::Ok, Some("list".to_string())));
+    }
+
+    #[test]
+    fn trimming_keeps_the_query() {
+        let client = client();
+        let expected = (Status::Ok, Some("node 5 fields=name".to_string()));
+
+        assert_eq!(body(&client, "/api/nodes/5?fields=name"), expected);
+        assert_eq!(body(&client, "/api/nodes/5/?fields=name"), expected);
+    }
+}
@@ -0,0 +1,326 @@
+use parking_lot::Mutex;
+use rocket::{Data, Outcome, Request, Response, State};
+use rocket::config::{Config, Value};
+use rocket::http::{Header, Method, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::content;
+use rocket::response::status;
+use time;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use clock::SharedClock;
+use db::{self, DbConn};
+use info::InfoSet;
+use session::ActiveSessions;
+
+/// The header the time a request arrived is kept in, so its latency
+/// can be found when the response goes out. It is replaced on every
+/// request, so clients can't set it.
+const START_HEADER: &str = "X-Metrics-Start";
+
+/// The upper bounds of the request latency histogram, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// How long a user counts as active after their last authorized
+/// request, in seconds.
+const ACTIVE_USER_WINDOW: u64 = 5 * 60;
+
+/// The `/metrics` endpoint, configured by the `metrics` table in
+/// Rocket.toml. It is off unless `enabled` is set. If `token` is set,
+/// scrapers have to send it as a bearer token.
+pub struct MetricsConfig {
+    token: Option<String>,
+}
+
+impl MetricsConfig {
+    /// Reads the metrics endpoint from the given config, or returns
+    /// `None` if it is not turned on.
+    pub fn from_config(config: &Config) -> Option<MetricsConfig> {
+        let table = config.get_table("metrics").ok()?;
+
+        if !table.get("enabled").and_then(Value::as_bool).unwrap_or(false) {
+            return None;
+        }
+
+        let token = table.get("token")
+            .and_then(Value::as_str)
+            .map(String::from);
+
+        if token.is_none() {
+            warn!("/metrics is on without a token, so anyone who can reach the server can read it.");
+        }
+
+        Some(MetricsConfig { token: token })
+    }
+}
+
+/// The requests that have been answered, by method and status class,
+/// and how long they took.
+#[derive(Default)]
+struct Requests {
+    counts: HashMap<(&'static str, &'static str), u64>,
+    /// How many requests fell in each latency bucket, with the last
+    /// one for requests slower than every bucket.
+    buckets: Vec<u64>,
+    latency_sum: f64,
+    timed: u64,
+}
+
+/// Counters kept for `/metrics`. Labels only ever take a few fixed
+/// values, so the number of series stays the same however many nodes
+/// and paths there are.
+struct Counters {
+    requests: Mutex<Requests>,
+    radio_readings: AtomicUsize,
+    api_readings: AtomicUsize,
+    users: Mutex<HashMap<i32, Instant>>,
+}
+
+/// The metrics collected by the server. Clones share the same
+/// counters, so the radio thread can count readings as well.
+#[derive(Clone)]
+pub struct Metrics(Arc<Counters>);
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics(Arc::new(Counters {
+            requests: Mutex::new(Requests {
+                buckets: vec![0; LATENCY_BUCKETS.len() + 1],
+                ..Requests::default()
+            }),
+            radio_readings: AtomicUsize::new(0),
+            api_readings: AtomicUsize::new(0),
+            users: Mutex::new(HashMap::new()),
+        }))
+    }
+}
+
+/// Returns the method as a label. Methods Rocket knows are a fixed
+/// set, so this can't grow.
+fn method_label(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Put => "PUT",
+        Method::Post => "POST",
+        Method::Delete => "DELETE",
+        Method::Options => "OPTIONS",
+        Method::Head => "HEAD",
+        Method::Trace => "TRACE",
+        Method::Connect => "CONNECT",
+        Method::Patch => "PATCH",
+    }
+}
+
+/// Returns the class of a status as a label, such as "2xx".
+fn status_label(status: Status) -> &'static str {
+    match status.code {
+        100...199 => "1xx",
+        200...299 => "2xx",
+        300...399 => "3xx",
+        400...499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+impl Metrics {
+    /// Records a response to a request that took `elapsed` seconds, if
+    /// it is known.
+    fn record_request(&self, method: Method, status: Status, elapsed: Option<f64>) {
+        let mut requests = self.0.requests.lock();
+        *requests.counts.entry((method_label(method), status_label(status))).or_insert(0) += 1;
+
+        if let Some(elapsed) = elapsed {
+            let bucket = LATENCY_BUCKETS.iter()
+                .position(|&bound| elapsed <= bound)
+                .unwrap_or(LATENCY_BUCKETS.len());
+            requests.buckets[bucket] += 1;
+            requests.latency_sum += elapsed;
+            requests.timed += 1;
+        }
+    }
+
+    /// Records readings received over the radio.
+    pub fn radio_readings(&self, count: usize) {
+        self.0.radio_readings.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records readings sent to /api/readings/batch.
+    pub fn api_readings(&self, count: usize) {
+        self.0.api_readings.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records that a user made an authorized request just now.
+    pub fn saw_user(&self, user: i32) {
+        self.0.users.lock().insert(user, Instant::now());
+    }
+
+    /// Returns how many users made an authorized request within
+    /// `ACTIVE_USER_WINDOW`, forgetting the ones that didn't.
+    fn active_users(&self) -> usize {
+        let now = Instant::now();
+        let mut users = self.0.users.lock();
+
+        users.retain(|_, seen| now.duration_since(*seen).as_secs() < ACTIVE_USER_WINDOW);
+        users.len()
+    }
+
+    /// Writes the metrics in the Prometheus text format. The node and
+    /// session counts are passed in, since they come from the
+    /// database, the radio and the session list rather than from
+    /// requests.
+    fn render(&self, configured_nodes: Option<usize>, active_nodes: usize, active_sessions: usize) -> String {
+        let mut out = String::new();
+
+        {
+            let requests = self.0.requests.lock();
+
+            let _ = writeln!(out, "# HELP sparkies_http_requests_total Requests answered, by method and status class.");
+            let _ = writeln!(out, "# TYPE sparkies_http_requests_total counter");
+
+            let mut counts = requests.counts.iter().collect::<Vec<_>>();
+            counts.sort();
+
+            for (&(method, status), count) in counts {
+                let _ = writeln!(out, "sparkies_http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+            }
+
+            let _ = writeln!(out, "# HELP sparkies_http_request_duration_seconds How long requests took to answer.");
+            let _ = writeln!(out, "# TYPE sparkies_http_request_duration_seconds histogram");
+
+            let mut cumulative = 0;
+
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&requests.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "sparkies_http_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+            }
+
+            let _ = writeln!(out, "sparkies_http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", requests.timed);
+            let _ = writeln!(out, "sparkies_http_request_duration_seconds_sum {}", requests.latency_sum);
+            let _ = writeln!(out, "sparkies_http_request_duration_seconds_count {}", requests.timed);
+        }
+
+        let _ = writeln!(out, "# HELP sparkies_readings_received_total Readings received, by where they came from.");
+        let _ = writeln!(out, "# TYPE sparkies_readings_received_total counter");
+        let _ = writeln!(out, "sparkies_readings_received_total{{source=\"radio\"}} {}", self.0.radio_readings.load(Ordering::Relaxed));
+        let _ = writeln!(out, "sparkies_readings_received_total{{source=\"api\"}} {}", self.0.api_readings.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP sparkies_nodes Nodes in the database, and nodes that sent their info over the radio.");
+        let _ = writeln!(out, "# TYPE sparkies_nodes gauge");
+
+        if let Some(configured) = configured_nodes {
+            let _ = writeln!(out, "sparkies_nodes{{state=\"configured\"}} {}", configured);
+        }
+
+        let _ = writeln!(out, "sparkies_nodes{{state=\"active\"}} {}", active_nodes);
+
+        let _ = writeln!(out, "# HELP sparkies_active_users Users who made an authorized request in the last {} seconds.", ACTIVE_USER_WINDOW);
+        let _ = writeln!(out, "# TYPE sparkies_active_users gauge");
+        let _ = writeln!(out, "sparkies_active_users {}", self.active_users());
+
+        let _ = writeln!(out, "# HELP sparkies_active_sessions Sessions that haven't expired.");
+        let _ = writeln!(out, "# TYPE sparkies_active_sessions gauge");
+        let _ = writeln!(out, "sparkies_active_sessions {}", active_sessions);
+
+        out
+    }
+}
+
+/// Notes when a request arrived, so `record_response` can time it.
+pub fn start_timer(request: &mut Request, _: &Data) {
+    request.replace_header(Header::new(START_HEADER, time::precise_time_ns().to_string()));
+}
+
+/// Counts the response to a request, and how long it took since
+/// `start_timer` saw it.
+pub fn record_response(request: &Request, response: &mut Response) {
+    let metrics = match request.guard::<State<Metrics>>() {
+        Outcome::Success(metrics) => metrics,
+        _ => return,
+    };
+
+    let elapsed = request.headers()
+        .get_one(START_HEADER)
+        .and_then(|start| start.parse::<u64>().ok())
+        .map(|start| time::precise_time_ns().saturating_sub(start) as f64 / 1e9);
+
+    metrics.record_request(request.method(), response.status(), elapsed);
+}
+
+/// The bearer token a request was sent with, if any.
+pub struct BearerToken(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for BearerToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<BearerToken, ()> {
+        let token = request.headers()
+            .get_one("Authorization")
+            .and_then(|header| {
+                let mut parts = header.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some("Bearer"), Some(token)) => Some(token.trim().to_string()),
+                    _ => None,
+                }
+            });
+
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+/// Returns the server's metrics in the Prometheus text format, for
+/// monitoring to scrape. This is 404 Not Found unless it is turned on
+/// with the `metrics` table in Rocket.toml.
+/// 
+/// There are request counts by method and status class, a histogram
+/// of request latency, readings received by whether they came over the
+/// radio or through /api/readings/batch, and how many nodes there are.
+/// Nodes aren't labeled by id, so the number of series doesn't grow
+/// with the network. There is a count of sessions that haven't
+/// expired, the same as /api/sessions/count, and of users who made an
+/// authorized request in the last 5 minutes.
+/// 
+/// **Note**: If the `token` setting is set, this endpoint requires
+/// that it is sent as a bearer token.
+/// 
+/// # Errors
+/// If the token is missing or wrong, an error is returned with a 401
+/// Unauthorized status.
+/// 
+/// # Example
+/// ```text
+/// # HELP sparkies_http_requests_total Requests answered, by method and status class.
+/// # TYPE sparkies_http_requests_total counter
+/// sparkies_http_requests_total{method="GET",status="2xx"} 1204
+/// ...
+/// # HELP sparkies_active_users Users who made an authorized request in the last 300 seconds.
+/// # TYPE sparkies_active_users gauge
+/// sparkies_active_users 3
+/// # HELP sparkies_active_sessions Sessions that haven't expired.
+/// # TYPE sparkies_active_sessions gauge
+/// sparkies_active_sessions 4
+/// ```
+#[get("/metrics")]
+pub fn metrics(metrics: State<Metrics>, config: State<Option<MetricsConfig>>, token: BearerToken, info: InfoSet, sessions: State<ActiveSessions>, clock: State<SharedClock>, conn: DbConn) -> Option<Result<content::Plain<String>, status::Custom<content::Plain<&'static str>>>> {
+    let config = config.as_ref()?;
+
+    if let Some(ref expected) = config.token {
+        if token.0.as_ref() != Some(expected) {
+            return Some(Err(status::Custom(Status::Unauthorized, content::Plain("A valid bearer token is required.\n"))));
+        }
+    }
+
+    let configured = match db::xbee_config_map(&conn) {
+        Ok(configs) => Some(configs.len()),
+        Err(why) => {
+            warn!("Could not get xbee configuration: {}", why);
+            None
+        }
+    };
+
+    Some(Ok(content::Plain(metrics.render(configured, info.nodes().len(), sessions.count(clock.now())))))
+}
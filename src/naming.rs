@@ -0,0 +1,138 @@
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeSeq;
+
+/// How the fields of JSON responses are named, from the
+/// `json_field_case` setting in Rocket.toml.
+/// 
+/// Responses are written in snake_case, so that is the default. Only
+/// response types with a camelCase twin, made with
+/// `#[serde(rename_all = "camelCase")]`, are renamed, so keys that are
+/// data rather than field names are never touched. Requests are always
+/// read with snake_case names, whichever is picked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldCase {
+    Snake,
+    Camel,
+}
+
+impl FieldCase {
+    /// Returns the field case with the given name, if there is one.
+    pub fn parse(name: &str) -> Option<FieldCase> {
+        match name {
+            "snake_case" => Some(FieldCase::Snake),
+            "camelCase" => Some(FieldCase::Camel),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the field case, as it is given in
+    /// Rocket.toml.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldCase::Snake => "snake_case",
+            FieldCase::Camel => "camelCase",
+        }
+    }
+
+    /// Returns what a snake_case field is named in this case.
+    pub fn field_name(self, name: &str) -> String {
+        match self {
+            FieldCase::Snake => name.to_string(),
+            FieldCase::Camel => camel_case(name),
+        }
+    }
+}
+
+/// Turns a snake_case name into camelCase, such as "last_update" into
+/// "lastUpdate". Leading underscores are kept.
+pub fn camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+
+    for c in name.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+
+    camel
+}
+
+/// A response type that can also be written with camelCase field
+/// names, usually by converting it to a twin type with
+/// `#[serde(rename_all = "camelCase")]`.
+pub trait CamelCase {
+    /// Writes the value with camelCase field names.
+    fn serialize_camel<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+}
+
+/// Writes a value with camelCase field names.
+pub struct Camel<'a, T: 'a>(pub &'a T);
+
+impl<'a, T: CamelCase> Serialize for Camel<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_camel(serializer)
+    }
+}
+
+/// A list of response values written with the field names picked by
+/// the `json_field_case` setting.
+pub struct Cased<'a, T: 'a> {
+    items: &'a [T],
+    case: FieldCase,
+}
+
+impl<'a, T> Cased<'a, T> {
+    pub fn new(items: &'a [T], case: FieldCase) -> Cased<'a, T> {
+        Cased {
+            items: items,
+            case: case,
+        }
+    }
+}
+
+impl<'a, T: Serialize + CamelCase> Serialize for Cased<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.case == FieldCase::Snake {
+            return self.items.serialize(serializer);
+        }
+
+        let mut seq = serializer.serialize_seq(Some(self.items.len()))?;
+
+        for item in self.items {
+            seq.serialize_element(&Camel(item))?;
+        }
+
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camel_case_joins_words() {
+        assert_eq!(camel_case("last_update"), "lastUpdate");
+        assert_eq!(camel_case("last_update_str"), "lastUpdateStr");
+        assert_eq!(camel_case("uuid"), "uuid");
+    }
+
+    #[test]
+    fn camel_case_keeps_leading_underscores() {
+        assert_eq!(camel_case("_private"), "_private");
+    }
+
+    #[test]
+    fn field_names_follow_the_case() {
+        assert_eq!(FieldCase::Snake.field_name("node_id"), "node_id");
+        assert_eq!(FieldCase::Camel.field_name("node_id"), "nodeId");
+        assert_eq!(FieldCase::parse("camelCase"), Some(FieldCase::Camel));
+        assert_eq!(FieldCase::parse("kebab-case"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use rocket::Outcome;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Stream};
+use rocket::response::content::Content;
+use rocket_contrib::JsonValue;
+use serde_json::{Map, Value};
+
+use std::io::Read;
+
+/// The format a client asked for in its `Accept` header.
+/// 
+/// Requests without the header, or that accept anything, get JSON.
+/// Types are tried from the highest `q` weight down, and the first
+/// supported one is used. Requests that only accept unsupported types
+/// fail with a 406 Not Acceptable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// Returns the format for a single media type, if it's supported.
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type.trim().to_lowercase().as_str() {
+            "*/*" | "application/*" | "application/json" => Some(Format::Json),
+            "text/*" | "text/csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// Picks the format from the value of an `Accept` header.
+    fn from_accept(accept: &str) -> Option<Format> {
+        let mut types = accept.split(',')
+            .map(|media_type| {
+                let mut params = media_type.split(';');
+                let name = params.next().unwrap_or("").trim().to_string();
+                let weight = params
+                    .filter_map(|param| {
+                        let mut pair = param.splitn(2, '=');
+                        match (pair.next(), pair.next()) {
+                            (Some(key), Some(value)) if key.trim() == "q" => value.trim().parse::<f32>().ok(),
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .unwrap_or(1.0);
+
+                (name, weight)
+            })
+            .filter(|&(_, weight)| weight > 0.0)
+            .collect::<Vec<(String, f32)>>();
+
+        //  The sort is stable, so types with the same weight keep
+        //  the order the client gave them in.
+        types.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+
+        types.iter()
+            .filter_map(|&(ref name, _)| Format::from_media_type(name))
+            .next()
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Format {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Format, ()> {
+        let accept = match request.headers().get_one("Accept") {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Outcome::Success(Format::Json),
+        };
+
+        match Format::from_accept(accept) {
+            Some(format) => Outcome::Success(format),
+            None => Outcome::Failure((Status::NotAcceptable, ())),
+        }
+    }
+}
+
+/// A response in the format the client negotiated. CSV that is too
+/// large to build in memory can be streamed with `CsvStream`.
+pub enum Negotiated {
+    Json(JsonValue),
+    Csv(String),
+    CsvStream(Box<Read>),
+}
+
+impl<'r> Responder<'r> for Negotiated {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Negotiated::Json(body) => body.respond_to(request),
+            Negotiated::Csv(body) => Content(ContentType::new("text", "csv"), body).respond_to(request),
+            Negotiated::CsvStream(body) => Content(ContentType::new("text", "csv"), Stream::from(body)).respond_to(request),
+        }
+    }
+}
+
+/// Quotes a CSV field if it has a comma, quote or line break in it.
+pub fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes text as a CSV field. Text that a spreadsheet would run as a
+/// formula, like `=1+1`, is prefixed with a `'` so it shows as text.
+pub fn csv_text(text: &str) -> String {
+    if text.starts_with(|c| c == '=' || c == '+' || c == '-' || c == '@') {
+        csv_field(&format!("'{}", text))
+    } else {
+        csv_field(text)
+    }
+}
+
+/// Writes the given JSON objects as CSV with a header row, one row
+/// per object and one column per name in `columns`. Missing and null
+/// fields are left empty. Arrays and objects can't be flattened into a
+/// single field, so they are written as JSON. Strings are written with
+/// `csv_text`, so they can't be run as formulas.
+pub fn to_csv(columns: &[&str], rows: &[Map<String, Value>]) -> String {
+    let mut csv = columns.iter()
+        .map(|column| csv_field(column))
+        .collect::<Vec<String>>()
+        .join(",");
+    csv.push_str("\r\n");
+
+    for row in rows {
+        let fields = columns.iter()
+            .map(|column| match row.get(*column) {
+                None | Some(&Value::Null) => String::new(),
+                Some(&Value::String(ref value)) => csv_text(value),
+                Some(value) => csv_field(&value.to_string()),
+            })
+            .collect::<Vec<String>>();
+
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_defuses_formulas_in_strings() {
+        let row = json!({ "name": "=HYPERLINK(\"x\")", "value": -4.5 });
+        let rows = vec![row.as_object().unwrap().clone()];
+
+        assert_eq!(to_csv(&["name", "value"], &rows), "name,value\r\n\"'=HYPERLINK(\"\"x\"\")\",-4.5\r\n");
+    }
+}
@@ -0,0 +1,27 @@
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{self, FormItems, FromForm, FromRequest, Request};
+
+/// Parses the query string of a request into `T`.
+/// 
+/// Rocket only matches routes that declare a query parameter when
+/// the request actually has a query string. Using this guard instead
+/// lets a single route handle requests both with and without one,
+/// since a missing query string is parsed as an empty one.
+/// 
+/// Unknown parameters are ignored. If a known parameter can't be
+/// parsed, the request fails with a 400 Bad Request.
+pub struct Query<T>(pub T);
+
+impl<'a, 'r, T: FromForm<'a>> FromRequest<'a, 'r> for Query<T> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Query<T>, ()> {
+        let query = request.uri().query().unwrap_or("");
+
+        match T::from_form(&mut FormItems::from(query), false) {
+            Ok(value) => Outcome::Success(Query(value)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
@@ -0,0 +1,581 @@
+use parking_lot::{Mutex, RwLock};
+use rocket::{Data, Outcome, Request, State};
+use rocket::config::{Config, Table, Value};
+use rocket::http::{Method, Status};
+use rocket::response::{self, Responder, Response};
+use rocket_contrib::JsonValue;
+use serde_json::{self, Value as JsonField};
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use api::AuthedUser;
+use query::Query;
+
+/// How many separately locked maps buckets are spread over, so
+/// requests from different clients rarely wait on each other.
+const SHARDS: usize = 16;
+
+/// How often each shard is swept for idle buckets, in seconds.
+const SWEEP_INTERVAL: u64 = 60;
+
+/// The path rate limited requests are rerouted to.
+pub const LIMITED_PATH: &str = "/api/rate-limited";
+
+/// The warning added to responses for clients close to the limit.
+const WARNING: &str = "You are close to the rate limit. Slow down to avoid being blocked.";
+
+/// How many requests a client can make at once, and how many more
+/// they get every second.
+#[derive(Clone, Copy)]
+struct Limit {
+    rate: f64,
+    burst: f64,
+}
+
+impl Limit {
+    /// Reads a limit from a table with `rate` and `burst` keys. The
+    /// burst defaults to the rate, rounded up.
+    fn from_table(table: &Table) -> Option<Limit> {
+        let rate = table.get("rate").and_then(Value::as_float)?;
+        let burst = table.get("burst")
+            .and_then(Value::as_integer)
+            .map_or(rate.ceil(), |burst| burst as f64);
+
+        if rate <= 0.0 || burst < 1.0 {
+            return None;
+        }
+
+        Some(Limit { rate: rate, burst: burst })
+    }
+}
+
+/// A rate limit tier a user can be put on by an admin. Users on the
+/// default tier, and clients that aren't logged in, get the limit
+/// from the `rate_limit` table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tier {
+    Default,
+    Elevated,
+    Unlimited,
+}
+
+/// The names of every rate limit tier.
+pub const TIERS: &[&str] = &["default", "elevated", "unlimited"];
+
+impl Tier {
+    /// Returns the tier with the given name, if there is one.
+    pub fn parse(name: &str) -> Option<Tier> {
+        match name {
+            "default" => Some(Tier::Default),
+            "elevated" => Some(Tier::Elevated),
+            "unlimited" => Some(Tier::Unlimited),
+            _ => None,
+        }
+    }
+
+    /// Returns the tier's name.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tier::Default => "default",
+            Tier::Elevated => "elevated",
+            Tier::Unlimited => "unlimited",
+        }
+    }
+}
+
+/// Who a bucket belongs to. Logged in users get their own bucket,
+/// wherever they make requests from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Caller {
+    Ip(IpAddr),
+    User(i32),
+}
+
+/// A client's bucket of request tokens, and the limit it refills
+/// with.
+struct Bucket {
+    tokens: f64,
+    limit: Limit,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Returns how many tokens the bucket has at the given time.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        (self.tokens + seconds_between(self.updated, now) * self.limit.rate).min(self.limit.burst)
+    }
+}
+
+/// A shard of buckets and when it was last swept.
+struct Shard {
+    buckets: HashMap<Caller, Bucket>,
+    swept: Instant,
+}
+
+/// How close a client is to the rate limit.
+#[derive(Serialize)]
+pub struct LimitStatus {
+    /// How many requests the client can make right now.
+    pub remaining: u64,
+    /// How many requests the client can make at once.
+    pub limit: u64,
+    /// How many seconds until the client's bucket is full again.
+    pub reset: u64,
+    /// The client's rate limit tier.
+    pub tier: &'static str,
+    /// Whether the client is close enough to the limit to be warned.
+    pub warning: bool,
+}
+
+/// A network given in CIDR notation, such as "10.0.0.0/8".
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    /// Parses a network in CIDR notation. A bare address is treated
+    /// as a network holding only that address.
+    fn parse(network: &str) -> Option<Network> {
+        let mut parts = network.trim().splitn(2, '/');
+        let address = parts.next()?.parse::<IpAddr>().ok()?;
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse::<u32>().ok()?,
+            None => max_prefix,
+        };
+
+        if prefix > max_prefix {
+            return None;
+        }
+
+        Some(Network { address: address, prefix: prefix })
+    }
+
+    /// Returns whether the given address is in the network.
+    fn contains(&self, address: &IpAddr) -> bool {
+        let (network, address) = match (self.address, *address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => (network.octets().to_vec(), address.octets().to_vec()),
+            (IpAddr::V6(network), IpAddr::V6(address)) => (network.octets().to_vec(), address.octets().to_vec()),
+            _ => return false,
+        };
+
+        let mut remaining = self.prefix;
+
+        for (network, address) in network.iter().zip(address.iter()) {
+            if remaining == 0 {
+                break;
+            }
+
+            let bits = remaining.min(8);
+            let mask = !0u8 << (8 - bits);
+
+            if network & mask != address & mask {
+                return false;
+            }
+
+            remaining -= bits;
+        }
+
+        true
+    }
+}
+
+/// A token bucket rate limiter keyed by client IP, or by user for
+/// logged in users, configured by the `rate_limit` table in
+/// Rocket.toml.
+/// 
+/// Each client can make `burst` requests at once, and gets `rate`
+/// more every second up to that limit. Users on the elevated tier
+/// get the limit from the `rate_limit.elevated` table instead, and
+/// users on the unlimited tier are never limited. Neither are clients
+/// in an `exempt` network. If the table is not set, no client is
+/// limited.
+/// 
+/// If `warn_fraction` is set, clients are warned once fewer than that
+/// fraction of their burst is left, before they are limited.
+pub struct RateLimiter {
+    default: Limit,
+    elevated: Limit,
+    warn_fraction: Option<f64>,
+    exempt: Vec<Network>,
+    tiers: RwLock<HashMap<i32, Tier>>,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl RateLimiter {
+    /// Reads the rate limit from the given config, or returns `None`
+    /// if rate limiting is not turned on. Exempt networks that can't
+    /// be parsed are ignored with a warning, and so is an elevated
+    /// tier without a positive rate and burst, which then gets the
+    /// default limit, and a `warn_fraction` that isn't between 0 and 1.
+    pub fn from_config(config: &Config) -> Option<RateLimiter> {
+        let table = config.get_table("rate_limit").ok()?;
+        table.get("rate")?;
+
+        let default = match Limit::from_table(table) {
+            Some(limit) => limit,
+            None => {
+                warn!("Rate limit must have a positive rate and burst, ignoring it.");
+                return None;
+            }
+        };
+
+        let elevated = match table.get("elevated").and_then(Value::as_table) {
+            Some(elevated) => Limit::from_table(elevated).unwrap_or_else(|| {
+                warn!("Elevated rate limit must have a positive rate and burst, using the default.");
+                default
+            }),
+            None => default,
+        };
+
+        let exempt = table.get("exempt")
+            .and_then(Value::as_array)
+            .map(|networks| {
+                networks.iter()
+                    .filter_map(|network| {
+                        let parsed = network.as_str().and_then(Network::parse);
+                        if parsed.is_none() {
+                            warn!("Exempt network {} is not valid, ignoring it.", network);
+                        }
+                        parsed
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let warn_fraction = table.get("warn_fraction")
+            .and_then(Value::as_float)
+            .and_then(|fraction| {
+                if fraction > 0.0 && fraction < 1.0 {
+                    Some(fraction)
+                } else {
+                    warn!("Rate limit warn_fraction must be between 0 and 1, ignoring it.");
+                    None
+                }
+            });
+
+        let mut limiter = RateLimiter::new(default, elevated, exempt);
+        limiter.warn_fraction = warn_fraction;
+
+        Some(limiter)
+    }
+
+    /// Creates a rate limiter with the given limits for the default
+    /// and elevated tiers, except from the exempt networks.
+    fn new(default: Limit, elevated: Limit, exempt: Vec<Network>) -> RateLimiter {
+        let now = Instant::now();
+        let shards = (0..SHARDS)
+            .map(|_| Mutex::new(Shard { buckets: HashMap::new(), swept: now }))
+            .collect();
+
+        RateLimiter {
+            default: default,
+            elevated: elevated,
+            warn_fraction: None,
+            exempt: exempt,
+            tiers: RwLock::new(HashMap::new()),
+            shards: shards,
+        }
+    }
+
+    /// Creates a rate limiter with the given rate and burst that
+    /// applies to every client, for limiting a single endpoint.
+    pub fn fixed(rate: f64, burst: f64) -> RateLimiter {
+        let limit = Limit { rate: rate, burst: burst };
+        RateLimiter::new(limit, limit, Vec::new())
+    }
+
+    /// Puts a user on the given tier. This only changes the tier in
+    /// memory, so it has to be saved to the database as well.
+    pub fn set_tier(&self, user: i32, tier: Tier) {
+        let mut tiers = self.tiers.write();
+
+        if tier == Tier::Default {
+            tiers.remove(&user);
+        } else {
+            tiers.insert(user, tier);
+        }
+    }
+
+    /// Returns the tier the given user is on.
+    pub fn tier(&self, user: i32) -> Tier {
+        self.tiers.read().get(&user).cloned().unwrap_or(Tier::Default)
+    }
+
+    /// Returns whose bucket a request counts against, with its tier
+    /// and limit, or `None` if the request is never limited.
+    fn limit_for(&self, client: IpAddr, user: Option<i32>) -> Option<(Caller, Tier, Limit)> {
+        if self.exempt.iter().any(|network| network.contains(&client)) {
+            return None;
+        }
+
+        let user = match user {
+            Some(user) => user,
+            None => return Some((Caller::Ip(client), Tier::Default, self.default)),
+        };
+
+        match self.tier(user) {
+            Tier::Default => Some((Caller::User(user), Tier::Default, self.default)),
+            Tier::Elevated => Some((Caller::User(user), Tier::Elevated, self.elevated)),
+            Tier::Unlimited => None,
+        }
+    }
+
+    /// Returns the shard the given caller's bucket is kept in.
+    fn shard(&self, caller: &Caller) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        caller.hash(&mut hasher);
+
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    /// Takes a token from the bucket of the given client, or of the
+    /// given user if they're logged in. Returns `None` if the request
+    /// is allowed, or how many seconds the client has to wait before
+    /// another request is allowed.
+    pub fn check(&self, client: IpAddr, user: Option<i32>) -> Option<u64> {
+        let (caller, _, limit) = self.limit_for(client, user)?;
+
+        let now = Instant::now();
+        let mut shard = self.shard(&caller).lock();
+
+        if now.duration_since(shard.swept).as_secs() >= SWEEP_INTERVAL {
+            sweep(&mut shard, now);
+        }
+
+        let bucket = shard.buckets.entry(caller)
+            .or_insert(Bucket { tokens: limit.burst, limit: limit, updated: now });
+
+        //  The user's tier may have changed since the bucket was made
+        bucket.tokens = bucket.tokens_at(now).min(limit.burst);
+        bucket.limit = limit;
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - bucket.tokens) / limit.rate).ceil() as u64)
+        }
+    }
+
+    /// Returns how close the given client or user is to the limit,
+    /// without taking a token, or `None` if they're never limited. A
+    /// client without a bucket has a full one.
+    pub fn status(&self, client: IpAddr, user: Option<i32>) -> Option<LimitStatus> {
+        let (caller, tier, limit) = self.limit_for(client, user)?;
+
+        let now = Instant::now();
+        let shard = self.shard(&caller).lock();
+
+        let tokens = shard.buckets.get(&caller)
+            .map_or(limit.burst, |bucket| bucket.tokens_at(now).min(limit.burst));
+
+        Some(LimitStatus {
+            remaining: tokens.floor() as u64,
+            limit: limit.burst as u64,
+            reset: ((limit.burst - tokens) / limit.rate).ceil() as u64,
+            tier: tier.as_str(),
+            warning: self.warn_fraction.map_or(false, |fraction| tokens < limit.burst * fraction),
+        })
+    }
+}
+
+/// Removes buckets that have refilled completely, since they're
+/// the same as a client that was never seen.
+fn sweep(shard: &mut Shard, now: Instant) {
+    shard.buckets.retain(|_, bucket| bucket.tokens_at(now) < bucket.limit.burst);
+    shard.swept = now;
+}
+
+/// Returns how many seconds passed between two instants.
+fn seconds_between(earlier: Instant, later: Instant) -> f64 {
+    let elapsed = later.duration_since(earlier);
+    elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9
+}
+
+/// Returns the id of the user making the request, if they're logged in.
+/// They are only found once per request, by `api::resolve_caller`, so
+/// checking here both before and after the request is cheap.
+fn caller_id(request: &Request) -> Option<i32> {
+    request.guard::<AuthedUser>().succeeded().map(|user| user.id)
+}
+
+/// Reroutes API requests from clients that are over the rate limit
+/// to `LIMITED_PATH`, which responds with a 429 Too Many Requests.
+/// Health checks are never limited.
+pub fn limit_request(request: &mut Request, _: &Data) {
+    let retry_after = {
+        let path = request.uri().path();
+
+        if !path.starts_with("/api/") || path.starts_with("/api/health") {
+            return;
+        }
+
+        let limiter = match request.guard::<State<Option<RateLimiter>>>() {
+            Outcome::Success(limiter) => limiter,
+            _ => return,
+        };
+
+        match (limiter.as_ref(), request.remote()) {
+            (Some(limiter), Some(remote)) => limiter.check(remote.ip(), caller_id(request)),
+            _ => None,
+        }
+    };
+
+    if let Some(seconds) = retry_after {
+        request.set_method(Method::Get);
+        request.set_uri(format!("{}?retry_after={}", LIMITED_PATH, seconds));
+    }
+}
+
+/// The response given to clients that are over the rate limit.
+#[derive(Debug)]
+pub struct Limited {
+    /// How many seconds the client has to wait before trying again.
+    pub retry_after: u64,
+}
+
+impl<'r> Responder<'r> for Limited {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let body = json!({
+            "error": "Too many requests.",
+            "success": false,
+        });
+
+        Response::build_from(body.respond_to(request)?)
+            .status(Status::TooManyRequests)
+            .raw_header("Retry-After", self.retry_after.to_string())
+            .ok()
+    }
+}
+
+/// Adds the rate limit warning to a JSON object response. Other
+/// responses, and ones that already have a `warning`, are left as
+/// they are.
+fn add_warning(response: &mut Response) {
+    if !response.content_type().map_or(false, |content_type| content_type.is_json()) {
+        return;
+    }
+
+    let body = match response.body_string() {
+        Some(body) => body,
+        None => return,
+    };
+
+    let warned = match serde_json::from_str::<JsonField>(&body) {
+        Ok(JsonField::Object(mut fields)) => {
+            if !fields.contains_key("warning") {
+                fields.insert("warning".to_string(), JsonField::from(WARNING));
+            }
+            serde_json::to_string(&fields).ok()
+        }
+        _ => None,
+    };
+
+    response.set_sized_body(Cursor::new(warned.unwrap_or(body)));
+}
+
+/// Tells clients how close they are to the rate limit on every API
+/// response, through the `X-RateLimit-Limit`, `X-RateLimit-Remaining`
+/// and `X-RateLimit-Reset` headers. The headers are left out if rate
+/// limiting is off or the client is exempt.
+/// 
+/// Clients within `warn_fraction` of the limit also get an
+/// `X-RateLimit-Warning: true` header, and a `warning` in JSON
+/// responses, so they can slow down before they are blocked.
+pub fn limit_headers(request: &Request, response: &mut Response) {
+    if !request.uri().path().starts_with("/api/") {
+        return;
+    }
+
+    let limiter = match request.guard::<State<Option<RateLimiter>>>() {
+        Outcome::Success(limiter) => limiter,
+        _ => return,
+    };
+
+    let status = match (limiter.as_ref(), request.remote()) {
+        (Some(limiter), Some(remote)) => limiter.status(remote.ip(), caller_id(request)),
+        _ => None,
+    };
+
+    if let Some(status) = status {
+        response.set_raw_header("X-RateLimit-Limit", status.limit.to_string());
+        response.set_raw_header("X-RateLimit-Remaining", status.remaining.to_string());
+        response.set_raw_header("X-RateLimit-Reset", status.reset.to_string());
+
+        //  Limited clients are already told why in the 429.
+        if status.warning && response.status() != Status::TooManyRequests {
+            response.set_raw_header("X-RateLimit-Warning", "true");
+            add_warning(response);
+        }
+    }
+}
+
+/// Returns how close the caller is to the rate limit, so clients can
+/// slow down before they are limited. `remaining` is how many requests
+/// they can make right now, after this one, `limit` is how many they
+/// can make at once, and `reset` is how many seconds until they can
+/// make `limit` requests again. `tier` is the caller's rate limit
+/// tier, which is `default` for callers that aren't logged in.
+/// 
+/// If rate limiting is off or the caller is exempt or unlimited,
+/// `limited` is false and the other fields are left out. Callers close
+/// to the limit also get a `warning`, the same as on every other API
+/// response.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "limited": true,
+///     "remaining": 17,
+///     "limit": 20,
+///     "reset": 1,
+///     "tier": "default",
+///     "success": true
+/// }
+/// ```
+#[get("/api/ratelimit")]
+pub fn status(limiter: State<Option<RateLimiter>>, remote: Option<SocketAddr>, user: Option<AuthedUser>) -> JsonValue {
+    let status = match (limiter.as_ref(), remote) {
+        (Some(limiter), Some(remote)) => limiter.status(remote.ip(), user.map(|user| user.id)),
+        _ => None,
+    };
+
+    match status {
+        Some(status) => json!({
+            "limited": true,
+            "remaining": status.remaining,
+            "limit": status.limit,
+            "reset": status.reset,
+            "tier": status.tier,
+            "success": true,
+        }),
+        None => json!({
+            "limited": false,
+            "success": true,
+        }),
+    }
+}
+
+/// Query parameters for the `LIMITED_PATH` route.
+#[derive(FromForm)]
+pub struct LimitedOptions {
+    pub retry_after: Option<u64>,
+}
+
+/// Answers requests that were rerouted by the rate limiter.
+#[get("/api/rate-limited")]
+pub fn limited(options: Query<LimitedOptions>) -> Limited {
+    Limited { retry_after: options.0.retry_after.unwrap_or(1) }
+}
@@ -0,0 +1,52 @@
+/// The page /api/xbee/<node>/report is rendered from.
+pub const NODE_REPORT: &str = include_str!("../templates/report.html");
+
+/// Escapes text so it can be put in HTML as it is.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Fills in a template, replacing every `{{name}}` with the value
+/// given for `name`. Values are put in as they are, so any text in
+/// them has to be passed through `escape` first. Placeholders without
+/// a value are left empty.
+pub fn render(template: &str, values: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let name = after[..end].trim();
+        if let Some(&(_, ref value)) = values.iter().find(|&&(key, _)| key == name) {
+            rendered.push_str(value);
+        }
+
+        rest = &after[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,450 @@
+use parking_lot::Mutex;
+use rocket::http::{Cookie, Cookies};
+use time::Duration;
+
+use std::collections::HashMap;
+
+use config::Settings;
+
+/// The name of the private cookie holding the logged in session.
+pub const AUTH_COOKIE: &str = "auth";
+
+/// The name of the private cookie holding the id of the admin
+/// impersonating the logged in user.
+pub const IMPERSONATED_BY_COOKIE: &str = "impersonated_by";
+
+/// The value of the `auth` cookie from before users were told apart,
+/// which only says that someone logged in.
+const LEGACY_AUTH_VALUE: &str = "true";
+
+/// The user id given to sessions from `LEGACY_AUTH_VALUE` cookies. No
+/// user has it, so they are authorized but aren't anyone, and are
+/// never admins.
+const ANONYMOUS_USER_ID: i32 = 0;
+
+/// A logged in session, as it is stored in the private `auth` cookie.
+/// Private cookies are encrypted, so clients can't change the times.
+struct Session {
+    /// The database id of the logged in user.
+    user_id: i32,
+    /// When the user logged in, as a Unix timestamp.
+    started: i64,
+    /// When the session was last renewed, as a Unix timestamp.
+    renewed: i64,
+}
+
+impl Session {
+    /// Parses a session from the value of an `auth` cookie. Cookies
+    /// from before sessions were renewed only hold the user id, and
+    /// are parsed by `parse_legacy` instead.
+    fn parse(value: &str) -> Option<Session> {
+        let mut parts = value.splitn(3, ':');
+        let user_id = parts.next()?.parse().ok()?;
+        let started = parts.next()?.parse().ok()?;
+        let renewed = parts.next()?.parse().ok()?;
+
+        Some(Session {
+            user_id: user_id,
+            started: started,
+            renewed: renewed,
+        })
+    }
+
+    /// Parses a cookie from before sessions were renewed, which only
+    /// holds the user id. These are only accepted until the
+    /// `legacy_sessions_until` setting.
+    /// 
+    /// When the cookie was set isn't known, so the session counts as
+    /// starting at 0. Every revocation of the user's sessions then
+    /// applies to it.
+    /// 
+    /// The oldest cookies, which are just `LEGACY_AUTH_VALUE`, are
+    /// parsed as a session of `ANONYMOUS_USER_ID`.
+    fn parse_legacy(value: &str, settings: &Settings, now: i64) -> Option<Session> {
+        let value = value.trim();
+        let user_id = if value == LEGACY_AUTH_VALUE {
+            ANONYMOUS_USER_ID
+        } else {
+            value.parse().ok()?
+        };
+
+        let owner = if user_id == ANONYMOUS_USER_ID {
+            "an anonymous user".to_string()
+        } else {
+            format!("user {}", user_id)
+        };
+
+        if !settings.legacy_sessions_until.map_or(false, |until| now < until) {
+            info!("Rejected legacy session cookie for {}.", owner);
+            return None;
+        }
+
+        info!("Accepted legacy session cookie for {}.", owner);
+
+        Some(Session {
+            user_id: user_id,
+            started: 0,
+            renewed: 0,
+        })
+    }
+
+    fn value(&self) -> String {
+        format!("{}:{}:{}", self.user_id, self.started, self.renewed)
+    }
+
+    /// Returns whether the session is still valid as of `now`. It has
+    /// to have been renewed within `session_window_secs`, and started
+    /// within `session_max_secs`.
+    fn valid(&self, settings: &Settings, now: i64) -> bool {
+        now - self.renewed < settings.session_window_secs
+            && now - self.started < settings.session_max_secs
+    }
+
+    /// Returns whether the session is more than halfway through its
+    /// window, and should be renewed.
+    fn renewal_due(&self, settings: &Settings, now: i64) -> bool {
+        now - self.renewed > settings.session_window_secs / 2
+    }
+
+    /// Returns when the session expires, at the end of its window or at
+    /// the absolute limit, whichever comes first.
+    fn expires(&self, settings: &Settings) -> i64 {
+        (self.renewed + settings.session_window_secs).min(self.started + settings.session_max_secs)
+    }
+}
+
+/// A session that hasn't expired, as it is listed for its user.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ActiveSession {
+    /// When the user logged in, as a Unix timestamp.
+    pub started: i64,
+    /// When the session expires unless it is renewed, as a Unix
+    /// timestamp.
+    pub expires: i64,
+}
+
+/// The sessions that haven't expired, kept so admins can see how many
+/// users are logged in. Sessions are still stored in their cookies;
+/// this only notes each one's user, start and expiry as it is started,
+/// renewed or ended.
+/// 
+/// Sessions started before the server did are counted from the first
+/// request they make. A session whose cookie is thrown away without
+/// logging out is counted until it would have expired.
+#[derive(Default)]
+pub struct ActiveSessions(Mutex<HashMap<(i32, i64), i64>>);
+
+impl ActiveSessions {
+    /// Notes that the session is going, until it expires.
+    fn seen(&self, session: &Session, settings: &Settings) {
+        self.0.lock().insert((session.user_id, session.started), session.expires(settings));
+    }
+
+    /// Notes that the session has ended.
+    fn ended(&self, session: &Session) {
+        self.0.lock().remove(&(session.user_id, session.started));
+    }
+
+    /// Returns how many sessions haven't expired as of `now`,
+    /// forgetting the ones that have.
+    pub fn count(&self, now: i64) -> usize {
+        let mut sessions = self.0.lock();
+
+        sessions.retain(|_, expires| now < *expires);
+        sessions.len()
+    }
+
+    /// Returns the given user's sessions that haven't expired as of
+    /// `now`, oldest first.
+    pub fn for_user(&self, user_id: i32, now: i64) -> Vec<ActiveSession> {
+        let mut found = self.0.lock().iter()
+            .filter(|&(&(user, _), &expires)| user == user_id && now < expires)
+            .map(|(&(_, started), &expires)| ActiveSession {
+                started: started,
+                expires: expires,
+            })
+            .collect::<Vec<ActiveSession>>();
+
+        found.sort_by_key(|session| session.started);
+        found
+    }
+}
+
+/// Returns the session in the cookies, if they hold one that parses.
+fn stored(cookies: &mut Cookies) -> Option<Session> {
+    cookies.get_private(AUTH_COOKIE)
+        .and_then(|cookie| Session::parse(cookie.value()))
+}
+
+/// Writes the session to the cookies. The cookies expire at the end
+/// of the window, or at the absolute limit if that comes first.
+fn write(cookies: &mut Cookies, session: &Session, impersonated_by: Option<i32>, settings: &Settings, now: i64) {
+    let max_age = Duration::seconds(settings.session_window_secs
+        .min(session.started + settings.session_max_secs - now));
+
+    cookies.add_private(Cookie::build(AUTH_COOKIE, session.value())
+        .max_age(max_age)
+        .finish());
+
+    //  The impersonation has to expire with the session, or the
+    //  session would turn into the impersonated user's own.
+    match impersonated_by {
+        Some(admin) => cookies.add_private(Cookie::build(IMPERSONATED_BY_COOKIE, admin.to_string())
+            .max_age(max_age)
+            .finish()),
+        None => cookies.remove_private(Cookie::named(IMPERSONATED_BY_COOKIE)),
+    }
+}
+
+/// Logs the given user in with a new session, ending any session
+/// that was already going on. If an admin is impersonating the user,
+/// their id is given as well.
+pub fn start(cookies: &mut Cookies, sessions: &ActiveSessions, user_id: i32, impersonated_by: Option<i32>, settings: &Settings, now: i64) {
+    if let Some(previous) = stored(cookies) {
+        sessions.ended(&previous);
+    }
+
+    let session = Session {
+        user_id: user_id,
+        started: now,
+        renewed: now,
+    };
+
+    write(cookies, &session, impersonated_by, settings, now);
+    sessions.seen(&session, settings);
+}
+
+/// Removes the session cookies. Removing ones that aren't there is
+/// not an error.
+pub fn end(cookies: &mut Cookies, sessions: &ActiveSessions) {
+    if let Some(session) = stored(cookies) {
+        sessions.ended(&session);
+    }
+
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    cookies.remove_private(Cookie::named(IMPERSONATED_BY_COOKIE));
+}
+
+/// Returns the id of the logged in user, the id of the admin
+/// impersonating them, if any, and when the session started, or `None`
+/// if there is no valid session.
+/// 
+/// Sessions past half of their window are renewed with a fresh
+/// window, so active users stay logged in. Sessions past the absolute
+/// limit are ended, however active they are.
+/// 
+/// Cookies from before sessions were renewed are accepted as they are
+/// until `legacy_sessions_until`, and ended after it. They aren't
+/// renewed or counted in `ActiveSessions`. Cookies from before users
+/// were told apart are accepted the same way, as an anonymous session
+/// that isn't any user.
+pub fn current(cookies: &mut Cookies, sessions: &ActiveSessions, settings: &Settings, now: i64) -> Option<(i32, Option<i32>, i64)> {
+    let value = cookies.get_private(AUTH_COOKIE)?.value().to_string();
+    let impersonated_by = cookies.get_private(IMPERSONATED_BY_COOKIE)
+        .and_then(|cookie| cookie.value().parse().ok());
+
+    let session = match Session::parse(&value) {
+        Some(session) => session,
+        //  Legacy cookies are kept as they are rather than written in
+        //  the new format, so none of them outlast the grace period.
+        None => match Session::parse_legacy(&value, settings, now) {
+            Some(session) => return Some((session.user_id, impersonated_by, session.started)),
+            None => {
+                end(cookies, sessions);
+                return None;
+            }
+        },
+    };
+
+    if !session.valid(settings, now) {
+        end(cookies, sessions);
+        return None;
+    }
+
+    if session.renewal_due(settings, now) {
+        let renewed = Session { renewed: now, ..session };
+        write(cookies, &renewed, impersonated_by, settings, now);
+        sessions.seen(&renewed, settings);
+    } else {
+        sessions.seen(&session, settings);
+    }
+
+    Some((session.user_id, impersonated_by, session.started))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::config::{Config, Environment};
+
+    use std::sync::Arc;
+    use std::thread;
+
+    fn settings() -> Settings {
+        let config = Config::build(Environment::Development)
+            .extra("session_window_secs", 100)
+            .extra("session_max_secs", 1000)
+            .extra("legacy_sessions_until", 500)
+            .finalize()
+            .unwrap();
+
+        Settings::from_config(&config)
+    }
+
+    fn session(user_id: i32, started: i64, renewed: i64) -> Session {
+        Session {
+            user_id: user_id,
+            started: started,
+            renewed: renewed,
+        }
+    }
+
+    #[test]
+    fn sessions_last_through_their_window() {
+        let settings = settings();
+        let session = session(1, 0, 0);
+
+        assert!(session.valid(&settings, 99));
+        assert!(!session.valid(&settings, 100));
+    }
+
+    #[test]
+    fn renewal_is_due_past_half_the_window() {
+        let settings = settings();
+        let session = session(1, 0, 0);
+
+        assert!(!session.renewal_due(&settings, 50));
+        assert!(session.renewal_due(&settings, 51));
+    }
+
+    #[test]
+    fn renewing_mid_window_extends_the_session() {
+        let settings = settings();
+        let renewed = session(1, 0, 60);
+
+        assert!(renewed.valid(&settings, 150));
+        assert!(!renewed.renewal_due(&settings, 100));
+        assert_eq!(renewed.expires(&settings), 160);
+    }
+
+    #[test]
+    fn active_sessions_still_end_at_the_absolute_limit() {
+        let settings = settings();
+        let renewed = session(1, 0, 990);
+
+        assert!(renewed.valid(&settings, 999));
+        assert!(!renewed.valid(&settings, 1000));
+        assert_eq!(renewed.expires(&settings), 1000);
+    }
+
+    #[test]
+    fn sessions_round_trip_through_their_cookie() {
+        let parsed = Session::parse(&session(7, 10, 20).value()).unwrap();
+
+        assert_eq!((parsed.user_id, parsed.started, parsed.renewed), (7, 10, 20));
+        assert!(Session::parse("7").is_none());
+        assert!(Session::parse("7:ten:20").is_none());
+    }
+
+    #[test]
+    fn concurrent_logins_and_logouts_are_all_counted() {
+        let sessions = Arc::new(ActiveSessions::default());
+
+        let threads = (0..8)
+            .map(|user_id| {
+                let sessions = sessions.clone();
+
+                thread::spawn(move || {
+                    let settings = settings();
+
+                    for started in 0..200 {
+                        sessions.seen(&session(user_id, started, started), &settings);
+                    }
+
+                    for started in 0..100 {
+                        sessions.ended(&session(user_id, started, started));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(sessions.count(0), 8 * 100);
+    }
+
+    #[test]
+    fn expired_sessions_arent_counted() {
+        let settings = settings();
+        let sessions = ActiveSessions::default();
+
+        sessions.seen(&session(1, 0, 0), &settings);
+        sessions.seen(&session(2, 0, 50), &settings);
+        sessions.seen(&session(3, 0, 950), &settings);
+
+        assert_eq!(sessions.count(99), 3);
+        assert_eq!(sessions.count(100), 2);
+        assert_eq!(sessions.count(150), 1);
+        //  The third session was renewed late, but still ends at the
+        //  absolute limit.
+        assert_eq!(sessions.count(1000), 0);
+    }
+
+    #[test]
+    fn renewing_a_session_doesnt_count_it_twice() {
+        let settings = settings();
+        let sessions = ActiveSessions::default();
+
+        sessions.seen(&session(1, 0, 0), &settings);
+        sessions.seen(&session(1, 0, 60), &settings);
+
+        assert_eq!(sessions.count(120), 1);
+    }
+
+    #[test]
+    fn oldest_cookies_are_anonymous_until_the_cutoff() {
+        let settings = settings();
+
+        let session = Session::parse_legacy("true", &settings, 499).unwrap();
+        assert_eq!(session.user_id, ANONYMOUS_USER_ID);
+        assert!(Session::parse_legacy("true", &settings, 500).is_none());
+    }
+
+    #[test]
+    fn user_id_cookies_are_accepted_until_the_cutoff() {
+        let settings = settings();
+
+        let session = Session::parse_legacy("7", &settings, 499).unwrap();
+        assert_eq!((session.user_id, session.started), (7, 0));
+        assert!(Session::parse_legacy("7", &settings, 500).is_none());
+        assert!(Session::parse_legacy("yes", &settings, 499).is_none());
+    }
+
+    #[test]
+    fn legacy_cookies_are_rejected_without_a_cutoff() {
+        let config = Config::build(Environment::Development).finalize().unwrap();
+        let settings = Settings::from_config(&config);
+
+        assert!(Session::parse_legacy("true", &settings, 0).is_none());
+        assert!(Session::parse_legacy("7", &settings, 0).is_none());
+    }
+
+    #[test]
+    fn sessions_are_listed_for_their_user() {
+        let settings = settings();
+        let sessions = ActiveSessions::default();
+        sessions.seen(&session(1, 200, 250), &settings);
+        sessions.seen(&session(1, 100, 150), &settings);
+        sessions.seen(&session(1, 0, 20), &settings);
+        sessions.seen(&session(2, 100, 100), &settings);
+
+        assert_eq!(sessions.for_user(1, 200), vec![
+            ActiveSession { started: 100, expires: 250 },
+            ActiveSession { started: 200, expires: 350 },
+        ]);
+        assert_eq!(sessions.for_user(3, 200), vec![]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::cmp::Ordering;
+
+use info::XbeeInfo;
+
+/// A field that nodes can be sorted by.
+#[derive(Clone, Copy, Debug)]
+pub enum SortKey {
+    DisplayOrder,
+    NodeId,
+    Name,
+    Units,
+    LastUpdate,
+    Reading,
+    Value,
+}
+
+impl SortKey {
+    /// Returns the key with the given name, if there is one.
+    pub fn from_name(name: &str) -> Option<SortKey> {
+        match name {
+            "display_order" => Some(SortKey::DisplayOrder),
+            "node_id" | "uuid" => Some(SortKey::NodeId),
+            "name" => Some(SortKey::Name),
+            "units" => Some(SortKey::Units),
+            "last_update" => Some(SortKey::LastUpdate),
+            "reading" => Some(SortKey::Reading),
+            "value" => Some(SortKey::Value),
+            _ => None,
+        }
+    }
+}
+
+/// A key to sort by and whether to sort by it in descending order.
+pub type SortSpec = Vec<(SortKey, bool)>;
+
+/// Parses a comma separated list of sort keys, such as
+/// "name,-last_update". A key starting with `-` is sorted in
+/// descending order.
+/// 
+/// # Errors
+/// If any key is unknown, an error message naming it is returned.
+pub fn parse(sort: &str) -> Result<SortSpec, String> {
+    sort.split(',')
+        .map(|key| key.trim())
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let (name, descending) = if key.starts_with('-') {
+                (&key[1..], true)
+            } else {
+                (key, false)
+            };
+
+            SortKey::from_name(name)
+                .map(|key| (key, descending))
+                .ok_or_else(|| format!("Unknown sort key '{}'.", name))
+        })
+        .collect()
+}
+
+/// Compares two nodes by a single key. `display_order` gives the
+/// display position of a node.
+fn compare<F>(a: &XbeeInfo, b: &XbeeInfo, key: SortKey, display_order: &F) -> Ordering
+    where F: Fn(u32) -> i32
+{
+    match key {
+        SortKey::DisplayOrder => display_order(a.uuid).cmp(&display_order(b.uuid)),
+        SortKey::NodeId => a.uuid.cmp(&b.uuid),
+        SortKey::Name => a.name.cmp(&b.name),
+        SortKey::Units => a.units.cmp(&b.units),
+        SortKey::LastUpdate => a.last_update.cmp(&b.last_update),
+        SortKey::Reading => a.reading.cmp(&b.reading),
+        SortKey::Value => a.value().partial_cmp(&b.value()).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Sorts nodes by the given keys in order.
+/// 
+/// Nodes that are equal on every key fall back to the default order,
+/// which is by display position and then by node id, so the result
+/// is the same every time.
+pub fn sort_nodes<F>(nodes: &mut [XbeeInfo], spec: &[(SortKey, bool)], display_order: F)
+    where F: Fn(u32) -> i32
+{
+    let defaults = [(SortKey::DisplayOrder, false), (SortKey::NodeId, false)];
+
+    nodes.sort_by(|a, b| {
+        spec.iter()
+            .chain(defaults.iter())
+            .map(|&(key, descending)| {
+                let ordering = compare(a, b, key, &display_order);
+                if descending { ordering.reverse() } else { ordering }
+            })
+            .find(|&ordering| ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+}
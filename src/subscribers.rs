@@ -0,0 +1,119 @@
+use parking_lot::Mutex;
+use rocket::State;
+use rocket_contrib::JsonValue;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use api::AdminUser;
+
+/// The event stream of server log lines at /api/admin/logs.
+pub const LOG_STREAM: &str = "logs";
+
+/// The event streams of a single node's readings at
+/// /api/xbee/<node>/tail.
+pub const TAIL_STREAM: &str = "tail";
+
+/// The names of every event stream clients can follow.
+pub const STREAMS: &[&str] = &[LOG_STREAM, TAIL_STREAM];
+
+/// How long a client counts as subscribed after it last polled, in
+/// seconds. Event stream clients reconnect every few seconds, so one
+/// that hasn't for this long has gone away.
+const SUBSCRIBER_TIMEOUT: u64 = 10;
+
+/// A client following an event stream, and the node it is following
+/// if the stream is for a single node. Streams end after every
+/// response and clients reconnect from a new port, so they are told
+/// apart by user and IP rather than by connection.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Subscriber {
+    stream: &'static str,
+    node: Option<u32>,
+    user: i32,
+    address: Option<IpAddr>,
+}
+
+/// The clients following each event stream, and when each of them
+/// last polled it.
+#[derive(Default)]
+pub struct Subscribers {
+    seen: Mutex<HashMap<Subscriber, Instant>>,
+}
+
+impl Subscribers {
+    /// Records that a client polled the given stream, for the given
+    /// node if it is a single node's stream, just now.
+    pub fn touch(&self, stream: &'static str, node: Option<u32>, user: i32, address: Option<IpAddr>) {
+        let subscriber = Subscriber {
+            stream: stream,
+            node: node,
+            user: user,
+            address: address,
+        };
+
+        self.seen.lock().insert(subscriber, Instant::now());
+    }
+
+    /// Returns how many clients are following each stream, and each
+    /// node's stream, forgetting the ones that have timed out.
+    pub fn active(&self) -> (HashMap<&'static str, usize>, HashMap<u32, usize>) {
+        let now = Instant::now();
+        let mut seen = self.seen.lock();
+
+        seen.retain(|_, polled| now.duration_since(*polled).as_secs() < SUBSCRIBER_TIMEOUT);
+
+        let mut counts = STREAMS.iter()
+            .map(|&stream| (stream, 0))
+            .collect::<HashMap<&'static str, usize>>();
+        let mut nodes = HashMap::new();
+
+        for subscriber in seen.keys() {
+            *counts.entry(subscriber.stream).or_insert(0) += 1;
+
+            if let Some(node) = subscriber.node {
+                *nodes.entry(node).or_insert(0) += 1;
+            }
+        }
+
+        (counts, nodes)
+    }
+}
+
+/// Returns how many clients are following the server's event streams,
+/// in total and by stream, to help find out why clients aren't
+/// getting events. Clients count as connected for 10 seconds after
+/// they last polled.
+/// 
+/// `logs` is the log stream at /api/admin/logs, and `tail` is every
+/// node's stream at /api/xbee/<node>/tail. `nodes` breaks the node
+/// streams down by node id, and leaves out nodes nobody follows.
+/// 
+/// **Note**: This endpoint requires that the user is an admin.
+/// 
+/// # Example
+/// ```json
+/// {
+///     "count": 3,
+///     "streams": {
+///         "logs": 1,
+///         "tail": 2
+///     },
+///     "nodes": {
+///         "1234": 2
+///     },
+///     "success": true
+/// }
+/// ```
+#[get("/api/subscribers")]
+pub fn subscribers(subscribers: State<Subscribers>, _admin: AdminUser) -> JsonValue {
+    let (streams, nodes) = subscribers.active();
+
+    json!({
+        "count": streams.values().sum::<usize>(),
+        "streams": streams,
+        "nodes": nodes,
+        "success": true,
+    })
+}
@@ -0,0 +1,74 @@
+use failure::Error;
+use jsonwebtoken::{self, Header, Validation};
+
+use config::Settings;
+use db::models::User;
+
+/// The claims stored in a JWT issued by /api/login.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Claims {
+    /// The database id of the user the token was issued to.
+    pub sub: i32,
+    /// The role the user had when the token was issued.
+    pub role: String,
+    /// When the token expires, as a Unix timestamp.
+    pub exp: i64,
+    /// When the token was issued, as a Unix timestamp. Tokens from
+    /// before this was added count as issued at 0.
+    #[serde(default)]
+    pub iat: i64,
+    /// The database id of the admin impersonating the user, if the
+    /// token was issued through /api/users/<id>/impersonate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<i32>,
+}
+
+#[derive(Debug, Fail)]
+pub enum TokenError {
+    #[fail(display = "No JWT secret is configured.")]
+    NoSecret,
+    #[fail(display = "Could not encode token: {}", _0)]
+    Encode(String),
+}
+
+/// Issues a signed JWT for the given user at the time `now`. If an
+/// admin is impersonating the user, their id is given as well.
+/// 
+/// Returns the token along with the Unix timestamp it expires at.
+/// The lifetime of the token is set by `jwt_lifetime` in Rocket.toml.
+pub fn issue(user: &User, settings: &Settings, now: i64, impersonated_by: Option<i32>) -> Result<(String, i64), Error> {
+    let secret = settings.jwt_secret.as_ref()
+        .ok_or(TokenError::NoSecret)?;
+
+    let claims = Claims {
+        sub: user.id,
+        role: user.role.clone(),
+        exp: now + settings.jwt_lifetime,
+        iat: now,
+        impersonated_by: impersonated_by,
+    };
+
+    let token = jsonwebtoken::encode(&Header::default(), &claims, secret.as_bytes())
+        .map_err(|why| TokenError::Encode(why.to_string()))?;
+
+    Ok((token, claims.exp))
+}
+
+/// Checks the signature of the given token and that it hasn't expired
+/// by the time `now`, returning its claims if it is valid.
+pub fn verify(token: &str, settings: &Settings, now: i64) -> Option<Claims> {
+    let secret = settings.jwt_secret.as_ref()?;
+
+    //  Expiry is checked against the given time rather than the
+    //  system time, so it is checked here instead. The issue time is
+    //  only used to find revoked tokens.
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    validation.validate_iat = false;
+
+    let claims = jsonwebtoken::decode::<Claims>(token, secret.as_bytes(), &validation)
+        .ok()?
+        .claims;
+
+    if claims.exp > now { Some(claims) } else { None }
+}
@@ -0,0 +1,196 @@
+use hyper::Client;
+use hyper::header::ContentType;
+use hyper::net::HttpsConnector;
+use hyper_sync_rustls::TlsClient;
+use rocket_contrib::JsonValue;
+use time;
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+/// How long reading or writing a webhook request can take before the
+/// attempt fails, in seconds.
+const TIMEOUT_SECS: u64 = 5;
+
+/// How many times a webhook request is tried before its delivery
+/// fails.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before trying a webhook request again, doubled
+/// after every failed attempt.
+const RETRY_DELAY_MILLIS: u64 = 500;
+
+/// The outcome of delivering a payload to a webhook. `status` is the
+/// HTTP status of the last attempt, or `None` if it got no response,
+/// in which case `error` says why. `latency_ms` is how long the last
+/// attempt took.
+#[derive(Debug, PartialEq)]
+pub struct Delivery {
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    /// Returns whether the webhook answered with a success status.
+    pub fn delivered(&self) -> bool {
+        self.status.map_or(false, |status| status >= 200 && status < 300)
+    }
+}
+
+/// Returns whether a webhook URL can be posted to, which is when it
+/// is an HTTP or HTTPS URL.
+pub fn valid_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Returns the payload posted to webhooks when a node starts or stops
+/// alerting. Test deliveries set `test`, so receivers can tell them
+/// apart from real alerts.
+pub fn alert_payload(node_id: u32, name: &str, alerting: bool, value: Option<f64>, timestamp: i64, test: bool) -> JsonValue {
+    json!({
+        "event": if alerting { "alert_started" } else { "alert_ended" },
+        "node_id": node_id,
+        "name": name,
+        "alerting": alerting,
+        "value": value,
+        "timestamp": timestamp,
+        "test": test,
+    })
+}
+
+/// Posts a payload to a webhook as JSON, trying again after a delay
+/// if it can't be reached or answers with a server error. This blocks
+/// until the delivery succeeds or every attempt has failed.
+pub fn deliver(url: &str, payload: &JsonValue) -> Delivery {
+    let mut client = Client::with_connector(HttpsConnector::new(TlsClient::new()));
+    client.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)));
+    client.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)));
+
+    let body = payload.to_string();
+
+    with_retries(Duration::from_millis(RETRY_DELAY_MILLIS), || {
+        client.post(url)
+            .header(ContentType::json())
+            .body(&body[..])
+            .send()
+            .map(|response| response.status.to_u16())
+            .map_err(|why| why.to_string())
+    })
+}
+
+/// Makes attempts until one gets a response that isn't a server error
+/// or `MAX_ATTEMPTS` have been made, waiting `delay` after the first
+/// failure and twice as long after each one after that.
+fn with_retries<F>(delay: Duration, mut attempt: F) -> Delivery
+    where F: FnMut() -> Result<u16, String>
+{
+    let mut delay = delay;
+    let mut attempts = 0;
+
+    loop {
+        let start = time::precise_time_ns();
+        let result = attempt();
+        let latency_ms = time::precise_time_ns().saturating_sub(start) / 1_000_000;
+        attempts += 1;
+
+        let retry = match result {
+            Ok(status) => status >= 500,
+            Err(_) => true,
+        };
+
+        if !retry || attempts >= MAX_ATTEMPTS {
+            return Delivery {
+                status: result.as_ref().ok().cloned(),
+                latency_ms: latency_ms,
+                attempts: attempts,
+                error: result.err(),
+            };
+        }
+
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+/// Compares the alert state of each node with the one it had last
+/// time, returning the nodes that started or stopped alerting and
+/// remembering their new states. A node is `None` while its alerts are
+/// muted, which keeps the state it had before the mute, so muting and
+/// unmuting a node doesn't look like a change. A node's first state is
+/// only remembered, so nothing is sent for the states nodes are in
+/// when the server starts.
+pub fn alert_changes(previous: &mut HashMap<u32, bool>, current: &[(u32, Option<bool>)]) -> Vec<(u32, bool)> {
+    current.iter()
+        .filter_map(|&(node, alerting)| {
+            let alerting = match alerting {
+                Some(alerting) => alerting,
+                None => return None,
+            };
+
+            match previous.insert(node, alerting) {
+                Some(was) if was != alerting => Some((node, alerting)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_are_retried_until_the_last_attempt() {
+        let mut calls = 0;
+        let delivery = with_retries(Duration::from_millis(0), || {
+            calls += 1;
+            Ok(503)
+        });
+
+        assert_eq!(calls, MAX_ATTEMPTS);
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(delivery.status, Some(503));
+        assert!(!delivery.delivered());
+    }
+
+    #[test]
+    fn connection_errors_are_retried_until_one_succeeds() {
+        let mut calls = 0;
+        let delivery = with_retries(Duration::from_millis(0), || {
+            calls += 1;
+            if calls == 1 { Err("connection refused".to_string()) } else { Ok(204) }
+        });
+
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.status, Some(204));
+        assert_eq!(delivery.error, None);
+        assert!(delivery.delivered());
+    }
+
+    #[test]
+    fn client_errors_arent_retried() {
+        let delivery = with_retries(Duration::from_millis(0), || Ok(404));
+
+        assert_eq!(delivery.attempts, 1);
+        assert!(!delivery.delivered());
+    }
+
+    #[test]
+    fn muted_nodes_keep_their_state() {
+        let mut previous = HashMap::new();
+
+        //  The first states are only remembered.
+        assert!(alert_changes(&mut previous, &[(1, Some(false)), (2, Some(true))]).is_empty());
+
+        //  Node 1 starts alerting while muted, then is unmuted.
+        assert!(alert_changes(&mut previous, &[(1, None), (2, Some(true))]).is_empty());
+        assert_eq!(alert_changes(&mut previous, &[(1, Some(true)), (2, Some(false))]), vec![(1, true), (2, false)]);
+
+        //  Node 2 is the same before and after its mute.
+        assert!(alert_changes(&mut previous, &[(1, Some(true)), (2, None)]).is_empty());
+        assert!(alert_changes(&mut previous, &[(1, Some(true)), (2, Some(false))]).is_empty());
+    }
+}